@@ -0,0 +1,332 @@
+use anyhow::{bail, Context};
+use clap::Args;
+use image::{imageops, RgbaImage};
+use serde::Serialize;
+use std::{fmt::Write, path::PathBuf};
+use tracing::{info, warn};
+
+use crate::{charset::Charset, glyph_set, sdf};
+
+/// Arguments for the `atlas` command.
+#[derive(Args, Debug)]
+pub struct AtlasArgs {
+    /// The directory containing letter images extracted by the `scan`
+    /// command.
+    #[arg(short, long)]
+    glyphs_dir: PathBuf,
+
+    /// Where to write the atlas image, like `atlas.png`.
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Where to write where each glyph is on the atlas. The name should end
+    /// in `.json` or `.csv`, which picks the format. By default, a JSON file
+    /// is written next to the atlas, with the same name.
+    #[arg(short, long)]
+    metadata: Option<PathBuf>,
+
+    /// The characters written on the template, in grid order (left to right,
+    /// top to bottom), or the path of a text file containing them. This is
+    /// needed to tell which character each glyph is when the letter images
+    /// are named by cell, like `letter-0.png`, rather than by character. A
+    /// space marks a cell with no character.
+    #[arg(short, long)]
+    charset: Option<String>,
+
+    /// The width of the atlas, in pixels. By default, the atlas is about
+    /// square, and its width is a power of two.
+    #[arg(long)]
+    width: Option<u32>,
+
+    /// The number of empty pixels left between glyphs, so that they don't
+    /// bleed into each other when the atlas is sampled.
+    #[arg(long, default_value = "1")]
+    gap: u32,
+
+    /// Make the atlas's height a power of two too, as some older graphics
+    /// hardware needs.
+    #[arg(long, default_value_t = false)]
+    pot: bool,
+
+    /// Pack each glyph as a signed distance field, with this many pixels of
+    /// distance around its outline, so it can be drawn crisply at any size.
+    /// The distance is stored in every channel, with the outline at 128.
+    #[arg(long, value_name = "SPREAD")]
+    sdf: Option<u32>,
+}
+
+/// Where one glyph is on the atlas.
+#[derive(Debug, Serialize)]
+struct AtlasGlyph {
+    /// The name of the letter image the glyph came from.
+    file: String,
+    /// The glyph's character, or characters for a ligature.
+    character: Option<String>,
+    /// The glyph's rectangle on the atlas, in pixels. With `--sdf`, this
+    /// includes the spread around the letter image.
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    /// The same rectangle in texture coordinates, from 0 to 1.
+    u0: f32,
+    v0: f32,
+    u1: f32,
+    v1: f32,
+    /// How far to move the pen after drawing the glyph, in pixels. This is
+    /// the letter image's width, since it carries its own whitespace.
+    advance: u32,
+}
+
+/// The atlas metadata written as JSON.
+#[derive(Debug, Serialize)]
+struct AtlasMetadata {
+    /// The name of the atlas image.
+    image: String,
+    width: u32,
+    height: u32,
+    /// How the glyphs were drawn as signed distance fields, if they were.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sdf: Option<SdfMetadata>,
+    glyphs: Vec<AtlasGlyph>,
+}
+
+/// What a renderer needs to know to draw glyphs packed with `--sdf`.
+#[derive(Debug, Serialize)]
+struct SdfMetadata {
+    /// How many pixels of distance the field covers on either side of each
+    /// outline, and how many pixels were added around each letter image.
+    spread: u32,
+    /// The field's value on the outlines, from 0 to 1. Pixels above it are
+    /// inside the ink.
+    edge: f32,
+}
+
+/// Pack extracted letterforms into a single atlas image, and describe where
+/// each one is.
+pub fn atlas(args: AtlasArgs) -> anyhow::Result<()> {
+    let AtlasArgs {
+        glyphs_dir,
+        output,
+        metadata,
+        charset,
+        width,
+        gap,
+        pot,
+        sdf,
+    } = args;
+    if !glyphs_dir.is_dir() {
+        bail!("glyphs_dir path doesn't exist or is not a directory.");
+    }
+    if image::ImageFormat::from_path(&output).is_err() {
+        bail!("output must be an image file, like atlas.png.");
+    }
+    let metadata = metadata.unwrap_or_else(|| output.with_extension("json"));
+    let csv = match metadata
+        .extension()
+        .and_then(|extension| extension.to_str())
+    {
+        Some("json") => false,
+        Some("csv") => true,
+        _ => bail!("metadata must be a .json or .csv file."),
+    };
+    let charset = match charset {
+        Some(charset) => Charset::from_arg(&charset)?,
+        None => Charset::default(),
+    };
+
+    let mut files = Vec::new();
+    let mut images = Vec::new();
+    for glyph_file in glyph_set::read_glyph_files(&glyphs_dir)? {
+        let image = image::open(&glyph_file)
+            .with_context(|| format!("opening {}", glyph_file.display()))?
+            .to_rgba8();
+        let image = match sdf {
+            Some(spread) => {
+                let field = sdf::signed_distance_field(&sdf::ink_mask(&image), spread);
+                RgbaImage::from_fn(field.width(), field.height(), |x, y| {
+                    image::Rgba([field.get_pixel(x, y).0[0]; 4])
+                })
+            }
+            None => image,
+        };
+        files.push(glyph_file);
+        images.push(image);
+    }
+    if images.is_empty() {
+        bail!(
+            "{} doesn't contain any letter images.",
+            glyphs_dir.display()
+        );
+    }
+
+    let sizes: Vec<(u32, u32)> = images.iter().map(|image| image.dimensions()).collect();
+    let widest = sizes.iter().map(|size| size.0).max().unwrap_or(0) + gap * 2;
+    let width = match width {
+        Some(width) if width < widest => {
+            bail!("width must be at least {widest} pixels to fit the widest glyph.")
+        }
+        Some(width) => width,
+        None => default_width(&sizes, gap).max(widest),
+    };
+    let (positions, mut height) = pack(&sizes, width, gap);
+    if pot {
+        height = height.next_power_of_two();
+    }
+
+    let mut atlas = RgbaImage::new(width, height);
+    let mut glyphs = Vec::new();
+    let mut unnamed = 0;
+    for ((file, image), (x, y)) in files.iter().zip(&images).zip(positions) {
+        imageops::replace(&mut atlas, image, x as i64, y as i64);
+        let character = glyph_set::text_for_file(file, &charset);
+        if character.is_none() {
+            unnamed += 1;
+        }
+        let (glyph_width, glyph_height) = image.dimensions();
+        glyphs.push(AtlasGlyph {
+            file: file
+                .file_name()
+                .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+            character,
+            x,
+            y,
+            width: glyph_width,
+            height: glyph_height,
+            u0: x as f32 / width as f32,
+            v0: y as f32 / height as f32,
+            u1: (x + glyph_width) as f32 / width as f32,
+            v1: (y + glyph_height) as f32 / height as f32,
+            advance: glyph_width - sdf.unwrap_or(0) * 2,
+        });
+    }
+    if unnamed > 0 {
+        warn!(
+            "{unnamed} letter images couldn't be matched to a character. Pass --charset to name them."
+        );
+    }
+
+    atlas
+        .save(&output)
+        .with_context(|| format!("saving {}", output.display()))?;
+    let metadata_contents = if csv {
+        to_csv(&glyphs)
+    } else {
+        let metadata = AtlasMetadata {
+            image: output
+                .file_name()
+                .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+            width,
+            height,
+            sdf: sdf.map(|spread| SdfMetadata {
+                spread,
+                edge: sdf::EDGE,
+            }),
+            glyphs,
+        };
+        serde_json::to_string_pretty(&metadata).context("serializing the atlas metadata")?
+    };
+    std::fs::write(&metadata, metadata_contents)
+        .with_context(|| format!("writing {}", metadata.display()))?;
+    info!(
+        "Packed {} glyphs into a {width}x{height} atlas at {}, described in {}.",
+        images.len(),
+        output.display(),
+        metadata.display()
+    );
+
+    Ok(())
+}
+
+/// A width that makes the atlas about square: the smallest power of two
+/// that's at least the square root of the glyphs' total area.
+fn default_width(sizes: &[(u32, u32)], gap: u32) -> u32 {
+    let area: u64 = sizes
+        .iter()
+        .map(|&(width, height)| (width + gap) as u64 * (height + gap) as u64)
+        .sum();
+    ((area as f64).sqrt().ceil() as u32)
+        .max(1)
+        .next_power_of_two()
+}
+
+/// Place rectangles of the given sizes in rows on an atlas `width` pixels
+/// wide, tallest first, with `gap` pixels around each one. Returns where each
+/// rectangle goes, in the order given, and the height the atlas needs.
+pub fn pack(sizes: &[(u32, u32)], width: u32, gap: u32) -> (Vec<(u32, u32)>, u32) {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(sizes[i].1));
+
+    let mut positions = vec![(0, 0); sizes.len()];
+    let (mut x, mut y) = (gap, gap);
+    let mut row_height = 0;
+    for i in order {
+        let (rect_width, rect_height) = sizes[i];
+        if x + rect_width + gap > width && x > gap {
+            // Start a new row.
+            x = gap;
+            y += row_height + gap;
+            row_height = 0;
+        }
+        positions[i] = (x, y);
+        x += rect_width + gap;
+        row_height = row_height.max(rect_height);
+    }
+
+    (positions, y + row_height + gap)
+}
+
+/// Describe where each glyph is on the atlas as CSV, one glyph per line.
+fn to_csv(glyphs: &[AtlasGlyph]) -> String {
+    let mut csv = String::from("file,character,x,y,width,height,u0,v0,u1,v1,advance\n");
+    for glyph in glyphs {
+        let character = glyph.character.as_deref().unwrap_or_default();
+        writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            csv_field(&glyph.file),
+            csv_field(character),
+            glyph.x,
+            glyph.y,
+            glyph.width,
+            glyph.height,
+            glyph.u0,
+            glyph.v0,
+            glyph.u1,
+            glyph.v1,
+            glyph.advance
+        )
+        .expect("writing to a string can't fail");
+    }
+
+    csv
+}
+
+/// Quote a CSV field if it holds a comma, quote, or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_rows_without_overlapping() {
+        let sizes = [(30, 10), (30, 40), (30, 20), (30, 40)];
+        let (positions, height) = pack(&sizes, 100, 1);
+        // The three tallest fill the first row, and the shortest goes on the
+        // next.
+        assert_eq!(positions[1], (1, 1));
+        assert_eq!(positions[3], (32, 1));
+        assert_eq!(positions[2], (63, 1));
+        assert_eq!(positions[0], (1, 42));
+        assert_eq!(height, 53);
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("\""), "\"\"\"\"");
+    }
+}
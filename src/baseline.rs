@@ -0,0 +1,186 @@
+use image::{imageops::FilterType, GenericImageView, GrayImage};
+
+/// How much the pages are shrunk by when estimating the offset between them.
+const ALIGNMENT_SCALE: u32 = 8;
+
+/// How far apart, as a fraction of the page size, two scans are allowed to be.
+const MAX_PAGE_SHIFT: f32 = 0.05;
+
+/// Estimate how far the `baseline` page must be moved to line up with the
+/// `current` page, as `(dx, dy)` in pixels. Both pages are expected to be
+/// thresholded and cropped the same way, and to have the same dimensions.
+///
+/// The search runs on shrunken copies of the pages, so the result is only
+/// accurate to within `ALIGNMENT_SCALE` pixels. [`cell_similarity`] refines it
+/// per cell.
+pub fn estimate_offset(current: &GrayImage, baseline: &GrayImage) -> (i64, i64) {
+    let shrink = |image: &GrayImage| {
+        image::imageops::resize(
+            image,
+            (image.width() / ALIGNMENT_SCALE).max(1),
+            (image.height() / ALIGNMENT_SCALE).max(1),
+            FilterType::Triangle,
+        )
+    };
+    let current = shrink(current);
+    let baseline = shrink(baseline);
+    let (width, height) = current.dimensions();
+    let max_shift = (width.max(height) as f32 * MAX_PAGE_SHIFT).ceil() as i64;
+
+    let mut best = (0, 0);
+    let mut best_difference = f64::MAX;
+    for dy in -max_shift..=max_shift {
+        for dx in -max_shift..=max_shift {
+            let mut total = 0u64;
+            let mut count = 0u64;
+            for (x, y, pixel) in current.enumerate_pixels() {
+                let (bx, by) = (x as i64 + dx, y as i64 + dy);
+                if bx < 0
+                    || by < 0
+                    || bx >= baseline.width() as i64
+                    || by >= baseline.height() as i64
+                {
+                    continue;
+                }
+                let other = baseline.get_pixel(bx as u32, by as u32);
+                total += pixel.0[0].abs_diff(other.0[0]) as u64;
+                count += 1;
+            }
+            if count == 0 {
+                continue;
+            }
+            let difference = total as f64 / count as f64;
+            if difference < best_difference {
+                best_difference = difference;
+                best = (dx, dy);
+            }
+        }
+    }
+
+    (
+        best.0 * ALIGNMENT_SCALE as i64,
+        best.1 * ALIGNMENT_SCALE as i64,
+    )
+}
+
+/// Compare a cell of the current page with the same cell of the `baseline`
+/// page, returning the overlap of their ink as a value between 0 (nothing in
+/// common) and 1 (identical). Two empty cells are identical.
+///
+/// `(x, y)` is the position of the cell on the baseline page, including the
+/// offset from [`estimate_offset`]. Small shifts around it are tried, and the
+/// best match is used.
+pub fn cell_similarity<I>(cell: &I, baseline: &GrayImage, x: i64, y: i64) -> f32
+where
+    I: GenericImageView<Pixel = image::Luma<u8>>,
+{
+    let is_ink = |value: u8| value < 128;
+    let ink: Vec<(u32, u32)> = cell
+        .pixels()
+        .filter(|(_, _, pixel)| is_ink(pixel.0[0]))
+        .map(|(x, y, _)| (x, y))
+        .collect();
+    let baseline_ink_at = |bx: i64, by: i64| {
+        bx >= 0
+            && by >= 0
+            && bx < baseline.width() as i64
+            && by < baseline.height() as i64
+            && is_ink(baseline.get_pixel(bx as u32, by as u32).0[0])
+    };
+
+    let (width, height) = (cell.width() as usize, cell.height() as usize);
+    let refine = (ALIGNMENT_SCALE / 2) as i64;
+    // Count the baseline's ink once, as running totals over the cell grown by
+    // `refine` on each side, so that the ink under each shifted cell is found
+    // from four of them.
+    let span = 2 * refine as usize;
+    let (sums_width, sums_height) = (width + span + 1, height + span + 1);
+    let mut sums = vec![0u32; sums_width * sums_height];
+    for sy in 1..sums_height {
+        let mut row = 0;
+        for sx in 1..sums_width {
+            let (bx, by) = (x - refine + sx as i64 - 1, y - refine + sy as i64 - 1);
+            row += baseline_ink_at(bx, by) as u32;
+            sums[sy * sums_width + sx] = sums[(sy - 1) * sums_width + sx] + row;
+        }
+    }
+    let sum = |sx: usize, sy: usize| sums[sy * sums_width + sx];
+
+    let mut best = 0.0f32;
+    for dy in -refine..=refine {
+        for dx in -refine..=refine {
+            let (ox, oy) = (x + dx, y + dy);
+            let (left, top) = ((dx + refine) as usize, (dy + refine) as usize);
+            let (right, bottom) = (left + width, top + height);
+            let baseline_count =
+                sum(right, bottom) + sum(left, top) - sum(left, bottom) - sum(right, top);
+            let overlap = ink
+                .iter()
+                .filter(|(cx, cy)| baseline_ink_at(ox + *cx as i64, oy + *cy as i64))
+                .count() as u32;
+            let union = ink.len() as u32 + baseline_count - overlap;
+            let similarity = if union == 0 {
+                1.0
+            } else {
+                overlap as f32 / union as f32
+            };
+            best = best.max(similarity);
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    /// The cells of a test page, as `(x, y, width, height)`.
+    const CELLS: [(u32, u32, u32, u32); 3] =
+        [(40, 40, 80, 80), (160, 40, 80, 80), (280, 40, 80, 80)];
+
+    /// Draw a page with an L shape in each cell, moved by `(dx, dy)`, and with
+    /// the last cell's L turned around when `changed` is set.
+    fn page(dx: u32, dy: u32, changed: bool) -> GrayImage {
+        let mut page = GrayImage::from_pixel(400, 160, Luma([255]));
+        for (i, &(x, y, _, _)) in CELLS.iter().enumerate() {
+            let turned = changed && i == CELLS.len() - 1;
+            for t in 0..8 {
+                for s in 0..50 {
+                    let (stem, foot) = if turned {
+                        ((60 - t, 15 + s), (20 + s, 15 + t))
+                    } else {
+                        ((20 + t, 15 + s), (20 + s, 58 + t))
+                    };
+                    for (px, py) in [stem, foot] {
+                        page.put_pixel(x + px + dx, y + py + dy, Luma([0]));
+                    }
+                }
+            }
+        }
+        page
+    }
+
+    #[test]
+    fn skips_cells_that_match_a_shifted_baseline() {
+        let current = page(0, 0, true);
+        let baseline = page(3, 2, false);
+        let (dx, dy) = estimate_offset(&current, &baseline);
+
+        let unchanged: Vec<bool> = CELLS
+            .iter()
+            .map(|&(x, y, width, height)| {
+                let cell = current.view(x, y, width, height);
+                cell_similarity(&*cell, &baseline, x as i64 + dx, y as i64 + dy) >= 0.75
+            })
+            .collect();
+        assert_eq!(unchanged, [true, true, false]);
+        // Two blank cells are the same.
+        let blank = GrayImage::from_pixel(400, 160, Luma([255]));
+        assert_eq!(
+            cell_similarity(&*blank.view(0, 0, 30, 30), &blank, 0, 0),
+            1.0
+        );
+    }
+}
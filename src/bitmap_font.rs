@@ -0,0 +1,390 @@
+use anyhow::{bail, Context};
+use image::{imageops, GrayImage, Luma, Rgba, RgbaImage};
+use std::{collections::BTreeMap, fmt::Write, path::Path};
+use tracing::warn;
+
+use crate::{
+    atlas,
+    build_font::{DESCENDERS, SIDE_BEARING, TALLEST_GLYPH, UNITS_PER_EM},
+    charset::Charset,
+    export::glyph_name,
+    glyph_metrics, glyph_set, guides,
+};
+
+/// Pixels darker than this are ink.
+const INK_THRESHOLD: u8 = 128;
+/// The advance width of the space character, as a fraction of the pixel
+/// size.
+const SPACE_WIDTH: f32 = 0.3;
+
+/// Letterforms scaled down to a pixel size and placed on a baseline, ready
+/// to be written as a bitmap font.
+#[derive(Debug)]
+pub struct BitmapFont {
+    pub family_name: String,
+    /// The height of a line of text, in pixels.
+    pub pixel_size: u32,
+    /// How far the tallest glyph rises above the baseline, in pixels.
+    pub ascent: i32,
+    /// How far the lowest glyph drops below the baseline, in pixels.
+    pub descent: i32,
+    pub glyphs: BTreeMap<char, BitmapGlyph>,
+}
+
+/// One glyph of a [`BitmapFont`].
+#[derive(Debug)]
+pub struct BitmapGlyph {
+    /// The glyph's ink, where 255 is ink and 0 is paper.
+    pub bitmap: GrayImage,
+    /// How far right of the pen the bitmap starts, in pixels.
+    pub x_offset: i32,
+    /// How far above the baseline the top of the bitmap is, in pixels.
+    pub top: i32,
+    /// How far to move the pen after drawing the glyph, in pixels.
+    pub advance: u32,
+}
+
+/// Scale every letter image in `glyphs_dir` to `pixel_size`, and place it on
+/// the baseline the same way `build-font` does: the tallest glyph is as tall
+/// as a capital, and letters sit on the baseline found when they were
+/// scanned, or, without one, letters with descenders drop below it. They're
+/// spaced by the side bearings measured when they were scanned, if the
+/// scan's manifest records them.
+pub fn rasterize_font(
+    glyphs_dir: &Path,
+    charset: &Charset,
+    family_name: String,
+    pixel_size: u32,
+) -> anyhow::Result<BitmapFont> {
+    if !glyphs_dir.is_dir() {
+        bail!("glyphs_dir path doesn't exist or is not a directory.");
+    }
+    if pixel_size < 4 {
+        bail!("pixel_size must be at least 4.");
+    }
+    if family_name.trim().is_empty() {
+        bail!("family_name must not be empty.");
+    }
+
+    // The letterforms cropped to their ink, at the size they were scanned,
+    // along with how far below their tops the baseline guide was, if it was
+    // found, and their measurements.
+    let mut letterforms = BTreeMap::new();
+    let found_guides = guides::read_from_manifests(glyphs_dir)?;
+    let found_metrics = glyph_metrics::read_from_manifests(glyphs_dir)?;
+    let mut unnamed = 0;
+    for glyph_file in glyph_set::read_glyph_files(glyphs_dir)? {
+        let Some(c) = glyph_set::char_for_file(&glyph_file, charset) else {
+            // Bitmap fonts have no ligatures, so those are left out quietly.
+            if glyph_set::text_for_file(&glyph_file, charset).is_none() {
+                unnamed += 1;
+            }
+            continue;
+        };
+        if c.is_whitespace() {
+            continue;
+        }
+        if letterforms.contains_key(&c) {
+            warn!(
+                "{} is another image of {c:?}; only the first is used.",
+                glyph_file.display()
+            );
+            continue;
+        }
+        let image = image::open(&glyph_file)
+            .with_context(|| format!("opening {}", glyph_file.display()))?
+            .to_luma8();
+        let Some((x, y, width, height)) = ink_rect(&image) else {
+            warn!("{} has no ink.", glyph_file.display());
+            continue;
+        };
+        let metrics = found_metrics.get(&glyph_file).copied();
+        let baseline = match (found_guides.get(&glyph_file), metrics) {
+            (Some(guides), _) => Some(guides.baseline - y as f32),
+            (None, Some(metrics)) => Some(metrics.ascent * metrics.x_height_in(height as f32)),
+            (None, None) => None,
+        };
+        letterforms.insert(
+            c,
+            (
+                imageops::crop_imm(&image, x, y, width, height).to_image(),
+                baseline,
+                metrics,
+            ),
+        );
+    }
+    if unnamed > 0 {
+        warn!(
+            "{unnamed} letter images couldn't be matched to a character. Pass --charset to name them."
+        );
+    }
+    if letterforms.is_empty() {
+        bail!("no letterforms were found to build a font from.");
+    }
+
+    let tallest = letterforms
+        .values()
+        .map(|(image, _, _)| image.height())
+        .max()
+        .unwrap_or(1);
+    let scale = pixel_size as f32 * TALLEST_GLYPH / tallest as f32;
+    let side_bearing =
+        ((pixel_size as f32 * SIDE_BEARING / UNITS_PER_EM as f32).round() as i32).max(1);
+
+    let mut glyphs = BTreeMap::new();
+    glyphs.insert(
+        ' ',
+        BitmapGlyph {
+            bitmap: GrayImage::new(0, 0),
+            x_offset: 0,
+            top: 0,
+            advance: ((pixel_size as f32 * SPACE_WIDTH).round() as u32).max(1),
+        },
+    );
+    for (c, (image, baseline, metrics)) in letterforms {
+        let width = ((image.width() as f32 * scale).round() as u32).max(1);
+        let height = ((image.height() as f32 * scale).round() as u32).max(1);
+        let scaled = imageops::resize(&image, width, height, imageops::FilterType::Triangle);
+        let bitmap = GrayImage::from_fn(width, height, |x, y| {
+            Luma([if scaled.get_pixel(x, y).0[0] < INK_THRESHOLD {
+                255
+            } else {
+                0
+            }])
+        });
+        let top = match baseline {
+            Some(baseline) => baseline * scale,
+            None => {
+                let drop = DESCENDERS
+                    .iter()
+                    .find(|(descender, _)| *descender == c)
+                    .map_or(0.0, |(_, drop)| height as f32 * drop);
+                height as f32 - drop
+            }
+        };
+        let (left_side_bearing, right_side_bearing) = match metrics {
+            Some(metrics) => {
+                let x_height = metrics.x_height_in(height as f32);
+                let bearing = |bearing: f32| ((bearing * x_height).round() as i32).max(1);
+                (
+                    bearing(metrics.left_side_bearing),
+                    bearing(metrics.right_side_bearing),
+                )
+            }
+            None => (side_bearing, side_bearing),
+        };
+        glyphs.insert(
+            c,
+            BitmapGlyph {
+                bitmap,
+                x_offset: left_side_bearing,
+                top: top.round() as i32,
+                advance: width + (left_side_bearing + right_side_bearing) as u32,
+            },
+        );
+    }
+
+    let ascent = glyphs.values().map(|glyph| glyph.top).max().unwrap_or(0);
+    let descent = glyphs
+        .values()
+        .map(|glyph| glyph.bitmap.height() as i32 - glyph.top)
+        .max()
+        .unwrap_or(0)
+        .max(0);
+
+    Ok(BitmapFont {
+        family_name,
+        pixel_size,
+        ascent,
+        descent,
+        glyphs,
+    })
+}
+
+/// The smallest rectangle holding every dark pixel of a letter image.
+fn ink_rect(image: &GrayImage) -> Option<(u32, u32, u32, u32)> {
+    let (mut left, mut top, mut right, mut bottom) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, Luma([value])) in image.enumerate_pixels() {
+        if *value < INK_THRESHOLD {
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x);
+            bottom = bottom.max(y);
+        }
+    }
+    (left <= right).then(|| (left, top, right - left + 1, bottom - top + 1))
+}
+
+/// Write a bitmap font in the Glyph Bitmap Distribution Format, which X11,
+/// most embedded display libraries, and many font editors read.
+pub fn bdf(font: &BitmapFont) -> String {
+    let BitmapFont {
+        family_name,
+        pixel_size,
+        ascent,
+        descent,
+        glyphs,
+    } = font;
+    // Hyphens separate the fields of the font's name, and quotes end strings.
+    let family_name = family_name.replace(['-', '"'], " ");
+    let max_width = glyphs
+        .values()
+        .map(|glyph| glyph.bitmap.width() as i32 + glyph.x_offset)
+        .max()
+        .unwrap_or(0);
+    let average_width =
+        glyphs.values().map(|glyph| glyph.advance).sum::<u32>() / glyphs.len().max(1) as u32;
+
+    let mut bdf = String::new();
+    let mut line = |text: String| {
+        bdf.push_str(&text);
+        bdf.push('\n');
+    };
+    line("STARTFONT 2.1".into());
+    line(format!(
+        "FONT -handwriting-{family_name}-Medium-R-Normal--{pixel_size}-{}-75-75-P-{}-ISO10646-1",
+        pixel_size * 10,
+        average_width * 10
+    ));
+    line(format!("SIZE {pixel_size} 75 75"));
+    line(format!(
+        "FONTBOUNDINGBOX {max_width} {} 0 {}",
+        ascent + descent,
+        -descent
+    ));
+    line("STARTPROPERTIES 6".into());
+    line(format!("FAMILY_NAME \"{family_name}\""));
+    line(format!("PIXEL_SIZE {pixel_size}"));
+    line(format!("FONT_ASCENT {ascent}"));
+    line(format!("FONT_DESCENT {descent}"));
+    line("CHARSET_REGISTRY \"ISO10646\"".into());
+    line("CHARSET_ENCODING \"1\"".into());
+    line("ENDPROPERTIES".into());
+    line(format!("CHARS {}", glyphs.len()));
+    for (c, glyph) in glyphs {
+        let (width, height) = glyph.bitmap.dimensions();
+        line(format!("STARTCHAR {}", glyph_name(*c)));
+        line(format!("ENCODING {}", *c as u32));
+        line(format!("SWIDTH {} 0", glyph.advance * 1000 / pixel_size));
+        line(format!("DWIDTH {} 0", glyph.advance));
+        line(format!(
+            "BBX {width} {height} {} {}",
+            glyph.x_offset,
+            glyph.top - height as i32
+        ));
+        line("BITMAP".into());
+        for y in 0..height {
+            // Each row is padded to a whole number of bytes, most significant
+            // bit first.
+            let mut row = String::new();
+            for byte_start in (0..width).step_by(8) {
+                let mut byte = 0u8;
+                for bit in 0..8 {
+                    let x = byte_start + bit;
+                    if x < width && glyph.bitmap.get_pixel(x, y).0[0] > 0 {
+                        byte |= 0x80 >> bit;
+                    }
+                }
+                write!(row, "{byte:02X}").expect("writing to a string can't fail");
+            }
+            line(row);
+        }
+        line("ENDCHAR".into());
+    }
+    line("ENDFONT".into());
+
+    bdf
+}
+
+/// Write a bitmap font in AngelCode's BMFont text format, which many game
+/// engines read. Returns the `.fnt` file and its page: an image of every
+/// glyph in white, on transparency, which must be saved as `page_file` next
+/// to the `.fnt` file.
+pub fn fnt(font: &BitmapFont, page_file: &str) -> (String, RgbaImage) {
+    const GAP: u32 = 1;
+    let glyphs: Vec<(&char, &BitmapGlyph)> = font.glyphs.iter().collect();
+    let sizes: Vec<(u32, u32)> = glyphs
+        .iter()
+        .map(|(_, glyph)| glyph.bitmap.dimensions())
+        .collect();
+    let area: u32 = sizes.iter().map(|(w, h)| (w + GAP) * (h + GAP)).sum();
+    let widest = sizes.iter().map(|size| size.0).max().unwrap_or(0) + GAP * 2;
+    let width = ((area as f32).sqrt().ceil() as u32)
+        .next_power_of_two()
+        .max(widest);
+    let (positions, height) = atlas::pack(&sizes, width, GAP);
+
+    let mut page = RgbaImage::new(width, height);
+    let mut fnt = String::new();
+    let family_name = font.family_name.replace('"', "'");
+    let mut line = |text: String| {
+        fnt.push_str(&text);
+        fnt.push('\n');
+    };
+    line(format!(
+        "info face=\"{family_name}\" size={} bold=0 italic=0 charset=\"\" unicode=1 \
+         stretchH=100 smooth=0 aa=1 padding=0,0,0,0 spacing={GAP},{GAP}",
+        font.pixel_size
+    ));
+    line(format!(
+        "common lineHeight={} base={} scaleW={width} scaleH={height} pages=1 packed=0",
+        font.ascent + font.descent,
+        font.ascent
+    ));
+    line(format!("page id=0 file=\"{page_file}\""));
+    line(format!("chars count={}", glyphs.len()));
+    for ((c, glyph), (x, y)) in glyphs.iter().zip(positions) {
+        for (gx, gy, Luma([value])) in glyph.bitmap.enumerate_pixels() {
+            page.put_pixel(x + gx, y + gy, Rgba([255, 255, 255, *value]));
+        }
+        let (glyph_width, glyph_height) = glyph.bitmap.dimensions();
+        line(format!(
+            "char id={} x={x} y={y} width={glyph_width} height={glyph_height} xoffset={} \
+             yoffset={} xadvance={} page=0 chnl=15",
+            **c as u32,
+            glyph.x_offset,
+            font.ascent - glyph.top,
+            glyph.advance
+        ));
+    }
+
+    (fnt, page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_bdf_rows_a_byte_at_a_time() {
+        // A 10 pixel wide bar, which needs two bytes per row.
+        let mut bitmap = GrayImage::new(10, 2);
+        for x in 0..10 {
+            bitmap.put_pixel(x, 0, Luma([255]));
+        }
+        bitmap.put_pixel(9, 1, Luma([255]));
+        let font = BitmapFont {
+            family_name: "Test-Hand".to_string(),
+            pixel_size: 16,
+            ascent: 2,
+            descent: 0,
+            glyphs: BTreeMap::from([(
+                'A',
+                BitmapGlyph {
+                    bitmap,
+                    x_offset: 1,
+                    top: 2,
+                    advance: 12,
+                },
+            )]),
+        };
+        let bdf = bdf(&font);
+        assert!(bdf.contains("FAMILY_NAME \"Test Hand\""));
+        assert!(bdf.contains("ENCODING 65\n"));
+        assert!(bdf.contains("BBX 10 2 1 0\nBITMAP\nFFC0\n0040\nENDCHAR"));
+
+        let (fnt, page) = fnt(&font, "test_0.png");
+        assert!(fnt.contains("char id=65 x=1 y=1 width=10 height=2 xoffset=1 yoffset=0"));
+        assert_eq!(page.get_pixel(1, 1).0, [255, 255, 255, 255]);
+    }
+}
@@ -0,0 +1,541 @@
+use anyhow::{bail, Context};
+use clap::Args;
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+use crate::{
+    charset::Charset,
+    glyph_metrics::{self, GlyphMetrics},
+    glyph_set,
+    guides::{self, Guides},
+    metrics::Metrics,
+    truetype::{self, FontInfo, Glyph, Point},
+    vectorize::{self, Outline},
+};
+
+/// The size of the em square, in font units.
+pub const UNITS_PER_EM: u16 = 1000;
+/// The height of the tallest glyph, as a fraction of the em. Capitals in most
+/// fonts are about this tall.
+pub const TALLEST_GLYPH: f32 = 0.7;
+/// The space left on each side of a glyph, in font units.
+pub const SIDE_BEARING: f32 = 50.0;
+/// The advance width of the space character, in font units.
+const SPACE_WIDTH: u16 = 300;
+/// Characters that hang below the baseline, along with how far they drop, as
+/// a fraction of their height.
+///
+/// This is only used for letter images whose manifest doesn't record where
+/// the template's baseline guide was, so it's a rough placement that's right
+/// for most handwriting.
+pub const DESCENDERS: &[(char, f32)] = &[
+    ('g', 0.35),
+    ('j', 0.35),
+    ('p', 0.35),
+    ('q', 0.35),
+    ('y', 0.35),
+    (',', 0.5),
+    (';', 0.25),
+];
+
+/// Arguments for the `build-font` command.
+#[derive(Args, Debug)]
+pub struct BuildFontArgs {
+    /// The directory containing letter images extracted by the `scan`
+    /// command.
+    #[arg(short, long)]
+    glyphs_dir: PathBuf,
+
+    /// Where to write the font. The file name should end in `.ttf` or `.otf`.
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// The characters written on the template, in grid order (left to right,
+    /// top to bottom), or the path of a text file containing them. This is
+    /// needed when the letter images are named by cell, like `letter-0.png`,
+    /// rather than by character. A space marks a cell with no character.
+    #[arg(short, long)]
+    charset: Option<String>,
+
+    /// The name of the font family, as shown in font menus.
+    #[arg(long, default_value = "My Handwriting")]
+    family_name: String,
+
+    /// How closely outlines follow the edges of each letter image, in pixels.
+    /// Larger values make smoother glyphs with fewer points, but lose detail.
+    #[arg(long, default_value_t = 1.0)]
+    simplify: f32,
+
+    /// Space the glyphs like your handwriting, using the measurements
+    /// written by the `metrics` command: the space between letters and
+    /// words, and the kerning of pairs of letters. By default, each glyph is
+    /// spaced by the side bearings measured when it was scanned, or, if the
+    /// scan's manifest doesn't record them, has the same space on each side.
+    #[arg(long, value_name = "FILE")]
+    metrics: Option<PathBuf>,
+}
+
+/// A letter image traced into outlines, in pixel coordinates.
+struct TracedGlyph {
+    outlines: Vec<Outline>,
+    /// The bounds of the outlines: left, top, right, and bottom.
+    bounds: (f32, f32, f32, f32),
+    /// Where the template's guide lines were in the letter image, if its
+    /// manifest records them.
+    guides: Option<Guides>,
+    /// How the letterform was spaced and where it sat when it was scanned,
+    /// if its manifest records it.
+    metrics: Option<GlyphMetrics>,
+}
+
+/// Build a TrueType font from extracted letterforms.
+pub fn build_font(args: BuildFontArgs) -> anyhow::Result<()> {
+    let BuildFontArgs {
+        glyphs_dir,
+        output,
+        charset,
+        family_name,
+        simplify,
+        metrics,
+    } = args;
+    let extension = output.extension().and_then(|e| e.to_str()).unwrap_or("");
+    if !extension.eq_ignore_ascii_case("ttf") && !extension.eq_ignore_ascii_case("otf") {
+        bail!("output must be a .ttf or .otf file.");
+    }
+    let charset = match charset {
+        Some(charset) => Charset::from_arg(&charset)?,
+        None => Charset::default(),
+    };
+    let metrics = metrics.as_deref().map(Metrics::load).transpose()?;
+    let traced = trace_font(
+        &glyphs_dir,
+        &charset,
+        family_name,
+        simplify,
+        metrics.as_ref(),
+    )?;
+
+    let mut glyphs = vec![notdef_glyph()];
+    let mut cmap = Vec::new();
+    for (c, glyph) in traced.glyphs {
+        cmap.push((c, glyphs.len() as u16));
+        glyphs.push(glyph);
+    }
+    // Alternates aren't in the character map; they're reached through the
+    // font's `rand` and `salt` features instead.
+    let mut alternates = Vec::new();
+    for (c, alternate_glyphs) in traced.alternates {
+        let Some(&(_, index)) = cmap.iter().find(|(mapped, _)| *mapped == c) else {
+            continue;
+        };
+        let first = glyphs.len() as u16;
+        glyphs.extend(alternate_glyphs);
+        alternates.push((index, (first..glyphs.len() as u16).collect()));
+    }
+    if !alternates.is_empty() {
+        info!("{} characters have alternates.", alternates.len());
+    }
+    // Ligatures are reached through the `liga` feature, which only works if
+    // the font has a glyph for each of their characters.
+    let mut ligatures = Vec::new();
+    for (text, glyph) in traced.ligatures {
+        let components: Option<Vec<u16>> = text
+            .chars()
+            .map(|c| cmap.iter().find(|(mapped, _)| *mapped == c).map(|m| m.1))
+            .collect();
+        let Some(components) = components else {
+            warn!(
+                "the {text:?} ligature is left out, because the font lacks some of its characters."
+            );
+            continue;
+        };
+        ligatures.push((components, glyphs.len() as u16));
+        glyphs.push(glyph);
+    }
+    if !ligatures.is_empty() {
+        info!("The font has {} ligatures.", ligatures.len());
+    }
+
+    let substitutions = truetype::Substitutions {
+        alternates,
+        ligatures,
+    };
+    let index_of = |c: char| cmap.iter().find(|(mapped, _)| *mapped == c).map(|m| m.1);
+    let kerning: Vec<(u16, u16, i16)> = traced
+        .kerning
+        .iter()
+        .filter_map(|&(left, right, amount)| Some((index_of(left)?, index_of(right)?, amount)))
+        .collect();
+    if !kerning.is_empty() {
+        info!("{} pairs of letters are kerned.", kerning.len());
+    }
+    let font = truetype::write_font(&traced.info, &glyphs, &cmap, &substitutions, &kerning);
+    std::fs::write(&output, font).with_context(|| format!("writing {}", output.display()))?;
+    info!(
+        "Built a font of {} glyphs at {}.",
+        glyphs.len() - 2,
+        output.display()
+    );
+
+    Ok(())
+}
+
+/// Letterforms traced into glyphs, ready to be written to a font.
+pub struct TracedFont {
+    pub info: FontInfo,
+    /// Each character's glyph, sorted by character. The space is always
+    /// included.
+    pub glyphs: Vec<(char, Glyph)>,
+    /// The other glyphs of characters that have alternates, like
+    /// `U+0041_A.alt1.png`, sorted by character, then by alternate.
+    pub alternates: Vec<(char, Vec<Glyph>)>,
+    /// The glyphs of sequences of characters written in one cell, like the
+    /// ligature `th`, sorted by sequence.
+    pub ligatures: Vec<(String, Glyph)>,
+    /// How much to move each pair of characters closer together (negative)
+    /// or farther apart (positive), in font units.
+    pub kerning: Vec<(char, char, i16)>,
+}
+
+/// Trace every letter image in `glyphs_dir` into a glyph for its character.
+///
+/// The glyphs are scaled together so that the tallest one is as tall as a
+/// capital letter, and placed on the baseline: the one found on the
+/// template when they were scanned, if the scan's manifest records it. They're
+/// spaced by the side bearings the manifest records for each of them, or
+/// evenly, and by `metrics` measured from a page of handwriting.
+pub fn trace_font(
+    glyphs_dir: &Path,
+    charset: &Charset,
+    family_name: String,
+    simplify: f32,
+    metrics: Option<&Metrics>,
+) -> anyhow::Result<TracedFont> {
+    if !glyphs_dir.is_dir() {
+        bail!("glyphs_dir path doesn't exist or is not a directory.");
+    }
+    if simplify < 0.0 {
+        bail!("simplify must not be negative.");
+    }
+    if family_name.trim().is_empty() {
+        bail!("family_name must not be empty.");
+    }
+
+    let mut traced = BTreeMap::new();
+    let mut alternates: BTreeMap<char, Vec<TracedGlyph>> = BTreeMap::new();
+    let mut ligatures: BTreeMap<String, TracedGlyph> = BTreeMap::new();
+    let mut unnamed = 0;
+    let found_guides = guides::read_from_manifests(glyphs_dir)?;
+    let found_metrics = glyph_metrics::read_from_manifests(glyphs_dir)?;
+    let trace = |path: &Path| {
+        trace_file(
+            path,
+            simplify,
+            found_guides.get(path).copied(),
+            found_metrics.get(path).copied(),
+        )
+    };
+    for glyph_file in glyph_set::read_glyph_files(glyphs_dir)? {
+        let Some(c) = glyph_set::char_for_file(&glyph_file, charset) else {
+            match glyph_set::text_for_file(&glyph_file, charset) {
+                Some(text) if ligatures.contains_key(&text) => warn!(
+                    "{} is another image of {text:?}; only the first is used.",
+                    glyph_file.display()
+                ),
+                Some(text) => {
+                    if let Some(glyph) = trace(&glyph_file)? {
+                        ligatures.insert(text, glyph);
+                    }
+                }
+                None => unnamed += 1,
+            }
+            continue;
+        };
+        if c.is_whitespace() {
+            continue;
+        }
+        let alternate = glyph_set::alternate(&glyph_file).is_some();
+        if traced.contains_key(&c) && !alternate {
+            warn!(
+                "{} is another image of {c:?}; only the first is used.",
+                glyph_file.display()
+            );
+            continue;
+        }
+        let Some(glyph) = trace(&glyph_file)? else {
+            continue;
+        };
+        // An alternate stands in for its character if the first image of it
+        // wasn't usable.
+        match traced.entry(c) {
+            Entry::Occupied(_) => alternates.entry(c).or_default().push(glyph),
+            Entry::Vacant(entry) => {
+                entry.insert(glyph);
+            }
+        }
+    }
+    if unnamed > 0 {
+        warn!(
+            "{unnamed} letter images couldn't be matched to a character. Pass --charset to name them."
+        );
+    }
+    if traced.is_empty() {
+        bail!("no letterforms were found to build a font from.");
+    }
+
+    let all_glyphs = || {
+        traced
+            .values()
+            .chain(alternates.values().flatten())
+            .chain(ligatures.values())
+    };
+    // Every glyph is scaled by the same amount, so that the tallest one is as
+    // tall as a capital letter.
+    let tallest = all_glyphs()
+        .map(|glyph| glyph.bounds.3 - glyph.bounds.1)
+        .fold(0.0, f32::max);
+    let scale = UNITS_PER_EM as f32 * TALLEST_GLYPH / tallest.max(1.0);
+
+    let on_guides = all_glyphs().filter(|glyph| glyph.guides.is_some()).count();
+    if on_guides > 0 {
+        info!(
+            "{on_guides} of {} letterforms are placed on the baseline found when they were scanned.",
+            all_glyphs().count()
+        );
+    }
+    // The template's x-height guide shows how tall lowercase letters are
+    // meant to be. Without it, the `x` is measured.
+    let mut x_heights: Vec<f32> = all_glyphs()
+        .filter_map(|glyph| glyph.guides?.x_height_size())
+        .collect();
+    x_heights.sort_by(f32::total_cmp);
+    let guide_x_height = x_heights
+        .get(x_heights.len() / 2)
+        .map(|x_height| x_height * scale);
+    let height_of = |c: char, default: f32| {
+        traced
+            .get(&c)
+            .map_or(default, |glyph| (glyph.bounds.3 - glyph.bounds.1) * scale)
+            .round() as i16
+    };
+    let info = FontInfo {
+        family_name,
+        units_per_em: UNITS_PER_EM,
+        ascender: (UNITS_PER_EM as f32 * 0.8) as i16,
+        descender: -((UNITS_PER_EM as f32 * 0.2) as i16),
+        x_height: match guide_x_height {
+            Some(x_height) => x_height.round() as i16,
+            None => height_of('x', UNITS_PER_EM as f32 * 0.45),
+        },
+        cap_height: height_of('H', UNITS_PER_EM as f32 * TALLEST_GLYPH),
+    };
+
+    // Measured spacing is a fraction of the x-height. The space between two
+    // letters is made of the side bearings of both, and the space between
+    // words of those and the space character.
+    let x_height = info.x_height as f32;
+    let side_bearing = metrics.map_or(SIDE_BEARING, |metrics| {
+        (metrics.letter_spacing * x_height / 2.0).max(0.0)
+    });
+    // Letterforms measured when they were scanned are spaced by their own
+    // side bearings instead, which are widened or narrowed to match the
+    // measured letter spacing.
+    let spacing = metrics.map_or(1.0, |metrics| {
+        (metrics.letter_spacing / 2.0 / glyph_metrics::SIDE_SPACE).max(0.0)
+    });
+    let space_width = metrics.map_or(SPACE_WIDTH, |metrics| {
+        ((metrics.word_spacing - metrics.letter_spacing) * x_height)
+            .round()
+            .max(0.0) as u16
+    });
+    let kerning = metrics
+        .into_iter()
+        .flat_map(Metrics::pairs)
+        .filter(|(left, right, _)| traced.contains_key(left) && traced.contains_key(right))
+        .map(|(left, right, amount)| (left, right, (amount * x_height).round() as i16))
+        .filter(|(_, _, amount)| *amount != 0)
+        .collect();
+
+    let space = Glyph {
+        contours: Vec::new(),
+        advance_width: space_width,
+    };
+    let glyphs = std::iter::once((' ', space))
+        .chain(traced.iter().map(|(c, glyph)| {
+            (
+                *c,
+                to_font_glyph(&c.to_string(), glyph, scale, side_bearing, spacing),
+            )
+        }))
+        .collect();
+    let alternates = alternates
+        .iter()
+        .map(|(c, glyphs)| {
+            let glyphs = glyphs
+                .iter()
+                .map(|glyph| to_font_glyph(&c.to_string(), glyph, scale, side_bearing, spacing))
+                .collect();
+            (*c, glyphs)
+        })
+        .collect();
+
+    let ligatures = ligatures
+        .iter()
+        .map(|(text, glyph)| {
+            (
+                text.clone(),
+                to_font_glyph(text, glyph, scale, side_bearing, spacing),
+            )
+        })
+        .collect();
+
+    Ok(TracedFont {
+        info,
+        glyphs,
+        alternates,
+        ligatures,
+        kerning,
+    })
+}
+
+/// Trace a letter image into outlines, or return `None` if it has no ink.
+fn trace_file(
+    path: &Path,
+    simplify: f32,
+    guides: Option<Guides>,
+    metrics: Option<GlyphMetrics>,
+) -> anyhow::Result<Option<TracedGlyph>> {
+    let image = image::open(path)
+        .with_context(|| format!("opening {}", path.display()))?
+        .to_luma8();
+    let outlines = vectorize::trace(&image, simplify);
+    let Some(bounds) = outline_bounds(&outlines) else {
+        warn!("{} has no ink to trace.", path.display());
+        return Ok(None);
+    };
+
+    Ok(Some(TracedGlyph {
+        outlines,
+        bounds,
+        guides,
+        metrics,
+    }))
+}
+
+fn outline_bounds(outlines: &[Outline]) -> Option<(f32, f32, f32, f32)> {
+    let mut points = outlines.iter().flat_map(|outline| &outline.points);
+    let first = *points.next()?;
+    Some(points.fold(
+        (first.0, first.1, first.0, first.1),
+        |(left, top, right, bottom), point| {
+            (
+                left.min(point.0),
+                top.min(point.1),
+                right.max(point.0),
+                bottom.max(point.1),
+            )
+        },
+    ))
+}
+
+/// Convert a traced letter image of `text`, one character or a ligature, to
+/// font units, sitting on the baseline. It's spaced by the side bearings
+/// measured when it was scanned, times `spacing`, or else with
+/// `side_bearing` font units of space on each side.
+fn to_font_glyph(
+    text: &str,
+    glyph: &TracedGlyph,
+    scale: f32,
+    side_bearing: f32,
+    spacing: f32,
+) -> Glyph {
+    let (left, top, right, bottom) = glyph.bounds;
+    let (left_side_bearing, right_side_bearing) = match glyph.metrics {
+        Some(metrics) => {
+            // How many font units an x-height of the letter image is.
+            let x_height = metrics.x_height_in(bottom - top) * scale;
+            (
+                metrics.left_side_bearing * x_height * spacing,
+                metrics.right_side_bearing * x_height * spacing,
+            )
+        }
+        None => (side_bearing, side_bearing),
+    };
+    let baseline = match (glyph.guides, glyph.metrics) {
+        (Some(guides), _) => guides.baseline,
+        (None, Some(metrics)) => top + metrics.ascent * metrics.x_height_in(bottom - top),
+        (None, None) => {
+            // A ligature drops as far as its deepest descender.
+            let drop = DESCENDERS
+                .iter()
+                .filter(|(descender, _)| text.contains(*descender))
+                .map(|(_, drop)| (bottom - top) * drop)
+                .fold(0.0, f32::max);
+            bottom - drop
+        }
+    };
+
+    // Flipping the image upright doesn't change which way outlines turn, so
+    // outer outlines must already run clockwise, and holes counterclockwise.
+    let to_font_units = |(x, y): (f32, f32)| Point {
+        x: ((x - left) * scale + left_side_bearing).round() as i16,
+        y: ((baseline - y) * scale).round() as i16,
+        on_curve: false,
+    };
+    let contours = glyph
+        .outlines
+        .iter()
+        .map(|outline| {
+            let mut points = outline.points.clone();
+            if (vectorize::signed_area(&points) > 0.0) == outline.is_hole {
+                points.reverse();
+            }
+            let (_, segments) = vectorize::smooth_closed(&points);
+            segments
+                .into_iter()
+                .flat_map(|(control, end)| {
+                    let end = Point {
+                        on_curve: true,
+                        ..to_font_units(end)
+                    };
+                    [to_font_units(control), end]
+                })
+                .collect()
+        })
+        .collect();
+
+    Glyph {
+        contours,
+        advance_width: ((right - left) * scale + left_side_bearing + right_side_bearing).round()
+            as u16,
+    }
+}
+
+/// The glyph shown for characters that the font doesn't have: an empty box.
+pub fn notdef_glyph() -> Glyph {
+    let rectangle = |left: i16, bottom: i16, right: i16, top: i16, clockwise: bool| {
+        let mut corners = vec![(left, bottom), (left, top), (right, top), (right, bottom)];
+        if !clockwise {
+            corners.reverse();
+        }
+        corners
+            .into_iter()
+            .map(|(x, y)| Point {
+                x,
+                y,
+                on_curve: true,
+            })
+            .collect()
+    };
+
+    Glyph {
+        contours: vec![
+            rectangle(50, 0, 450, 700, true),
+            rectangle(100, 50, 400, 650, false),
+        ],
+        advance_width: 500,
+    }
+}
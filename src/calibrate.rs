@@ -0,0 +1,420 @@
+use anyhow::bail;
+use clap::Args;
+use image::GrayImage;
+use std::path::PathBuf;
+use tracing::{debug_span, info};
+
+use crate::{dpi, grid::Grid, layout_code, orientation, pipeline, threshold};
+
+/// How far the printed cells may be from their expected size, as a fraction,
+/// before the print's scale is reported as a problem.
+const MAX_SCALE_ERROR: f32 = 0.02;
+/// How far the page may be tilted, in degrees, before it's reported.
+const MAX_SKEW_DEGREES: f32 = 0.5;
+/// The smallest margin around the grid, as a fraction of a cell, that
+/// doesn't suggest the grid was cut off.
+const MIN_MARGIN: f32 = 0.5;
+/// The fraction of a grid line's length that must be ink for it to count as
+/// found.
+const MIN_LINE_INK: f32 = 0.6;
+/// The fraction of a blank cell's middle that may be ink, to allow for
+/// specks of dust.
+const MAX_CELL_INK: f32 = 0.02;
+
+/// Arguments for the `calibrate` command.
+#[derive(Args, Debug)]
+pub struct CalibrateArgs {
+    /// A scan of a printed template that hasn't been written on.
+    #[arg(short, long)]
+    input_file: PathBuf,
+
+    /// The page to check, if the input file is a PDF. The default is the
+    /// first page.
+    #[arg(long)]
+    page: Option<u32>,
+
+    /// The number of rows of cells on the template. The default value is 9,
+    /// which matches the bundled template.
+    #[arg(long, default_value = "9")]
+    rows: u32,
+
+    /// The number of columns of cells on the template. The default value is
+    /// 12, which matches the bundled template.
+    #[arg(long, default_value = "12")]
+    cols: u32,
+
+    /// Ignore the QR code that templates made by `generate-template` carry,
+    /// and use `--rows` and `--cols` instead.
+    #[arg(long, default_value_t = false)]
+    no_layout_code: bool,
+
+    /// Threshold value to use during processing, the same as for the `scan`
+    /// command. The default value is 190.
+    #[arg(short, long, default_value = "190")]
+    threshold: threshold::Threshold,
+
+    /// The resolution the page was scanned at, in dots per inch. This is
+    /// needed to check the print's scale when the input file doesn't record
+    /// its resolution.
+    #[arg(long)]
+    dpi: Option<f32>,
+
+    /// The width and height of the template's cells on paper, in inches.
+    /// The default value is 0.75, the size `generate-template` uses.
+    #[arg(long, default_value_t = dpi::DEFAULT_CELL_SIZE)]
+    cell_size: f32,
+}
+
+/// What was measured on a scan of a blank template.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Calibration {
+    /// Whether the template's corner markers were found.
+    pub markers: bool,
+    /// How far the page was turned clockwise to turn it upright, in degrees.
+    pub rotation: u32,
+    /// How far the page is tilted, in degrees, clockwise.
+    pub skew: f32,
+    /// The average width and height of the cells, in pixels.
+    pub pitch: (f32, f32),
+    /// How large the cells were printed across and down, as a fraction of
+    /// their intended size, if the scan's resolution is known.
+    pub scale: Option<(f32, f32)>,
+    /// The space between the grid and the left, top, right, and bottom
+    /// edges of the scan, in pixels.
+    pub margins: [u32; 4],
+    /// How many of the grid's lines were found, and how many there are.
+    pub lines: (usize, usize),
+    /// How many cells have ink in them, and how many there are.
+    pub inked_cells: (usize, usize),
+}
+
+/// Check a scan of a blank template for problems with how it was printed or
+/// scanned, before any handwriting is wasted on it.
+pub fn calibrate(args: CalibrateArgs) -> anyhow::Result<()> {
+    let CalibrateArgs {
+        input_file,
+        page,
+        rows,
+        cols,
+        no_layout_code,
+        threshold,
+        dpi,
+        cell_size,
+    } = args;
+    if !input_file.exists() {
+        bail!("input_file path doesn't exist.");
+    }
+    if rows == 0 || cols == 0 {
+        bail!("rows and cols must be at least 1.");
+    }
+    if cell_size <= 0.0 {
+        bail!("cell_size must be more than 0.");
+    }
+
+    let (image, input_dpi) = pipeline::open_input(&input_file, page, None)?;
+    let dpi = dpi.or(input_dpi);
+    let level = threshold.level(&image.to_luma8());
+    let markers = debug_span!("orientation")
+        .in_scope(|| orientation::detect(&image.to_luma8(), level).is_some());
+    let options = pipeline::PreprocessOptions {
+        threshold: threshold::Threshold::Fixed(level),
+        adaptive_window: None,
+        orient: true,
+        lighting: None,
+        deskew: true,
+        camera: false,
+        grayscale: false,
+        pen_color: None,
+        straightened: false,
+    };
+    let (page, report) = pipeline::preprocess(image, options)?;
+    let layout = if no_layout_code {
+        None
+    } else {
+        debug_span!("layout_code").in_scope(|| layout_code::read(&page))
+    };
+    let (rows, cols) = match &layout {
+        Some(layout) => {
+            info!(
+                "Read the template's layout code: {} × {} cells.",
+                layout.cols, layout.rows
+            );
+            (layout.rows, layout.cols)
+        }
+        None => (rows, cols),
+    };
+
+    let Some(grid) = debug_span!("grid").in_scope(|| crate::grid::detect_grid(&page, cols, rows))
+    else {
+        println!("Grid: not found.");
+        bail!(
+            "couldn't find the grid's lines. Make sure the whole grid is in the scan, and that \
+             it printed dark enough; raising --threshold counts lighter lines as ink."
+        );
+    };
+    let calibration = Calibration {
+        markers,
+        rotation: report.rotation,
+        skew: report.skew.unwrap_or(0.0),
+        ..measure(&page, &grid, dpi.map(|dpi| dpi * cell_size))
+    };
+
+    print_report(&calibration);
+    let problems = problems(&calibration);
+    for problem in &problems {
+        println!("Problem: {problem}");
+    }
+    match problems.len() {
+        0 => {
+            println!("The template looks ready to fill in.");
+            Ok(())
+        }
+        1 => bail!("found a problem with the template."),
+        count => bail!("found {count} problems with the template."),
+    }
+}
+
+/// Measure the grid found on a thresholded page. Its cells are expected to
+/// be `cell_pixels` wide and tall, if that's known. Everything that can't be
+/// seen from the grid alone, like the page's skew, is left at its default.
+pub fn measure(page: &GrayImage, grid: &Grid, cell_pixels: Option<f32>) -> Calibration {
+    let (width, height) = page.dimensions();
+    let (left, right) = (grid.columns[0], *grid.columns.last().unwrap_or(&width));
+    let (top, bottom) = (grid.rows[0], *grid.rows.last().unwrap_or(&height));
+    let cols = grid.columns.len() - 1;
+    let rows = grid.rows.len() - 1;
+    let pitch = (
+        (right - left) as f32 / cols as f32,
+        (bottom - top) as f32 / rows as f32,
+    );
+    let is_ink = |x: u32, y: u32| x < width && y < height && page.get_pixel(x, y).0[0] < 128;
+
+    // A line counts as found if most of the pixels along it are ink. Skew
+    // that's left over after straightening moves the line by a pixel or two,
+    // so its neighbors are checked too.
+    let reach = (pitch.0.min(pitch.1) / 50.0).ceil() as u32;
+    let near = |position: u32| position.saturating_sub(reach)..=position + reach;
+    let found = |along: &mut dyn Iterator<Item = bool>, length: u32| {
+        along.filter(|ink| *ink).count() as f32 >= length as f32 * MIN_LINE_INK
+    };
+    let vertical = grid
+        .columns
+        .iter()
+        .filter(|&&x| {
+            found(
+                &mut (top..bottom).map(|y| near(x).any(|x| is_ink(x, y))),
+                bottom - top,
+            )
+        })
+        .count();
+    let horizontal = grid
+        .rows
+        .iter()
+        .filter(|&&y| {
+            found(
+                &mut (left..right).map(|x| near(y).any(|y| is_ink(x, y))),
+                right - left,
+            )
+        })
+        .count();
+
+    // Only the middle of each cell is checked, away from the grid lines.
+    let cells = grid.cells();
+    let inked_cells = cells
+        .iter()
+        .filter(|&&(x, y, cell_width, cell_height)| {
+            let (inset_x, inset_y) = (cell_width / 5, cell_height / 5);
+            let xs = x + inset_x..x + cell_width - inset_x;
+            let ys = y + inset_y..y + cell_height - inset_y;
+            let area = xs.len() * ys.len();
+            let ink = ys
+                .flat_map(|y| xs.clone().map(move |x| (x, y)))
+                .filter(|&(x, y)| is_ink(x, y))
+                .count();
+            ink as f32 > area as f32 * MAX_CELL_INK
+        })
+        .count();
+
+    Calibration {
+        markers: true,
+        rotation: 0,
+        skew: 0.0,
+        pitch,
+        scale: cell_pixels.map(|expected| (pitch.0 / expected, pitch.1 / expected)),
+        margins: [
+            left,
+            top,
+            width.saturating_sub(right),
+            height.saturating_sub(bottom),
+        ],
+        lines: (vertical + horizontal, grid.columns.len() + grid.rows.len()),
+        inked_cells: (inked_cells, cells.len()),
+    }
+}
+
+/// Print what was measured, one line for each check.
+fn print_report(calibration: &Calibration) {
+    let Calibration {
+        markers,
+        rotation,
+        skew,
+        pitch,
+        scale,
+        margins: [left, top, right, bottom],
+        lines: (lines_found, lines),
+        inked_cells: (inked_cells, cells),
+    } = calibration;
+    println!(
+        "Corner markers: {}",
+        if *markers { "found" } else { "not found" }
+    );
+    if *rotation != 0 {
+        println!("Rotation: the page was scanned turned by {rotation}°.");
+    }
+    println!("Skew: {skew:.2}°");
+    println!("Cells: {:.1} × {:.1} pixels", pitch.0, pitch.1);
+    match scale {
+        Some((across, down)) => println!(
+            "Print scale: {:.1}% across, {:.1}% down",
+            across * 100.0,
+            down * 100.0
+        ),
+        None => println!(
+            "Print scale: unknown, because the scan doesn't record its resolution. Pass --dpi \
+             to check it."
+        ),
+    }
+    println!("Margins: {left} left, {top} top, {right} right, {bottom} bottom (pixels)");
+    println!("Grid lines: found {lines_found} of {lines}");
+    println!("Blank cells: {} of {cells}", cells - inked_cells);
+}
+
+/// Describe each problem with a calibration, and what to do about it.
+pub fn problems(calibration: &Calibration) -> Vec<String> {
+    let Calibration {
+        markers,
+        skew,
+        pitch,
+        scale,
+        margins,
+        lines: (lines_found, lines),
+        inked_cells: (inked_cells, _),
+        ..
+    } = *calibration;
+    let mut problems = Vec::new();
+    if !markers {
+        problems.push(
+            "the corner markers weren't found, so scans can't be turned upright automatically. \
+             Make sure the solid squares beside the grid's corners printed and are in the scan."
+                .to_string(),
+        );
+    }
+    if skew.abs() > MAX_SKEW_DEGREES {
+        problems.push(format!(
+            "the page is tilted by {skew:.1}°. Scans are straightened automatically, but \
+             laying the page straight on the scanner keeps the letters sharper."
+        ));
+    }
+    if let Some((across, down)) = scale {
+        for (name, scale) in [("across", across), ("down", down)] {
+            if (scale - 1.0).abs() > MAX_SCALE_ERROR {
+                problems.push(format!(
+                    "the page was printed at {:.0}% scale {name}. Print it at 100% (\"actual \
+                     size\"), or pass the cell size you measure with a ruler to --cell-size.",
+                    scale * 100.0
+                ));
+            }
+        }
+    }
+    let sides = ["left", "top", "right", "bottom"];
+    for (i, (margin, side)) in margins.iter().zip(sides).enumerate() {
+        let cell = if i % 2 == 0 { pitch.0 } else { pitch.1 };
+        if (*margin as f32) < cell * MIN_MARGIN {
+            problems.push(format!(
+                "the grid is only {margin} pixels from the {side} edge of the scan, so it may \
+                 have been cut off. Check the printer's margins and how the page sits on the \
+                 scanner."
+            ));
+        }
+    }
+    if lines_found < lines {
+        problems.push(format!(
+            "{} of the {lines} grid lines are faint or broken. Print darker, or raise \
+             --threshold so that lighter lines count as ink.",
+            lines - lines_found
+        ));
+    }
+    if inked_cells > 0 {
+        problems.push(format!(
+            "{inked_cells} cells have ink in them. Calibrate with a blank template, or lower \
+             --threshold if the guide characters are being read as ink."
+        ));
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{charset::Charset, template::render_template};
+
+    #[test]
+    fn measures_the_scale_of_a_printed_template() {
+        let page = render_template(3, 4, 120, &Charset::default(), None, false);
+        let scan = |page: &GrayImage| {
+            let page = imageproc::contrast::threshold(page, 190);
+            let grid = crate::grid::detect_grid(&page, 4, 3).unwrap();
+            measure(&page, &grid, Some(120.0))
+        };
+
+        let calibration = scan(&page);
+        assert_eq!(calibration.lines, (9, 9));
+        assert_eq!(calibration.inked_cells, (0, 12));
+        assert_eq!(problems(&calibration), Vec::<String>::new());
+
+        // Printed at 97% scale.
+        let (width, height) = page.dimensions();
+        let shrunk = image::imageops::resize(
+            &page,
+            width * 97 / 100,
+            height * 97 / 100,
+            image::imageops::FilterType::Triangle,
+        );
+        let calibration = scan(&shrunk);
+        let (across, down) = calibration.scale.unwrap();
+        assert!((across - 0.97).abs() < 0.01, "scaled {across} across");
+        assert!((down - 0.97).abs() < 0.01, "scaled {down} down");
+        let problems = problems(&calibration);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].contains("97% scale"), "{}", problems[0]);
+    }
+
+    #[test]
+    fn passes_a_freshly_generated_template_of_any_size() {
+        // At 200 DPI, the default 0.75-inch cells are 150 pixels.
+        let font = crate::template::system_font();
+        let charset = Charset::parse("abcdefghijklmnopqrst");
+        let mut page = render_template(4, 5, 150, &charset, font.as_ref(), true);
+        if font.is_none() {
+            // Stand in for the title, which is only printed with a font.
+            let rect = imageproc::rect::Rect::at(300, 124).of_size(450, 10);
+            imageproc::drawing::draw_filled_rect_mut(&mut page, rect, image::Luma([0]));
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let input_file = dir.path().join("template.png");
+        crate::template::save(&page, &input_file, 200).unwrap();
+
+        let args = CalibrateArgs {
+            input_file,
+            page: None,
+            rows: 4,
+            cols: 5,
+            no_layout_code: false,
+            threshold: threshold::Threshold::Fixed(190),
+            dpi: None,
+            cell_size: dpi::DEFAULT_CELL_SIZE,
+        };
+        calibrate(args).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+use clap::ValueEnum;
+use image::{imageops::FilterType, GrayImage};
+use serde::Serialize;
+use std::str::FromStr;
+
+use crate::{glyph::PAPER, guides::Guides, template::BASELINE_POSITION};
+
+/// The size of the image that every letterform is placed on, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Canvas {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Canvas {
+    type Err = String;
+
+    /// Parse a canvas size written as `WxH`, like `128x128`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{s:?} isn't a size like 128x128");
+        let (width, height) = s.split_once(['x', 'X']).ok_or_else(invalid)?;
+        let width: u32 = width.trim().parse().map_err(|_| invalid())?;
+        let height: u32 = height.trim().parse().map_err(|_| invalid())?;
+        if width == 0 || height == 0 {
+            return Err(format!("canvas {s:?} must be at least 1x1"));
+        }
+
+        Ok(Self { width, height })
+    }
+}
+
+/// Where a letterform is placed on its canvas.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Align {
+    /// Center the letterform, shrinking it if it doesn't fit.
+    #[default]
+    Center,
+    /// Scale every cell by the same amount to fit the canvas, and line up the
+    /// template's baseline guide with the same row of every canvas, so that
+    /// letters keep their size and position relative to each other.
+    Baseline,
+}
+
+/// Place a cell's letterform, whose `(x, y, width, height)` are `bounds`, on a
+/// canvas. With [`Align::Center`], at least `padding` pixels are left around
+/// it. An empty cell, with no `bounds`, gives a blank canvas.
+///
+/// If the template's guide lines were found in the cell, [`Align::Baseline`]
+/// lines up the baseline that was found instead of where the template
+/// usually puts it, and the guides are returned where they end up on the
+/// canvas.
+pub fn place(
+    cell: &GrayImage,
+    bounds: Option<(u32, u32, u32, u32)>,
+    canvas: Canvas,
+    align: Align,
+    padding: u32,
+    guides: Option<Guides>,
+) -> (GrayImage, Option<Guides>) {
+    let mut placed = GrayImage::from_pixel(canvas.width, canvas.height, PAPER);
+    let Some((x, y, width, height)) = bounds else {
+        return (placed, None);
+    };
+    let letterform = image::imageops::crop_imm(cell, x, y, width, height).to_image();
+
+    let (scale, left, top) = match align {
+        Align::Center => {
+            let room_x = canvas.width.saturating_sub(padding * 2).max(1);
+            let room_y = canvas.height.saturating_sub(padding * 2).max(1);
+            let scale = (room_x as f32 / width as f32)
+                .min(room_y as f32 / height as f32)
+                .min(1.0);
+            let (scaled_width, scaled_height) = scaled((width, height), scale);
+            let left = (canvas.width as i64 - scaled_width as i64) / 2;
+            let top = (canvas.height as i64 - scaled_height as i64) / 2;
+            (scale, left, top)
+        }
+        Align::Baseline => {
+            let (cell_width, cell_height) = cell.dimensions();
+            let scale = (canvas.width as f32 / cell_width as f32)
+                .min(canvas.height as f32 / cell_height as f32);
+            let (scaled_width, _) = scaled((width, height), scale);
+            let left = (canvas.width as i64 - scaled_width as i64) / 2;
+            // Keep the letterform the same distance from the baseline.
+            let cell_baseline = guides.map_or(cell_height as f32 * BASELINE_POSITION, |guides| {
+                guides.baseline
+            });
+            let above_baseline = (cell_baseline - y as f32) * scale;
+            let baseline = canvas.height as f32 * BASELINE_POSITION;
+            (scale, left, (baseline - above_baseline).round() as i64)
+        }
+    };
+    let (scaled_width, scaled_height) = scaled((width, height), scale);
+    let letterform = if (scaled_width, scaled_height) == (width, height) {
+        letterform
+    } else {
+        image::imageops::resize(
+            &letterform,
+            scaled_width,
+            scaled_height,
+            FilterType::Triangle,
+        )
+    };
+    image::imageops::replace(&mut placed, &letterform, left, top);
+    let guides = guides.map(|guides| guides.transform(scale, top as f32 - y as f32 * scale));
+
+    (placed, guides)
+}
+
+fn scaled((width, height): (u32, u32), scale: f32) -> (u32, u32) {
+    (
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glyph::ink_bounds;
+    use image::Luma;
+
+    #[test]
+    fn parses_canvas_sizes() {
+        assert_eq!(
+            "128x64".parse(),
+            Ok(Canvas {
+                width: 128,
+                height: 64
+            })
+        );
+        assert!("128".parse::<Canvas>().is_err());
+        assert!("0x10".parse::<Canvas>().is_err());
+    }
+
+    #[test]
+    fn places_letterforms_on_the_canvas() {
+        let mut cell = GrayImage::from_pixel(200, 200, PAPER);
+        // A letterform sitting on the baseline, which is at y = 130.
+        for y in 90..130 {
+            for x in 20..40 {
+                cell.put_pixel(x, y, Luma([0]));
+            }
+        }
+        let bounds = Some((20, 90, 20, 40));
+        let canvas = Canvas {
+            width: 100,
+            height: 100,
+        };
+
+        let (centered, _) = place(&cell, bounds, canvas, Align::Center, 10, None);
+        assert_eq!(centered.dimensions(), (100, 100));
+        assert_eq!(ink_bounds(&centered), Some((40, 30, 20, 40)));
+
+        let (on_baseline, _) = place(&cell, bounds, canvas, Align::Baseline, 10, None);
+        assert_eq!(ink_bounds(&on_baseline), Some((45, 45, 10, 20)));
+
+        // A baseline guide found higher up than usual, so the letterform
+        // drops a little below it.
+        let guides = Guides {
+            baseline: 120.0,
+            x_height: Some(100.0),
+        };
+        let (on_guide, placed_guides) =
+            place(&cell, bounds, canvas, Align::Baseline, 10, Some(guides));
+        assert_eq!(ink_bounds(&on_guide), Some((45, 50, 10, 20)));
+        assert_eq!(
+            placed_guides,
+            Some(Guides {
+                baseline: 65.0,
+                x_height: Some(55.0),
+            })
+        );
+
+        let (empty, _) = place(&cell, None, canvas, Align::Center, 10, None);
+        assert_eq!(ink_bounds(&empty), None);
+    }
+}
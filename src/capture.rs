@@ -0,0 +1,249 @@
+use anyhow::{bail, Context};
+use clap::Args;
+use image::{imageops, GrayImage, Rgb, RgbImage};
+use imageproc::drawing::draw_line_segment_mut;
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+use nokhwa::{
+    pixel_format::RgbFormat,
+    utils::{CameraIndex, RequestedFormat, RequestedFormatType},
+    Camera,
+};
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::{
+    perspective::{self, Corners},
+    scan,
+};
+
+/// The widest the preview window is drawn, in pixels. Frames are scaled down
+/// to fit, which also keeps finding the grid quick enough to run on every
+/// frame.
+const PREVIEW_WIDTH: u32 = 960;
+/// How far each corner of the grid may move between frames, as a fraction of
+/// the frame's width, for the template to count as holding still.
+const STABLE_TOLERANCE: f32 = 0.01;
+/// How close the grid's corners may come to the edge of the frame, as a
+/// fraction of its width, for the template to count as in frame.
+const EDGE_MARGIN: f32 = 0.02;
+/// The smallest the grid may be, as a fraction of the frame's area. Anything
+/// smaller is probably not the template, or too far away to read.
+const MIN_GRID_AREA: f32 = 0.2;
+const FOUND_COLOR: Rgb<u8> = Rgb([40, 200, 60]);
+const SEARCHING_COLOR: Rgb<u8> = Rgb([220, 40, 40]);
+
+/// Arguments for the `capture` command.
+#[derive(Args, Debug)]
+pub struct CaptureArgs {
+    /// The number of the camera to use. The default is the first camera
+    /// found.
+    #[arg(long, default_value = "0")]
+    camera_index: u32,
+
+    /// Where the snapshot of the page is saved before it's scanned.
+    #[arg(long, default_value = "capture.png")]
+    snapshot: PathBuf,
+
+    /// How many frames in a row the template has to hold still, fully in
+    /// frame, before the snapshot is taken. At a typical 30 frames per
+    /// second, the default is about half a second.
+    #[arg(long, default_value = "15")]
+    stable_frames: u32,
+
+    /// Arguments for the `scan` command, which is run on the snapshot, like
+    /// `-- -o letters --rows 6`. `--input-file` is set to the snapshot, and
+    /// `--camera` is always passed.
+    #[arg(last = true)]
+    scan_args: Vec<String>,
+}
+
+/// Watch the camera until the template holds still in frame, save a
+/// snapshot of it, then scan the snapshot.
+///
+/// While watching, a preview window shows the camera's view, with the grid
+/// outlined in green once it's found, or the frame outlined in red while it
+/// isn't. Press space to take the snapshot right away, or escape to give up.
+/// `confirm` is asked whether to save the letters, as in [`scan::scan`].
+pub fn capture(
+    args: CaptureArgs,
+    confirm: &dyn Fn() -> anyhow::Result<bool>,
+) -> anyhow::Result<()> {
+    // Check the scan arguments before opening the camera, so that a typo
+    // doesn't cost a capture.
+    let mut scan_args = vec![
+        "--input-file".to_string(),
+        args.snapshot.display().to_string(),
+    ];
+    scan_args.push("--camera".to_string());
+    scan_args.extend(args.scan_args.iter().cloned());
+    let scan_args =
+        scan::ScanArgs::try_parse_from(&scan_args).context("reading the scan arguments")?;
+
+    let format = RequestedFormat::new::<RgbFormat>(RequestedFormatType::AbsoluteHighestResolution);
+    let mut camera = Camera::new(CameraIndex::Index(args.camera_index), format)
+        .with_context(|| format!("opening camera {}", args.camera_index))?;
+    camera.open_stream().context("starting the camera")?;
+    info!(
+        "Capturing from {}. Hold the template still in front of the camera, \
+         or press space to take the snapshot.",
+        camera.info().human_name()
+    );
+
+    let snapshot = watch(&mut camera, args.stable_frames);
+    camera.stop_stream().ok();
+    let Some(snapshot) = snapshot? else {
+        bail!("the capture was cancelled");
+    };
+
+    snapshot
+        .save(&args.snapshot)
+        .with_context(|| format!("saving the snapshot to {}", args.snapshot.display()))?;
+    info!("Saved the snapshot to {}", args.snapshot.display());
+
+    scan::scan(scan_args, confirm).map(|_| ())
+}
+
+/// Show the camera's frames until the template has held still for
+/// `stable_frames` frames, or space is pressed. Returns the last frame at
+/// full resolution, or `None` if the window was closed first.
+fn watch(camera: &mut Camera, stable_frames: u32) -> anyhow::Result<Option<RgbImage>> {
+    let resolution = camera.resolution();
+    let (width, height) = (resolution.width(), resolution.height());
+    let scale = (PREVIEW_WIDTH as f32 / width as f32).min(1.0);
+    let (preview_width, preview_height) = (
+        ((width as f32 * scale) as u32).max(1),
+        ((height as f32 * scale) as u32).max(1),
+    );
+    let mut window = Window::new(
+        "handwriting-scan-tool capture",
+        preview_width as usize,
+        preview_height as usize,
+        WindowOptions::default(),
+    )
+    .context("opening the preview window")?;
+    window.set_target_fps(30);
+
+    let mut buffer = Vec::new();
+    let mut previous: Option<Corners> = None;
+    let mut stable = 0;
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        let frame = camera
+            .frame()
+            .context("reading a frame from the camera")?
+            .decode_image::<RgbFormat>()
+            .context("decoding a frame from the camera")?;
+        let mut preview = imageops::resize(
+            &frame,
+            preview_width,
+            preview_height,
+            imageops::FilterType::Triangle,
+        );
+
+        let luma = imageops::grayscale(&preview);
+        let corners = find_template(&luma);
+        match (corners, previous) {
+            (Some(corners), Some(previous))
+                if holds_still(corners, previous, preview_width as f32) =>
+            {
+                stable += 1
+            }
+            _ => stable = 0,
+        }
+        previous = corners;
+
+        draw_overlay(&mut preview, corners);
+        window.set_title(&match corners {
+            Some(_) => format!("Template found, hold still ({stable}/{stable_frames})"),
+            None => "Looking for the template".to_string(),
+        });
+        buffer.clear();
+        buffer.extend(
+            preview
+                .pixels()
+                .map(|Rgb([r, g, b])| u32::from_be_bytes([0, *r, *g, *b])),
+        );
+        window
+            .update_with_buffer(&buffer, preview_width as usize, preview_height as usize)
+            .context("drawing the preview")?;
+
+        if stable >= stable_frames || window.is_key_pressed(Key::Space, KeyRepeat::No) {
+            return Ok(Some(frame));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Find the template's grid in a frame, if all of it is in frame and it's
+/// big enough to read.
+fn find_template(frame: &GrayImage) -> Option<Corners> {
+    let threshold = imageproc::contrast::otsu_level(frame);
+    let corners = perspective::find_grid_corners(frame, threshold)?;
+    let (width, height) = (frame.width() as f32, frame.height() as f32);
+    let margin = width * EDGE_MARGIN;
+    let in_frame = corners
+        .iter()
+        .all(|&(x, y)| x >= margin && y >= margin && x <= width - margin && y <= height - margin);
+    (in_frame && area(corners) >= width * height * MIN_GRID_AREA).then_some(corners)
+}
+
+/// Whether no corner moved further than the tolerance between two frames
+/// `width` pixels wide.
+fn holds_still(corners: Corners, previous: Corners, width: f32) -> bool {
+    let tolerance = width * STABLE_TOLERANCE;
+    corners
+        .iter()
+        .zip(previous.iter())
+        .all(|(a, b)| (a.0 - b.0).hypot(a.1 - b.1) <= tolerance)
+}
+
+/// The area of a quadrilateral, by the shoelace formula.
+fn area(corners: Corners) -> f32 {
+    let mut sum = 0.0;
+    for i in 0..4 {
+        let (x1, y1) = corners[i];
+        let (x2, y2) = corners[(i + 1) % 4];
+        sum += x1 * y2 - x2 * y1;
+    }
+    sum.abs() / 2.0
+}
+
+/// Outline the grid in green if it was found, or the frame in red if not.
+fn draw_overlay(preview: &mut RgbImage, corners: Option<Corners>) {
+    let (width, height) = (preview.width() as f32 - 1.0, preview.height() as f32 - 1.0);
+    let (outline, color) = match corners {
+        Some(corners) => (corners, FOUND_COLOR),
+        None => (
+            [(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)],
+            SEARCHING_COLOR,
+        ),
+    };
+    for i in 0..4 {
+        let (start, end) = (outline[i], outline[(i + 1) % 4]);
+        // Draw each side a few pixels thick, so it shows up on a busy desk.
+        for offset in -1..=1 {
+            let offset = offset as f32;
+            draw_line_segment_mut(
+                preview,
+                (start.0 + offset, start.1 + offset),
+                (end.0 + offset, end.1 + offset),
+                color,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_still_within_tolerance() {
+        let corners = [(10.0, 10.0), (90.0, 10.0), (90.0, 90.0), (10.0, 90.0)];
+        let nudged = corners.map(|(x, y)| (x + 0.5, y));
+        let moved = corners.map(|(x, y)| (x + 5.0, y));
+        assert!(holds_still(corners, nudged, 100.0));
+        assert!(!holds_still(corners, moved, 100.0));
+        assert_eq!(area(corners), 6400.0);
+    }
+}
@@ -0,0 +1,338 @@
+use anyhow::Context;
+use std::path::Path;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// The charsets built into the app, by name, for handwriting in other
+/// scripts than the Latin alphabet. Each is a text file in the `charsets`
+/// directory of the repository.
+pub const PRESETS: &[(&str, &str)] = &[
+    ("greek", include_str!("../charsets/greek.txt")),
+    ("cyrillic", include_str!("../charsets/cyrillic.txt")),
+    ("hebrew", include_str!("../charsets/hebrew.txt")),
+];
+
+/// Combining marks written after this character, like `◌́`, take a cell of
+/// their own instead of joining the letter before them.
+const DOTTED_CIRCLE: char = '\u{25CC}';
+
+/// The characters written on a template, one per cell, in grid order (left to
+/// right, then top to bottom). A cell may also hold a sequence of characters
+/// written as one, like the ligature `th`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Charset {
+    cells: Vec<Option<String>>,
+}
+
+impl Charset {
+    /// Parse a charset from a string. Every character is assigned to the next
+    /// cell, except that a space marks a cell with no character, and line
+    /// breaks are ignored so that each row may be written on its own line.
+    /// Two or more characters in braces, like `{th}` or `{->}`, share one
+    /// cell; braces around anything else are ordinary characters.
+    ///
+    /// Combining marks share the cell of the letter before them, and letters
+    /// with marks that Unicode has a single character for, like `é`, are
+    /// written as that character. A mark after a dotted circle, like `◌́`,
+    /// takes a cell of its own.
+    pub fn parse(s: &str) -> Self {
+        let chars: Vec<char> = s.nfc().filter(|c| *c != '\n' && *c != '\r').collect();
+        let mut cells: Vec<Option<String>> = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            if let Some(len) = sequence_len(&chars[i..]) {
+                cells.push(Some(chars[i + 1..i + 1 + len].iter().collect()));
+                i += len + 2;
+            } else if is_combining_mark(chars[i]) {
+                match cells.last_mut() {
+                    Some(Some(text)) => text.push(chars[i]),
+                    _ => cells.push(Some(chars[i].to_string())),
+                }
+                i += 1;
+            } else if chars[i] == DOTTED_CIRCLE
+                && chars.get(i + 1).is_some_and(|c| is_combining_mark(*c))
+            {
+                cells.push(Some(String::new()));
+                i += 1;
+            } else {
+                cells.push((chars[i] != ' ').then(|| chars[i].to_string()));
+                i += 1;
+            }
+        }
+
+        Self { cells }
+    }
+
+    /// Load a charset from `value`, which is the name of a built-in charset,
+    /// the path of a text file containing the charset, or the charset itself.
+    pub fn from_arg(value: &str) -> anyhow::Result<Self> {
+        if let Some((_, contents)) = PRESETS.iter().find(|(name, _)| *name == value) {
+            return Ok(Self::parse(contents));
+        }
+        let path = Path::new(value);
+        if path.is_file() {
+            let contents = std::fs::read_to_string(path).context("reading charset file")?;
+            Ok(Self::parse(contents.trim_end_matches(['\n', '\r'])))
+        } else {
+            Ok(Self::parse(value))
+        }
+    }
+
+    /// The character written in the cell at `index`, if it holds exactly one.
+    pub fn get(&self, index: usize) -> Option<char> {
+        let mut chars = self.text(index)?.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    }
+
+    /// What's written in the cell at `index`, if anything: one character, or
+    /// a sequence of them.
+    pub fn text(&self, index: usize) -> Option<&str> {
+        self.cells.get(index)?.as_deref()
+    }
+
+    /// The number of cells this charset describes.
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Whether this charset describes no cells at all.
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// Split the charset into pages of `cells` cells each, for charsets too
+    /// long to fit on one template. The last page may be shorter.
+    pub fn pages(&self, cells: usize) -> Vec<Self> {
+        self.cells
+            .chunks(cells.max(1))
+            .map(|page| Self {
+                cells: page.to_vec(),
+            })
+            .collect()
+    }
+
+    /// The charset in the order a grid `cols` cells wide is filled in by
+    /// someone writing from right to left: each row's first character is in
+    /// its rightmost cell. A short last row is filled from the right, too.
+    pub fn right_to_left(&self, cols: usize) -> Self {
+        let mut cells = Vec::new();
+        for row in self.cells.chunks(cols.max(1)) {
+            let mut row = row.to_vec();
+            row.resize(cols.max(1), None);
+            row.reverse();
+            cells.extend(row);
+        }
+
+        Self { cells }
+    }
+}
+
+impl std::fmt::Display for Charset {
+    /// Write the charset the way [`Charset::parse`] reads it, on one line.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for cell in &self.cells {
+            match cell.as_deref() {
+                None => f.write_str(" ")?,
+                Some(text) if text.chars().all(is_combining_mark) => {
+                    write!(f, "{DOTTED_CIRCLE}{text}")?
+                }
+                Some(text) if text.chars().skip(1).all(is_combining_mark) => f.write_str(text)?,
+                Some(text) => write!(f, "{{{text}}}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How to show a cell's text, like on a template: combining marks on their
+/// own are shown on a dotted circle, like `◌́`, as they usually are.
+pub fn display_text(text: &str) -> String {
+    if text.chars().all(is_combining_mark) {
+        format!("{DOTTED_CIRCLE}{text}")
+    } else {
+        text.to_string()
+    }
+}
+
+/// If `chars` starts with a sequence in braces, like `{th}`, the number of
+/// characters inside the braces.
+fn sequence_len(chars: &[char]) -> Option<usize> {
+    if chars.first() != Some(&'{') {
+        return None;
+    }
+    let len = chars[1..].iter().position(|c| *c == '}')?;
+    let inside = &chars[1..1 + len];
+    (len >= 2 && !inside.iter().any(|c| c.is_whitespace() || *c == '{')).then_some(len)
+}
+
+/// Build a file name, without an extension, for the image of a character:
+/// its Unicode codepoint, followed by the character itself or, if that isn't
+/// safe to use in a file name, its glyph name. For example, `A` becomes
+/// `U+0041_A`, and `/` becomes `U+002F_slash`.
+pub fn file_stem(c: char) -> String {
+    let name = glyph_name(c);
+    format!("U+{:04X}_{name}", c as u32)
+}
+
+/// Build a file name, without an extension, for the image of a cell's text.
+/// A single character is named by [`file_stem`]; a sequence is named by its
+/// codepoints joined by `+`, followed by the names of its characters joined
+/// by `_`. For example, `th` becomes `U+0074+0068_t_h`.
+pub fn text_file_stem(text: &str) -> String {
+    let mut chars = text.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return file_stem(c);
+    }
+    let codepoints: Vec<String> = text.chars().map(|c| format!("{:04X}", c as u32)).collect();
+    let names: Vec<String> = text.chars().map(glyph_name).collect();
+    format!("U+{}_{}", codepoints.join("+"), names.join("_"))
+}
+
+/// The name of a character as used in font editors. Letters and digits are
+/// their own names; ASCII punctuation uses the Adobe Glyph List names; other
+/// characters are named by codepoint, as `uniXXXX`.
+pub fn glyph_name(c: char) -> String {
+    if c.is_alphanumeric() {
+        return c.to_string();
+    }
+    let name = match c {
+        ' ' => "space",
+        '!' => "exclam",
+        '"' => "quotedbl",
+        '#' => "numbersign",
+        '$' => "dollar",
+        '%' => "percent",
+        '&' => "ampersand",
+        '\'' => "quotesingle",
+        '(' => "parenleft",
+        ')' => "parenright",
+        '*' => "asterisk",
+        '+' => "plus",
+        ',' => "comma",
+        '-' => "hyphen",
+        '.' => "period",
+        '/' => "slash",
+        ':' => "colon",
+        ';' => "semicolon",
+        '<' => "less",
+        '=' => "equal",
+        '>' => "greater",
+        '?' => "question",
+        '@' => "at",
+        '[' => "bracketleft",
+        '\\' => "backslash",
+        ']' => "bracketright",
+        '^' => "asciicircum",
+        '_' => "underscore",
+        '`' => "grave",
+        '{' => "braceleft",
+        '|' => "bar",
+        '}' => "braceright",
+        '~' => "asciitilde",
+        _ => return format!("uni{:04X}", c as u32),
+    };
+
+    name.to_string()
+}
+
+/// Recover the character from a file stem made by [`file_stem`].
+pub fn char_from_file_stem(stem: &str) -> Option<char> {
+    let text = text_from_file_stem(stem)?;
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+/// Recover a cell's text from a file stem made by [`text_file_stem`], or
+/// from one with only the codepoints, like `U+4E2D`.
+pub fn text_from_file_stem(stem: &str) -> Option<String> {
+    let codepoints = stem.strip_prefix("U+")?;
+    let end = codepoints
+        .find(|c: char| !c.is_ascii_hexdigit() && c != '+')
+        .unwrap_or(codepoints.len());
+    codepoints[..end]
+        .split('+')
+        .map(|codepoint| char::from_u32(u32::from_str_radix(codepoint, 16).ok()?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spaces_mark_empty_cells_and_line_breaks_are_ignored() {
+        let charset = Charset::parse("AB\nC D");
+        assert_eq!(charset.len(), 5);
+        assert_eq!(charset.get(2), Some('C'));
+        assert_eq!(charset.get(3), None);
+        assert_eq!(charset.get(4), Some('D'));
+        assert_eq!(charset.get(5), None);
+    }
+
+    #[test]
+    fn file_stems_round_trip() {
+        assert_eq!(file_stem('A'), "U+0041_A");
+        assert_eq!(file_stem('/'), "U+002F_slash");
+        assert_eq!(file_stem('€'), "U+20AC_uni20AC");
+        for c in ['A', '/', '€', 'ß'] {
+            assert_eq!(char_from_file_stem(&file_stem(c)), Some(c));
+        }
+        assert_eq!(char_from_file_stem("letter-3"), None);
+    }
+
+    #[test]
+    fn braces_hold_sequences() {
+        let charset = Charset::parse("a{th}{}{ f}{->}");
+        assert_eq!(charset.text(1), Some("th"));
+        assert_eq!(charset.get(1), None);
+        assert_eq!(charset.get(2), Some('{'));
+        assert_eq!(charset.get(3), Some('}'));
+        assert_eq!(charset.get(4), Some('{'));
+        assert_eq!(charset.get(5), None);
+        assert_eq!(charset.text(8), Some("->"));
+        assert_eq!(charset.len(), 9);
+        assert_eq!(text_file_stem("th"), "U+0074+0068_t_h");
+        assert_eq!(
+            text_from_file_stem("U+0074+0068_t_h.alt1").as_deref(),
+            Some("th")
+        );
+        assert_eq!(char_from_file_stem("U+0074+0068_t_h"), None);
+        assert_eq!(Charset::parse("a{th}\nb c").to_string(), "a{th}b c");
+    }
+
+    #[test]
+    fn reads_other_scripts() {
+        for (name, _) in PRESETS {
+            let charset = Charset::from_arg(name).unwrap();
+            assert!(charset.len() > 20 && charset.len() <= 108, "{name}");
+            assert!(
+                (0..charset.len()).all(|i| charset.get(i).is_some()),
+                "{name}"
+            );
+        }
+        assert_eq!(file_stem('中'), "U+4E2D_中");
+        assert_eq!(char_from_file_stem("U+4E2D"), Some('中'));
+        assert_eq!(char_from_file_stem("U+4E2D.alt1"), Some('中'));
+
+        // A decomposed é becomes one character, a mark with no precomposed
+        // form joins its letter, and a mark after a dotted circle stands
+        // alone.
+        let charset = Charset::parse("e\u{301}\u{44B}\u{304}\u{25CC}\u{301}a");
+        assert_eq!(charset.get(0), Some('é'));
+        assert_eq!(charset.text(1), Some("\u{44B}\u{304}"));
+        assert_eq!(charset.text(2), Some("\u{301}"));
+        assert_eq!(charset.get(3), Some('a'));
+        assert_eq!(Charset::parse(&charset.to_string()), charset);
+        assert_eq!(display_text("\u{301}"), "\u{25CC}\u{301}");
+
+        let charset = Charset::parse("אבגדה").right_to_left(3);
+        assert_eq!(charset.to_string(), "גבא הד");
+        assert_eq!(Charset::parse("abcde").pages(2).len(), 3);
+    }
+}
@@ -0,0 +1,281 @@
+use anyhow::{bail, Context};
+use clap::Args;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+/// Arguments for the `clean` command.
+#[derive(Args, Debug)]
+pub struct CleanArgs {
+    /// The directory the `scan` command wrote letter images to, or a
+    /// manifest written by it. Every manifest in the directory and its
+    /// subdirectories, like those of each page of a batch scan, is cleaned.
+    #[arg(short, long)]
+    glyphs_dir: PathBuf,
+
+    /// List the files that would be deleted, without deleting them.
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+
+    /// By default, the app will ask for confirmation before deleting the
+    /// files. If you want to skip confirmation, pass this flag.
+    #[arg(short, long, default_value_t = false)]
+    yes: bool,
+}
+
+/// Delete the files that earlier scans created: the letter images and
+/// outlines listed in their manifests, their contact sheets and other
+/// images, and the manifests themselves. Nothing else is touched, so other
+/// files in the same directory are safe, and nothing outside of it is.
+pub fn clean(args: CleanArgs) -> anyhow::Result<()> {
+    let CleanArgs {
+        glyphs_dir,
+        dry_run,
+        yes,
+    } = args;
+    let (manifests, root) = if glyphs_dir.is_dir() {
+        (find_manifests(&glyphs_dir)?, glyphs_dir.clone())
+    } else if glyphs_dir.is_file() {
+        let root = glyphs_dir
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        (vec![glyphs_dir.clone()], root.to_path_buf())
+    } else {
+        bail!("{} doesn't exist.", glyphs_dir.display());
+    };
+    if manifests.is_empty() {
+        bail!(
+            "{} doesn't hold any manifests written by the scan command.",
+            glyphs_dir.display()
+        );
+    }
+
+    let mut files = Vec::new();
+    for manifest in &manifests {
+        files.extend(generated_files(manifest, &root)?);
+    }
+    let mut seen = HashSet::new();
+    files.retain(|file| seen.insert(file.clone()));
+    for file in &files {
+        println!("{}", file.display());
+    }
+    if dry_run {
+        info!("{} files would be deleted.", files.len());
+        return Ok(());
+    }
+    if !yes && !confirm(files.len())? {
+        return Ok(());
+    }
+
+    for file in &files {
+        std::fs::remove_file(file).with_context(|| format!("removing {}", file.display()))?;
+    }
+    // Directories that scans made for each page, or for their debug images,
+    // are removed too, once they're empty. Deeper ones are removed first.
+    let root = std::fs::canonicalize(&root).ok();
+    let mut dirs: Vec<PathBuf> = files
+        .iter()
+        .filter_map(|file| std::fs::canonicalize(file.parent()?).ok())
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    for dir in &dirs {
+        if Some(dir) != root.as_ref() && is_empty_dir(dir) {
+            std::fs::remove_dir(dir).with_context(|| format!("removing {}", dir.display()))?;
+        }
+    }
+    info!("Deleted {} files.", files.len());
+
+    Ok(())
+}
+
+/// Find the manifests in `dir` and its immediate subdirectories, including
+/// those named with a `--suffix`, like `manifest-v2.json`.
+fn find_manifests(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let is_manifest = |path: &Path| {
+        path.is_file()
+            && path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("manifest") && name.ends_with(".json"))
+    };
+    let mut manifests = Vec::new();
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("reading {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            let mut inner: Vec<PathBuf> = std::fs::read_dir(&path)
+                .with_context(|| format!("reading {}", path.display()))?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| is_manifest(path))
+                .collect();
+            inner.sort();
+            manifests.extend(inner);
+        } else if is_manifest(&path) {
+            manifests.push(path);
+        }
+    }
+
+    Ok(manifests)
+}
+
+/// The files a scan created, as listed in its manifest at `path`, that are
+/// still there, followed by the manifest itself. Files that aren't in
+/// `root` weren't written by a scan, or shouldn't be deleted with it, so
+/// they're left out.
+pub fn generated_files(path: &Path, root: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let manifest: serde_json::Value =
+        serde_json::from_str(&contents).with_context(|| format!("parsing {}", path.display()))?;
+    let (Some(pages), Some(glyphs)) = (manifest["pages"].as_array(), manifest["glyphs"].as_array())
+    else {
+        bail!(
+            "{} isn't a manifest written by the scan command.",
+            path.display()
+        );
+    };
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut files = Vec::new();
+    for glyph in glyphs {
+        let Some(file) = glyph["file"].as_str() else {
+            continue;
+        };
+        let page = glyph["page"]
+            .as_u64()
+            .and_then(|page| pages.get(page as usize));
+        let vectorized = page.is_some_and(|page| page["preprocessing"]["vectorize"] == true);
+        let strokes = page.and_then(|page| match page["preprocessing"]["skeleton"].as_str() {
+            Some("json") => Some("strokes.json"),
+            Some("svg") => Some("strokes.svg"),
+            _ => None,
+        });
+        let file = dir.join(file);
+        files.push(file.clone());
+        if vectorized {
+            files.push(file.with_extension("svg"));
+        }
+        if let Some(extension) = strokes {
+            files.push(file.with_extension(extension));
+        }
+    }
+    for page in pages {
+        if let Some(contact_sheet) = page["contact_sheet"].as_str() {
+            files.push(dir.join(contact_sheet));
+        }
+    }
+    let artifacts = manifest["artifacts"].as_array().into_iter().flatten();
+    for artifact in artifacts.filter_map(|artifact| artifact.as_str()) {
+        files.push(dir.join(artifact));
+    }
+    files.retain(|file| file.is_file());
+    let root =
+        std::fs::canonicalize(root).with_context(|| format!("reading {}", root.display()))?;
+    files.retain(|file| {
+        let inside = std::fs::canonicalize(file).is_ok_and(|file| file.starts_with(&root));
+        if !inside {
+            warn!(
+                "{} lists {}, which isn't in {}; leaving it alone.",
+                path.display(),
+                file.display(),
+                root.display()
+            );
+        }
+        inside
+    });
+    files.push(path.to_path_buf());
+
+    Ok(files)
+}
+
+fn is_empty_dir(dir: &Path) -> bool {
+    std::fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_none())
+}
+
+/// Ask whether to delete `count` files.
+fn confirm(count: usize) -> anyhow::Result<bool> {
+    loop {
+        println!("OK to delete these {count} files? (y/n)");
+
+        let mut input = String::new();
+        std::io::stdin()
+            .read_line(&mut input)
+            .context("reading the answer")?;
+        let input = input.trim();
+        if input.starts_with('y') {
+            return Ok(true);
+        } else if input.starts_with('n') {
+            return Ok(false);
+        } else {
+            println!("Invalid input.");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_only_the_files_a_scan_wrote() {
+        let parent = tempfile::tempdir().unwrap();
+        let dir = parent.path().join("scans");
+        std::fs::create_dir_all(dir.join("debug")).unwrap();
+        let files = [
+            "letter-0.png",
+            "letter-0.svg",
+            "letter-0.strokes.json",
+            "other.png",
+            "sheet.png",
+            "threshold-sweep.png",
+            "debug/page-1-overlay.png",
+        ];
+        for file in files {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        // Files outside of the scan's directory are never listed, however
+        // the manifest names them.
+        let mine = parent.path().join("mine.png");
+        std::fs::write(&mine, "").unwrap();
+        let manifest = serde_json::json!({
+            "pages": [
+                {
+                    "preprocessing": { "vectorize": true, "skeleton": "json" },
+                    "contact_sheet": dir.join("sheet.png"),
+                },
+                { "preprocessing": {}, "contact_sheet": mine },
+            ],
+            "glyphs": [
+                { "page": 0, "file": "letter-0.png" },
+                { "page": 0, "file": "letter-1.png" },
+                { "page": 0, "file": null },
+                { "page": 1, "file": "../mine.png" },
+            ],
+            "artifacts": ["threshold-sweep.png", "debug/page-1-overlay.png", "../mine.png"],
+        });
+        let path = dir.join("manifest.json");
+        std::fs::write(&path, manifest.to_string()).unwrap();
+
+        assert_eq!(
+            generated_files(&path, &dir).unwrap(),
+            vec![
+                dir.join("letter-0.png"),
+                dir.join("letter-0.svg"),
+                dir.join("letter-0.strokes.json"),
+                dir.join("sheet.png"),
+                dir.join("threshold-sweep.png"),
+                dir.join("debug/page-1-overlay.png"),
+                path.clone(),
+            ]
+        );
+        assert_eq!(find_manifests(&dir).unwrap(), vec![path]);
+    }
+}
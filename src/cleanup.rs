@@ -0,0 +1,158 @@
+use image::{GrayImage, Luma};
+use imageproc::{
+    distance_transform::{distance_transform, Norm},
+    morphology,
+    region_labelling::{connected_components, Connectivity},
+};
+use std::collections::HashMap;
+
+use crate::glyph::PAPER;
+
+/// Erase every speck of ink smaller than `min_size` pixels, such as dust and
+/// paper grain that survived thresholding.
+pub fn despeckle(cell: &mut GrayImage, min_size: u32) {
+    let labels = connected_components(&ink(cell), Connectivity::Eight, Luma([0]));
+    let mut sizes = HashMap::new();
+    for label in labels.pixels() {
+        *sizes.entry(label.0[0]).or_insert(0u32) += 1;
+    }
+    for (x, y, label) in labels.enumerate_pixels() {
+        if label.0[0] != 0 && sizes[&label.0[0]] < min_size {
+            cell.put_pixel(x, y, PAPER);
+        }
+    }
+}
+
+/// Remove bits of ink thinner than `radius * 2 + 1` pixels, such as specks
+/// and the rough edges of strokes, without thinning the rest.
+pub fn open(cell: &mut GrayImage, radius: u8) {
+    *cell = paper(&morphology::open(&ink(cell), Norm::LInf, radius));
+}
+
+/// Fill gaps in the ink narrower than `radius * 2 + 1` pixels, such as
+/// pinholes and breaks in a stroke, without thickening the rest.
+pub fn close(cell: &mut GrayImage, radius: u8) {
+    *cell = paper(&morphology::close(&ink(cell), Norm::LInf, radius));
+}
+
+/// Measure how wide the strokes in a cell are, in pixels, as the median width
+/// along the middle of every stroke. Returns `None` if the cell has no ink.
+pub fn stroke_width(cell: &GrayImage) -> Option<f32> {
+    let ink = ink(cell);
+    // How far each pixel of ink is from the paper. Along the middle of a
+    // stroke, that's half the stroke's width.
+    let distances = distance_transform(&paper(&ink), Norm::L1);
+    let (width, height) = distances.dimensions();
+    let distance = |x: u32, y: u32| distances.get_pixel(x, y).0[0];
+    let mut widths: Vec<u8> = Vec::new();
+    for (x, y, pixel) in ink.enumerate_pixels() {
+        if pixel.0[0] == 0 {
+            continue;
+        }
+        let d = distance(x, y);
+        let is_middle = [
+            (x.wrapping_sub(1), y),
+            (x + 1, y),
+            (x, y.wrapping_sub(1)),
+            (x, y + 1),
+        ]
+        .into_iter()
+        .filter(|(x, y)| *x < width && *y < height)
+        .all(|(x, y)| distance(x, y) <= d);
+        if is_middle {
+            widths.push(d.saturating_mul(2).saturating_sub(1));
+        }
+    }
+    if widths.is_empty() {
+        return None;
+    }
+    widths.sort_unstable();
+
+    Some(widths[widths.len() / 2] as f32)
+}
+
+/// Thicken or thin the strokes in a cell, whose strokes are `width` pixels
+/// wide, so that they're about `target` pixels wide instead.
+pub fn normalize_stroke_width(cell: &mut GrayImage, width: f32, target: f32) {
+    // Growing or shrinking the ink by one pixel changes a stroke's width by
+    // two, once on each side.
+    let radius = ((target - width) / 2.0).round();
+    let amount = radius.abs().min(u8::MAX as f32) as u8;
+    if amount == 0 {
+        return;
+    }
+    *cell = if radius > 0.0 {
+        paper(&morphology::dilate(&ink(cell), Norm::L1, amount))
+    } else {
+        paper(&morphology::erode(&ink(cell), Norm::L1, amount))
+    };
+}
+
+/// A mask of a cell's ink, which `imageproc` expects to be white.
+fn ink(cell: &GrayImage) -> GrayImage {
+    GrayImage::from_fn(cell.width(), cell.height(), |x, y| {
+        Luma([if cell.get_pixel(x, y).0[0] < 128 {
+            255
+        } else {
+            0
+        }])
+    })
+}
+
+/// Turn a mask from [`ink`] back into black ink on white paper.
+fn paper(mask: &GrayImage) -> GrayImage {
+    GrayImage::from_fn(mask.width(), mask.height(), |x, y| {
+        Luma([255 - mask.get_pixel(x, y).0[0]])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell_with_speck() -> GrayImage {
+        let mut cell = GrayImage::from_pixel(40, 40, PAPER);
+        for y in 10..30 {
+            for x in 10..30 {
+                cell.put_pixel(x, y, Luma([0]));
+            }
+        }
+        cell.put_pixel(2, 2, Luma([0]));
+        cell
+    }
+
+    #[test]
+    fn despeckles_small_specks_only() {
+        let mut cell = cell_with_speck();
+        despeckle(&mut cell, 4);
+        assert_eq!(cell.get_pixel(2, 2), &PAPER);
+        assert_eq!(cell.get_pixel(20, 20).0[0], 0);
+    }
+
+    #[test]
+    fn measures_and_normalizes_stroke_width() {
+        let mut cell = GrayImage::from_pixel(60, 60, PAPER);
+        for y in 10..50 {
+            for x in 28..33 {
+                cell.put_pixel(x, y, Luma([0]));
+            }
+        }
+        assert_eq!(stroke_width(&cell), Some(5.0));
+        normalize_stroke_width(&mut cell, 5.0, 9.0);
+        assert_eq!(stroke_width(&cell), Some(9.0));
+        normalize_stroke_width(&mut cell, 9.0, 3.0);
+        assert_eq!(stroke_width(&cell), Some(3.0));
+        assert_eq!(stroke_width(&GrayImage::from_pixel(5, 5, PAPER)), None);
+    }
+
+    #[test]
+    fn opens_and_closes() {
+        let mut cell = cell_with_speck();
+        cell.put_pixel(20, 20, PAPER);
+        close(&mut cell, 1);
+        assert_eq!(cell.get_pixel(20, 20).0[0], 0);
+        open(&mut cell, 1);
+        assert_eq!(cell.get_pixel(2, 2), &PAPER);
+        assert_eq!(cell.get_pixel(10, 10).0[0], 0);
+    }
+}
@@ -0,0 +1,268 @@
+use ab_glyph::{Font, FontVec, PxScale};
+use anyhow::{bail, Context};
+use clap::Args;
+use image::{imageops::FilterType, GrayImage, Luma, Rgb, RgbImage};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::{charset::Charset, glyph_set};
+
+/// Pixels drawn only by the reference glyph.
+const REFERENCE_COLOR: Rgb<u8> = Rgb([60, 120, 255]);
+/// Pixels drawn only by the scanned glyph.
+const SCANNED_COLOR: Rgb<u8> = Rgb([230, 50, 50]);
+/// Pixels drawn by both glyphs.
+const OVERLAP_COLOR: Rgb<u8> = Rgb([40, 40, 40]);
+const PAPER_COLOR: Rgb<u8> = Rgb([255, 255, 255]);
+
+/// Arguments for the `compare` command.
+#[derive(Args, Debug)]
+pub struct CompareArgs {
+    /// The directory containing letter images extracted by the `scan`
+    /// command.
+    #[arg(short, long)]
+    glyphs_dir: PathBuf,
+
+    /// The reference alphabet. This may be a TrueType/OpenType font file, or
+    /// a directory of images named the same as the extracted letter images.
+    #[arg(short, long)]
+    reference: PathBuf,
+
+    /// The characters written on the template, in grid order (left to right,
+    /// top to bottom), or the path of a text file containing them. This is
+    /// needed when the reference is a font and the letter images are named by
+    /// cell, like `letter-0.png`, rather than by character. A space marks a
+    /// cell with no character.
+    #[arg(short, long)]
+    charset: Option<String>,
+
+    /// The directory that the difference images will be written to. If not
+    /// provided, the images will be written to the current working directory.
+    #[arg(short, long)]
+    output_dir: Option<PathBuf>,
+
+    /// The width and height of each difference image, in pixels.
+    #[arg(short, long, default_value = "256")]
+    size: u32,
+
+    /// Pixels darker than this value are considered ink. This value should be
+    /// between 0 and 255.
+    #[arg(short, long, default_value = "128")]
+    threshold: u8,
+}
+
+/// Where the reference glyphs come from.
+enum Reference {
+    Font { font: FontVec, charset: Charset },
+    Images(PathBuf),
+}
+
+/// Compare extracted letterforms against a reference alphabet.
+///
+/// Both glyphs are cropped to their ink and scaled to fit the same canvas.
+/// Then, each difference image is drawn with the reference in blue, the
+/// scanned glyph in red, and the overlap in black.
+pub fn compare(args: CompareArgs) -> anyhow::Result<()> {
+    let CompareArgs {
+        glyphs_dir,
+        reference,
+        charset,
+        output_dir,
+        size,
+        threshold,
+    } = args;
+    if !glyphs_dir.is_dir() {
+        bail!("glyphs_dir path doesn't exist or is not a directory.");
+    }
+    let output_dir = match output_dir {
+        Some(dir) => dir,
+        None => std::env::current_dir()?,
+    };
+    if output_dir.is_file() {
+        bail!("output_dir path must be a directory.");
+    }
+
+    let reference = if reference.is_dir() {
+        Reference::Images(reference)
+    } else if reference.is_file() {
+        let charset = match charset {
+            Some(charset) => Charset::from_arg(&charset)?,
+            None => Charset::default(),
+        };
+        let bytes = std::fs::read(&reference).context("reading reference font")?;
+        let font = FontVec::try_from_vec(bytes).context("parsing reference font")?;
+        Reference::Font { font, charset }
+    } else {
+        bail!("reference path doesn't exist.");
+    };
+
+    let glyph_files = glyph_set::read_glyph_files(&glyphs_dir)?;
+
+    std::fs::create_dir_all(&output_dir).context("creating output dir")?;
+    let mut compared = 0;
+    for glyph_file in &glyph_files {
+        let Some(stem) = glyph_file.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let reference_image = match &reference {
+            Reference::Images(dir) => match find_image_with_stem(dir, stem)? {
+                Some(path) => image::open(&path)
+                    .with_context(|| format!("opening {}", path.display()))?
+                    .to_luma8(),
+                None => continue,
+            },
+            Reference::Font { font, charset } => {
+                let Some(c) = glyph_set::char_for_file(glyph_file, charset) else {
+                    continue;
+                };
+                match render_font_glyph(font, c, size) {
+                    Some(image) => image,
+                    None => {
+                        warn!("the reference font has no outline for {c:?}.");
+                        continue;
+                    }
+                }
+            }
+        };
+        let scanned_image = image::open(glyph_file)
+            .with_context(|| format!("opening {}", glyph_file.display()))?
+            .to_luma8();
+
+        let reference_image = normalize_glyph(&reference_image, threshold, size);
+        let scanned_image = normalize_glyph(&scanned_image, threshold, size);
+        let overlay = overlay_glyphs(&reference_image, &scanned_image);
+        overlay.save(output_dir.join(format!("diff-{stem}.png")))?;
+        compared += 1;
+    }
+
+    info!("{compared} letterforms were compared against the reference.");
+
+    Ok(())
+}
+
+fn find_image_with_stem(dir: &Path, stem: &str) -> anyhow::Result<Option<PathBuf>> {
+    for entry in std::fs::read_dir(dir).context("reading reference dir")? {
+        let path = entry?.path();
+        if path.file_stem().and_then(|s| s.to_str()) == Some(stem)
+            && image::ImageFormat::from_path(&path).is_ok()
+        {
+            return Ok(Some(path));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Render a single character as black ink on white paper.
+fn render_font_glyph(font: &FontVec, c: char, size: u32) -> Option<GrayImage> {
+    let glyph = font.glyph_id(c).with_scale(PxScale::from(size as f32));
+    let outline = font.outline_glyph(glyph)?;
+    let bounds = outline.px_bounds();
+    let mut image = GrayImage::from_pixel(
+        bounds.width().ceil() as u32,
+        bounds.height().ceil() as u32,
+        Luma([255]),
+    );
+    outline.draw(|x, y, coverage| {
+        if let Some(pixel) = image.get_pixel_mut_checked(x, y) {
+            pixel.0[0] = 255 - (coverage.clamp(0.0, 1.0) * 255.0) as u8;
+        }
+    });
+
+    Some(image)
+}
+
+/// Crop a glyph to its ink, then scale it to fit a square canvas while
+/// keeping its aspect ratio. The result is binarized: ink is black, paper is
+/// white.
+pub fn normalize_glyph(image: &GrayImage, threshold: u8, size: u32) -> GrayImage {
+    let mut canvas = GrayImage::from_pixel(size, size, Luma([255]));
+    let Some((x, y, width, height)) = ink_bounds(image, threshold) else {
+        return canvas;
+    };
+
+    // Leave a small margin so strokes don't touch the canvas edge.
+    let inner = (size as f32 * 0.9).max(1.0);
+    let scale = inner / width.max(height) as f32;
+    let new_width = ((width as f32 * scale).round() as u32).clamp(1, size);
+    let new_height = ((height as f32 * scale).round() as u32).clamp(1, size);
+
+    let cropped = image::imageops::crop_imm(image, x, y, width, height).to_image();
+    let resized = image::imageops::resize(&cropped, new_width, new_height, FilterType::Triangle);
+    let resized = imageproc::contrast::threshold(&resized, threshold);
+    image::imageops::overlay(
+        &mut canvas,
+        &resized,
+        ((size - new_width) / 2) as i64,
+        ((size - new_height) / 2) as i64,
+    );
+
+    canvas
+}
+
+/// Find the smallest rectangle containing every ink pixel, as
+/// `(x, y, width, height)`.
+fn ink_bounds(image: &GrayImage, threshold: u8) -> Option<(u32, u32, u32, u32)> {
+    let (mut min_x, mut min_y) = (u32::MAX, u32::MAX);
+    let (mut max_x, mut max_y) = (0, 0);
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if pixel.0[0] < threshold {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+
+    (min_x != u32::MAX).then(|| (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1))
+}
+
+/// Overlay two glyphs normalized by [`normalize_glyph`]: the reference in
+/// blue, the scanned glyph in red, and the overlap in black.
+pub fn overlay_glyphs(reference: &GrayImage, scanned: &GrayImage) -> RgbImage {
+    RgbImage::from_fn(reference.width(), reference.height(), |x, y| {
+        let in_reference = reference.get_pixel(x, y).0[0] < 128;
+        let in_scanned = scanned.get_pixel(x, y).0[0] < 128;
+        match (in_reference, in_scanned) {
+            (true, true) => OVERLAP_COLOR,
+            (true, false) => REFERENCE_COLOR,
+            (false, true) => SCANNED_COLOR,
+            (false, false) => PAPER_COLOR,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A glyph of one black bar, on paper with room around it.
+    fn bar(width: u32, height: u32) -> GrayImage {
+        let mut image = GrayImage::from_pixel(100, 100, Luma([255]));
+        for y in 0..height {
+            for x in 0..width {
+                image.put_pixel(10 + x, 20 + y, Luma([0]));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn overlays_glyphs_in_their_colors() {
+        let upright = normalize_glyph(&bar(10, 40), 128, 64);
+        let overlay = overlay_glyphs(&upright, &upright);
+        assert!(overlay
+            .pixels()
+            .all(|pixel| *pixel == OVERLAP_COLOR || *pixel == PAPER_COLOR));
+        assert_eq!(*overlay.get_pixel(32, 8), OVERLAP_COLOR);
+        assert_eq!(*overlay.get_pixel(8, 32), PAPER_COLOR);
+
+        // Crossed with a lying bar, only the middle is shared.
+        let lying = normalize_glyph(&bar(40, 10), 128, 64);
+        let overlay = overlay_glyphs(&upright, &lying);
+        assert_eq!(*overlay.get_pixel(32, 32), OVERLAP_COLOR);
+        assert_eq!(*overlay.get_pixel(32, 8), REFERENCE_COLOR);
+        assert_eq!(*overlay.get_pixel(8, 32), SCANNED_COLOR);
+        assert_eq!(*overlay.get_pixel(5, 5), PAPER_COLOR);
+    }
+}
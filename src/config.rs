@@ -0,0 +1,164 @@
+use anyhow::{bail, Context};
+use clap::builder::OsStr;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// The environment variable that points to a config file, in place of the
+/// usual one.
+pub const PATH_VARIABLE: &str = "HANDWRITING_SCAN_TOOL_CONFIG";
+
+/// Default values for each command's arguments, read from a TOML file with
+/// a table for each command, like:
+///
+/// ```toml
+/// [scan]
+/// output_dir = "~/fonts/scans"
+/// charset = "~/fonts/charset.txt"
+/// yes = true
+/// ```
+///
+/// Arguments given on the command line take precedence.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    /// The default values of each command's arguments, by the command's
+    /// name and then the argument's.
+    pub commands: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+}
+
+/// Where the config file is: the path in [`PATH_VARIABLE`], or
+/// `handwriting-scan-tool/config.toml` in the user's config directory. This
+/// is `$XDG_CONFIG_HOME`, or `~/.config`, or `%APPDATA%` on Windows.
+pub fn path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(PATH_VARIABLE) {
+        return Some(PathBuf::from(path));
+    }
+    let config_dir = if cfg!(windows) {
+        PathBuf::from(std::env::var_os("APPDATA")?)
+    } else {
+        match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        }
+    };
+    Some(config_dir.join("handwriting-scan-tool").join("config.toml"))
+}
+
+impl Config {
+    /// Read a config file.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    /// Read a config file's contents. Values can be strings, numbers, or
+    /// booleans, or arrays of them for arguments that take several values.
+    /// A leading `~/` in a string stands for the home directory.
+    pub fn parse(contents: &str) -> anyhow::Result<Self> {
+        let table: toml::Table = toml::from_str(contents)?;
+        let mut config = Self::default();
+        for (command, args) in table {
+            let toml::Value::Table(args) = args else {
+                bail!("{command} must be a table of arguments, like [{command}].");
+            };
+            let mut defaults = BTreeMap::new();
+            for (arg, value) in args {
+                let values = match value {
+                    toml::Value::Array(values) => values,
+                    value => vec![value],
+                };
+                let values = values
+                    .into_iter()
+                    .map(|value| match value {
+                        toml::Value::String(text) => Ok(expand_home(text)),
+                        toml::Value::Integer(number) => Ok(number.to_string()),
+                        toml::Value::Float(number) => Ok(number.to_string()),
+                        toml::Value::Boolean(flag) => Ok(flag.to_string()),
+                        _ => bail!("{command}.{arg} must be a string, number, or boolean."),
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+                defaults.insert(arg.replace('-', "_"), values);
+            }
+            config.commands.insert(command, defaults);
+        }
+
+        Ok(config)
+    }
+
+    /// Set the default values of `command`'s subcommands' arguments to the
+    /// ones in the config.
+    pub fn apply(&self, mut command: clap::Command) -> anyhow::Result<clap::Command> {
+        for (name, defaults) in &self.commands {
+            let Some(subcommand) = command.find_subcommand(name) else {
+                bail!("the config file has settings for {name}, which isn't a command.");
+            };
+            for arg in defaults.keys() {
+                if !subcommand
+                    .get_arguments()
+                    .any(|known| known.get_id() == arg.as_str())
+                {
+                    bail!("the config file sets {arg} for {name}, which doesn't take it.");
+                }
+            }
+            let name = name.clone();
+            command = command.mut_subcommand(&name, |mut subcommand| {
+                for (arg, values) in defaults {
+                    let values: Vec<OsStr> =
+                        values.iter().map(|value| value.clone().into()).collect();
+                    subcommand = subcommand.mut_arg(arg, |arg| arg.default_values(values));
+                }
+                subcommand
+            });
+        }
+
+        Ok(command)
+    }
+}
+
+/// Replace a leading `~/` with the home directory.
+fn expand_home(text: String) -> String {
+    match (text.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest).to_string_lossy().into_owned(),
+        _ => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_defaults_under_the_command_line() {
+        let config = Config::parse(
+            r#"
+            [scan]
+            output-dir = "out"
+            rows = 6
+            yes = true
+            "#,
+        )
+        .unwrap();
+        let command = clap::Command::new("app").subcommand(
+            clap::Command::new("scan")
+                .arg(clap::arg!(--output_dir <DIR>))
+                .arg(clap::arg!(--rows <ROWS>).default_value("9"))
+                .arg(clap::arg!(--yes).action(clap::ArgAction::SetTrue)),
+        );
+        let command = config.apply(command).unwrap();
+
+        let matches = command
+            .clone()
+            .get_matches_from(["app", "scan", "--rows", "4"]);
+        let scan = matches.subcommand_matches("scan").unwrap();
+        assert_eq!(scan.get_one::<String>("output_dir").unwrap(), "out");
+        assert_eq!(scan.get_one::<String>("rows").unwrap(), "4");
+        assert!(scan.get_flag("yes"));
+
+        let unknown = Config::parse("[scan]\ncolumns = 3").unwrap();
+        assert!(unknown.apply(command.clone()).is_err());
+        let unknown = Config::parse("[scna]\nrows = 3").unwrap();
+        assert!(unknown.apply(command).is_err());
+    }
+}
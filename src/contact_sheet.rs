@@ -0,0 +1,85 @@
+use ab_glyph::FontVec;
+use image::{GenericImageView, GrayImage, Luma, Rgb, RgbImage, SubImage};
+use imageproc::{
+    drawing::{draw_filled_rect_mut, draw_text_mut},
+    rect::Rect,
+};
+
+/// The size of each cell's square on the sheet, in pixels.
+const TILE_SIZE: u32 = 160;
+/// The height of the label above each cell.
+const LABEL_HEIGHT: u32 = 24;
+const GAP: u32 = 4;
+const BACKGROUND_COLOR: Rgb<u8> = Rgb([90, 90, 90]);
+const LABEL_COLOR: Rgb<u8> = Rgb([200, 30, 30]);
+/// Cells that won't be saved are tinted with this color.
+const SKIPPED_TINT: Rgb<u8> = Rgb([170, 190, 230]);
+
+/// Lay out every cell of a page in its grid, each scaled to the same size and
+/// labeled with `labels`. Cells that won't be saved are tinted, so they stand
+/// out. Labels are only drawn if a `font` is given.
+pub fn render(
+    cells: &[SubImage<&GrayImage>],
+    cols: u32,
+    labels: &[String],
+    skipped: &[bool],
+    font: Option<&FontVec>,
+) -> RgbImage {
+    let rows = (cells.len() as u32).div_ceil(cols.max(1));
+    let (tile_width, tile_height) = (TILE_SIZE + GAP, TILE_SIZE + LABEL_HEIGHT + GAP);
+    let mut sheet = RgbImage::from_pixel(
+        cols * tile_width + GAP,
+        rows * tile_height + GAP,
+        BACKGROUND_COLOR,
+    );
+
+    for (i, cell) in cells.iter().enumerate() {
+        let cell = &**cell;
+        let (col, row) = (i as u32 % cols, i as u32 / cols);
+        let (x, y) = (GAP + col * tile_width, GAP + row * tile_height);
+        draw_filled_rect_mut(
+            &mut sheet,
+            Rect::at(x as i32, y as i32).of_size(TILE_SIZE, TILE_SIZE + LABEL_HEIGHT),
+            Rgb([255, 255, 255]),
+        );
+        if let (Some(font), Some(label)) = (font, labels.get(i)) {
+            let scale = LABEL_HEIGHT as f32 * 0.8;
+            draw_text_mut(
+                &mut sheet,
+                LABEL_COLOR,
+                x as i32 + 4,
+                y as i32 + 2,
+                scale,
+                font,
+                label,
+            );
+        }
+
+        // Scale the cell to fit its square, keeping its shape.
+        let (width, height) = cell.dimensions();
+        let scale =
+            (TILE_SIZE as f32 / width.max(1) as f32).min(TILE_SIZE as f32 / height.max(1) as f32);
+        let (scaled_width, scaled_height) = (
+            ((width as f32 * scale) as u32).max(1),
+            ((height as f32 * scale) as u32).max(1),
+        );
+        let left = x + (TILE_SIZE - scaled_width) / 2;
+        let top = y + LABEL_HEIGHT + (TILE_SIZE - scaled_height) / 2;
+        let tint = skipped.get(i).copied().unwrap_or(false);
+        for ty in 0..scaled_height {
+            for tx in 0..scaled_width {
+                let source_x = ((tx as f32 / scale) as u32).min(width - 1);
+                let source_y = ((ty as f32 / scale) as u32).min(height - 1);
+                let Luma([value]) = cell.get_pixel(source_x, source_y);
+                let pixel = if tint && value > 127 {
+                    SKIPPED_TINT
+                } else {
+                    Rgb([value; 3])
+                };
+                sheet.put_pixel(left + tx, top + ty, pixel);
+            }
+        }
+    }
+
+    sheet
+}
@@ -0,0 +1,272 @@
+use anyhow::{bail, Context};
+use clap::Args;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+use crate::{charset::Charset, glyph_set, template};
+
+/// The width and height of each cell of the "still needed" template, in
+/// pixels: 0.75 inches at 300 DPI, like the `generate-template` command's
+/// defaults.
+const CELL_SIZE: u32 = 225;
+const DPI: u32 = 300;
+
+/// Arguments for the `coverage` command.
+#[derive(Args, Debug)]
+pub struct CoverageArgs {
+    /// The directory containing letter images extracted by the `scan`
+    /// command, or a manifest written by it. Letter images in the
+    /// directory's subdirectories, like those of each page of a batch scan,
+    /// are counted too.
+    #[arg(short, long)]
+    glyphs_dir: PathBuf,
+
+    /// The characters written on the template, in grid order (left to right,
+    /// top to bottom), or the path of a text file containing them. This is
+    /// needed to tell which character each glyph is when the letter images
+    /// are named by cell, like `letter-0.png`, rather than by character.
+    #[arg(short, long)]
+    charset: Option<String>,
+
+    /// The characters you want to capture, or the path of a text file
+    /// containing them. The default is every character in the charset.
+    #[arg(short, long)]
+    target: Option<String>,
+
+    /// Where to write a template of just the missing characters, so that
+    /// they can be written on a new sheet. The file name should end in
+    /// `.pdf`, or in the extension of an image format, such as `.png`.
+    #[arg(long)]
+    template: Option<PathBuf>,
+
+    /// The number of columns of cells on the "still needed" template.
+    #[arg(long, default_value = "12")]
+    cols: u32,
+}
+
+/// Which of the target characters have been captured. Ligatures and other
+/// sequences of characters count as one.
+#[derive(Debug, Default, PartialEq)]
+pub struct Coverage {
+    /// The target characters with at least one letter image, in order.
+    pub captured: Vec<String>,
+    /// The target characters with no letter image, in order.
+    pub missing: Vec<String>,
+    /// The target characters with more than one letter image, and how many
+    /// each has.
+    pub repeated: Vec<(String, usize)>,
+}
+
+/// Report which characters of a target charset have been captured, which
+/// are missing, and which have more than one sample.
+pub fn coverage(args: CoverageArgs) -> anyhow::Result<()> {
+    let CoverageArgs {
+        glyphs_dir,
+        charset,
+        target,
+        template,
+        cols,
+    } = args;
+    if !glyphs_dir.exists() {
+        bail!("glyphs_dir path doesn't exist.");
+    }
+    if cols == 0 {
+        bail!("cols must be at least 1.");
+    }
+    if let Some(template) = &template {
+        template::check_output(template)?;
+    }
+    let charset = match charset {
+        Some(charset) => Charset::from_arg(&charset)?,
+        None => Charset::default(),
+    };
+    let target = match target {
+        Some(target) => Charset::from_arg(&target)?,
+        None => charset.clone(),
+    };
+    if (0..target.len()).all(|i| target.text(i).is_none()) {
+        bail!("there are no characters to look for. Pass --charset or --target.");
+    }
+
+    let samples = if glyphs_dir.is_dir() {
+        count_files(&glyphs_dir, &charset)?
+    } else {
+        count_manifest(&glyphs_dir, &charset)?
+    };
+    let coverage = tally(&target, &samples);
+
+    let total = coverage.captured.len() + coverage.missing.len();
+    println!(
+        "Captured {} of {total} characters.",
+        coverage.captured.len()
+    );
+    if !coverage.missing.is_empty() {
+        let missing = charset_string(&coverage.missing);
+        println!("Missing ({}): {missing}", coverage.missing.len());
+    }
+    if !coverage.repeated.is_empty() {
+        let repeated: Vec<String> = coverage
+            .repeated
+            .iter()
+            .map(|(c, count)| format!("{c} ×{count}"))
+            .collect();
+        println!(
+            "More than one sample ({}): {}",
+            coverage.repeated.len(),
+            repeated.join(", ")
+        );
+    }
+
+    if let Some(output) = template {
+        if coverage.missing.is_empty() {
+            info!("Nothing is missing, so no template was made.");
+            return Ok(());
+        }
+        let missing = charset_string(&coverage.missing);
+        let cols = cols.min(coverage.missing.len() as u32);
+        let rows = (coverage.missing.len() as u32).div_ceil(cols);
+        let font = template::system_font();
+        if font.is_none() {
+            warn!("no font was found for the guide characters; the template's cells are blank.");
+        }
+        let sheet = template::render_template(
+            rows,
+            cols,
+            CELL_SIZE,
+            &Charset::parse(&missing),
+            font.as_ref(),
+            true,
+        );
+        template::save(&sheet, &output, DPI)?;
+        info!(
+            "Saved a template of the missing characters to {}. Its layout code tells the scan \
+             command its {rows} rows, {cols} columns, and charset \"{missing}\".",
+            output.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Count the letter images of each character in `dir` and its
+/// subdirectories.
+fn count_files(dir: &Path, charset: &Charset) -> anyhow::Result<BTreeMap<String, usize>> {
+    let mut samples = BTreeMap::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    let mut unnamed = 0;
+    while let Some(dir) = dirs.pop() {
+        for entry in
+            std::fs::read_dir(&dir).with_context(|| format!("reading {}", dir.display()))?
+        {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            }
+        }
+        for file in glyph_set::read_glyph_files(&dir)? {
+            match glyph_set::text_for_file(&file, charset) {
+                Some(c) => *samples.entry(c).or_insert(0) += 1,
+                None => unnamed += 1,
+            }
+        }
+    }
+    if unnamed > 0 {
+        warn!(
+            "{unnamed} letter images couldn't be matched to a character. Pass --charset to name them."
+        );
+    }
+
+    Ok(samples)
+}
+
+/// Count the saved letter images of each character in a manifest. Cells
+/// saved without a character are named by the `charset`.
+fn count_manifest(path: &Path, charset: &Charset) -> anyhow::Result<BTreeMap<String, usize>> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let manifest: serde_json::Value =
+        serde_json::from_str(&contents).with_context(|| format!("parsing {}", path.display()))?;
+    let Some(glyphs) = manifest["glyphs"].as_array() else {
+        bail!(
+            "{} isn't a manifest written by the scan command.",
+            path.display()
+        );
+    };
+    let mut samples = BTreeMap::new();
+    for glyph in glyphs {
+        let saved = matches!(glyph["status"].as_str(), Some("saved" | "existing"));
+        let character = match glyph["character"].as_str() {
+            Some(text) => Some(text),
+            None => glyph["cell"]
+                .as_u64()
+                .and_then(|cell| charset.text(cell as usize)),
+        };
+        if let (true, Some(text)) = (saved, character) {
+            *samples.entry(text.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Sort the characters of `target` by how many `samples` each has.
+pub fn tally(target: &Charset, samples: &BTreeMap<String, usize>) -> Coverage {
+    let mut coverage = Coverage::default();
+    let mut seen = Vec::new();
+    for text in (0..target.len()).filter_map(|i| target.text(i)) {
+        if seen.contains(&text) {
+            continue;
+        }
+        seen.push(text);
+        match samples.get(text).copied().unwrap_or(0) {
+            0 => coverage.missing.push(text.to_string()),
+            count => {
+                coverage.captured.push(text.to_string());
+                if count > 1 {
+                    coverage.repeated.push((text.to_string(), count));
+                }
+            }
+        }
+    }
+
+    coverage
+}
+
+/// Write characters as a charset, with sequences like ligatures in braces.
+fn charset_string(texts: &[String]) -> String {
+    texts
+        .iter()
+        .map(|text| match text.chars().count() {
+            1 => text.clone(),
+            _ => format!("{{{text}}}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tallies_captured_missing_and_repeated_characters() {
+        let samples = BTreeMap::from([
+            ("a".to_string(), 1),
+            ("b".to_string(), 3),
+            ("th".to_string(), 1),
+        ]);
+        let coverage = tally(&Charset::parse("ab c\nab{th}{ff}"), &samples);
+        let strings = |texts: &[&str]| texts.iter().map(|text| text.to_string()).collect();
+        assert_eq!(
+            coverage,
+            Coverage {
+                captured: strings(&["a", "b", "th"]),
+                missing: strings(&["c", "ff"]),
+                repeated: vec![("b".to_string(), 3)],
+            }
+        );
+        assert_eq!(charset_string(&coverage.missing), "c{ff}");
+    }
+}
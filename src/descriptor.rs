@@ -0,0 +1,475 @@
+use anyhow::{bail, Context};
+use image::{GenericImageView, GrayImage};
+use serde::Deserialize;
+use std::{path::Path, str::FromStr};
+use tracing::debug_span;
+
+use crate::grid::{self, Grid, Margin, Margins};
+
+/// A rectangle of a page picked out with `--region`, to be cut into cells as
+/// it is, for pages that aren't a template.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Selection {
+    /// The whole page, written `page`, to start from before drawing the
+    /// region by hand with `--gui`.
+    Page,
+    /// A rectangle of the input image, written `x,y,width,height` in pixels.
+    Pixels {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+}
+
+impl Selection {
+    /// The rectangle's bounds on an image of `width` × `height` pixels, as
+    /// fractions of its width and height: `[left, top, right, bottom]`.
+    pub fn bounds(self, width: u32, height: u32) -> anyhow::Result<[f32; 4]> {
+        let Self::Pixels {
+            x,
+            y,
+            width: selection_width,
+            height: selection_height,
+        } = self
+        else {
+            return Ok([0.0, 0.0, 1.0, 1.0]);
+        };
+        if x + selection_width > width || y + selection_height > height {
+            bail!(
+                "the region {x},{y},{selection_width},{selection_height} doesn't fit on the \
+                 {width} × {height} page."
+            );
+        }
+        let (width, height) = (width as f32, height as f32);
+        Ok([
+            x as f32 / width,
+            y as f32 / height,
+            (x + selection_width) as f32 / width,
+            (y + selection_height) as f32 / height,
+        ])
+    }
+}
+
+impl FromStr for Selection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "page" {
+            return Ok(Self::Page);
+        }
+        let numbers: Vec<u32> = s
+            .split(',')
+            .map(|number| number.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| {
+                format!("{s:?} isn't \"page\" or a rectangle like \"120,340,1800,2400\"")
+            })?;
+        let &[x, y, width, height] = &numbers[..] else {
+            return Err(format!(
+                "{s:?} should have four numbers: x, y, width, and height, like \"120,340,1800,2400\""
+            ));
+        };
+        if width == 0 || height == 0 {
+            return Err(format!(
+                "the region {s:?} must be at least a pixel wide and tall"
+            ));
+        }
+        Ok(Self::Pixels {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+/// The template descriptors built into the app, by name. Each is a TOML file
+/// in the `templates` directory of the repository, which makes a good
+/// starting point for your own.
+pub const PRESETS: &[(&str, &str)] = &[
+    ("standard", include_str!("../templates/standard.toml")),
+    ("symbols", include_str!("../templates/symbols.toml")),
+];
+
+/// The layout of a template, loaded from a TOML file with `--template`: where
+/// its grids are, which of their cells hold letters, and which characters
+/// they hold. Without one, a page is taken to hold one grid of `--rows` ×
+/// `--cols` cells.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Descriptor {
+    /// The characters written in the template's letter cells, in order,
+    /// written like `--charset`. Label rows and skipped cells don't take a
+    /// character. `--charset` replaces it.
+    pub charset: Option<String>,
+    /// Whether the charset runs through each row of cells from right to
+    /// left, for scripts like Hebrew and Arabic. `--right-to-left` sets it.
+    #[serde(default, rename = "right-to-left")]
+    pub right_to_left: bool,
+    /// The template's grids, in the order their cells are read.
+    #[serde(rename = "region")]
+    pub regions: Vec<Region>,
+}
+
+/// One grid of a template.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Region {
+    pub rows: u32,
+    pub cols: u32,
+    /// The part of the page the grid lies in, as fractions of the page's
+    /// width and height: `[left, top, right, bottom]`. The grid's lines are
+    /// only looked for there. By default, the whole page.
+    pub bounds: Option<[f32; 4]>,
+    /// The space to the left and right of the grid, and above and below it,
+    /// inside the region's bounds, where the grid is assumed to be if its
+    /// lines can't be found. With bounds, there are none by default. Without
+    /// either, a page whose grid lines can't be found stops the scan.
+    pub margin_x: Option<Margin>,
+    pub margin_y: Option<Margin>,
+    /// Cut the region's bounds into even cells as they are, without looking
+    /// for the grid's lines, for pages like grid paper whose lines aren't
+    /// the template's.
+    #[serde(default)]
+    pub fixed: bool,
+    /// Rows of cells that hold printed labels rather than letters, counted
+    /// from 0. They're left out.
+    #[serde(default)]
+    pub label_rows: Vec<u32>,
+    /// Cells that are left out, as `[row, column]`, counted from 0.
+    #[serde(default)]
+    pub skip: Vec<[u32; 2]>,
+}
+
+/// A cell of a template that holds a letter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    /// The cell's rectangle on the page, as `(x, y, width, height)`.
+    pub area: (u32, u32, u32, u32),
+    /// The cell's row, counted across all of the template's regions, and its
+    /// column in its own region.
+    pub row: u32,
+    pub col: u32,
+    /// Where the cell is in its region's [`Grid::cells`].
+    pub index: usize,
+}
+
+/// The grids found on a page, one for each region of a template.
+#[derive(Clone, Debug)]
+pub struct Located {
+    pub grids: Vec<Grid>,
+    /// The regions whose grid lines couldn't be found, which were cut inside
+    /// their margins instead.
+    pub missing: Vec<usize>,
+}
+
+impl Located {
+    /// How many of the grids' lines weren't found, and were placed where they
+    /// were expected to be instead.
+    pub fn guessed(&self) -> u32 {
+        self.grids.iter().map(|grid| grid.guessed).sum()
+    }
+}
+
+impl Descriptor {
+    /// A template with one grid of `rows` × `cols` cells, covering the page,
+    /// with `margins` around it if its lines can't be found.
+    pub fn grid(rows: u32, cols: u32, margins: Margins) -> Self {
+        Self {
+            charset: None,
+            right_to_left: false,
+            regions: vec![Region {
+                rows,
+                cols,
+                bounds: None,
+                margin_x: margins.x,
+                margin_y: margins.y,
+                fixed: false,
+                label_rows: Vec::new(),
+                skip: Vec::new(),
+            }],
+        }
+    }
+
+    /// A page that isn't a template, with one grid of `rows` × `cols` cells
+    /// that fills `bounds`, as fractions of the page's width and height:
+    /// `[left, top, right, bottom]`. Its lines aren't looked for.
+    pub fn fixed(rows: u32, cols: u32, bounds: [f32; 4]) -> Self {
+        let mut descriptor = Self::grid(rows, cols, Margins::default());
+        descriptor.regions[0].bounds = Some(bounds);
+        descriptor.regions[0].fixed = true;
+        descriptor
+    }
+
+    /// Load a template descriptor from `--template`: the name of a preset,
+    /// or the path of a TOML file.
+    pub fn from_arg(value: &str) -> anyhow::Result<Self> {
+        if let Some((_, contents)) = PRESETS.iter().find(|(name, _)| *name == value) {
+            return Self::parse(contents).with_context(|| format!("parsing the {value} template"));
+        }
+        let path = Path::new(value);
+        if !path.is_file() {
+            let names: Vec<&str> = PRESETS.iter().map(|(name, _)| *name).collect();
+            bail!(
+                "{value} isn't a template file or one of the built-in templates: {}.",
+                names.join(", ")
+            );
+        }
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    /// Read a template descriptor from the contents of a TOML file, and check
+    /// that its layout makes sense.
+    pub fn parse(contents: &str) -> anyhow::Result<Self> {
+        let descriptor: Self = toml::from_str(contents)?;
+        if descriptor.regions.is_empty() {
+            bail!("a template needs at least one [[region]].");
+        }
+        for (i, region) in descriptor.regions.iter().enumerate() {
+            let n = i + 1;
+            if region.rows == 0 || region.cols == 0 {
+                bail!("region {n} needs at least one row and one column.");
+            }
+            if let Some([left, top, right, bottom]) = region.bounds {
+                let fits = |start: f32, end: f32| 0.0 <= start && start < end && end <= 1.0;
+                if !fits(left, right) || !fits(top, bottom) {
+                    bail!(
+                        "region {n}'s bounds must be fractions of the page between 0 and 1, \
+                         as [left, top, right, bottom]."
+                    );
+                }
+            }
+            if let Some(row) = region.label_rows.iter().find(|row| **row >= region.rows) {
+                bail!("region {n} has no row {row} to be a label row.");
+            }
+            if let Some([row, col]) = region
+                .skip
+                .iter()
+                .find(|[row, col]| *row >= region.rows || *col >= region.cols)
+            {
+                bail!("region {n} has no cell at row {row}, column {col} to skip.");
+            }
+        }
+        if descriptor.cell_count() == 0 {
+            bail!("every cell of the template is a label or skipped.");
+        }
+
+        Ok(descriptor)
+    }
+
+    /// The number of cells that hold letters.
+    pub fn cell_count(&self) -> usize {
+        self.regions
+            .iter()
+            .map(|region| {
+                (0..region.rows)
+                    .flat_map(|row| (0..region.cols).map(move |col| (row, col)))
+                    .filter(|&(row, col)| region.holds_letter(row, col))
+                    .count()
+            })
+            .sum()
+    }
+
+    /// The number of rows of cells in every region together, and the number
+    /// of columns in the widest.
+    pub fn size(&self) -> (u32, u32) {
+        let rows = self.regions.iter().map(|region| region.rows).sum();
+        let cols = self.regions.iter().map(|region| region.cols).max();
+        (rows, cols.unwrap_or(0))
+    }
+
+    /// Find each region's grid in a preprocessed page. Regions whose grid
+    /// lines can't be found are assumed to fill their bounds, inside their
+    /// margins.
+    pub fn locate(&self, page: &GrayImage) -> Located {
+        let (width, height) = page.dimensions();
+        let mut located = Located {
+            grids: Vec::new(),
+            missing: Vec::new(),
+        };
+        for (i, region) in self.regions.iter().enumerate() {
+            let (x, y, region_width, region_height) = region.area(width, height);
+            if region.fixed {
+                located.grids.push(Grid::uniform(
+                    x,
+                    y,
+                    region_width,
+                    region_height,
+                    region.cols,
+                    region.rows,
+                ));
+                continue;
+            }
+            let detected = debug_span!("grid").in_scope(|| {
+                if (region_width, region_height) == (width, height) {
+                    grid::detect_grid(page, region.cols, region.rows)
+                } else {
+                    let part = page.view(x, y, region_width, region_height).to_image();
+                    grid::detect_grid(&part, region.cols, region.rows)
+                }
+            });
+            let grid = detected.unwrap_or_else(|| {
+                located.missing.push(i);
+                Grid::from_margins(
+                    region_width,
+                    region_height,
+                    region.cols,
+                    region.rows,
+                    region.margins(),
+                )
+            });
+            located.grids.push(Grid {
+                columns: grid.columns.iter().map(|column| column + x).collect(),
+                rows: grid.rows.iter().map(|row| row + y).collect(),
+                guessed: grid.guessed,
+            });
+        }
+
+        located
+    }
+
+    /// The cells of `grids`, one for each region, that hold letters, in the
+    /// order the charset runs through them.
+    pub fn cells(&self, grids: &[Grid]) -> Vec<Cell> {
+        let mut cells = Vec::new();
+        let mut first_row = 0;
+        for (region, grid) in self.regions.iter().zip(grids) {
+            let first_cell = cells.len();
+            for (index, area) in grid.cells().into_iter().enumerate() {
+                let (row, col) = (index as u32 / region.cols, index as u32 % region.cols);
+                if region.holds_letter(row, col) {
+                    cells.push(Cell {
+                        area,
+                        row: first_row + row,
+                        col,
+                        index,
+                    });
+                }
+            }
+            if self.right_to_left {
+                cells[first_cell..].sort_by_key(|cell| (cell.row, std::cmp::Reverse(cell.col)));
+            }
+            first_row += region.rows;
+        }
+
+        cells
+    }
+}
+
+impl Region {
+    /// Whether the cell at `row` and `col` holds a letter, rather than a
+    /// label or nothing.
+    pub fn holds_letter(&self, row: u32, col: u32) -> bool {
+        !self.label_rows.contains(&row) && !self.skip.contains(&[row, col])
+    }
+
+    /// Whether the region says where its grid is, with its bounds or margins,
+    /// or is cut as it is,
+    /// for when its lines can't be found.
+    pub fn is_placed(&self) -> bool {
+        self.fixed || self.bounds.is_some() || self.margin_x.is_some() || self.margin_y.is_some()
+    }
+
+    /// The region's rectangle on a page of `width` × `height` pixels, as
+    /// `(x, y, width, height)`.
+    fn area(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let Some([left, top, right, bottom]) = self.bounds else {
+            return (0, 0, width, height);
+        };
+        let at = |fraction: f32, extent: u32| (extent as f32 * fraction).round() as u32;
+        let (x, y) = (at(left, width), at(top, height));
+        (x, y, at(right, width) - x, at(bottom, height) - y)
+    }
+
+    fn margins(&self) -> Margins {
+        // A region's bounds are the edges of its grid, unless it's given
+        // margins of its own.
+        let none = self.bounds.map(|_| Margin::Fraction(0.0));
+        Margins {
+            x: self.margin_x.or(none),
+            y: self.margin_y.or(none),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_presets() {
+        for (name, contents) in PRESETS {
+            Descriptor::parse(contents).unwrap_or_else(|err| panic!("{name}: {err:#}"));
+        }
+    }
+
+    #[test]
+    fn leaves_out_labels_and_skipped_cells() {
+        let descriptor = Descriptor::parse(
+            r#"
+            [[region]]
+            rows = 2
+            cols = 3
+            bounds = [0.0, 0.0, 1.0, 0.5]
+            label-rows = [0]
+
+            [[region]]
+            rows = 1
+            cols = 2
+            bounds = [0.0, 0.5, 1.0, 1.0]
+            margin-x = "10px"
+            skip = [[0, 1]]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(descriptor.cell_count(), 4);
+        assert_eq!(descriptor.size(), (3, 3));
+
+        // Neither region has grid lines, so both fill their bounds.
+        let page = GrayImage::from_pixel(120, 100, image::Luma([255]));
+        let located = descriptor.locate(&page);
+        assert_eq!(located.missing, vec![0, 1]);
+        let cells = descriptor.cells(&located.grids);
+        let places: Vec<_> = cells.iter().map(|cell| (cell.row, cell.col)).collect();
+        assert_eq!(places, vec![(1, 0), (1, 1), (1, 2), (2, 0)]);
+        assert_eq!(cells[0].area, (0, 25, 40, 25));
+        assert_eq!(cells[3].area, (10, 50, 50, 50));
+
+        let mut right_to_left = descriptor.clone();
+        right_to_left.right_to_left = true;
+        let cells = right_to_left.cells(&located.grids);
+        let places: Vec<_> = cells.iter().map(|cell| (cell.row, cell.col)).collect();
+        assert_eq!(places, vec![(1, 2), (1, 1), (1, 0), (2, 0)]);
+
+        assert!(Descriptor::parse("[[region]]\nrows = 1\ncols = 1\nlabel-rows = [0]").is_err());
+        assert!(Descriptor::parse("[[region]]\nrows = 2\ncols = 2\nskip = [[2, 0]]").is_err());
+    }
+
+    #[test]
+    fn cuts_a_selected_region_of_grid_paper_as_it_is() {
+        // Grid paper, with a line every 10 pixels.
+        let page = GrayImage::from_fn(200, 100, |x, y| {
+            image::Luma([if x % 10 == 0 || y % 10 == 0 { 0 } else { 255 }])
+        });
+        let selection: Selection = "20,10,120,60".parse().unwrap();
+        let bounds = selection.bounds(200, 100).unwrap();
+        let descriptor = Descriptor::fixed(2, 3, bounds);
+        let located = descriptor.locate(&page);
+        assert!(located.missing.is_empty());
+        let areas: Vec<_> = descriptor
+            .cells(&located.grids)
+            .iter()
+            .map(|cell| cell.area)
+            .collect();
+        assert_eq!(areas[0], (20, 10, 40, 30));
+        assert_eq!(areas[5], (100, 40, 40, 30));
+
+        assert_eq!("page".parse(), Ok(Selection::Page));
+        assert!("1,2,3".parse::<Selection>().is_err());
+        assert!("0,0,0,10".parse::<Selection>().is_err());
+        assert!(selection.bounds(100, 100).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use image::{GrayImage, Luma};
+use imageproc::geometric_transformations::{rotate_about_center, Interpolation};
+use rayon::prelude::*;
+
+/// The largest rotation, in degrees, that deskewing will correct.
+const MAX_SKEW_DEGREES: f32 = 5.0;
+
+/// How much the page is shrunk by when estimating its skew.
+const ESTIMATION_SCALE: u32 = 4;
+
+/// Skews smaller than this, in degrees, aren't worth resampling the page for.
+const MIN_CORRECTION_DEGREES: f32 = 0.05;
+
+/// Estimate how far a page is rotated, in degrees, clockwise.
+///
+/// This uses projection profiles: the page's ink is projected onto the
+/// vertical axis at a range of angles, and the angle at which rows of ink
+/// (the grid lines and lines of handwriting) line up most sharply wins.
+pub fn estimate_skew(image: &GrayImage, threshold: u8) -> f32 {
+    let small = image::imageops::thumbnail(
+        image,
+        (image.width() / ESTIMATION_SCALE).max(1),
+        (image.height() / ESTIMATION_SCALE).max(1),
+    );
+    let ink: Vec<(f32, f32)> = small
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel.0[0] < threshold)
+        .map(|(x, y, _)| (x as f32, y as f32))
+        .collect();
+    if ink.is_empty() {
+        return 0.0;
+    }
+
+    let (width, height) = small.dimensions();
+    let sharpness = |degrees: f32| {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        // Leave room for rows that rotate above or below the page.
+        let offset = width as f32;
+        let mut profile = vec![0u32; (height + width * 2) as usize];
+        for (x, y) in &ink {
+            let row = (y * cos - x * sin + offset) as usize;
+            if let Some(count) = profile.get_mut(row) {
+                *count += 1;
+            }
+        }
+        profile
+            .iter()
+            .map(|count| (*count as u64).pow(2))
+            .sum::<u64>()
+    };
+
+    // Search coarsely, then refine around the best angle.
+    let mut best = 0.0;
+    for (step, range) in [(0.1, MAX_SKEW_DEGREES), (0.01, 0.1)] {
+        let center = best;
+        let steps = (range / step).round() as i32;
+        best = (-steps..=steps)
+            .into_par_iter()
+            .map(|i| center + i as f32 * step)
+            .max_by_key(|degrees| sharpness(*degrees))
+            .unwrap_or(center);
+    }
+
+    best
+}
+
+/// Rotate a page counterclockwise by `degrees`, to undo the skew found by
+/// [`estimate_skew`]. Returns `None` if the skew is too small to bother
+/// correcting.
+pub fn straighten(image: &GrayImage, degrees: f32) -> Option<GrayImage> {
+    if degrees.abs() < MIN_CORRECTION_DEGREES {
+        return None;
+    }
+
+    Some(rotate_about_center(
+        image,
+        -degrees.to_radians(),
+        Interpolation::Bilinear,
+        Luma([255]),
+    ))
+}
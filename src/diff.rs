@@ -0,0 +1,350 @@
+use anyhow::{bail, Context};
+use clap::Args;
+use image::{GrayImage, Rgb, RgbImage};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+use tracing::{debug_span, info};
+
+use crate::{
+    charset::{self, Charset},
+    compare, glyph, glyph_set, layout_code, pipeline, threshold,
+};
+
+/// The space between the images of a side-by-side comparison, in pixels.
+const GAP: u32 = 8;
+const GAP_COLOR: Rgb<u8> = Rgb([200, 200, 200]);
+/// Cells with less of their area covered by ink than this are taken to be
+/// empty when scanning a page, like `scan`'s default `--min-ink`.
+const MIN_INK: f32 = 0.003;
+
+/// Arguments for the `diff` command.
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// The earlier letter images: a directory written by the `scan` command,
+    /// or a scanned page, which is scanned first.
+    #[arg(short, long)]
+    before: PathBuf,
+
+    /// The later letter images, in the same form as `--before`.
+    #[arg(short, long)]
+    after: PathBuf,
+
+    /// The characters written on the template, in grid order, or the path of
+    /// a text file containing them. This names the letterforms of scanned
+    /// pages the way `scan --charset` does, so they can be matched with
+    /// letter images named by character.
+    #[arg(short, long)]
+    charset: Option<String>,
+
+    /// The number of rows of cells on the template, for scanned pages. The
+    /// default value is 9, which matches the bundled template.
+    #[arg(long, default_value = "9")]
+    rows: u32,
+
+    /// The number of columns of cells on the template, for scanned pages.
+    /// The default value is 12, which matches the bundled template.
+    #[arg(long, default_value = "12")]
+    cols: u32,
+
+    /// Ignore the QR code that templates made by `generate-template` carry,
+    /// and use `--rows` and `--cols` instead.
+    #[arg(long, default_value_t = false)]
+    no_layout_code: bool,
+
+    /// Threshold value to use when scanning pages, the same as for the
+    /// `scan` command. The default value is 190.
+    #[arg(short, long, default_value = "190")]
+    threshold: threshold::Threshold,
+
+    /// The directory that the comparison images will be written to. If not
+    /// provided, the images will be written to the current working directory.
+    #[arg(short, long)]
+    output_dir: Option<PathBuf>,
+
+    /// How many of the most changed letterforms to write comparison images
+    /// for.
+    #[arg(long, default_value = "10")]
+    top: usize,
+
+    /// The width and height that each letterform is scaled to before it's
+    /// compared, in pixels.
+    #[arg(short, long, default_value = "256")]
+    size: u32,
+}
+
+/// How much a letterform changed between two runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// The name of the letter image, without its extension.
+    pub name: String,
+    /// How much the two letterforms' ink overlaps, as the intersection over
+    /// the union, from 0 (not at all) to 1 (exactly).
+    pub iou: f32,
+    /// The fraction of pixels that are ink in one letterform but not the
+    /// other.
+    pub changed: f32,
+}
+
+/// Compare two sets of letterforms glyph by glyph.
+///
+/// Letterforms are matched by the names of their letter images. Each pair is
+/// cropped to its ink and scaled to the same size, like the `compare` command
+/// does, so only the shapes are compared, not where they sat in their cells.
+/// The changes are printed, most changed first, and the most changed
+/// letterforms are written as images of the earlier letterform, the later
+/// one, and the two overlaid: the earlier in blue, the later in red, and the
+/// overlap in black.
+pub fn diff(args: DiffArgs) -> anyhow::Result<()> {
+    let DiffArgs {
+        before,
+        after,
+        charset,
+        rows,
+        cols,
+        no_layout_code,
+        threshold,
+        output_dir,
+        top,
+        size,
+    } = args;
+    if rows == 0 || cols == 0 {
+        bail!("rows and cols must be at least 1.");
+    }
+    if size == 0 {
+        bail!("size must be at least 1.");
+    }
+    let output_dir = match output_dir {
+        Some(dir) => dir,
+        None => std::env::current_dir()?,
+    };
+    if output_dir.is_file() {
+        bail!("output_dir path must be a directory.");
+    }
+    let charset = match charset {
+        Some(charset) => Charset::from_arg(&charset)?,
+        None => Charset::default(),
+    };
+    let layout = Layout {
+        rows,
+        cols,
+        no_layout_code,
+        threshold,
+    };
+
+    let before = load_glyphs(&before, &charset, &layout)?;
+    let after = load_glyphs(&after, &charset, &layout)?;
+    let mut changes = Vec::new();
+    let mut pairs = BTreeMap::new();
+    for (name, before_image) in &before {
+        let Some(after_image) = after.get(name) else {
+            continue;
+        };
+        let before_image = compare::normalize_glyph(before_image, 128, size);
+        let after_image = compare::normalize_glyph(after_image, 128, size);
+        changes.push(measure_change(name, &before_image, &after_image));
+        pairs.insert(name.clone(), (before_image, after_image));
+    }
+    changes.sort_by(|a, b| a.iou.total_cmp(&b.iou).then(a.name.cmp(&b.name)));
+
+    println!("{:<24} {:>6} {:>8}", "glyph", "IoU", "changed");
+    for change in &changes {
+        println!(
+            "{:<24} {:>6.3} {:>7.2}%",
+            change.name,
+            change.iou,
+            change.changed * 100.0
+        );
+    }
+    let removed: Vec<&str> = before
+        .keys()
+        .filter(|name| !after.contains_key(*name))
+        .map(String::as_str)
+        .collect();
+    let added: Vec<&str> = after
+        .keys()
+        .filter(|name| !before.contains_key(*name))
+        .map(String::as_str)
+        .collect();
+    if !removed.is_empty() {
+        println!("Only before: {}", removed.join(", "));
+    }
+    if !added.is_empty() {
+        println!("Only after: {}", added.join(", "));
+    }
+    if changes.is_empty() {
+        bail!("no letterforms were found in both sets; check that they're named the same way.");
+    }
+    let mean = changes.iter().map(|change| change.iou).sum::<f32>() / changes.len() as f32;
+    println!(
+        "{} letterforms compared, with a mean IoU of {mean:.3}.",
+        changes.len()
+    );
+
+    std::fs::create_dir_all(&output_dir).context("creating output dir")?;
+    let mut written = 0;
+    for change in changes.iter().filter(|change| change.iou < 1.0).take(top) {
+        let (before_image, after_image) = &pairs[&change.name];
+        let path = output_dir.join(format!("diff-{}.png", change.name));
+        side_by_side(before_image, after_image)
+            .save(&path)
+            .with_context(|| format!("saving {}", path.display()))?;
+        written += 1;
+    }
+    info!(
+        "Wrote {written} comparison images to {}.",
+        output_dir.display()
+    );
+
+    Ok(())
+}
+
+/// How to scan pages given to `diff`.
+struct Layout {
+    rows: u32,
+    cols: u32,
+    no_layout_code: bool,
+    threshold: threshold::Threshold,
+}
+
+/// Load a set of letterforms by name: the letter images in a directory, or
+/// the letterforms scanned from a page.
+fn load_glyphs(
+    path: &Path,
+    charset: &Charset,
+    layout: &Layout,
+) -> anyhow::Result<BTreeMap<String, GrayImage>> {
+    if path.is_dir() {
+        let mut glyphs = BTreeMap::new();
+        for file in glyph_set::read_glyph_files(path)? {
+            let Some(stem) = file.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let image = image::open(&file)
+                .with_context(|| format!("opening {}", file.display()))?
+                .to_luma8();
+            glyphs.insert(stem.to_string(), image);
+        }
+        Ok(glyphs)
+    } else if path.is_file() {
+        info!("Scanning {}...", path.display());
+        scan_page(path, charset, layout)
+    } else {
+        bail!("{} doesn't exist.", path.display());
+    }
+}
+
+/// Scan the letterforms from a page, named like the `scan` command names
+/// them.
+fn scan_page(
+    path: &Path,
+    charset: &Charset,
+    layout: &Layout,
+) -> anyhow::Result<BTreeMap<String, GrayImage>> {
+    let (image, _) = pipeline::open_input(path, None, None)?;
+    let options = pipeline::PreprocessOptions {
+        threshold: layout.threshold,
+        adaptive_window: None,
+        orient: true,
+        lighting: None,
+        deskew: true,
+        camera: false,
+        grayscale: false,
+        pen_color: None,
+        straightened: false,
+    };
+    let (page, report) = pipeline::preprocess(image, options)?;
+    let code = if layout.no_layout_code {
+        None
+    } else {
+        debug_span!("layout_code").in_scope(|| layout_code::read(&page))
+    };
+    let (rows, cols) = code.map_or((layout.rows, layout.cols), |code| (code.rows, code.cols));
+    let grid = pipeline::locate_grid(&page, cols, rows);
+    let cells = pipeline::cut_cells(report.pen.as_ref().unwrap_or(&page), &grid);
+
+    let mut glyphs = BTreeMap::new();
+    for (i, cell) in cells.iter().enumerate() {
+        if glyph::ink_coverage(&**cell) < MIN_INK {
+            continue;
+        }
+        let mut image = cell.to_image();
+        glyph::remove_grid_lines(&mut image);
+        // Crop to the letterform, like `scan` does, so specks around it
+        // aren't compared.
+        let Some(bounds) = glyph::ink_bounds(&image) else {
+            continue;
+        };
+        let image = glyph::crop_to(&image, bounds, 0);
+        let name = match charset.text(i) {
+            Some(text) => charset::text_file_stem(text),
+            None => format!("letter-{i}"),
+        };
+        glyphs.insert(name, image);
+    }
+
+    Ok(glyphs)
+}
+
+/// Measure how much a letterform changed, from two images normalized by
+/// [`compare::normalize_glyph`].
+pub fn measure_change(name: &str, before: &GrayImage, after: &GrayImage) -> Change {
+    let (mut both, mut either) = (0u32, 0u32);
+    for (before, after) in before.pixels().zip(after.pixels()) {
+        let (before, after) = (before.0[0] < 128, after.0[0] < 128);
+        both += (before && after) as u32;
+        either += (before || after) as u32;
+    }
+    let pixels = (before.width() * before.height()).max(1);
+    Change {
+        name: name.to_string(),
+        iou: if either == 0 {
+            1.0
+        } else {
+            both as f32 / either as f32
+        },
+        changed: (either - both) as f32 / pixels as f32,
+    }
+}
+
+/// Lay out the earlier letterform, the later one, and the two overlaid, side
+/// by side.
+fn side_by_side(before: &GrayImage, after: &GrayImage) -> RgbImage {
+    let (width, height) = before.dimensions();
+    let mut image = RgbImage::from_pixel(width * 3 + GAP * 2, height, GAP_COLOR);
+    let overlay = compare::overlay_glyphs(before, after);
+    for (i, part) in [
+        image::DynamicImage::ImageLuma8(before.clone()).to_rgb8(),
+        image::DynamicImage::ImageLuma8(after.clone()).to_rgb8(),
+        overlay,
+    ]
+    .iter()
+    .enumerate()
+    {
+        image::imageops::replace(&mut image, part, (i as u32 * (width + GAP)) as i64, 0);
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+    use imageproc::{drawing::draw_filled_rect_mut, rect::Rect};
+
+    #[test]
+    fn measures_how_much_letterforms_changed() {
+        let mut before = GrayImage::from_pixel(10, 10, Luma([255]));
+        draw_filled_rect_mut(&mut before, Rect::at(0, 0).of_size(4, 10), Luma([0]));
+        let mut after = GrayImage::from_pixel(10, 10, Luma([255]));
+        draw_filled_rect_mut(&mut after, Rect::at(2, 0).of_size(4, 10), Luma([0]));
+
+        let change = measure_change("a", &before, &after);
+        // 20 pixels are ink in both, out of 60 that are ink in either.
+        assert!((change.iou - 1.0 / 3.0).abs() < 1e-6);
+        assert!((change.changed - 0.4).abs() < 1e-6);
+        assert_eq!(measure_change("a", &before, &before).iou, 1.0);
+        assert_eq!(side_by_side(&before, &after).dimensions(), (46, 10));
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+/// The resolution that the default threshold was tuned for.
+pub const REFERENCE_DPI: f32 = 300.0;
+
+/// How much the threshold moves for every doubling (or halving) of the
+/// resolution relative to [`REFERENCE_DPI`].
+const THRESHOLD_STEP_PER_OCTAVE: f32 = 8.0;
+
+/// Read the horizontal resolution of an image file, in dots per inch.
+///
+/// EXIF metadata is checked first, then the JFIF density of a JPEG and the
+/// `pHYs` chunk of a PNG. Returns `None` if the file doesn't record its
+/// resolution, or records it without a physical unit.
+pub fn read_dpi(path: &Path) -> Option<f32> {
+    if let Some(dpi) = read_exif_dpi(path) {
+        return Some(dpi);
+    }
+
+    let mut bytes = Vec::new();
+    File::open(path).ok()?.read_to_end(&mut bytes).ok()?;
+    read_jfif_dpi(&bytes).or_else(|| read_png_dpi(&bytes))
+}
+
+/// The width and height of a template's cells, in inches, unless told
+/// otherwise. This is the size `generate-template` uses by default.
+pub const DEFAULT_CELL_SIZE: f32 = 0.75;
+
+/// Estimate a scan's resolution from the heights of its grid's cells, in
+/// pixels, when the cells are `cell_size` inches tall on paper. Returns
+/// `None` if there are no cells.
+pub fn estimate_from_cells(cell_heights: &[u32], cell_size: f32) -> Option<f32> {
+    let mut heights = cell_heights.to_vec();
+    heights.sort_unstable();
+    let median = *heights.get(heights.len() / 2)?;
+    Some(median as f32 / cell_size)
+}
+
+/// Adjust a threshold tuned at [`REFERENCE_DPI`] so that it has the same
+/// effect at `dpi`.
+///
+/// At lower resolutions, anti-aliasing smears thin strokes into lighter
+/// grays, so the threshold is raised to keep them as ink. At higher
+/// resolutions strokes are fully resolved and the wider gray fringe is
+/// trimmed by lowering the threshold. The threshold moves by
+/// `THRESHOLD_STEP_PER_OCTAVE` for every doubling of the resolution:
+///
+/// | DPI  | Threshold (tuned at 190) |
+/// |------|--------------------------|
+/// | 150  | 198                      |
+/// | 300  | 190                      |
+/// | 600  | 182                      |
+/// | 1200 | 174                      |
+pub fn scale_threshold(threshold: u8, dpi: f32) -> u8 {
+    let octaves = (dpi / REFERENCE_DPI).log2();
+    let scaled = threshold as f32 - octaves * THRESHOLD_STEP_PER_OCTAVE;
+    scaled.round().clamp(0.0, 255.0) as u8
+}
+
+fn read_exif_dpi(path: &Path) -> Option<f32> {
+    let file = File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+    let resolution = match exif
+        .get_field(exif::Tag::XResolution, exif::In::PRIMARY)?
+        .value
+    {
+        exif::Value::Rational(ref values) => values.first()?.to_f64() as f32,
+        _ => return None,
+    };
+    // A missing unit means inches, per the EXIF spec.
+    let unit = exif
+        .get_field(exif::Tag::ResolutionUnit, exif::In::PRIMARY)
+        .and_then(|field| field.value.get_uint(0))
+        .unwrap_or(2);
+    match unit {
+        2 => Some(resolution),
+        3 => Some(resolution * 2.54),
+        _ => None,
+    }
+    .filter(|dpi| *dpi > 0.0)
+}
+
+fn read_jfif_dpi(bytes: &[u8]) -> Option<f32> {
+    // SOI, then an APP0 segment: length (2), "JFIF\0" (5), version (2),
+    // units (1), X density (2), Y density (2).
+    if bytes.get(0..4)? != [0xFF, 0xD8, 0xFF, 0xE0] || bytes.get(6..11)? != b"JFIF\0" {
+        return None;
+    }
+    let units = *bytes.get(13)?;
+    let density = u16::from_be_bytes([*bytes.get(14)?, *bytes.get(15)?]) as f32;
+    match units {
+        1 => Some(density),
+        2 => Some(density * 2.54),
+        _ => None,
+    }
+    .filter(|dpi| *dpi > 0.0)
+}
+
+fn read_png_dpi(bytes: &[u8]) -> Option<f32> {
+    if bytes.get(0..8)? != b"\x89PNG\r\n\x1a\n" {
+        return None;
+    }
+    let mut offset = 8;
+    while let Some(header) = bytes.get(offset..offset + 8) {
+        let length = u32::from_be_bytes(header[0..4].try_into().ok()?) as usize;
+        let data = bytes.get(offset + 8..offset + 8 + length)?;
+        match &header[4..8] {
+            b"pHYs" => {
+                let pixels_per_unit = u32::from_be_bytes(data.get(0..4)?.try_into().ok()?);
+                // Unit 1 is the meter; 0 means the aspect ratio only.
+                return (*data.get(8)? == 1 && pixels_per_unit > 0)
+                    .then_some(pixels_per_unit as f32 * 0.0254);
+            }
+            b"IDAT" | b"IEND" => return None,
+            _ => {}
+        }
+        // Chunk data is followed by a 4-byte CRC.
+        offset += 8 + length + 4;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_dpi_from_cells() {
+        assert_eq!(
+            estimate_from_cells(&[225, 400, 224, 225], 0.75),
+            Some(300.0)
+        );
+        assert_eq!(estimate_from_cells(&[], 0.75), None);
+    }
+
+    #[test]
+    fn scales_the_threshold_with_the_resolution() {
+        assert_eq!(scale_threshold(190, 150.0), 198);
+        assert_eq!(scale_threshold(190, 300.0), 190);
+        assert_eq!(scale_threshold(190, 600.0), 182);
+        // Thresholds near black or white stop at them, rather than wrapping.
+        assert_eq!(scale_threshold(4, 1200.0), 0);
+        assert_eq!(scale_threshold(252, 150.0), 255);
+    }
+}
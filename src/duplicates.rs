@@ -0,0 +1,156 @@
+use image::{imageops::FilterType, GrayImage};
+
+use crate::glyph;
+
+/// The width and height that letterforms are scaled to before they're
+/// compared, in pixels.
+const SIZE: u32 = 32;
+/// Letterforms whose ink differs in width or height by more than this
+/// fraction aren't copies, however alike their shapes are. This tells apart
+/// letters that are alike once scaled, like `l` and `|`.
+const MAX_SIZE_CHANGE: f32 = 0.1;
+
+/// A letterform's shape, scaled down so it can be compared quickly with
+/// many others.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// Which pixels of the letterform, cropped to its ink and scaled to fit
+    /// [`SIZE`] × [`SIZE`] pixels, are ink, row by row.
+    bits: [u64; (SIZE * SIZE / 64) as usize],
+    /// The width and height of the letterform's ink, in pixels.
+    size: (u32, u32),
+}
+
+impl Fingerprint {
+    /// Take the fingerprint of a cleaned-up cell's letterform. Returns `None`
+    /// if the cell is empty.
+    pub fn of(cell: &GrayImage) -> Option<Self> {
+        let bounds = glyph::ink_bounds(cell)?;
+        let (_, _, width, height) = bounds;
+        let letterform = glyph::crop_to(cell, bounds, 0);
+        let scale = SIZE as f32 / width.max(height) as f32;
+        let scaled = image::imageops::resize(
+            &letterform,
+            ((width as f32 * scale).round() as u32).clamp(1, SIZE),
+            ((height as f32 * scale).round() as u32).clamp(1, SIZE),
+            FilterType::Triangle,
+        );
+        let mut canvas = GrayImage::from_pixel(SIZE, SIZE, glyph::PAPER);
+        image::imageops::replace(
+            &mut canvas,
+            &scaled,
+            ((SIZE - scaled.width()) / 2) as i64,
+            ((SIZE - scaled.height()) / 2) as i64,
+        );
+
+        let mut bits = [0; (SIZE * SIZE / 64) as usize];
+        for (i, pixel) in canvas.pixels().enumerate() {
+            if pixel.0[0] < 128 {
+                bits[i / 64] |= 1 << (i % 64);
+            }
+        }
+        Some(Self {
+            bits,
+            size: (width, height),
+        })
+    }
+
+    /// How alike two letterforms are, from 0 to 1, as the intersection over
+    /// the union of their ink once they're scaled to the same size. Letterforms
+    /// of quite different sizes are 0.
+    pub fn similarity(&self, other: &Self) -> f32 {
+        let changed = |a: u32, b: u32| a.abs_diff(b) as f32 / a.max(b).max(1) as f32;
+        if changed(self.size.0, other.size.0) > MAX_SIZE_CHANGE
+            || changed(self.size.1, other.size.1) > MAX_SIZE_CHANGE
+        {
+            return 0.0;
+        }
+        let (mut both, mut either) = (0, 0);
+        for (a, b) in self.bits.iter().zip(&other.bits) {
+            both += (a & b).count_ones();
+            either += (a | b).count_ones();
+        }
+        if either == 0 {
+            1.0
+        } else {
+            both as f32 / either as f32
+        }
+    }
+}
+
+/// A letterform that looks like a copy of an earlier one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Duplicate {
+    /// The position of the earlier letterform.
+    pub original: usize,
+    /// The position of the copy, which is after the original.
+    pub copy: usize,
+    pub similarity: f32,
+}
+
+/// Find the letterforms that are at least `min_similarity` alike with an
+/// earlier one, given their fingerprints in order. Each copy is paired with
+/// the earlier letterform it's most alike.
+pub fn find(fingerprints: &[Option<Fingerprint>], min_similarity: f32) -> Vec<Duplicate> {
+    let mut duplicates = Vec::new();
+    for (copy, fingerprint) in fingerprints.iter().enumerate() {
+        let Some(fingerprint) = fingerprint else {
+            continue;
+        };
+        let best = fingerprints[..copy]
+            .iter()
+            .enumerate()
+            .filter_map(|(original, other)| {
+                Some((original, fingerprint.similarity(other.as_ref()?)))
+            })
+            .filter(|(_, similarity)| *similarity >= min_similarity)
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((original, similarity)) = best {
+            duplicates.push(Duplicate {
+                original,
+                copy,
+                similarity,
+            });
+        }
+    }
+    duplicates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+    use imageproc::{drawing::draw_filled_rect_mut, rect::Rect};
+
+    /// A cell with an L drawn at `(x, y)`, `height` pixels tall.
+    fn l_shape(x: i32, y: i32, height: u32) -> GrayImage {
+        let mut cell = GrayImage::from_pixel(100, 100, glyph::PAPER);
+        let stroke = height / 8;
+        draw_filled_rect_mut(&mut cell, Rect::at(x, y).of_size(stroke, height), Luma([0]));
+        let foot = Rect::at(x, y + (height - stroke) as i32).of_size(height * 2 / 3, stroke);
+        draw_filled_rect_mut(&mut cell, foot, Luma([0]));
+        cell
+    }
+
+    #[test]
+    fn finds_copies_wherever_they_sit_in_their_cells() {
+        let mut square = GrayImage::from_pixel(100, 100, glyph::PAPER);
+        draw_filled_rect_mut(&mut square, Rect::at(20, 20).of_size(60, 60), Luma([0]));
+        let fingerprints = vec![
+            Fingerprint::of(&l_shape(30, 20, 60)),
+            Fingerprint::of(&square),
+            None,
+            Fingerprint::of(&l_shape(40, 25, 60)),
+            // The same shape, but much smaller.
+            Fingerprint::of(&l_shape(40, 25, 30)),
+        ];
+        assert_eq!(
+            find(&fingerprints, 0.9),
+            vec![Duplicate {
+                original: 0,
+                copy: 3,
+                similarity: 1.0
+            }]
+        );
+    }
+}
@@ -0,0 +1,569 @@
+use anyhow::{bail, Context};
+use clap::{Args, ValueEnum};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+use crate::{
+    bitmap_font,
+    build_font::{self, TracedFont},
+    charset::{self, Charset},
+    metrics::Metrics,
+    truetype::{Glyph, Point},
+};
+
+/// The kinds of font source project that can be exported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// A Unified Font Object directory, as used by Glyphs, RoboFont, and
+    /// most other font editors.
+    Ufo,
+    /// A FontForge project file.
+    Sfd,
+    /// A bitmap font in the Glyph Bitmap Distribution Format, for X11 and
+    /// embedded displays. The letterforms are scaled to `--pixel-size`
+    /// rather than traced.
+    Bdf,
+    /// A bitmap font in AngelCode's BMFont text format, for game engines,
+    /// along with its page image. The letterforms are scaled to
+    /// `--pixel-size` rather than traced.
+    Fnt,
+}
+
+/// Arguments for the `export` command.
+#[derive(Args, Debug)]
+pub struct ExportArgs {
+    /// The directory containing letter images extracted by the `scan`
+    /// command.
+    #[arg(short, long)]
+    glyphs_dir: PathBuf,
+
+    /// Where to write the project. A UFO is a directory, and its name should
+    /// end in `.ufo`; the other formats are single files, and their names
+    /// should end in `.sfd`, `.bdf`, or `.fnt`. A BMFont's page image is
+    /// saved next to it, named like `font_0.png`.
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// The kind of project to write.
+    #[arg(short, long, value_enum)]
+    format: ExportFormat,
+
+    /// The characters written on the template, in grid order (left to right,
+    /// top to bottom), or the path of a text file containing them. This is
+    /// needed when the letter images are named by cell, like `letter-0.png`,
+    /// rather than by character. A space marks a cell with no character.
+    #[arg(short, long)]
+    charset: Option<String>,
+
+    /// The name of the font family.
+    #[arg(long, default_value = "My Handwriting")]
+    family_name: String,
+
+    /// How closely outlines follow the edges of each letter image, in pixels.
+    /// Larger values make smoother glyphs with fewer points, but lose detail.
+    #[arg(long, default_value_t = 1.0)]
+    simplify: f32,
+
+    /// The height of a line of text in a bitmap font, in pixels.
+    #[arg(long, default_value = "32")]
+    pixel_size: u32,
+
+    /// Space the glyphs like your handwriting, using the measurements
+    /// written by the `metrics` command. Kerning is only exported to UFOs,
+    /// and bitmap fonts are only spaced by the side bearings measured when
+    /// the letterforms were scanned.
+    #[arg(long, value_name = "FILE")]
+    metrics: Option<PathBuf>,
+}
+
+/// Export extracted letterforms as a font source project.
+pub fn export(args: ExportArgs) -> anyhow::Result<()> {
+    let ExportArgs {
+        glyphs_dir,
+        output,
+        format,
+        charset,
+        family_name,
+        simplify,
+        pixel_size,
+        metrics,
+    } = args;
+    if format != ExportFormat::Ufo && output.is_dir() {
+        bail!("output must be a file when exporting an SFD, BDF, or BMFont.");
+    }
+    if format == ExportFormat::Ufo && output.is_file() {
+        bail!("output must be a directory when exporting a UFO.");
+    }
+    let charset = match charset {
+        Some(charset) => Charset::from_arg(&charset)?,
+        None => Charset::default(),
+    };
+    if matches!(format, ExportFormat::Bdf | ExportFormat::Fnt) {
+        if metrics.is_some() {
+            warn!("bitmap fonts aren't spaced by measured handwriting; ignoring --metrics.");
+        }
+        return export_bitmap_font(
+            &glyphs_dir,
+            &output,
+            format,
+            &charset,
+            family_name,
+            pixel_size,
+        );
+    }
+    let metrics = metrics.as_deref().map(Metrics::load).transpose()?;
+    let font = build_font::trace_font(
+        &glyphs_dir,
+        &charset,
+        family_name,
+        simplify,
+        metrics.as_ref(),
+    )?;
+
+    match format {
+        ExportFormat::Ufo => write_ufo(&font, &output)?,
+        ExportFormat::Sfd => {
+            if !font.alternates.is_empty() || !font.ligatures.is_empty() {
+                warn!(
+                    "alternates and ligatures are only exported to UFOs; they're left out of the SFD."
+                );
+            }
+            if !font.kerning.is_empty() {
+                warn!("kerning is only exported to UFOs; it's left out of the SFD.");
+            }
+            std::fs::write(&output, sfd(&font))
+                .with_context(|| format!("writing {}", output.display()))?;
+        }
+        ExportFormat::Bdf | ExportFormat::Fnt => unreachable!("bitmap fonts are exported above"),
+    }
+    let alternates: usize = font.alternates.iter().map(|(_, glyphs)| glyphs.len()).sum();
+    info!(
+        "Exported {} glyphs to {}.",
+        font.glyphs.len() - 1 + alternates + font.ligatures.len(),
+        output.display()
+    );
+
+    Ok(())
+}
+
+/// Scale the letterforms to `pixel_size` and write them as a bitmap font.
+fn export_bitmap_font(
+    glyphs_dir: &Path,
+    output: &Path,
+    format: ExportFormat,
+    charset: &Charset,
+    family_name: String,
+    pixel_size: u32,
+) -> anyhow::Result<()> {
+    let font = bitmap_font::rasterize_font(glyphs_dir, charset, family_name, pixel_size)?;
+    if format == ExportFormat::Bdf {
+        std::fs::write(output, bitmap_font::bdf(&font))
+            .with_context(|| format!("writing {}", output.display()))?;
+    } else {
+        let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+        let page_file = format!("{stem}_0.png");
+        let (fnt, page) = bitmap_font::fnt(&font, &page_file);
+        std::fs::write(output, fnt).with_context(|| format!("writing {}", output.display()))?;
+        let page_path = output.with_file_name(&page_file);
+        page.save(&page_path)
+            .with_context(|| format!("saving {}", page_path.display()))?;
+    }
+    info!(
+        "Exported {} glyphs to {}.",
+        font.glyphs.len() - 1,
+        output.display()
+    );
+
+    Ok(())
+}
+
+/// The name of a character's glyph in a font project. Unlike the names of
+/// letter images, these must be plain ASCII, and can't start with a digit.
+pub fn glyph_name(c: char) -> String {
+    const DIGITS: [&str; 10] = [
+        "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+    ];
+    match c {
+        '0'..='9' => DIGITS[c as usize - '0' as usize].to_string(),
+        _ if c.is_ascii() => charset::glyph_name(c),
+        // Characters past U+FFFF, like most emoji, are named like `u1F600`.
+        _ if c as u32 > 0xFFFF => format!("u{:X}", c as u32),
+        _ => format!("uni{:04X}", c as u32),
+    }
+}
+
+/// The name of a ligature's glyph: the names of its characters joined by
+/// underscores, like `f_f_i`.
+pub fn ligature_name(text: &str) -> String {
+    let names: Vec<String> = text.chars().map(glyph_name).collect();
+    names.join("_")
+}
+
+/// Every glyph to export, with its name and character. The `.notdef` glyph
+/// comes first, as font editors expect, and alternates and ligatures come
+/// last, with no character and names like `A.alt1` and `t_h`.
+fn named_glyphs(font: &TracedFont) -> Vec<(String, Option<char>, Glyph)> {
+    let alternates = font.alternates.iter().flat_map(|(c, glyphs)| {
+        glyphs.iter().enumerate().map(|(i, glyph)| {
+            let name = format!("{}.alt{}", glyph_name(*c), i + 1);
+            (name, None, glyph.clone())
+        })
+    });
+    std::iter::once((".notdef".to_string(), None, build_font::notdef_glyph()))
+        .chain(
+            font.glyphs
+                .iter()
+                .map(|(c, glyph)| (glyph_name(*c), Some(*c), glyph.clone())),
+        )
+        .chain(alternates)
+        .chain(
+            font.ligatures
+                .iter()
+                .map(|(text, glyph)| (ligature_name(text), None, glyph.clone())),
+        )
+        .collect()
+}
+
+/// OpenType feature code that joins ligatures through the `liga` feature,
+/// and offers each character's alternates through the `rand` and `salt`
+/// features, or `None` if there are neither.
+fn features(font: &TracedFont) -> Option<String> {
+    if font.alternates.is_empty() && font.ligatures.is_empty() {
+        return None;
+    }
+    let mut fea = String::from("languagesystem DFLT dflt;\nlanguagesystem latn dflt;\n");
+    if !font.ligatures.is_empty() {
+        // Longer ligatures come first, so that `ffi` wins over `ff`.
+        // Ligatures of characters the font lacks would be rejected by font
+        // editors, so they're left out.
+        let mut ligatures: Vec<&String> = font
+            .ligatures
+            .iter()
+            .map(|(text, _)| text)
+            .filter(|text| {
+                text.chars()
+                    .all(|c| font.glyphs.iter().any(|(glyph, _)| *glyph == c))
+            })
+            .collect();
+        ligatures.sort_by_key(|text| std::cmp::Reverse(text.chars().count()));
+        fea.push_str("\nfeature liga {\n");
+        for text in ligatures {
+            let components: Vec<String> = text.chars().map(glyph_name).collect();
+            fea.push_str(&format!(
+                "    sub {} by {};\n",
+                components.join(" "),
+                ligature_name(text)
+            ));
+        }
+        fea.push_str("} liga;\n");
+    }
+    if !font.alternates.is_empty() {
+        fea.push_str("\nlookup alternates {\n");
+        for (c, glyphs) in &font.alternates {
+            let name = glyph_name(*c);
+            let alternates: Vec<String> = (1..=glyphs.len())
+                .map(|n| format!("{name}.alt{n}"))
+                .collect();
+            fea.push_str(&format!(
+                "    sub {name} from [{}];\n",
+                alternates.join(" ")
+            ));
+        }
+        fea.push_str(
+            "} alternates;\n\n\
+             feature rand {\n    lookup alternates;\n} rand;\n\n\
+             feature salt {\n    lookup alternates;\n} salt;\n",
+        );
+    }
+
+    Some(fea)
+}
+
+fn write_ufo(font: &TracedFont, dir: &std::path::Path) -> anyhow::Result<()> {
+    let glyphs_dir = dir.join("glyphs");
+    std::fs::create_dir_all(&glyphs_dir).context("creating output dir")?;
+    let write = |name: &str, contents: String| {
+        std::fs::write(dir.join(name), contents).with_context(|| format!("writing {name}"))
+    };
+    let info = &font.info;
+
+    write(
+        "metainfo.plist",
+        plist(
+            "<key>creator</key>\n\
+             <string>handwriting-scan-tool</string>\n\
+             <key>formatVersion</key>\n\
+             <integer>3</integer>\n",
+        ),
+    )?;
+    write(
+        "fontinfo.plist",
+        plist(&format!(
+            "<key>familyName</key>\n\
+             <string>{}</string>\n\
+             <key>styleName</key>\n\
+             <string>Regular</string>\n\
+             <key>unitsPerEm</key>\n\
+             <integer>{}</integer>\n\
+             <key>ascender</key>\n\
+             <integer>{}</integer>\n\
+             <key>descender</key>\n\
+             <integer>{}</integer>\n\
+             <key>xHeight</key>\n\
+             <integer>{}</integer>\n\
+             <key>capHeight</key>\n\
+             <integer>{}</integer>\n",
+            escape_xml(&info.family_name),
+            info.units_per_em,
+            info.ascender,
+            info.descender,
+            info.x_height,
+            info.cap_height,
+        )),
+    )?;
+    write(
+        "layercontents.plist",
+        plist_array(
+            "<array>\n\
+             <string>public.default</string>\n\
+             <string>glyphs</string>\n\
+             </array>\n",
+        ),
+    )?;
+
+    let mut contents = String::new();
+    let mut glyph_order = String::new();
+    for (name, c, glyph) in named_glyphs(font) {
+        let file_name = glif_file_name(&name);
+        writeln!(contents, "<key>{name}</key>\n<string>{file_name}</string>")?;
+        writeln!(glyph_order, "<string>{name}</string>")?;
+        std::fs::write(glyphs_dir.join(&file_name), glif(&name, c, &glyph))
+            .with_context(|| format!("writing {file_name}"))?;
+    }
+    std::fs::write(glyphs_dir.join("contents.plist"), plist(&contents))
+        .context("writing contents.plist")?;
+    if let Some(fea) = features(font) {
+        write("features.fea", fea)?;
+    }
+    if !font.kerning.is_empty() {
+        write("kerning.plist", plist(&kerning(font)))?;
+    }
+    write(
+        "lib.plist",
+        plist(&format!(
+            "<key>public.glyphOrder</key>\n<array>\n{glyph_order}</array>\n"
+        )),
+    )?;
+
+    Ok(())
+}
+
+/// The kerning of a UFO, as the contents of a dictionary: each pair's first
+/// glyph, then its second glyph and the amount.
+fn kerning(font: &TracedFont) -> String {
+    let mut firsts: BTreeMap<char, Vec<(char, i16)>> = BTreeMap::new();
+    for &(left, right, amount) in &font.kerning {
+        firsts.entry(left).or_default().push((right, amount));
+    }
+    let mut dict = String::new();
+    for (left, pairs) in firsts {
+        dict.push_str(&format!("<key>{}</key>\n<dict>\n", glyph_name(left)));
+        for (right, amount) in pairs {
+            dict.push_str(&format!(
+                "<key>{}</key>\n<integer>{amount}</integer>\n",
+                glyph_name(right)
+            ));
+        }
+        dict.push_str("</dict>\n");
+    }
+
+    dict
+}
+
+fn plist(dict: &str) -> String {
+    plist_array(&format!("<dict>\n{dict}</dict>\n"))
+}
+
+fn plist_array(value: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n\
+         {value}\
+         </plist>\n"
+    )
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The name of a glyph's file in a UFO. File systems may ignore case, so
+/// every capital letter is followed by an underscore, as the UFO
+/// specification recommends.
+fn glif_file_name(name: &str) -> String {
+    let mut file_name = String::new();
+    for (i, c) in name.chars().enumerate() {
+        match c {
+            '.' if i == 0 => file_name.push('_'),
+            c if c.is_ascii_uppercase() => {
+                file_name.push(c);
+                file_name.push('_');
+            }
+            c => file_name.push(c),
+        }
+    }
+
+    format!("{file_name}.glif")
+}
+
+fn glif(name: &str, c: Option<char>, glyph: &Glyph) -> String {
+    let mut glif = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <glyph name=\"{name}\" format=\"2\">\n  \
+         <advance width=\"{}\"/>\n",
+        glyph.advance_width
+    );
+    if let Some(c) = c {
+        glif.push_str(&format!("  <unicode hex=\"{:04X}\"/>\n", c as u32));
+    }
+    glif.push_str("  <outline>\n");
+    for contour in &glyph.contours {
+        glif.push_str("    <contour>\n");
+        for (i, point) in contour.iter().enumerate() {
+            // An on-curve point ends a curve if the point before it is a
+            // control point, and a straight line otherwise.
+            let previous = contour[(i + contour.len() - 1) % contour.len()];
+            let kind = match (point.on_curve, previous.on_curve) {
+                (false, _) => "",
+                (true, false) => " type=\"qcurve\"",
+                (true, true) => " type=\"line\"",
+            };
+            glif.push_str(&format!(
+                "      <point x=\"{}\" y=\"{}\"{kind}/>\n",
+                point.x, point.y
+            ));
+        }
+        glif.push_str("    </contour>\n");
+    }
+    glif.push_str("  </outline>\n</glyph>\n");
+
+    glif
+}
+
+fn sfd(font: &TracedFont) -> String {
+    let info = &font.info;
+    let font_name: String = info
+        .family_name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    // `.notdef` has no character, and FontForge adds its own. Alternates and
+    // ligatures have no character either, and are only exported to UFOs.
+    let glyphs: Vec<_> = named_glyphs(font)
+        .into_iter()
+        .filter_map(|(name, c, glyph)| Some((name, c?, glyph)))
+        .collect();
+
+    let mut sfd = format!(
+        "SplineFontDB: 3.0\n\
+         FontName: {font_name}-Regular\n\
+         FullName: {family} Regular\n\
+         FamilyName: {family}\n\
+         Weight: Regular\n\
+         Version: 1.000\n\
+         ItalicAngle: 0\n\
+         UnderlinePosition: -100\n\
+         UnderlineWidth: 50\n\
+         Ascent: {}\n\
+         Descent: {}\n\
+         LayerCount: 2\n\
+         Layer: 0 0 \"Back\" 1\n\
+         Layer: 1 0 \"Fore\" 0\n\
+         OS2Version: 0\n\
+         OS2_WeightWidthSlopeOnly: 0\n\
+         OS2_UseTypoMetrics: 1\n\
+         CapHeight: {}\n\
+         XHeight: {}\n\
+         Encoding: UnicodeFull\n\
+         UnicodeInterp: none\n\
+         NameList: AGL For New Fonts\n\
+         DisplaySize: -48\n\
+         AntiAlias: 1\n\
+         FitToEm: 0\n\
+         BeginChars: 1114112 {}\n",
+        info.ascender,
+        -info.descender,
+        info.cap_height,
+        info.x_height,
+        glyphs.len(),
+        family = info.family_name,
+    );
+    for (index, (name, c, glyph)) in glyphs.iter().enumerate() {
+        let codepoint = *c as u32;
+        sfd.push_str(&format!(
+            "\nStartChar: {name}\n\
+             Encoding: {codepoint} {codepoint} {index}\n\
+             Width: {}\n\
+             VWidth: 0\n\
+             Flags: W\n\
+             LayerCount: 2\n\
+             Fore\n\
+             SplineSet\n",
+            glyph.advance_width
+        ));
+        for contour in &glyph.contours {
+            sfd.push_str(&sfd_contour(contour));
+        }
+        sfd.push_str("EndSplineSet\nEndChar\n");
+    }
+    sfd.push_str("EndChars\nEndSplineFont\n");
+
+    sfd
+}
+
+/// Write a contour as FontForge spline commands. The layers are cubic, so
+/// each quadratic curve is raised to the cubic curve with the same shape.
+fn sfd_contour(contour: &[Point]) -> String {
+    let Some(start) = contour.iter().rposition(|point| point.on_curve) else {
+        return String::new();
+    };
+    let as_f32 = |point: &Point| (point.x as f32, point.y as f32);
+    let mut current = as_f32(&contour[start]);
+    let mut commands = format!("{} {} m 1\n", current.0, current.1);
+    let mut control = None;
+    for i in 1..=contour.len() {
+        let point = &contour[(start + i) % contour.len()];
+        if !point.on_curve {
+            control = Some(as_f32(point));
+            continue;
+        }
+        let end = as_f32(point);
+        match control.take() {
+            Some(control) => {
+                let toward = |from: (f32, f32)| {
+                    (
+                        from.0 + (control.0 - from.0) * 2.0 / 3.0,
+                        from.1 + (control.1 - from.1) * 2.0 / 3.0,
+                    )
+                };
+                let (first, second) = (toward(current), toward(end));
+                commands.push_str(&format!(
+                    " {} {} {} {} {} {} c 0\n",
+                    first.0, first.1, second.0, second.1, end.0, end.1
+                ));
+            }
+            None => commands.push_str(&format!(" {} {} l 1\n", end.0, end.1)),
+        }
+        current = end;
+    }
+
+    commands
+}
@@ -0,0 +1,526 @@
+use image::{GrayImage, ImageBuffer, Luma};
+use std::collections::{HashMap, HashSet};
+
+/// The color that glyph images are padded with.
+pub const PAPER: Luma<u8> = Luma([255]);
+
+/// Pad a glyph image up to the next power-of-two width and height, keeping the
+/// glyph centered. Returns the padded image along with the `(left, top)`
+/// padding that was added, so that texture coordinates can be recovered.
+pub fn pad_to_power_of_two(image: &GrayImage) -> (GrayImage, (u32, u32)) {
+    let (width, height) = image.dimensions();
+    let padded_width = width.max(1).next_power_of_two();
+    let padded_height = height.max(1).next_power_of_two();
+    let left = (padded_width - width) / 2;
+    let top = (padded_height - height) / 2;
+
+    let mut padded = GrayImage::from_pixel(padded_width, padded_height, PAPER);
+    image::imageops::replace(&mut padded, image, left as i64, top as i64);
+
+    (padded, (left, top))
+}
+
+/// The fraction of a cell's edges that's left out when measuring its ink, so
+/// that fragments of the grid lines around it aren't counted.
+const INK_INSET: f32 = 0.1;
+
+/// Measure how much of a cell is covered by ink, as a fraction between 0 and
+/// 1. A thin border around the cell is ignored.
+pub fn ink_coverage<I>(cell: &I) -> f32
+where
+    I: image::GenericImageView<Pixel = Luma<u8>>,
+{
+    let (width, height) = cell.dimensions();
+    let inset_x = (width as f32 * INK_INSET) as u32;
+    let inset_y = (height as f32 * INK_INSET) as u32;
+    let inner_width = width.saturating_sub(inset_x * 2);
+    let inner_height = height.saturating_sub(inset_y * 2);
+    if inner_width == 0 || inner_height == 0 {
+        return 0.0;
+    }
+
+    let mut ink = 0u32;
+    for y in inset_y..inset_y + inner_height {
+        for x in inset_x..inset_x + inner_width {
+            if cell.get_pixel(x, y).0[0] < 128 {
+                ink += 1;
+            }
+        }
+    }
+
+    ink as f32 / (inner_width * inner_height) as f32
+}
+
+/// Shapes of ink smaller than this fraction of a cell's area are ignored when
+/// finding its letterform.
+const MIN_STROKE_AREA: f32 = 0.0003;
+
+/// Find the smallest rectangle containing a cell's letterform, as
+/// `(x, y, width, height)`.
+///
+/// Only strokes that reach into the middle of the cell count as part of the
+/// letterform, so fragments of grid lines along the cell's edges are ignored,
+/// but a descender that reaches the edge is kept whole. Specks too small to
+/// be strokes are ignored too. Returns `None` if the cell is empty.
+pub fn ink_bounds(cell: &GrayImage) -> Option<(u32, u32, u32, u32)> {
+    let (width, height) = cell.dimensions();
+    let Strokes {
+        labels,
+        sizes,
+        in_middle,
+    } = strokes(cell);
+    // Specks of dust and paper grain are too small to be part of a letterform.
+    let min_size = ((width * height) as f32 * MIN_STROKE_AREA) as u32;
+    let in_letterform: HashSet<u32> = in_middle
+        .into_iter()
+        .filter(|label| sizes[label] >= min_size)
+        .collect();
+
+    let (mut min_x, mut min_y) = (u32::MAX, u32::MAX);
+    let (mut max_x, mut max_y) = (0, 0);
+    for (x, y, label) in labels.enumerate_pixels() {
+        if in_letterform.contains(&label.0[0]) {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+
+    (min_x != u32::MAX).then(|| (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1))
+}
+
+/// The separate strokes of ink in a cell.
+struct Strokes {
+    /// The stroke each pixel belongs to, with 0 for paper.
+    labels: ImageBuffer<Luma<u32>, Vec<u32>>,
+    /// The number of pixels in each stroke.
+    sizes: HashMap<u32, u32>,
+    /// The strokes that reach into the middle of the cell.
+    in_middle: HashSet<u32>,
+}
+
+fn strokes(cell: &GrayImage) -> Strokes {
+    use imageproc::region_labelling::{connected_components, Connectivity};
+
+    let (width, height) = cell.dimensions();
+    let ink = GrayImage::from_fn(width, height, |x, y| {
+        Luma([if cell.get_pixel(x, y).0[0] < 128 {
+            255
+        } else {
+            0
+        }])
+    });
+    let labels = connected_components(&ink, Connectivity::Eight, Luma([0]));
+
+    let mut sizes = HashMap::new();
+    for label in labels.pixels() {
+        *sizes.entry(label.0[0]).or_insert(0u32) += 1;
+    }
+
+    let inset_x = (width as f32 * INK_INSET) as u32;
+    let inset_y = (height as f32 * INK_INSET) as u32;
+    let mut in_middle = HashSet::new();
+    for y in inset_y..height.saturating_sub(inset_y) {
+        for x in inset_x..width.saturating_sub(inset_x) {
+            let label = labels.get_pixel(x, y).0[0];
+            if label != 0 {
+                in_middle.insert(label);
+            }
+        }
+    }
+
+    Strokes {
+        labels,
+        sizes,
+        in_middle,
+    }
+}
+
+/// A letterform that comes within this fraction of a cell's width or height
+/// of its edge is taken to reach it, since the grid line along the edge, and
+/// any stroke running into it, has been erased.
+const EDGE_TOLERANCE: f32 = 0.02;
+
+/// Whether a letterform's `bounds`, as found by [`ink_bounds`], reach the
+/// edge of a cell of size `dimensions`. A letterform that does may have been
+/// cut off by the cell's border, or written across the grid line.
+pub fn touches_edge(dimensions: (u32, u32), bounds: (u32, u32, u32, u32)) -> bool {
+    edges_reached(dimensions, bounds).contains(&true)
+}
+
+/// Which edges of a cell of size `dimensions` a letterform's `bounds` reach,
+/// as `[left, top, right, bottom]`.
+fn edges_reached(dimensions: (u32, u32), bounds: (u32, u32, u32, u32)) -> [bool; 4] {
+    let (width, height) = dimensions;
+    let (x, y, ink_width, ink_height) = bounds;
+    let tolerance_x = (width as f32 * EDGE_TOLERANCE).ceil() as u32;
+    let tolerance_y = (height as f32 * EDGE_TOLERANCE).ceil() as u32;
+    [
+        x <= tolerance_x,
+        y <= tolerance_y,
+        x + ink_width + tolerance_x >= width,
+        y + ink_height + tolerance_y >= height,
+    ]
+}
+
+/// A row or column along a cell's edge is taken to be part of a grid line if
+/// it has an unbroken run of ink at least this fraction of its length.
+const GRID_LINE_RUN: f32 = 0.5;
+
+/// Erase the fragments of grid lines left along a cell's edges, so that they
+/// don't end up in the letter image. Only rows and columns within the cell's
+/// border that are mostly ink are erased, and where a stroke crosses a line,
+/// the stroke is left whole. Then any ink that doesn't reach into the middle of
+/// the cell is erased, like [`ink_bounds`] ignores it.
+pub fn remove_grid_lines(cell: &mut GrayImage) {
+    let (width, height) = cell.dimensions();
+    let rows = line_bands(
+        near_edges(height, (height as f32 * INK_INSET) as u32),
+        |y| is_row_line(cell, y),
+    );
+    let columns = line_bands(near_edges(width, (width as f32 * INK_INSET) as u32), |x| {
+        is_column_line(cell, x)
+    });
+    erase_lines(cell, &rows, &columns);
+
+    // Whatever's left of the lines, such as the gaps where a line was broken
+    // up, doesn't reach into the middle of the cell like a letterform does.
+    let Strokes {
+        labels, in_middle, ..
+    } = strokes(cell);
+    for (x, y, label) in labels.enumerate_pixels() {
+        if label.0[0] != 0 && !in_middle.contains(&label.0[0]) {
+            cell.put_pixel(x, y, PAPER);
+        }
+    }
+}
+
+fn is_ink(cell: &GrayImage, x: u32, y: u32) -> bool {
+    cell.get_pixel(x, y).0[0] < 128
+}
+
+/// Whether row `y` of a cell looks like part of a grid line.
+fn is_row_line(cell: &GrayImage, y: u32) -> bool {
+    let width = cell.width();
+    longest_run((0..width).map(|x| is_ink(cell, x, y))) as f32 >= width as f32 * GRID_LINE_RUN
+}
+
+/// Whether column `x` of a cell looks like part of a grid line.
+fn is_column_line(cell: &GrayImage, x: u32) -> bool {
+    let height = cell.height();
+    longest_run((0..height).map(|y| is_ink(cell, x, y))) as f32 >= height as f32 * GRID_LINE_RUN
+}
+
+/// Erase the bands of `rows` and `columns` from a cell, except where a
+/// stroke crosses them.
+fn erase_lines(cell: &mut GrayImage, rows: &[(u32, u32)], columns: &[(u32, u32)]) {
+    let (width, height) = cell.dimensions();
+    // Find every pixel to erase before erasing any, so that erasing one line
+    // doesn't break up a stroke crossing another.
+    let mut erase = Vec::new();
+    for &(top, bottom) in rows {
+        for x in 0..width {
+            let crossed = top > 0
+                && bottom + 1 < height
+                && is_ink(cell, x, top - 1)
+                && is_ink(cell, x, bottom + 1);
+            if !crossed {
+                erase.extend((top..=bottom).map(|y| (x, y)));
+            }
+        }
+    }
+    for &(left, right) in columns {
+        for y in 0..height {
+            let crossed = left > 0
+                && right + 1 < width
+                && is_ink(cell, left - 1, y)
+                && is_ink(cell, right + 1, y);
+            if !crossed {
+                erase.extend((left..=right).map(|x| (x, y)));
+            }
+        }
+    }
+    for (x, y) in erase {
+        cell.put_pixel(x, y, PAPER);
+    }
+}
+
+/// The rows (or columns) within `border` of either end of `length`, in
+/// order.
+fn near_edges(length: u32, border: u32) -> impl Iterator<Item = u32> {
+    (0..border.min(length)).chain(length.saturating_sub(border).max(border)..length)
+}
+
+/// Group the rows (or columns) of `indices`, in order, for which `is_line` is
+/// true into bands of neighbors, as `(first, last)`.
+fn line_bands(
+    indices: impl Iterator<Item = u32>,
+    is_line: impl Fn(u32) -> bool,
+) -> Vec<(u32, u32)> {
+    let mut bands: Vec<(u32, u32)> = Vec::new();
+    for i in indices.filter(|i| is_line(*i)) {
+        match bands.last_mut() {
+            Some((_, last)) if *last + 1 == i => *last = i,
+            _ => bands.push((i, i)),
+        }
+    }
+    bands
+}
+
+/// How far past its cell's edge a letterform that runs into it is followed
+/// with `--expand-clipped`, as a fraction of the cell's width or height.
+pub const EXPANSION: f32 = 0.25;
+
+/// The area to cut a letterform from, as `(x, y, width, height)`: its
+/// `cell`, grown by [`EXPANSION`] past each edge that the letterform's
+/// `bounds` reach, as far as the edges of a page of size `page`.
+pub fn expand_cell(
+    page: (u32, u32),
+    cell: (u32, u32, u32, u32),
+    bounds: (u32, u32, u32, u32),
+) -> (u32, u32, u32, u32) {
+    let (x, y, width, height) = cell;
+    let [left, top, right, bottom] = edges_reached((width, height), bounds);
+    let grow_x = (width as f32 * EXPANSION).round() as u32;
+    let grow_y = (height as f32 * EXPANSION).round() as u32;
+    let left = if left { x.saturating_sub(grow_x) } else { x };
+    let top = if top { y.saturating_sub(grow_y) } else { y };
+    let right = if right {
+        (x + width + grow_x).min(page.0)
+    } else {
+        x + width
+    };
+    let bottom = if bottom {
+        (y + height + grow_y).min(page.1)
+    } else {
+        y + height
+    };
+    (left, top, right - left, bottom - top)
+}
+
+/// Follow a letterform past the edges of its cell. `letterform` is the
+/// cleaned-up cell at `cell` on the thresholded `page`, and `area` is the
+/// larger area around it, as found by [`expand_cell`]. Returns the letterform
+/// cut from `area`: the cleaned-up cell, along with the ink around it that's
+/// joined to it once the grid lines are erased. Letterforms in the
+/// neighboring cells are left out.
+pub fn follow_past_edges(
+    page: &GrayImage,
+    cell: (u32, u32, u32, u32),
+    letterform: &GrayImage,
+    area: (u32, u32, u32, u32),
+) -> GrayImage {
+    let (x, y, width, height) = area;
+    let mut expanded = image::imageops::crop_imm(page, x, y, width, height).to_image();
+    let (left, top) = (cell.0 - x, cell.1 - y);
+    let (right, bottom) = (left + cell.2, top + cell.3);
+
+    // Erase the grid lines around the cell, which are still whole on the
+    // page, so strokes crossing them are kept.
+    let near = |edge: u32, border: u32, length: u32| {
+        edge.saturating_sub(border)..(edge + border).min(length)
+    };
+    let border_y = (cell.3 as f32 * INK_INSET) as u32;
+    let border_x = (cell.2 as f32 * INK_INSET) as u32;
+    let rows = line_bands(
+        near(top, border_y, height).chain(near(bottom, border_y, height)),
+        |y| is_row_line(&expanded, y),
+    );
+    let columns = line_bands(
+        near(left, border_x, width).chain(near(right, border_x, width)),
+        |x| is_column_line(&expanded, x),
+    );
+    erase_lines(&mut expanded, &rows, &columns);
+
+    // Keep the letterform, along with the ink that's joined to it.
+    let in_letterform = |x: u32, y: u32| {
+        (left..right).contains(&x)
+            && (top..bottom).contains(&y)
+            && is_ink(letterform, x - left, y - top)
+    };
+    let Strokes { labels, .. } = strokes(&expanded);
+    let joined: HashSet<u32> = labels
+        .enumerate_pixels()
+        .filter(|(x, y, label)| label.0[0] != 0 && in_letterform(*x, *y))
+        .map(|(_, _, label)| label.0[0])
+        .collect();
+    GrayImage::from_fn(width, height, |x, y| {
+        if in_letterform(x, y) {
+            *letterform.get_pixel(x - left, y - top)
+        } else if joined.contains(&labels.get_pixel(x, y).0[0]) {
+            *expanded.get_pixel(x, y)
+        } else {
+            PAPER
+        }
+    })
+}
+
+/// The length of the longest run of `true`s.
+fn longest_run(values: impl Iterator<Item = bool>) -> usize {
+    let (mut longest, mut run) = (0, 0);
+    for value in values {
+        run = if value { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    longest
+}
+
+/// Replace the ink of a cleaned-up cell with the same pixels of its
+/// `grayscale` version, so that strokes keep their smooth edges. Only pixels
+/// on or right next to the ink are taken, so whatever was cleaned up stays
+/// gone.
+pub fn restore_grayscale<I>(cell: &GrayImage, grayscale: &I) -> GrayImage
+where
+    I: image::GenericImageView<Pixel = Luma<u8>>,
+{
+    let (width, height) = cell.dimensions();
+    let is_ink = |x: u32, y: u32| cell.get_pixel(x, y).0[0] < 128;
+    GrayImage::from_fn(width, height, |x, y| {
+        let near_ink = (y.saturating_sub(1)..(y + 2).min(height))
+            .any(|y| (x.saturating_sub(1)..(x + 2).min(width)).any(|x| is_ink(x, y)));
+        if near_ink {
+            grayscale.get_pixel(x, y)
+        } else {
+            PAPER
+        }
+    })
+}
+
+/// Resize a cell by `scale`. Unless `keep_gray` is set, the result is
+/// thresholded again, so that it stays black and white.
+pub fn scale(cell: &GrayImage, scale: f32, keep_gray: bool) -> GrayImage {
+    let (width, height) = cell.dimensions();
+    let mut scaled = image::imageops::resize(
+        cell,
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+        image::imageops::FilterType::CatmullRom,
+    );
+    if !keep_gray {
+        for pixel in scaled.pixels_mut() {
+            *pixel = if pixel.0[0] < 128 { Luma([0]) } else { PAPER };
+        }
+    }
+    scaled
+}
+
+/// Crop a cell to its letterform's `bounds`, as found by [`ink_bounds`],
+/// leaving `padding` pixels of paper on every side.
+pub fn crop_to(cell: &GrayImage, bounds: (u32, u32, u32, u32), padding: u32) -> GrayImage {
+    let (x, y, width, height) = bounds;
+    let mut cropped = GrayImage::from_pixel(width + padding * 2, height + padding * 2, PAPER);
+    let letterform = image::imageops::crop_imm(cell, x, y, width, height).to_image();
+    // Only the letterform is copied, so that nothing else in the cell shows up
+    // in the padding.
+    image::imageops::replace(&mut cropped, &letterform, padding as i64, padding as i64);
+
+    cropped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pads_to_powers_of_two() {
+        let image = GrayImage::from_pixel(5, 9, Luma([0]));
+        let (padded, (left, top)) = pad_to_power_of_two(&image);
+        assert_eq!(padded.dimensions(), (8, 16));
+        assert_eq!((left, top), (1, 3));
+        assert_eq!(padded.get_pixel(0, 0), &PAPER);
+        assert_eq!(padded.get_pixel(1, 3).0[0], 0);
+    }
+
+    #[test]
+    fn crops_to_the_letterform_and_ignores_grid_lines() {
+        let mut cell = GrayImage::from_pixel(100, 100, PAPER);
+        // A grid line along the top edge, and a letterform in the middle.
+        for x in 0..100 {
+            cell.put_pixel(x, 0, Luma([0]));
+        }
+        for y in 40..60 {
+            for x in 45..55 {
+                cell.put_pixel(x, y, Luma([0]));
+            }
+        }
+
+        assert_eq!(ink_bounds(&cell), Some((45, 40, 10, 20)));
+        assert!(!touches_edge((100, 100), (45, 40, 10, 20)));
+        assert!(touches_edge((100, 100), (45, 40, 10, 60)));
+        let cropped = crop_to(&cell, (45, 40, 10, 20), 5);
+        assert_eq!(cropped.dimensions(), (20, 30));
+        assert!((ink_coverage(&cell) - 200.0 / 6400.0).abs() < 1e-6);
+        assert_eq!(ink_bounds(&GrayImage::from_pixel(10, 10, PAPER)), None);
+    }
+
+    #[test]
+    fn restores_grayscale_edges_around_ink() {
+        let mut cell = GrayImage::from_pixel(10, 1, PAPER);
+        cell.put_pixel(4, 0, Luma([0]));
+        let grayscale = GrayImage::from_fn(10, 1, |x, _| Luma([[90, 180][x as usize % 2]]));
+        let restored = restore_grayscale(&cell, &grayscale);
+        let values: Vec<u8> = restored.pixels().map(|pixel| pixel.0[0]).collect();
+        assert_eq!(values, [255, 255, 255, 180, 90, 180, 255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn removes_grid_lines_but_not_strokes_crossing_them() {
+        let mut cell = GrayImage::from_pixel(100, 100, PAPER);
+        // Two-pixel grid lines along the top and left edges, and a
+        // descender that crosses a grid line near the bottom.
+        for i in 0..100 {
+            for j in 0..2 {
+                cell.put_pixel(i, j, Luma([0]));
+                cell.put_pixel(j, i, Luma([0]));
+                cell.put_pixel(i, 95 + j, Luma([0]));
+            }
+        }
+        for y in 40..100 {
+            cell.put_pixel(50, y, Luma([0]));
+        }
+
+        remove_grid_lines(&mut cell);
+        assert_eq!(cell.get_pixel(30, 0), &PAPER);
+        assert_eq!(cell.get_pixel(0, 30), &PAPER);
+        assert_eq!(cell.get_pixel(30, 95), &PAPER);
+        assert_eq!(cell.get_pixel(50, 95).0[0], 0);
+        assert_eq!(cell.get_pixel(50, 60).0[0], 0);
+    }
+
+    #[test]
+    fn follows_letterforms_past_their_cells() {
+        // Two cells, one above the other, split by a grid line. A descender
+        // in the top cell crosses into the bottom one, where there's also a
+        // letterform of the bottom cell's own.
+        let mut page = GrayImage::from_pixel(100, 200, PAPER);
+        for x in 0..100 {
+            for y in 98..102 {
+                page.put_pixel(x, y, Luma([0]));
+            }
+        }
+        for y in 40..120 {
+            page.put_pixel(50, y, Luma([0]));
+        }
+        for y in 110..118 {
+            for x in 10..18 {
+                page.put_pixel(x, y, Luma([0]));
+            }
+        }
+
+        let cell = (0, 0, 100, 100);
+        let mut letterform = image::imageops::crop_imm(&page, 0, 0, 100, 100).to_image();
+        // Erasing the grid line leaves the descender short of the cell's edge.
+        remove_grid_lines(&mut letterform);
+        let bounds = ink_bounds(&letterform).unwrap();
+        assert_eq!(bounds, (50, 40, 1, 58));
+        assert!(touches_edge((100, 100), bounds));
+        let area = expand_cell(page.dimensions(), cell, bounds);
+        assert_eq!(area, (0, 0, 100, 125));
+
+        let expanded = follow_past_edges(&page, cell, &letterform, area);
+        assert_eq!(ink_bounds(&expanded), Some((50, 40, 1, 80)));
+        assert_eq!(expanded.get_pixel(30, 100), &PAPER);
+        assert_eq!(expanded.get_pixel(12, 112), &PAPER);
+    }
+}
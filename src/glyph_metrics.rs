@@ -0,0 +1,159 @@
+use image::GrayImage;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::manifest;
+
+/// Pixels darker than this are ink.
+const INK_THRESHOLD: u8 = 128;
+/// The space left on each side of a letterform whose side is straight up
+/// and down, like the stems of `n`, as a fraction of the x-height.
+pub const SIDE_SPACE: f32 = 0.12;
+/// How much of the paper between the edge of a letterform's rectangle and
+/// its ink counts toward its side's space. Round letters like `o` and
+/// slanted ones like `v` already have paper beside them, so they need less.
+const OPEN_SPACE_WEIGHT: f32 = 0.5;
+/// The least space left on each side of a letterform, as a fraction of the
+/// x-height, so that neighboring letters never touch.
+const MIN_SIDE_SPACE: f32 = 0.02;
+
+/// How a letterform is spaced and where it sits, measured from its ink and
+/// the template's guide lines when it was scanned. Every distance is a
+/// fraction of the x-height, like [`crate::metrics::Metrics`], so it holds
+/// however the letter image was scaled.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GlyphMetrics {
+    /// How far the pen moves on after the letterform: its width and both of
+    /// its side bearings.
+    pub advance_width: f32,
+    /// The space left before the letterform's ink.
+    pub left_side_bearing: f32,
+    /// The space left after the letterform's ink.
+    pub right_side_bearing: f32,
+    /// How far the ink rises above the baseline.
+    pub ascent: f32,
+    /// How far the ink drops below the baseline, or a negative number if it
+    /// doesn't reach it, like an apostrophe.
+    pub descent: f32,
+}
+
+impl GlyphMetrics {
+    /// How many pixels an x-height is in a letter image whose letterform is
+    /// `ink_height` pixels tall.
+    pub fn x_height_in(&self, ink_height: f32) -> f32 {
+        ink_height / (self.ascent + self.descent).max(f32::EPSILON)
+    }
+}
+
+/// Measure the letterform whose ink is within `bounds`, `(x, y, width,
+/// height)`, of `image`, given the rows of the baseline and of the x-height
+/// line in it.
+///
+/// The side bearings are [`SIDE_SPACE`], less some of the paper that's
+/// already between the ink and the edge of its rectangle, from the x-height
+/// line down to the baseline, where most letters meet their neighbors.
+pub fn measure(
+    image: &GrayImage,
+    bounds: (u32, u32, u32, u32),
+    baseline: f32,
+    x_height: f32,
+) -> GlyphMetrics {
+    let (x, y, width, height) = bounds;
+    let size = (baseline - x_height).max(1.0);
+    let (top, bottom) = (y as f32, (y + height) as f32);
+
+    // Letterforms that are nowhere between the lines, like an apostrophe,
+    // are measured all the way down.
+    let band_top = (x_height.max(top) as u32).max(y);
+    let band_bottom = (baseline.min(bottom).ceil() as u32).min(y + height);
+    let rows = if band_top < band_bottom {
+        band_top..band_bottom
+    } else {
+        y..y + height
+    };
+    let is_ink = |x: u32, y: u32| image.get_pixel(x, y).0[0] < INK_THRESHOLD;
+    let (mut left_gap, mut right_gap) = (0.0, 0.0);
+    for row in rows.clone() {
+        let first = (x..x + width).find(|&x| is_ink(x, row));
+        let last = (x..x + width).rev().find(|&x| is_ink(x, row));
+        let (left, right) = match (first, last) {
+            (Some(first), Some(last)) => ((first - x) as f32, (x + width - 1 - last) as f32),
+            _ => (width as f32, width as f32),
+        };
+        left_gap += left.min(size);
+        right_gap += right.min(size);
+    }
+    let row_count = rows.len().max(1) as f32;
+    let bearing =
+        |gap: f32| (SIDE_SPACE - gap / row_count / size * OPEN_SPACE_WEIGHT).max(MIN_SIDE_SPACE);
+    let (left_side_bearing, right_side_bearing) = (bearing(left_gap), bearing(right_gap));
+
+    GlyphMetrics {
+        advance_width: width as f32 / size + left_side_bearing + right_side_bearing,
+        left_side_bearing,
+        right_side_bearing,
+        ascent: (baseline - top) / size,
+        descent: (bottom - baseline) / size,
+    }
+}
+
+/// Read the measurements of each letter image in `dir`, from the manifests
+/// that the `scan` command wrote there. Letter images without them, or
+/// without a manifest, are left out.
+pub fn read_from_manifests(dir: &Path) -> anyhow::Result<HashMap<PathBuf, GlyphMetrics>> {
+    manifest::read_glyph_field(dir, "metrics")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    /// A letter image with ink wherever `is_ink` says, between a baseline at
+    /// row 80 and an x-height line at row 40.
+    fn letter(is_ink: impl Fn(u32, u32) -> bool) -> (GrayImage, (u32, u32, u32, u32)) {
+        let image = GrayImage::from_fn(100, 120, |x, y| Luma([if is_ink(x, y) { 0 } else { 255 }]));
+        let bounds = crate::glyph::ink_bounds(&image).unwrap();
+        (image, bounds)
+    }
+
+    #[test]
+    fn spaces_round_letters_closer_than_straight_ones() {
+        // An `n`-like box, straight on both sides.
+        let (n, n_bounds) = letter(|x, y| (30..70).contains(&x) && (40..80).contains(&y));
+        let n = measure(&n, n_bounds, 80.0, 40.0);
+        assert!((n.left_side_bearing - SIDE_SPACE).abs() < 0.001);
+        assert!((n.right_side_bearing - SIDE_SPACE).abs() < 0.001);
+        assert!((n.advance_width - (1.0 + SIDE_SPACE * 2.0)).abs() < 0.001);
+        assert!(n.ascent > 0.99 && n.ascent < 1.01);
+        assert!(n.descent.abs() < 0.01);
+
+        // An `o`-like disc, with paper beside it above and below its middle.
+        let (o, o_bounds) = letter(|x, y| {
+            let (dx, dy) = (x as f32 - 49.5, y as f32 - 59.5);
+            dx * dx + dy * dy <= 400.0
+        });
+        let o = measure(&o, o_bounds, 80.0, 40.0);
+        assert!(o.left_side_bearing < n.left_side_bearing);
+        assert!(o.right_side_bearing < n.right_side_bearing);
+        assert!(o.left_side_bearing >= MIN_SIDE_SPACE);
+    }
+
+    #[test]
+    fn measures_descenders_and_marks_above_the_line() {
+        // A `p`-like stem dropping half an x-height below the baseline.
+        let (p, bounds) = letter(|x, y| (45..55).contains(&x) && (40..100).contains(&y));
+        let p = measure(&p, bounds, 80.0, 40.0);
+        assert!((p.descent - 0.5).abs() < 0.01);
+        assert!((p.x_height_in(60.0) - 40.0).abs() < 0.01);
+
+        // An apostrophe, above the x-height line.
+        let (mark, bounds) = letter(|x, y| (45..50).contains(&x) && (10..25).contains(&y));
+        let mark = measure(&mark, bounds, 80.0, 40.0);
+        assert!(mark.descent < -1.0);
+        assert!(mark.ascent > 1.5);
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+use crate::charset::{self, Charset};
+
+/// List the letter images in a directory written by the `scan` command,
+/// sorted by cell.
+pub fn read_glyph_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut glyph_files = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_file() && image::ImageFormat::from_path(&path).is_ok() {
+            glyph_files.push(path);
+        }
+    }
+    // Alternates come after the image they're alternates of.
+    glyph_files.sort_by(|a, b| {
+        letter_index(a)
+            .cmp(&letter_index(b))
+            .then(alternate(a).cmp(&alternate(b)))
+            .then(a.cmp(b))
+    });
+
+    Ok(glyph_files)
+}
+
+/// Get the index of a letter image named like `letter-12.png`. Anything
+/// after the number, like a `--suffix`, is ignored.
+pub fn letter_index(path: &Path) -> Option<usize> {
+    let number = path.file_stem()?.to_str()?.strip_prefix("letter-")?;
+    let digits = number
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(number.len());
+    number[..digits].parse().ok()
+}
+
+/// Get which alternate a letter image named like `U+0041_A.alt2.png` is, or
+/// `None` if it isn't one.
+pub fn alternate(path: &Path) -> Option<u32> {
+    alternate_number(path.file_stem()?.to_str()?)
+}
+
+/// Get which alternate a file stem like `U+0041_A.alt2` names.
+pub fn alternate_number(stem: &str) -> Option<u32> {
+    stem.rsplit_once(".alt")?.1.parse().ok()
+}
+
+/// Work out which character a letter image shows, either from its name (like
+/// `U+0041_A.png`) or, for images named by cell, from the `charset`. Images
+/// of sequences of characters, like ligatures, have none.
+pub fn char_for_file(path: &Path, charset: &Charset) -> Option<char> {
+    let stem = path.file_stem()?.to_str()?;
+    charset::char_from_file_stem(stem).or_else(|| letter_index(path).and_then(|i| charset.get(i)))
+}
+
+/// Work out what a letter image shows, like [`char_for_file`], whether it's
+/// one character or a sequence of them, like the ligature `th`.
+pub fn text_for_file(path: &Path, charset: &Charset) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?;
+    charset::text_from_file_stem(stem).or_else(|| {
+        letter_index(path)
+            .and_then(|i| charset.text(i))
+            .map(String::from)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_alternate_numbers() {
+        assert_eq!(alternate(Path::new("U+0041_A.alt2.png")), Some(2));
+        assert_eq!(alternate(Path::new("letter-3-v2.alt10.png")), Some(10));
+        assert_eq!(alternate(Path::new("U+0041_A.png")), None);
+        assert_eq!(letter_index(Path::new("letter-3.alt1.png")), Some(3));
+        let charset = Charset::default();
+        assert_eq!(
+            char_for_file(Path::new("U+0041_A.alt1.png"), &charset),
+            Some('A')
+        );
+    }
+}
@@ -0,0 +1,420 @@
+use image::GrayImage;
+use serde::{Deserialize, Deserializer};
+use std::str::FromStr;
+
+/// The fraction of the page's width and height that the bundled template
+/// leaves as a margin around its grid. This is only used when the grid can't
+/// be found in the image, and `--margin-x` or `--margin-y` aren't given.
+const FALLBACK_MARGIN_X: f32 = 0.066;
+const FALLBACK_MARGIN_Y: f32 = 0.079;
+
+/// A row or column of pixels is part of a grid line when at least this
+/// fraction of the longest line's pixels are ink.
+const LINE_DENSITY: f32 = 0.5;
+
+/// The boundaries of a template's cells, in pixels. `columns` holds the x
+/// position of each vertical grid line from left to right, and `rows` holds
+/// the y position of each horizontal grid line from top to bottom. A grid of
+/// 12 × 9 cells has 13 columns and 10 rows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Grid {
+    pub columns: Vec<u32>,
+    pub rows: Vec<u32>,
+    /// How many of the lines weren't found on the page, and were placed
+    /// where they were expected to be instead.
+    pub guessed: u32,
+}
+
+/// The space between the edge of a page and its grid, on each side.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Margin {
+    /// A fraction of the page's width or height, like `0.066`.
+    Fraction(f32),
+    /// A number of pixels, like `120px`.
+    Pixels(u32),
+}
+
+impl Margin {
+    /// How many pixels the margin is on a page `extent` pixels across.
+    pub fn pixels(self, extent: u32) -> u32 {
+        match self {
+            Self::Fraction(fraction) => (extent as f32 * fraction).floor() as u32,
+            Self::Pixels(pixels) => pixels,
+        }
+    }
+}
+
+impl FromStr for Margin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(pixels) = s.strip_suffix("px") {
+            return pixels
+                .trim()
+                .parse()
+                .map(Self::Pixels)
+                .map_err(|_| format!("{s:?} isn't a whole number of pixels, like \"120px\""));
+        }
+        match s.parse::<f32>() {
+            Ok(fraction) if (0.0..0.5).contains(&fraction) => Ok(Self::Fraction(fraction)),
+            _ => Err(format!(
+                "{s:?} isn't a fraction of the page between 0 and 0.5, like \"0.066\", or a \
+                 number of pixels, like \"120px\""
+            )),
+        }
+    }
+}
+
+/// Margins in template files are written like on the command line, or as a
+/// bare fraction.
+impl<'de> Deserialize<'de> for Margin {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Number(f32),
+            Text(String),
+        }
+
+        let text = match Value::deserialize(deserializer)? {
+            Value::Number(fraction) => fraction.to_string(),
+            Value::Text(text) => text,
+        };
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// The margins to assume around a grid whose lines can't be found. Margins
+/// that aren't given are the bundled template's.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Margins {
+    /// The margin on the left and right of the grid.
+    pub x: Option<Margin>,
+    /// The margin above and below the grid.
+    pub y: Option<Margin>,
+}
+
+impl Grid {
+    /// Divide the rectangle at `(x, y)` evenly into `cols` × `rows` cells.
+    /// When the rectangle doesn't divide evenly, the leftover pixels are
+    /// spread across the cells, so that the last line still lies on the
+    /// rectangle's edge.
+    pub fn uniform(x: u32, y: u32, width: u32, height: u32, cols: u32, rows: u32) -> Self {
+        let spread = |start: u32, extent: u32, cells: u32| -> Vec<u32> {
+            (0..=cells)
+                .map(|i| start + (extent as u64 * i as u64 / cells as u64) as u32)
+                .collect()
+        };
+        Self {
+            columns: spread(x, width, cols),
+            rows: spread(y, height, rows),
+            guessed: 0,
+        }
+    }
+
+    /// Assume the grid sits where it does on the bundled template, as a fixed
+    /// fraction of the page size. Every line is counted as guessed.
+    pub fn from_template_margins(width: u32, height: u32, cols: u32, rows: u32) -> Self {
+        Self::from_margins(width, height, cols, rows, Margins::default())
+    }
+
+    /// Assume the grid fills the page inside `margins`. Every line is counted
+    /// as guessed.
+    pub fn from_margins(width: u32, height: u32, cols: u32, rows: u32, margins: Margins) -> Self {
+        let x = margins
+            .x
+            .unwrap_or(Margin::Fraction(FALLBACK_MARGIN_X))
+            .pixels(width);
+        let (top, bottom) = match margins.y {
+            Some(margin) => (margin.pixels(height), margin.pixels(height)),
+            // The bundled template's grid sits a little below the middle of
+            // the page.
+            None => {
+                let y = Margin::Fraction(FALLBACK_MARGIN_Y).pixels(height);
+                (y, y - (y as f32 * 0.2) as u32)
+            }
+        };
+        Self {
+            guessed: cols + rows + 2,
+            ..Self::uniform(
+                x,
+                top,
+                width.saturating_sub(x * 2),
+                height.saturating_sub(top + bottom),
+                cols,
+                rows,
+            )
+        }
+    }
+
+    /// The rectangle of each cell as `(x, y, width, height)`, from left to
+    /// right and then top to bottom.
+    pub fn cells(&self) -> Vec<(u32, u32, u32, u32)> {
+        let mut cells = Vec::new();
+        for rows in self.rows.windows(2) {
+            for columns in self.columns.windows(2) {
+                cells.push((
+                    columns[0],
+                    rows[0],
+                    columns[1] - columns[0],
+                    rows[1] - rows[0],
+                ));
+            }
+        }
+
+        cells
+    }
+}
+
+/// Find the template's grid lines in a thresholded page.
+///
+/// Rows and columns of the page that are mostly ink are taken to be grid
+/// lines. The grid is bounded by the two lines that the most others fall
+/// evenly between, so a title or markers beside the grid aren't mistaken for
+/// its edge. Lines that aren't found where they're expected are placed there
+/// instead. Returns `None` if no plausible grid is found.
+pub fn detect_grid(image: &GrayImage, cols: u32, rows: u32) -> Option<Grid> {
+    let (width, height) = image.dimensions();
+    let (vertical_lines, horizontal_lines) = find_grid_lines(image);
+    tracing::debug!(
+        "Found vertical lines at {vertical_lines:?} and horizontal lines at {horizontal_lines:?}."
+    );
+    let (columns, guessed_columns) = fit_lines(&vertical_lines, cols, width)?;
+    let (rows, guessed_rows) = fit_lines(&horizontal_lines, rows, height)?;
+
+    Some(Grid {
+        columns,
+        rows,
+        guessed: guessed_columns + guessed_rows,
+    })
+}
+
+/// Find the lines in a thresholded page that could be grid lines: the x
+/// position of each vertical line, and the y position of each horizontal
+/// line.
+pub fn find_grid_lines(image: &GrayImage) -> (Vec<u32>, Vec<u32>) {
+    let (width, height) = image.dimensions();
+    let mut column_profile = vec![0u32; width as usize];
+    let mut row_profile = vec![0u32; height as usize];
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if pixel.0[0] < 128 {
+            column_profile[x as usize] += 1;
+            row_profile[y as usize] += 1;
+        }
+    }
+
+    (find_lines(&column_profile), find_lines(&row_profile))
+}
+
+/// Find the center of every run of dense rows (or columns) in a projection
+/// profile.
+fn find_lines(profile: &[u32]) -> Vec<u32> {
+    let len = profile.len();
+    // Sum each row's neighborhood, so that slightly skewed lines, which spread
+    // across several rows, still stand out.
+    let half_window = len / 300;
+    let mut prefix = vec![0u64; len + 1];
+    for (i, count) in profile.iter().enumerate() {
+        prefix[i + 1] = prefix[i] + *count as u64;
+    }
+    let mut smoothed: Vec<u64> = (0..len)
+        .map(|i| prefix[(i + half_window + 1).min(len)] - prefix[i.saturating_sub(half_window)])
+        .collect();
+    // The sharpening filter darkens the edges of the page, so ignore them.
+    for i in (0..=half_window + 1).chain(len.saturating_sub(half_window + 2)..len) {
+        if let Some(value) = smoothed.get_mut(i) {
+            *value = 0;
+        }
+    }
+
+    let max = smoothed.iter().copied().max().unwrap_or(0);
+    if max == 0 {
+        return Vec::new();
+    }
+    let min_density = (max as f32 * LINE_DENSITY) as u64;
+
+    let mut lines = Vec::new();
+    let mut run: Option<(usize, u64)> = None;
+    for (i, value) in smoothed.iter().chain(std::iter::once(&0)).enumerate() {
+        if *value >= min_density {
+            // Track the densest neighborhood of the run.
+            if run.is_none_or(|(_, best)| *value > best) {
+                run = Some((i, *value));
+            }
+        } else if let Some((peak, _)) = run.take() {
+            // Then, find the line itself within the peak's neighborhood.
+            let start = peak.saturating_sub(half_window);
+            let end = (peak + half_window + 1).min(len);
+            let center = (start..end).max_by_key(|j| profile[*j]).unwrap_or(peak);
+            lines.push(center as u32);
+        }
+    }
+
+    lines
+}
+
+/// Turn the lines found in one direction into `cells + 1` cell boundaries.
+///
+/// Any two of the lines could be the edges of the grid, since a title or
+/// the corner markers can look like a line too. Each pair that spans at least
+/// half of the page is tried, with the boundaries between them spread evenly,
+/// and the pair whose boundaries land on the most lines, and then the closest
+/// to them, is kept. Each boundary is snapped to a detected line if one is
+/// close to where it's expected; otherwise, the expected position is used.
+/// Returns the boundaries, and how many of them were placed where they were
+/// expected.
+fn fit_lines(lines: &[u32], cells: u32, extent: u32) -> Option<(Vec<u32>, u32)> {
+    let mut best: Option<(Vec<u32>, u32, f32)> = None;
+    for (i, &first) in lines.iter().enumerate() {
+        for &last in &lines[i + 1..] {
+            // A grid spanning less than half of the page is more likely to be
+            // noise.
+            if last - first < extent / 2 {
+                continue;
+            }
+            let (boundaries, guessed, deviation) = spread_lines(lines, first, last, cells);
+            let better = best
+                .as_ref()
+                .is_none_or(|(_, best_guessed, best_deviation)| {
+                    (guessed, deviation) < (*best_guessed, *best_deviation)
+                });
+            if better {
+                best = Some((boundaries, guessed, deviation));
+            }
+        }
+    }
+
+    best.map(|(boundaries, guessed, _)| (boundaries, guessed))
+}
+
+/// Spread `cells + 1` boundaries evenly from `first` to `last`, snapping each
+/// to the closest of `lines` within a little of where it's expected. Returns
+/// the boundaries, how many weren't near a line, and how far the snapped ones
+/// were from where they were expected, as a fraction of a cell.
+fn spread_lines(lines: &[u32], first: u32, last: u32, cells: u32) -> (Vec<u32>, u32, f32) {
+    let pitch = (last - first) as f32 / cells as f32;
+    let tolerance = pitch * 0.15;
+    let (mut guessed, mut deviation) = (0, 0.0);
+    let boundaries = (0..=cells)
+        .map(|i| {
+            let expected = first as f32 + pitch * i as f32;
+            let nearest = lines
+                .iter()
+                .copied()
+                .map(|line| (line, (line as f32 - expected).abs()))
+                .filter(|(_, distance)| *distance <= tolerance)
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            match nearest {
+                Some((line, distance)) => {
+                    deviation += distance / pitch;
+                    line
+                }
+                None => {
+                    guessed += 1;
+                    expected.round() as u32
+                }
+            }
+        })
+        .collect();
+
+    (boundaries, guessed, deviation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{charset::Charset, template::render_template};
+
+    #[test]
+    fn detects_the_lines_of_a_generated_template() {
+        let cell_size = 100;
+        let page = render_template(2, 3, cell_size, &Charset::default(), None, false);
+        let page = imageproc::contrast::threshold(&page, 128);
+        let grid = detect_grid(&page, 3, 2).unwrap();
+        assert_eq!(grid.guessed, 0);
+
+        // The grid starts one cell in from the edge of the page.
+        for (i, column) in grid.columns.iter().enumerate() {
+            let expected = cell_size + i as u32 * cell_size;
+            assert!(column.abs_diff(expected) <= 1, "column {i} is at {column}");
+        }
+        for (i, row) in grid.rows.iter().enumerate() {
+            let expected = cell_size + i as u32 * cell_size;
+            assert!(row.abs_diff(expected) <= 1, "row {i} is at {row}");
+        }
+    }
+
+    #[test]
+    fn ignores_the_title_above_a_small_template() {
+        let cell_size = 100;
+        let charset = Charset::parse("abcdefghijklmnopqrst");
+        let font = crate::template::system_font();
+        let mut page = render_template(4, 5, cell_size, &charset, font.as_ref(), false);
+        if font.is_none() {
+            // Stand in for the title, which is only printed with a font.
+            let rect = imageproc::rect::Rect::at(200, 82).of_size(300, 8);
+            imageproc::drawing::draw_filled_rect_mut(&mut page, rect, image::Luma([0]));
+        }
+        let page = imageproc::contrast::threshold(&page, 128);
+        let grid = detect_grid(&page, 5, 4).unwrap();
+        assert_eq!(grid.guessed, 0);
+
+        for (i, (x, y, width, height)) in grid.cells().into_iter().enumerate() {
+            let (col, row) = (i as u32 % 5, i as u32 / 5);
+            assert!(
+                x.abs_diff(cell_size * (col + 1)) <= 2,
+                "cell {i} is at x {x}"
+            );
+            assert!(
+                y.abs_diff(cell_size * (row + 1)) <= 2,
+                "cell {i} is at y {y}"
+            );
+            assert!(width.abs_diff(cell_size) <= 2, "cell {i} is {width} wide");
+            assert!(height.abs_diff(cell_size) <= 2, "cell {i} is {height} tall");
+        }
+    }
+
+    #[test]
+    fn spreads_leftover_pixels_across_the_cells() {
+        let grid = Grid::uniform(0, 0, 62, 40, 4, 2);
+        assert_eq!(grid.columns, vec![0, 15, 31, 46, 62]);
+        assert_eq!(grid.rows, vec![0, 20, 40]);
+    }
+
+    #[test]
+    fn places_the_grid_inside_the_margins() {
+        let margins = Margins {
+            x: Some("0.1".parse().unwrap()),
+            y: Some("20px".parse().unwrap()),
+        };
+        let grid = Grid::from_margins(200, 100, 3, 2, margins);
+        assert_eq!(grid.columns, vec![20, 73, 126, 180]);
+        assert_eq!(grid.rows, vec![20, 50, 80]);
+        assert_eq!(grid.guessed, 7);
+
+        assert!("0.5".parse::<Margin>().is_err());
+        assert!("wide".parse::<Margin>().is_err());
+    }
+
+    #[test]
+    fn finds_no_grid_on_a_blank_page() {
+        let page = GrayImage::from_pixel(300, 200, image::Luma([255]));
+        assert_eq!(detect_grid(&page, 3, 2), None);
+    }
+
+    #[test]
+    fn lists_cells_in_reading_order() {
+        let grid = Grid::uniform(10, 20, 60, 40, 3, 2);
+        assert_eq!(
+            grid.cells(),
+            vec![
+                (10, 20, 20, 20),
+                (30, 20, 20, 20),
+                (50, 20, 20, 20),
+                (10, 40, 20, 20),
+                (30, 40, 20, 20),
+                (50, 40, 20, 20),
+            ]
+        );
+    }
+}
@@ -0,0 +1,417 @@
+use anyhow::Context;
+use image::{imageops, GrayImage, Rgb, RgbImage};
+use imageproc::{drawing::draw_filled_rect_mut, rect::Rect};
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
+
+use crate::grid::Grid;
+
+/// The largest the page is drawn in the window, in pixels. Pages are scaled
+/// down to fit.
+const MAX_PREVIEW_SIZE: (u32, u32) = (1280, 860);
+/// The height of the threshold slider below the page, in pixels.
+const SLIDER_HEIGHT: u32 = 28;
+/// How far from a corner of the grid a click may land to grab it, in pixels
+/// of the window.
+const GRAB_DISTANCE: f32 = 12.0;
+/// How far the mouse may move while clicking a cell for the click to reject
+/// it, rather than being taken as a drag.
+const CLICK_DISTANCE: f32 = 4.0;
+const GRID_COLOR: Rgb<u8> = Rgb([40, 200, 60]);
+const HANDLE_COLOR: Rgb<u8> = Rgb([20, 120, 220]);
+const REJECTED_COLOR: Rgb<u8> = Rgb([220, 40, 40]);
+const SLIDER_COLOR: Rgb<u8> = Rgb([90, 90, 90]);
+const SLIDER_BACKGROUND: Rgb<u8> = Rgb([210, 210, 210]);
+
+/// What was changed while adjusting a page.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Adjustment {
+    pub grid: Grid,
+    pub threshold: u8,
+    /// Whether each cell, from left to right and then top to bottom, was
+    /// rejected.
+    pub rejected: Vec<bool>,
+}
+
+/// A corner of the grid, clockwise from the top left.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomRight,
+    BottomLeft,
+}
+
+const CORNERS: [Corner; 4] = [
+    Corner::TopLeft,
+    Corner::TopRight,
+    Corner::BottomRight,
+    Corner::BottomLeft,
+];
+
+/// What the mouse is holding.
+enum Drag {
+    Corner(Corner),
+    Slider,
+    /// A cell that was pressed, and where the mouse was when it was.
+    Cell(usize, (f32, f32)),
+    /// A new grid being drawn from where the mouse was pressed on the page.
+    Region((u32, u32)),
+}
+
+/// Show a straightened `page` that hasn't been thresholded yet, with `grid`
+/// drawn over it, so that the grid can be fixed by hand before the cells are
+/// cut out.
+///
+/// The page is drawn thresholded at `threshold`, which can be changed with
+/// the slider below it or the arrow keys. Drag a corner of the grid to move
+/// it, and the lines between are spread out to match. Drag across the page
+/// outside the grid, or anywhere while holding Shift, to draw the grid over
+/// again, cut into even cells. Click a cell to reject it, or click it again
+/// to keep it. Press S or Enter to save, or
+/// Escape to give up. `labels` holds the character of each cell, which is
+/// shown while pointing at it. Returns `None` if the window was closed
+/// without saving.
+pub fn adjust(
+    page: &GrayImage,
+    grid: &Grid,
+    threshold: u8,
+    labels: &[Option<String>],
+) -> anyhow::Result<Option<Adjustment>> {
+    let (width, height) = page.dimensions();
+    let scale = (MAX_PREVIEW_SIZE.0 as f32 / width as f32)
+        .min(MAX_PREVIEW_SIZE.1 as f32 / height as f32)
+        .min(1.0);
+    let (preview_width, preview_height) = (
+        ((width as f32 * scale) as u32).max(1),
+        ((height as f32 * scale) as u32).max(1),
+    );
+    let preview = imageops::resize(
+        page,
+        preview_width,
+        preview_height,
+        imageops::FilterType::Triangle,
+    );
+    let window_height = preview_height + SLIDER_HEIGHT;
+    let mut window = Window::new(
+        "handwriting-scan-tool",
+        preview_width as usize,
+        window_height as usize,
+        WindowOptions::default(),
+    )
+    .context("opening the window")?;
+    window.set_target_fps(30);
+
+    let mut adjustment = Adjustment {
+        grid: grid.clone(),
+        threshold,
+        rejected: vec![false; grid.cells().len()],
+    };
+    let to_page = |(x, y): (f32, f32)| {
+        (
+            ((x / scale).round().max(0.0) as u32).min(width),
+            ((y / scale).round().max(0.0) as u32).min(height),
+        )
+    };
+    let mut buffer = Vec::new();
+    let mut drag = None;
+    let mut was_down = false;
+    let mut dirty = true;
+    let mut hovered = None;
+    while window.is_open() {
+        if window.is_key_pressed(Key::Escape, KeyRepeat::No) {
+            return Ok(None);
+        }
+        if window.is_key_pressed(Key::S, KeyRepeat::No)
+            || window.is_key_pressed(Key::Enter, KeyRepeat::No)
+        {
+            return Ok(Some(adjustment));
+        }
+        if window.is_key_pressed(Key::Up, KeyRepeat::Yes) {
+            adjustment.threshold = adjustment.threshold.saturating_add(1);
+            dirty = true;
+        }
+        if window.is_key_pressed(Key::Down, KeyRepeat::Yes) {
+            adjustment.threshold = adjustment.threshold.saturating_sub(1);
+            dirty = true;
+        }
+
+        let mouse = window.get_mouse_pos(MouseMode::Clamp);
+        let down = window.get_mouse_down(MouseButton::Left);
+        if let Some((x, y)) = mouse {
+            let on_page = y < preview_height as f32;
+            let point = to_page((x, y));
+            let cell = on_page.then(|| cell_at(&adjustment.grid, point)).flatten();
+            if cell != hovered {
+                hovered = cell;
+                dirty = true;
+            }
+            if down && !was_down {
+                let corner = CORNERS.into_iter().find(|&corner| {
+                    let (cx, cy) = corner_position(&adjustment.grid, corner);
+                    (cx as f32 * scale - x).hypot(cy as f32 * scale - y) <= GRAB_DISTANCE
+                });
+                let shift =
+                    window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+                drag = match (corner, cell) {
+                    _ if !on_page => Some(Drag::Slider),
+                    _ if shift => Some(Drag::Region(point)),
+                    (Some(corner), _) => Some(Drag::Corner(corner)),
+                    (None, Some(cell)) => Some(Drag::Cell(cell, (x, y))),
+                    (None, None) => Some(Drag::Region(point)),
+                };
+            }
+            match drag {
+                Some(Drag::Corner(corner)) if down => {
+                    adjustment.grid = move_corner(&adjustment.grid, corner, point);
+                    dirty = true;
+                }
+                Some(Drag::Region(start)) if down => {
+                    adjustment.grid = draw_region(&adjustment.grid, start, point);
+                    dirty = true;
+                }
+                Some(Drag::Slider) if down => {
+                    adjustment.threshold = (x / preview_width as f32 * 255.0).round() as u8;
+                    dirty = true;
+                }
+                Some(Drag::Cell(cell, start))
+                    if !down && (x - start.0).hypot(y - start.1) <= CLICK_DISTANCE =>
+                {
+                    adjustment.rejected[cell] = !adjustment.rejected[cell];
+                    dirty = true;
+                }
+                _ => {}
+            }
+        }
+        if !down {
+            drag = None;
+        }
+        was_down = down;
+
+        if dirty {
+            dirty = false;
+            let frame = draw(&preview, &adjustment, scale);
+            buffer.clear();
+            buffer.extend(
+                frame
+                    .pixels()
+                    .map(|Rgb([r, g, b])| u32::from_be_bytes([0, *r, *g, *b])),
+            );
+            let rejected = adjustment.rejected.iter().filter(|r| **r).count();
+            let pointing_at = match hovered {
+                Some(cell) => {
+                    let cols = adjustment.grid.columns.len() - 1;
+                    let label = labels.get(cell).cloned().flatten();
+                    format!(
+                        " · cell {cell} (row {}, column {}){}",
+                        cell / cols,
+                        cell % cols,
+                        label
+                            .map(|label| format!(", {label:?}"))
+                            .unwrap_or_default()
+                    )
+                }
+                None => String::new(),
+            };
+            window.set_title(&format!(
+                "Threshold {} · {rejected} rejected{pointing_at} · drag the corners, or \
+                 shift-drag to draw the grid, click cells to reject them, S to save, Esc to \
+                 cancel",
+                adjustment.threshold
+            ));
+        }
+        window
+            .update_with_buffer(&buffer, preview_width as usize, window_height as usize)
+            .context("drawing the window")?;
+    }
+
+    Ok(None)
+}
+
+/// Draw the page thresholded, with its grid, the cells that were rejected,
+/// and the threshold slider below it.
+fn draw(preview: &GrayImage, adjustment: &Adjustment, scale: f32) -> RgbImage {
+    let (width, height) = preview.dimensions();
+    let mut frame = RgbImage::from_pixel(width, height + SLIDER_HEIGHT, SLIDER_BACKGROUND);
+    for (x, y, pixel) in preview.enumerate_pixels() {
+        let value = pixel.0[0];
+        // What will be thresholded away is still shown faintly, so that
+        // it's clear what a lower threshold would keep.
+        let shade = if value < adjustment.threshold {
+            0
+        } else {
+            255 - (255 - value) / 3
+        };
+        frame.put_pixel(x, y, Rgb([shade; 3]));
+    }
+
+    let grid = &adjustment.grid;
+    let to_preview = |value: u32| (value as f32 * scale) as i32;
+    for (cell, &(x, y, cell_width, cell_height)) in grid.cells().iter().enumerate() {
+        if !adjustment.rejected[cell] {
+            continue;
+        }
+        let (left, top) = (to_preview(x).max(0) as u32, to_preview(y).max(0) as u32);
+        let right = (to_preview(x + cell_width) as u32).min(width);
+        let bottom = (to_preview(y + cell_height) as u32).min(height);
+        for py in top..bottom {
+            for px in left..right {
+                let Rgb([r, g, b]) = *frame.get_pixel(px, py);
+                let tint = |channel: u8, tint: u8| ((channel as u16 + tint as u16) / 2) as u8;
+                frame.put_pixel(
+                    px,
+                    py,
+                    Rgb([
+                        tint(r, REJECTED_COLOR.0[0]),
+                        tint(g, REJECTED_COLOR.0[1]),
+                        tint(b, REJECTED_COLOR.0[2]),
+                    ]),
+                );
+            }
+        }
+    }
+    let (first_column, last_column) = (grid.columns[0], *grid.columns.last().unwrap());
+    let (first_row, last_row) = (grid.rows[0], *grid.rows.last().unwrap());
+    for &column in &grid.columns {
+        let rect = Rect::at(to_preview(column), to_preview(first_row))
+            .of_size(1, ((last_row - first_row) as f32 * scale).max(1.0) as u32);
+        draw_filled_rect_mut(&mut frame, rect, GRID_COLOR);
+    }
+    for &row in &grid.rows {
+        let rect = Rect::at(to_preview(first_column), to_preview(row)).of_size(
+            ((last_column - first_column) as f32 * scale).max(1.0) as u32,
+            1,
+        );
+        draw_filled_rect_mut(&mut frame, rect, GRID_COLOR);
+    }
+    for corner in CORNERS {
+        let (x, y) = corner_position(grid, corner);
+        let half = GRAB_DISTANCE as i32 / 2;
+        let rect = Rect::at(to_preview(x) - half, to_preview(y) - half)
+            .of_size(half as u32 * 2, half as u32 * 2);
+        draw_filled_rect_mut(&mut frame, rect, HANDLE_COLOR);
+    }
+
+    let filled = (adjustment.threshold as f32 / 255.0 * width as f32) as u32;
+    if filled > 0 {
+        let rect = Rect::at(0, height as i32 + SLIDER_HEIGHT as i32 / 3)
+            .of_size(filled, SLIDER_HEIGHT / 3);
+        draw_filled_rect_mut(&mut frame, rect, SLIDER_COLOR);
+    }
+    let knob = Rect::at(filled as i32 - 3, height as i32 + 2).of_size(6, SLIDER_HEIGHT - 4);
+    draw_filled_rect_mut(&mut frame, knob, HANDLE_COLOR);
+
+    frame
+}
+
+/// Where a corner of the grid is on the page.
+fn corner_position(grid: &Grid, corner: Corner) -> (u32, u32) {
+    let (left, right) = (grid.columns[0], *grid.columns.last().unwrap());
+    let (top, bottom) = (grid.rows[0], *grid.rows.last().unwrap());
+    match corner {
+        Corner::TopLeft => (left, top),
+        Corner::TopRight => (right, top),
+        Corner::BottomRight => (right, bottom),
+        Corner::BottomLeft => (left, bottom),
+    }
+}
+
+/// The cell of `grid` that `(x, y)` is in, if any.
+fn cell_at(grid: &Grid, (x, y): (u32, u32)) -> Option<usize> {
+    let col = grid
+        .columns
+        .windows(2)
+        .position(|c| (c[0]..c[1]).contains(&x))?;
+    let row = grid
+        .rows
+        .windows(2)
+        .position(|r| (r[0]..r[1]).contains(&y))?;
+    Some(row * (grid.columns.len() - 1) + col)
+}
+
+/// Move a corner of `grid` to `to`. The two edges that meet at the corner
+/// move with it, and the lines between the edges are spread out to keep
+/// their places relative to them. Every cell is kept at least a pixel wide
+/// and tall.
+pub fn move_corner(grid: &Grid, corner: Corner, to: (u32, u32)) -> Grid {
+    let (mut left, mut right) = (grid.columns[0], *grid.columns.last().unwrap());
+    let (mut top, mut bottom) = (grid.rows[0], *grid.rows.last().unwrap());
+    let cols = grid.columns.len() as u32 - 1;
+    let rows = grid.rows.len() as u32 - 1;
+    match corner {
+        Corner::TopLeft | Corner::BottomLeft => left = to.0.min(right.saturating_sub(cols)),
+        Corner::TopRight | Corner::BottomRight => right = to.0.max(left + cols),
+    }
+    match corner {
+        Corner::TopLeft | Corner::TopRight => top = to.1.min(bottom.saturating_sub(rows)),
+        Corner::BottomLeft | Corner::BottomRight => bottom = to.1.max(top + rows),
+    }
+
+    Grid {
+        columns: spread(&grid.columns, left, right),
+        rows: spread(&grid.rows, top, bottom),
+        guessed: grid.guessed,
+    }
+}
+
+/// A grid with as many rows and columns as `grid`, cut evenly from the
+/// rectangle between the corners `from` and `to`. It's kept at least a pixel
+/// wide and tall for every cell.
+pub fn draw_region(grid: &Grid, from: (u32, u32), to: (u32, u32)) -> Grid {
+    let cols = grid.columns.len() as u32 - 1;
+    let rows = grid.rows.len() as u32 - 1;
+    let (left, top) = (from.0.min(to.0), from.1.min(to.1));
+    let width = from.0.abs_diff(to.0).max(cols);
+    let height = from.1.abs_diff(to.1).max(rows);
+    Grid::uniform(left, top, width, height, cols, rows)
+}
+
+/// Move the first and last of `lines` to `first` and `last`, and the lines
+/// between them so that they keep their places relative to the ends.
+fn spread(lines: &[u32], first: u32, last: u32) -> Vec<u32> {
+    let (old_first, old_last) = (lines[0], *lines.last().unwrap());
+    let count = lines.len() as u32 - 1;
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, &line)| {
+            let fraction = if old_last > old_first {
+                (line - old_first) as f32 / (old_last - old_first) as f32
+            } else {
+                i as f32 / count as f32
+            };
+            first + ((last - first) as f32 * fraction).round() as u32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_a_corner_spreads_the_lines() {
+        let grid = Grid::uniform(10, 10, 100, 100, 2, 2);
+        let moved = move_corner(&grid, Corner::BottomRight, (210, 160));
+        assert_eq!(moved.columns, vec![10, 110, 210]);
+        assert_eq!(moved.rows, vec![10, 85, 160]);
+        assert_eq!(cell_at(&moved, (150, 100)), Some(3));
+        assert_eq!(cell_at(&moved, (5, 100)), None);
+
+        // A corner dragged past the opposite one stops short of it.
+        let squashed = move_corner(&grid, Corner::TopLeft, (500, 500));
+        assert_eq!(squashed.columns, vec![108, 109, 110]);
+        assert_eq!(squashed.rows, vec![108, 109, 110]);
+    }
+
+    #[test]
+    fn drawing_a_region_cuts_it_evenly() {
+        let grid = Grid::uniform(10, 10, 100, 100, 2, 2);
+        let drawn = draw_region(&grid, (300, 200), (100, 80));
+        assert_eq!(drawn.columns, vec![100, 200, 300]);
+        assert_eq!(drawn.rows, vec![80, 140, 200]);
+        // A click without a drag still leaves a cell for every row and
+        // column.
+        let clicked = draw_region(&grid, (50, 50), (50, 50));
+        assert_eq!(clicked.columns, vec![50, 51, 52]);
+    }
+}
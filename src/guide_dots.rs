@@ -0,0 +1,145 @@
+use image::{GrayImage, Luma};
+use std::str::FromStr;
+
+/// The radius used when a guide dot doesn't specify one, as a fraction of the
+/// cell width.
+const DEFAULT_RADIUS: f32 = 0.02;
+
+/// A dot printed at the same place in every cell of a template. Positions and
+/// the radius are fractions of the cell's width and height, so `0.5,0.5` is
+/// the center of the cell.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GuideDot {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+}
+
+impl FromStr for GuideDot {
+    type Err = String;
+
+    /// Parse a guide dot written as `x,y` or `x,y,radius`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("invalid guide dot {s:?}: {err}"))?;
+        let (x, y, radius) = match values[..] {
+            [x, y] => (x, y, DEFAULT_RADIUS),
+            [x, y, radius] => (x, y, radius),
+            _ => {
+                return Err(format!(
+                    "guide dot {s:?} must be written as x,y or x,y,radius"
+                ))
+            }
+        };
+        if !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y) {
+            return Err(format!("guide dot {s:?} must lie within the cell (0 to 1)"));
+        }
+        if radius <= 0.0 {
+            return Err(format!("guide dot {s:?} must have a positive radius"));
+        }
+
+        Ok(Self { x, y, radius })
+    }
+}
+
+/// Paint over the guide dots in a cell with paper, so that they don't end up
+/// as part of the letterform.
+pub fn mask_guide_dots(cell: &mut GrayImage, guide_dots: &[GuideDot]) {
+    let (width, height) = cell.dimensions();
+    for dot in guide_dots {
+        let center_x = dot.x * width as f32;
+        let center_y = dot.y * height as f32;
+        let radius = dot.radius * width as f32;
+        let min_x = (center_x - radius).floor().max(0.0) as u32;
+        let min_y = (center_y - radius).floor().max(0.0) as u32;
+        let max_x = ((center_x + radius).ceil() as u32).min(width);
+        let max_y = ((center_y + radius).ceil() as u32).min(height);
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let dx = x as f32 + 0.5 - center_x;
+                let dy = y as f32 + 0.5 - center_y;
+                if dx * dx + dy * dy <= radius * radius {
+                    cell.put_pixel(x, y, Luma([255]));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_guide_dots() {
+        assert_eq!(
+            "0.5,0.8".parse(),
+            Ok(GuideDot {
+                x: 0.5,
+                y: 0.8,
+                radius: DEFAULT_RADIUS
+            })
+        );
+        assert_eq!(
+            " 0.25, 1 ,0.03".parse(),
+            Ok(GuideDot {
+                x: 0.25,
+                y: 1.0,
+                radius: 0.03
+            })
+        );
+        for bad in [
+            "0.5",
+            "0.5,0.5,0.1,0.1",
+            "a,0.5",
+            "1.5,0.5",
+            "0.5,-0.1",
+            "0.5,0.5,0",
+            "",
+        ] {
+            assert!(bad.parse::<GuideDot>().is_err(), "{bad:?}");
+        }
+
+        let scan = |dots: &[&str]| {
+            let mut args = vec!["--input-file", "page.png"];
+            for dot in dots {
+                args.extend(["--guide-dots", dot]);
+            }
+            crate::scan::ScanArgs::try_parse_from(args)
+        };
+        assert!(scan(&["0.5,0.8", "0.1,0.1,0.05"]).is_ok());
+        assert!(scan(&["0.5,0.8", "0.5;0.8"]).is_err());
+    }
+
+    #[test]
+    fn masks_guide_dots_and_keeps_other_ink() {
+        let mut cell = GrayImage::from_pixel(100, 100, Luma([255]));
+        // A dot at the bottom center, and a stroke just above it.
+        for y in 76..85 {
+            for x in 46..55 {
+                cell.put_pixel(x, y, Luma([0]));
+            }
+        }
+        for y in 40..70 {
+            cell.put_pixel(50, y, Luma([0]));
+        }
+        let dot = GuideDot {
+            x: 0.505,
+            y: 0.805,
+            radius: 0.07,
+        };
+
+        mask_guide_dots(&mut cell, &[dot]);
+        for y in 76..85 {
+            for x in 46..55 {
+                assert_eq!(cell.get_pixel(x, y).0[0], 255, "({x}, {y})");
+            }
+        }
+        for y in 40..70 {
+            assert_eq!(cell.get_pixel(50, y).0[0], 0, "(50, {y})");
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use image::{GenericImageView, Luma};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    manifest,
+    template::{BASELINE_POSITION, X_HEIGHT_POSITION},
+};
+
+/// How much darker than the paper a pixel must be to be part of a guide
+/// line.
+const GUIDE_CONTRAST: u8 = 12;
+/// How much of a row must be the color of the guides to be a guide line.
+/// The x-height guide is dashed, so only about half of its row is.
+const MIN_COVERAGE: f32 = 0.3;
+/// How far from where the template puts them the guide lines are looked
+/// for, as a fraction of the cell's height.
+const SEARCH_RANGE: f32 = 0.12;
+/// The fraction of a cell's width that's left out on each side when
+/// looking for guide lines, so that the grid lines aren't mistaken for them.
+const INSET: f32 = 0.1;
+
+/// Where the template's guide lines are in a cell or a letter image, as rows
+/// of its pixels counted from the top. Letter images are cropped to their
+/// ink, so the lines may lie outside of them.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Guides {
+    /// The line that letters sit on, and that descenders hang below.
+    pub baseline: f32,
+    /// The line that lowercase letters without ascenders reach up to, if it
+    /// was found. Templates made before it was printed only have baselines.
+    pub x_height: Option<f32>,
+}
+
+impl Guides {
+    /// Where the lines are once the image they're in is scaled by `scale`,
+    /// then moved `offset` pixels down.
+    pub fn transform(self, scale: f32, offset: f32) -> Self {
+        Self {
+            baseline: self.baseline * scale + offset,
+            x_height: self.x_height.map(|x_height| x_height * scale + offset),
+        }
+    }
+
+    /// How tall lowercase letters like `x` are meant to be, in pixels.
+    pub fn x_height_size(&self) -> Option<f32> {
+        self.x_height.map(|x_height| self.baseline - x_height)
+    }
+}
+
+/// Find the template's baseline and x-height guides in a cell of a page that
+/// hasn't been thresholded yet. They're light enough to be paper once it is,
+/// so pixels between the `threshold` and the paper's color are taken to be
+/// the guides' ink. Returns `None` if the cell has no baseline guide.
+pub fn find<I>(cell: &I, threshold: u8) -> Option<Guides>
+where
+    I: GenericImageView<Pixel = Luma<u8>>,
+{
+    let (width, height) = cell.dimensions();
+    let inset = (width as f32 * INSET) as u32;
+    let columns = inset..width.saturating_sub(inset);
+    if columns.is_empty() || height == 0 {
+        return None;
+    }
+
+    // Most of a cell is paper, so its middle value is the paper's color.
+    let mut values: Vec<u8> = (0..height)
+        .flat_map(|y| columns.clone().map(move |x| (x, y)))
+        .map(|(x, y)| cell.get_pixel(x, y).0[0])
+        .collect();
+    let middle = values.len() / 2;
+    let paper = *values.select_nth_unstable(middle).1;
+    let darkest_guide = paper.checked_sub(GUIDE_CONTRAST)?;
+    if darkest_guide < threshold {
+        return None;
+    }
+
+    let coverage: Vec<f32> = (0..height)
+        .map(|y| {
+            let guide = columns
+                .clone()
+                .filter(|&x| (threshold..=darkest_guide).contains(&cell.get_pixel(x, y).0[0]))
+                .count();
+            guide as f32 / columns.len() as f32
+        })
+        .collect();
+    let find_line = |position: f32| {
+        let range = height as f32 * SEARCH_RANGE;
+        let first = ((height as f32 * position - range).max(0.0)) as usize;
+        let last = ((height as f32 * position + range) as usize).min(coverage.len() - 1);
+        let (peak, &peak_coverage) = coverage[first..=last]
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))?;
+        if peak_coverage < MIN_COVERAGE {
+            return None;
+        }
+        // A line can be a few pixels thick, so it's placed in the middle of
+        // the rows around the peak.
+        let peak = first + peak;
+        let is_line = |y: &usize| coverage[*y] >= peak_coverage / 2.0;
+        let top = (0..peak).rev().take_while(is_line).last().unwrap_or(peak);
+        let bottom = (peak + 1..coverage.len())
+            .take_while(is_line)
+            .last()
+            .unwrap_or(peak);
+        Some((top + bottom + 1) as f32 / 2.0)
+    };
+
+    Some(Guides {
+        baseline: find_line(BASELINE_POSITION)?,
+        x_height: find_line(X_HEIGHT_POSITION),
+    })
+}
+
+/// Read where the guide lines are in each letter image in `dir`, from the
+/// manifests that the `scan` command wrote there. Letter images without
+/// guides, or without a manifest, are left out.
+pub fn read_from_manifests(dir: &Path) -> anyhow::Result<HashMap<PathBuf, Guides>> {
+    manifest::read_glyph_field(dir, "guides")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{charset::Charset, template::render_template};
+    use image::GrayImage;
+    use imageproc::{drawing::draw_filled_rect_mut, rect::Rect};
+
+    #[test]
+    fn finds_the_templates_guide_lines() {
+        let mut page = render_template(1, 1, 200, &Charset::default(), None, false);
+        // A letter written across both guides.
+        draw_filled_rect_mut(&mut page, Rect::at(290, 250).of_size(6, 100), Luma([0]));
+        let cell = page.view(200, 200, 200, 200);
+        let guides = find(&*cell, 190).unwrap();
+        // Both lines are 2 pixels thick.
+        assert_eq!(guides.baseline, 131.0);
+        assert_eq!(guides.x_height, Some(81.0));
+        assert_eq!(guides.x_height_size(), Some(50.0));
+        assert_eq!(
+            guides.transform(2.0, -10.0),
+            Guides {
+                baseline: 252.0,
+                x_height: Some(152.0),
+            }
+        );
+
+        // Once thresholded, the guides are gone.
+        let blank = GrayImage::from_pixel(200, 200, Luma([255]));
+        assert_eq!(find(&blank, 190), None);
+    }
+}
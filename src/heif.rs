@@ -0,0 +1,99 @@
+use anyhow::{bail, Context};
+use image::DynamicImage;
+use std::{
+    io::ErrorKind,
+    path::Path,
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tracing::debug;
+
+/// The extensions of HEIF images: the HEIC photos that phones take, and AVIF.
+const EXTENSIONS: &[&str] = &["heic", "heif", "hif", "avif"];
+
+/// The programs that can turn a HEIF image into a PNG, in the order they're
+/// tried, with their arguments. `{input}` and `{output}` stand for the two
+/// files.
+const CONVERTERS: &[(&str, &[&str])] = &[
+    // From libheif. `heif-convert` is its older name.
+    ("heif-dec", &["{input}", "{output}"]),
+    ("heif-convert", &["{input}", "{output}"]),
+    ("magick", &["{input}", "{output}"]),
+    // Built into macOS.
+    (
+        "sips",
+        &["-s", "format", "png", "{input}", "--out", "{output}"],
+    ),
+];
+
+/// Whether `path` names a HEIF image, which the `image` crate can't read.
+pub fn is_heif(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        EXTENSIONS
+            .iter()
+            .any(|heif| extension.eq_ignore_ascii_case(heif))
+    })
+}
+
+/// Open a HEIF image by converting it to a PNG with the first program in
+/// [`CONVERTERS`] that's installed. The image is turned the way it's meant to
+/// be seen, so its EXIF orientation shouldn't be applied again.
+pub fn open(path: &Path) -> anyhow::Result<DynamicImage> {
+    // Each conversion gets its own file, since pages can be opened at once.
+    static CONVERSIONS: AtomicUsize = AtomicUsize::new(0);
+    let output = std::env::temp_dir().join(format!(
+        "handwriting-scan-tool-{}-{}.png",
+        std::process::id(),
+        CONVERSIONS.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let mut failures = Vec::new();
+    for (program, args) in CONVERTERS {
+        let args = args.iter().map(|arg| match *arg {
+            "{input}" => path.as_os_str(),
+            "{output}" => output.as_os_str(),
+            arg => arg.as_ref(),
+        });
+        let result = match Command::new(program).args(args).output() {
+            Ok(result) => result,
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => {
+                failures.push(format!("{program} couldn't be run: {err}"));
+                continue;
+            }
+        };
+        if !result.status.success() || !output.is_file() {
+            let stderr = String::from_utf8_lossy(&result.stderr);
+            failures.push(format!("{program} failed: {}", stderr.trim()));
+            continue;
+        }
+
+        debug!("Converted {} with {program}.", path.display());
+        let image = image::open(&output);
+        let _ = std::fs::remove_file(&output);
+        return image.with_context(|| format!("opening {} converted by {program}", path.display()));
+    }
+
+    if failures.is_empty() {
+        bail!(
+            "couldn't open {}: HEIC and AVIF images are opened with heif-dec or heif-convert \
+             (from libheif), ImageMagick, or sips on macOS, and none of them are installed. \
+             Install one, or convert the image to JPEG or PNG first.",
+            path.display()
+        );
+    }
+    bail!("couldn't open {}: {}", path.display(), failures.join("; "));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_heif_images() {
+        assert!(is_heif(Path::new("IMG_0001.HEIC")));
+        assert!(is_heif(Path::new("scans/page.avif")));
+        assert!(!is_heif(Path::new("scans/page.jpeg")));
+        assert!(!is_heif(Path::new("heic")));
+    }
+}
@@ -0,0 +1,204 @@
+use anyhow::{bail, Context};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use crate::{heif, pdf};
+
+/// Expand the `--input-file` arguments into a list of image and PDF files.
+/// Each argument may be a file, a directory (every image and PDF in it is
+/// used), or a pattern like `scans/page-*.jpeg`, where `*` matches any run of
+/// characters and `?` matches any one character in the file name.
+pub fn expand_inputs(inputs: &[PathBuf]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if input.is_file() {
+            files.push(input.clone());
+        } else if input.is_dir() {
+            let images = images_in(input, |_| true)?;
+            if images.is_empty() {
+                bail!("{} doesn't contain any images.", input.display());
+            }
+            files.extend(images);
+        } else if let Some(pattern) = input
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| name.contains(['*', '?']))
+        {
+            let dir = match input.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let images = images_in(dir, |name| matches_pattern(pattern, name))?;
+            if images.is_empty() {
+                bail!("{} doesn't match any images.", input.display());
+            }
+            files.extend(images);
+        } else {
+            bail!("input_file path {} doesn't exist.", input.display());
+        }
+    }
+
+    Ok(files)
+}
+
+/// List the images and PDFs in `dir` whose file names pass `filter`, sorted
+/// by name.
+fn images_in(dir: &Path, filter: impl Fn(&str) -> bool) -> anyhow::Result<Vec<PathBuf>> {
+    let mut images = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if path.is_file() && is_input(&path) && filter(name) {
+            images.push(path);
+        }
+    }
+    images.sort();
+
+    Ok(images)
+}
+
+/// Save what's piped to stdin in `dir`, named `stdin` with the extension of
+/// its format, so that it can be scanned like any other file.
+pub fn save_stdin(dir: &Path) -> anyhow::Result<PathBuf> {
+    let mut bytes = Vec::new();
+    std::io::stdin()
+        .lock()
+        .read_to_end(&mut bytes)
+        .context("reading stdin")?;
+    if bytes.is_empty() {
+        bail!("nothing was piped to stdin.");
+    }
+    let Some(extension) = sniff_extension(&bytes) else {
+        bail!("what was piped to stdin isn't an image or a PDF.");
+    };
+    let path = dir.join(format!("stdin.{extension}"));
+    std::fs::write(&path, &bytes).with_context(|| format!("saving {}", path.display()))?;
+
+    Ok(path)
+}
+
+/// Guess a file's extension from its first few bytes, so that it's opened the
+/// right way.
+pub fn sniff_extension(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"%PDF") {
+        return Some("pdf");
+    }
+    // HEIF images start with a box naming the brand of the file.
+    if bytes.get(4..8) == Some(b"ftyp") {
+        match bytes.get(8..12) {
+            Some(b"avif" | b"avis") => return Some("avif"),
+            Some(b"heic" | b"heix" | b"heim" | b"heis" | b"mif1" | b"msf1") => return Some("heic"),
+            _ => {}
+        }
+    }
+    let format = image::guess_format(bytes).ok()?;
+    format.extensions_str().first().copied()
+}
+
+/// Whether `path` looks like something that can be scanned: an image or a
+/// PDF.
+pub fn is_input(path: &Path) -> bool {
+    image::ImageFormat::from_path(path).is_ok() || heif::is_heif(path) || pdf::is_pdf(path)
+}
+
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // `matched[j]` is whether the pattern so far matches the first `j`
+    // characters of the name.
+    let mut matched = vec![false; name.len() + 1];
+    matched[0] = true;
+    for p in pattern {
+        let previous = matched.clone();
+        matched[0] = p == '*' && previous[0];
+        for j in 1..=name.len() {
+            matched[j] = match p {
+                '*' => previous[j] || matched[j - 1],
+                '?' => previous[j - 1],
+                p => previous[j - 1] && p == name[j - 1],
+            };
+        }
+    }
+
+    matched[name.len()]
+}
+
+/// Pick an output directory for each page when scanning several at once: a
+/// subdirectory with the page's name, with a number added if two pages share
+/// a name.
+pub fn output_dirs(names: &[String], output_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    for name in names {
+        let mut dir = output_dir.join(name);
+        let mut n = 2;
+        while dirs.contains(&dir) {
+            dir = output_dir.join(format!("{name}-{n}"));
+            n += 1;
+        }
+        dirs.push(dir);
+    }
+
+    dirs
+}
+
+/// Pick an output directory for a page named `name` that doesn't exist yet:
+/// a subdirectory of `output_dir` with the page's name, with a number added
+/// if a directory with that name is already there.
+pub fn unused_output_dir(name: &str, output_dir: &Path) -> PathBuf {
+    let mut dir = output_dir.join(name);
+    let mut n = 2;
+    while dir.exists() {
+        dir = output_dir.join(format!("{name}-{n}"));
+        n += 1;
+    }
+
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches_pattern("page-*.jpeg", "page-12.jpeg"));
+        assert!(matches_pattern("page-?.jpeg", "page-1.jpeg"));
+        assert!(!matches_pattern("page-?.jpeg", "page-12.jpeg"));
+        assert!(!matches_pattern("page-*.jpeg", "page-1.png"));
+        assert!(matches_pattern("*", ""));
+    }
+
+    #[test]
+    fn recognizes_inputs() {
+        assert!(is_input(Path::new("scans/page-1.jpeg")));
+        assert!(is_input(Path::new("scans/pages.pdf")));
+        assert!(is_input(Path::new("scans/IMG_0001.HEIC")));
+        assert!(!is_input(Path::new("scans/notes.txt")));
+        assert!(!is_input(Path::new("scans/page-1.jpeg.part")));
+    }
+
+    #[test]
+    fn sniffs_formats() {
+        assert_eq!(sniff_extension(b"%PDF-1.7"), Some("pdf"));
+        assert_eq!(sniff_extension(b"\0\0\0\x18ftypheic\0\0\0\0"), Some("heic"));
+        assert_eq!(sniff_extension(b"\x89PNG\r\n\x1a\n"), Some("png"));
+        assert_eq!(sniff_extension(b"hello"), None);
+    }
+
+    #[test]
+    fn numbers_pages_that_share_a_name() {
+        let names = ["scan".to_string(), "other".to_string(), "scan".to_string()];
+        assert_eq!(
+            output_dirs(&names, Path::new("out")),
+            [
+                Path::new("out/scan"),
+                Path::new("out/other"),
+                Path::new("out/scan-2")
+            ]
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use image::{GrayImage, Luma};
+use qrcode::{Color, EcLevel, QrCode};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+/// The version of the layout code's contents. A scan ignores codes of later
+/// versions, which it wouldn't know how to read.
+pub const VERSION: u32 = 1;
+/// The smallest each square of a layout code may be drawn, in pixels, for it
+/// to survive printing and scanning.
+const MIN_MODULE_SIZE: u32 = 2;
+/// The number of empty squares left around a QR code, so that it stands out
+/// from whatever's near it.
+const QUIET_ZONE: u32 = 4;
+
+/// How a template is laid out, as recorded in the QR code printed on it, so
+/// that scans of it can be read without being told.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Layout {
+    /// The version of the layout code, which is [`VERSION`] for codes made
+    /// by this version of the app.
+    #[serde(rename = "v")]
+    pub version: u32,
+    pub rows: u32,
+    pub cols: u32,
+    /// The characters written on the template, as given to `--charset`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub charset: Option<String>,
+}
+
+impl Layout {
+    /// Describe a template of `rows` by `cols` cells with this version of
+    /// the layout code.
+    pub fn new(rows: u32, cols: u32, charset: Option<String>) -> Self {
+        Self {
+            version: VERSION,
+            rows,
+            cols,
+            charset,
+        }
+    }
+}
+
+/// Draw a layout as a QR code no more than `max_size` pixels wide, including
+/// its quiet zone. Returns `None` if it wouldn't be readable at that size.
+pub fn render(layout: &Layout, max_size: u32) -> Option<GrayImage> {
+    let contents = serde_json::to_string(layout).ok()?;
+    let code = QrCode::with_error_correction_level(contents, EcLevel::M).ok()?;
+    let modules = code.width() as u32;
+    let module_size = max_size / (modules + QUIET_ZONE * 2);
+    if module_size < MIN_MODULE_SIZE {
+        return None;
+    }
+
+    let size = (modules + QUIET_ZONE * 2) * module_size;
+    let colors = code.to_colors();
+    Some(GrayImage::from_fn(size, size, |x, y| {
+        let (x, y) = (x / module_size, y / module_size);
+        let inside = QUIET_ZONE..QUIET_ZONE + modules;
+        if !inside.contains(&x) || !inside.contains(&y) {
+            return Luma([255]);
+        }
+        let i = ((y - QUIET_ZONE) * modules + x - QUIET_ZONE) as usize;
+        match colors[i] {
+            Color::Dark => Luma([0]),
+            Color::Light => Luma([255]),
+        }
+    }))
+}
+
+/// Find and read the layout code on a page, if it has one.
+pub fn read(page: &GrayImage) -> Option<Layout> {
+    let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(
+        page.width() as usize,
+        page.height() as usize,
+        |x, y| page.get_pixel(x as u32, y as u32).0[0],
+    );
+    for grid in prepared.detect_grids() {
+        let Ok((_, contents)) = grid.decode() else {
+            continue;
+        };
+        match serde_json::from_str::<Layout>(&contents) {
+            Ok(layout) if layout.version <= VERSION && layout.rows > 0 && layout.cols > 0 => {
+                return Some(layout)
+            }
+            Ok(layout) => debug!("Ignoring a layout code of version {}.", layout.version),
+            Err(_) => debug!("Ignoring a QR code that isn't a layout code."),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_codes_round_trip() {
+        let layout = Layout::new(6, 8, Some("abc{th} d".to_string()));
+        let code = render(&layout, 200).unwrap();
+        // Place the code on a larger page, like it is on a template.
+        let mut page = GrayImage::from_pixel(400, 300, Luma([255]));
+        image::imageops::replace(&mut page, &code, 150, 80);
+        assert_eq!(read(&page), Some(layout));
+        assert!(render(&Layout::new(6, 8, None), 20).is_none());
+    }
+}
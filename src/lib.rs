@@ -0,0 +1,75 @@
+//! Scan a page of handwriting and extract the letters as individual images.
+//!
+//! The steps of a scan are in [`pipeline`]: open a scanned page with
+//! [`open_input`], clean it up with [`preprocess`], find its grid with
+//! [`locate_grid`] (or [`grid::detect_grid`]), cut it into cells with
+//! [`cut_cells`], and save each letter image with [`save_glyph`], or many at
+//! once with [`save_glyphs`]. The way the `scan` command runs these steps,
+//! finding a template's layout, reading a page again when its grid can't be
+//! found, and naming and recording each letter image, is in [`stages`]. The
+//! `handwriting-scan-tool` binary is a thin command line interface over this
+//! library.
+
+pub mod atlas;
+pub mod baseline;
+pub mod bitmap_font;
+pub mod build_font;
+pub mod calibrate;
+pub mod canvas;
+#[cfg(feature = "capture")]
+pub mod capture;
+pub mod charset;
+pub mod clean;
+pub mod cleanup;
+pub mod compare;
+pub mod config;
+pub mod contact_sheet;
+pub mod coverage;
+pub mod descriptor;
+pub mod deskew;
+pub mod diff;
+pub mod dpi;
+pub mod duplicates;
+pub mod export;
+pub mod glyph;
+pub mod glyph_metrics;
+pub mod glyph_set;
+pub mod grid;
+#[cfg(feature = "gui")]
+pub mod gui;
+pub mod guide_dots;
+pub mod guides;
+pub mod heif;
+pub mod inputs;
+pub mod layout_code;
+pub mod lighting;
+pub mod manifest;
+pub mod metrics;
+pub mod orientation;
+pub mod output;
+pub mod overlay;
+pub mod pdf;
+pub mod pen;
+pub mod perspective;
+pub mod pipeline;
+pub mod profile;
+pub mod quality;
+pub mod recovery;
+pub mod review;
+pub mod scan;
+pub mod sdf;
+pub mod serve;
+pub mod skeleton;
+pub mod stages;
+pub mod summary;
+pub mod template;
+pub mod threshold;
+pub mod truetype;
+pub mod upscale;
+pub mod vectorize;
+pub mod watch;
+
+pub use pipeline::{
+    cut_cells, locate_grid, open_input, preprocess, process_bytes, save_glyph, save_glyphs, Glyph,
+    PreprocessOptions, PreprocessReport, ProcessOptions, SaveOptions,
+};
@@ -0,0 +1,192 @@
+use clap::ValueEnum;
+use image::{imageops, GrayImage, Luma};
+use serde::Serialize;
+
+/// The longest side of the shrunk copy of a page that its lighting is
+/// measured on, in pixels. Handwriting and grid lines are thinner than a
+/// pixel at this size, so little but the paper is left.
+const BACKGROUND_SIZE: u32 = 256;
+/// How far around each pixel of the shrunk page the brightest paper is
+/// looked for, in pixels, so that what's left of the ink is passed over.
+const BACKGROUND_RADIUS: i64 = 2;
+/// How much the shrunk page's lighting is smoothed, so that it changes
+/// gradually across the page, like light does.
+const BACKGROUND_SIGMA: f32 = 4.0;
+/// The number of tiles across and down a page that CLAHE evens out the
+/// contrast of separately.
+pub const CLAHE_TILES: u32 = 8;
+/// How many times more often than average a brightness may appear in a
+/// tile before CLAHE stops stretching it, which keeps it from turning the
+/// grain of blank paper into noise.
+pub const CLAHE_CLIP_LIMIT: f32 = 2.0;
+
+/// A way to even out a page lit more brightly on one side than the other,
+/// like a phone photo, so that no part of it thresholds to solid black.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Lighting {
+    /// Measure how brightly each part of the paper is lit, and divide it
+    /// out. This suits most photos.
+    Flatten,
+    /// Contrast-limited adaptive histogram equalization: stretch the
+    /// contrast of each part of the page on its own, which brings out faint
+    /// strokes in dim or washed-out parts, and the paper's grain, too. It
+    /// doesn't brighten dim paper, so pair it with `--adaptive-threshold`.
+    Clahe,
+}
+
+impl Lighting {
+    /// Even out the lighting of a grayscale page.
+    pub fn apply(self, image: &GrayImage) -> GrayImage {
+        match self {
+            Self::Flatten => flatten(image),
+            Self::Clahe => clahe(image, CLAHE_TILES, CLAHE_CLIP_LIMIT),
+        }
+    }
+}
+
+/// Make the paper of a page equally bright all over, by dividing each pixel
+/// by how brightly the paper around it is lit. Ink stays as dark, compared
+/// with its paper, as it was.
+pub fn flatten(image: &GrayImage) -> GrayImage {
+    let (width, height) = image.dimensions();
+    let scale = (BACKGROUND_SIZE as f32 / width.max(height) as f32).min(1.0);
+    let (small_width, small_height) = (
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+    );
+    let small = imageops::resize(
+        image,
+        small_width,
+        small_height,
+        imageops::FilterType::Triangle,
+    );
+    let paper = GrayImage::from_fn(small_width, small_height, |x, y| {
+        let mut brightest = 0;
+        for dy in -BACKGROUND_RADIUS..=BACKGROUND_RADIUS {
+            for dx in -BACKGROUND_RADIUS..=BACKGROUND_RADIUS {
+                let x = (x as i64 + dx).clamp(0, small_width as i64 - 1) as u32;
+                let y = (y as i64 + dy).clamp(0, small_height as i64 - 1) as u32;
+                brightest = brightest.max(small.get_pixel(x, y).0[0]);
+            }
+        }
+        Luma([brightest])
+    });
+    let paper = imageproc::filter::gaussian_blur_f32(&paper, BACKGROUND_SIGMA);
+    let paper = imageops::resize(&paper, width, height, imageops::FilterType::Triangle);
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let value = image.get_pixel(x, y).0[0] as f32;
+        let paper = paper.get_pixel(x, y).0[0].max(1) as f32;
+        Luma([(value * 255.0 / paper).min(255.0) as u8])
+    })
+}
+
+/// Contrast-limited adaptive histogram equalization. The page is divided
+/// into `tiles` × `tiles` parts, and each part's contrast is stretched by
+/// its own histogram, clipped at `clip_limit` times the average count so
+/// that flat areas aren't stretched too far. Each pixel is mapped by the
+/// four tiles nearest it, so the seams between them don't show.
+pub fn clahe(image: &GrayImage, tiles: u32, clip_limit: f32) -> GrayImage {
+    let (width, height) = image.dimensions();
+    let (tiles_x, tiles_y) = (tiles.clamp(1, width.max(1)), tiles.clamp(1, height.max(1)));
+    let tile_width = width.div_ceil(tiles_x);
+    let tile_height = height.div_ceil(tiles_y);
+
+    // Each tile's mapping from brightness to brightness.
+    let mut maps = Vec::with_capacity((tiles_x * tiles_y) as usize);
+    for tile_y in 0..tiles_y {
+        for tile_x in 0..tiles_x {
+            let (left, top) = (tile_x * tile_width, tile_y * tile_height);
+            let (right, bottom) = (
+                (left + tile_width).min(width),
+                (top + tile_height).min(height),
+            );
+            let mut histogram = [0u32; 256];
+            for y in top..bottom {
+                for x in left..right {
+                    histogram[image.get_pixel(x, y).0[0] as usize] += 1;
+                }
+            }
+            let count = (right.saturating_sub(left) * bottom.saturating_sub(top)).max(1);
+            let limit = ((clip_limit * count as f32 / 256.0) as u32).max(1);
+            let mut excess = 0;
+            for bin in &mut histogram {
+                excess += bin.saturating_sub(limit);
+                *bin = (*bin).min(limit);
+            }
+            // What's clipped off is spread evenly over every brightness.
+            let (share, remainder) = (excess / 256, (excess % 256) as usize);
+            let mut map = [0u8; 256];
+            let mut total = 0;
+            for (i, bin) in histogram.iter().enumerate() {
+                total += bin + share + u32::from(i < remainder);
+                map[i] = (total as f32 * 255.0 / count as f32).round().min(255.0) as u8;
+            }
+            maps.push(map);
+        }
+    }
+
+    // The tiles on either side of a pixel, and how far it is between their
+    // centers.
+    let neighbors = |position: u32, size: u32, count: u32| {
+        let at = (position as f32 + 0.5) / size as f32 - 0.5;
+        let first = at.floor().clamp(0.0, (count - 1) as f32) as u32;
+        let second = (first + 1).min(count - 1);
+        (first, second, (at - first as f32).clamp(0.0, 1.0))
+    };
+    GrayImage::from_fn(width, height, |x, y| {
+        let value = image.get_pixel(x, y).0[0] as usize;
+        let (left, right, across) = neighbors(x, tile_width, tiles_x);
+        let (top, bottom, down) = neighbors(y, tile_height, tiles_y);
+        let map =
+            |tile_x: u32, tile_y: u32| maps[(tile_y * tiles_x + tile_x) as usize][value] as f32;
+        let upper = map(left, top) * (1.0 - across) + map(right, top) * across;
+        let lower = map(left, bottom) * (1.0 - across) + map(right, bottom) * across;
+        Luma([(upper * (1.0 - down) + lower * down).round() as u8])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A page lit brightly on the left and dimly on the right, with a dark
+    /// stroke down each side.
+    fn unevenly_lit_page() -> GrayImage {
+        GrayImage::from_fn(400, 300, |x, y| {
+            let paper = 240.0 - x as f32 * 0.35;
+            let ink = (50..54).contains(&x) || (350..354).contains(&x);
+            let value = if ink && (50..250).contains(&y) {
+                paper * 0.3
+            } else {
+                paper
+            };
+            Luma([value as u8])
+        })
+    }
+
+    #[test]
+    fn flattens_lighting() {
+        let page = unevenly_lit_page();
+        // Thresholding the page as it is turns its dim side to ink.
+        assert!(page.get_pixel(380, 20).0[0] < 128);
+        let even = Lighting::Flatten.apply(&page);
+        for (x, y) in [(20, 20), (200, 150), (380, 20)] {
+            assert!(even.get_pixel(x, y).0[0] >= 128, "paper at {x}, {y}");
+        }
+        for (x, y) in [(51, 150), (351, 150)] {
+            assert!(even.get_pixel(x, y).0[0] < 128, "ink at {x}, {y}");
+        }
+    }
+
+    #[test]
+    fn stretches_contrast_of_dim_parts() {
+        let page = unevenly_lit_page();
+        let contrast = |page: &GrayImage| {
+            page.get_pixel(380, 150).0[0] as i32 - page.get_pixel(351, 150).0[0] as i32
+        };
+        let stretched = Lighting::Clahe.apply(&page);
+        assert!(contrast(&stretched) > contrast(&page));
+    }
+}
@@ -1,15 +1,44 @@
 use anyhow::{bail, Context};
-use clap::{Args, Parser, Subcommand};
-use image::{GenericImageView, ImageBuffer, Pixel, SubImage};
-use std::{ops::Deref, path::PathBuf};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use handwriting_scan_tool::{
+    atlas, build_font, calibrate, clean, compare, config, coverage, diff, export, metrics, scan,
+    serve, summary, template,
+};
+use std::process::ExitCode;
+use tracing::Level;
+use tracing_subscriber::{
+    fmt::{format::FmtSpan, time::Uptime},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+};
 
 #[derive(Debug, Parser)]
 struct Cli {
     #[clap(subcommand)]
     cmd: Command,
+
+    /// Show more about what's happening, including how long each step takes.
+    /// Pass this twice to show everything.
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Only show warnings and errors.
+    #[arg(
+        short,
+        long,
+        default_value_t = false,
+        global = true,
+        conflicts_with = "verbose"
+    )]
+    quiet: bool,
+
+    /// Ignore the config file, and use the built-in defaults.
+    #[arg(long, default_value_t = false, global = true)]
+    no_config: bool,
 }
 
-/// Doc comment
+/// Scan pages of handwriting, extract the letters as individual images, and
+/// turn them into fonts.
 #[derive(Debug, Subcommand)]
 enum Command {
     /// Scan a page of handwriting and extract the letters as individual images.
@@ -28,161 +57,247 @@ enum Command {
     ///
     /// _Good luck!_ － Zelda
     #[command()]
-    Scan(ScanArgs),
-}
+    Scan(Box<scan::ScanArgs>),
+
+    /// Compare extracted letterforms against a reference alphabet.
+    ///
+    /// For every extracted letter image, the matching reference glyph is
+    /// taken from a font or from a directory of images. Both glyphs are
+    /// cropped to their ink and scaled to the same size, then overlaid: the
+    /// reference is drawn in blue, your letterform in red, and the overlap in
+    /// black. This makes it easy to see where your handwriting deviates from
+    /// the reference.
+    #[command()]
+    Compare(compare::CompareArgs),
+
+    /// Compare two runs of extracted letterforms, glyph by glyph.
+    ///
+    /// Each side is a directory of letter images written by the `scan`
+    /// command, or a scanned page. Letterforms with the same name are
+    /// overlaid, and how much each one changed is printed, most changed
+    /// first. Images of the most changed ones are written side by side, so
+    /// you can see exactly which letters improved after a rescan.
+    #[command()]
+    Diff(diff::DiffArgs),
+
+    /// Build a font from extracted letterforms.
+    ///
+    /// Each letter image is traced into smooth outlines and assigned to its
+    /// character, either by the image's name or by the charset. The glyphs are
+    /// scaled together so that your tallest letter is as tall as a capital,
+    /// placed on the baseline, and written to a TrueType font that can be
+    /// installed and used like any other.
+    #[command()]
+    BuildFont(build_font::BuildFontArgs),
+
+    /// Export extracted letterforms as a font source project.
+    ///
+    /// Each letter image is traced and placed the same way as by the
+    /// `build-font` command, then written to a UFO or FontForge project, one
+    /// glyph per character, with glyph names, Unicode values, and placeholder
+    /// metrics. Open the project in a font editor to keep refining the font.
+    #[command()]
+    Export(export::ExportArgs),
+
+    /// Pack extracted letterforms into a single texture atlas.
+    ///
+    /// Every letter image is placed on one image, in rows, tallest first.
+    /// Alongside it, a JSON or CSV file gives each glyph's character, its
+    /// rectangle on the atlas in pixels and in texture coordinates, and how
+    /// far to advance after drawing it. This is the form game engines and
+    /// other bitmap font renderers expect.
+    #[command()]
+    Atlas(atlas::AtlasArgs),
+
+    /// Report which characters have been captured, and which are missing.
+    ///
+    /// The letter images in a directory, or the cells saved in a manifest,
+    /// are matched to the characters you want to capture. Characters with
+    /// no letter image are listed as missing, and those with several are
+    /// listed too. Pass `--template` to print a template of just the
+    /// missing characters, to fill in on a new sheet.
+    #[command()]
+    Coverage(coverage::CoverageArgs),
+
+    /// Delete the files that an earlier scan created.
+    ///
+    /// Every letter image, outline, and contact sheet listed in the scan's
+    /// manifests is deleted, along with the manifests, and nothing else.
+    /// This makes it safe to clean up a scan saved in a directory shared
+    /// with other files. Pass `--dry-run` to list the files first.
+    #[command()]
+    Clean(clean::CleanArgs),
+
+    /// Measure how you space your handwriting, from a page of sentences.
+    ///
+    /// Print a lined template with `generate-template --lined`, copy out its
+    /// sentences in your usual hand, and scan it. This command measures the
+    /// space you leave between letters and between words, and which pairs
+    /// of letters you tuck closer together or leave farther apart. Pass the
+    /// file it writes to `build-font --metrics` or `export --metrics`, so
+    /// your font is spaced like your writing rather than like a typewriter.
+    #[command()]
+    Metrics(metrics::MetricsArgs),
+
+    /// Make a printable template with a grid of any size.
+    ///
+    /// The template is laid out like the bundled one, so it can be scanned
+    /// the same way. If a charset is given, each character is printed faintly
+    /// in its cell, so you know what to write where. Pass the same number of
+    /// rows and columns to the `scan` command when scanning it.
+    #[command()]
+    GenerateTemplate(template::TemplateArgs),
+
+    /// Check a scan of a blank template before writing on it.
+    ///
+    /// Print the template, scan it without writing anything, and pass the
+    /// scan to this command. It checks that the template was printed at the
+    /// right scale, that the page sits straight with the whole grid in view,
+    /// and that every grid line can be found, and says what to fix if not.
+    /// It's much cheaper to find a bad print run now than after filling in
+    /// a page.
+    #[command()]
+    Calibrate(calibrate::CalibrateArgs),
 
-/// Doc comment
-#[derive(Args, Debug)]
-struct ScanArgs {
-    /// The image file that will be scanned.
-    ///
-    /// See
-    /// https://github.com/image-rs/image/blob/main/README.md#supported-image-formats
-    /// for a list of supported image formats.
-    ///
-    /// An example handwriting scan image is included in this app's repository.
-    /// The file is named `example-handwriting-scan.jpeg`.
-    #[arg(short, long)]
-    input_file: PathBuf,
-
-    /// The directory that the letter images will be written to. If not
-    /// provided, the images will be written to the current working directory.
-    #[arg(short, long)]
-    output_dir: Option<PathBuf>,
-
-    /// By default, the app will ask for confirmation before saving the images.
-    /// If you want to skip confirmation, pass this flag.
-    #[arg(short, long, default_value_t = false)]
-    yes: bool,
-
-    /// Threshold value to use during processing. The default value is 190.
-    /// This value should be between 0 and 255.
-    #[arg(short, long, default_value = "190")]
-    threshold: u8,
+    /// Scan pages uploaded over HTTP.
+    ///
+    /// POST an image or PDF of a page to `/scan`, and its letters are sent
+    /// back as JSON, with each file encoded as base64, or as a zip file if
+    /// `format=zip` is in the query string. A few settings, like `rows` and
+    /// `cols`, can be changed in the query string too. This makes it easy to
+    /// put the app behind a web form, so others can scan their handwriting
+    /// without installing it.
+    #[command()]
+    Serve(serve::ServeArgs),
+
+    /// Scan a page held up to a webcam.
+    ///
+    /// The camera's view is shown in a window, with the template's grid
+    /// outlined once it's found. When the template has held still, fully in
+    /// frame, for a moment, a snapshot is saved and scanned as a photo, just
+    /// like `scan --camera`. Only available when built with the `capture`
+    /// feature.
+    #[cfg(feature = "capture")]
+    #[command()]
+    Capture(handwriting_scan_tool::capture::CaptureArgs),
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = Cli::parse();
+fn main() -> anyhow::Result<ExitCode> {
+    let args = parse_args()?;
+    init_logging(args.verbose, args.quiet);
     match args.cmd {
         Command::Scan(scan_args) => {
-            scan(scan_args)?;
+            let status = scan::scan(*scan_args, &confirm_save)?;
+            return Ok(ExitCode::from(status.exit_code()));
+        }
+        Command::Compare(compare_args) => {
+            compare::compare(compare_args)?;
+        }
+        Command::Diff(diff_args) => {
+            diff::diff(diff_args)?;
+        }
+        Command::BuildFont(build_font_args) => {
+            build_font::build_font(build_font_args)?;
+        }
+        Command::Export(export_args) => {
+            export::export(export_args)?;
+        }
+        Command::Atlas(atlas_args) => {
+            atlas::atlas(atlas_args)?;
+        }
+        Command::Coverage(coverage_args) => {
+            coverage::coverage(coverage_args)?;
+        }
+        Command::Clean(clean_args) => {
+            clean::clean(clean_args)?;
+        }
+        Command::Metrics(metrics_args) => {
+            metrics::metrics(metrics_args)?;
+        }
+        Command::GenerateTemplate(template_args) => {
+            template::generate_template(template_args)?;
+        }
+        Command::Calibrate(calibrate_args) => {
+            calibrate::calibrate(calibrate_args)?;
+        }
+        Command::Serve(serve_args) => {
+            serve::serve(serve_args)?;
+        }
+        #[cfg(feature = "capture")]
+        Command::Capture(capture_args) => {
+            handwriting_scan_tool::capture::capture(capture_args, &confirm_save)?;
         }
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
 
-fn scan(args: ScanArgs) -> anyhow::Result<()> {
-    let ScanArgs {
-        input_file,
-        output_dir,
-        yes,
-        threshold,
-    } = args;
-    // validate input file
-    if !input_file.is_file() {
-        bail!("input_file path doesn't exist or is not a file.");
+/// Parse the command line, with the defaults in the config file, if there is
+/// one, filled in for any arguments that weren't given.
+fn parse_args() -> anyhow::Result<Cli> {
+    let mut command = Cli::command();
+    let no_config = std::env::args_os().any(|arg| arg == "--no-config");
+    if let Some(path) = config::path().filter(|path| !no_config && path.is_file()) {
+        command = config::Config::load(&path)?
+            .apply(command)
+            .with_context(|| format!("reading {}", path.display()))?;
     }
+    let matches = command.get_matches();
 
-    // validate output directory
-    let output_dir = match output_dir {
-        Some(dir) => dir,
-        None => std::env::current_dir()?,
-    };
-    if output_dir.is_file() {
-        bail!("output_dir path must be a directory.");
-    }
+    Ok(Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit()))
+}
 
-    println!("Loading image...");
-    let mut image = image::open(&input_file).context("opening input_file")?;
-    if image.height() > image.width() {
-        image = image.rotate270();
-    }
-    println!("Scanning handwriting...");
-    // Generic sharpening filter
-    let image = image.filter3x3(&[0.0, -1.0, 0.0, -1.0, 5.0, -1.0, 0.0, -1.0, 0.0]);
-    // Threshold the image
-    let image = imageproc::contrast::threshold(&image.to_luma8(), threshold);
-    // Crop the border
-    let (width, height) = image.dimensions();
-    let x = (width as f32 * 0.066).floor() as u32;
-    let y = (height as f32 * 0.079).floor() as u32;
-    let width = width - x * 2;
-    let height = height - y * 2 + (y as f32 * 0.2) as u32;
-
-    let image = image.view(x, y, width, height).to_image();
-
-    let letter_images = grid_cut_image(&image, 12, 9);
-
-    println!(
-        "Scan complete; {} letterforms were detected.",
-        letter_images.len()
-    );
-    let confirmation = if yes {
-        true
-    } else {
-        loop {
-            println!("OK to save the images? (y/n)");
-
-            let mut input = String::new();
-            std::io::stdin().read_line(&mut input).unwrap();
-            let input = input.trim();
-            if input.starts_with('y') {
-                break true;
-            } else if input.starts_with('n') {
-                break false;
-            } else {
-                println!("Invalid input.");
-            }
+/// Ask on stdin whether to save a page's letter images, until the answer is
+/// yes or no.
+fn confirm_save() -> anyhow::Result<bool> {
+    loop {
+        println!("OK to save the images? (y/n)");
+
+        let mut input = String::new();
+        let read = std::io::stdin()
+            .read_line(&mut input)
+            .context("reading the answer")?;
+        if read == 0 {
+            bail!("stdin was closed before the images could be saved. Pass --yes to save them without asking.");
         }
-    };
-    if confirmation {
-        println!("Saving images...");
-        std::fs::create_dir_all(&output_dir).context("creating output dir")?;
-        for (i, letter_image) in letter_images.iter().enumerate() {
-            let output_file = output_dir.join(format!("letter-{}.jpeg", i));
-            letter_image.to_image().save(&output_file)?;
+        let input = input.trim();
+        if input.starts_with('y') {
+            return Ok(true);
+        } else if input.starts_with('n') {
+            return Ok(false);
+        } else {
+            println!("Invalid input.");
         }
-        println!("Images saved successfully.");
-    } else {
-        println!("Very well. Exiting without saving...");
     }
-
-    Ok(())
 }
 
-fn grid_cut_image<P, Container>(
-    image_buffer: &ImageBuffer<P, Container>,
-    width: u32,
-    height: u32,
-) -> Vec<SubImage<&ImageBuffer<P, Container>>>
-where
-    P: Pixel,
-    Container: Deref<Target = [P::Subpixel]>,
-{
-    let mut letter_images = Vec::with_capacity((width * height) as usize);
-    // Divide the image into a grid of cells. Then, push each cell into the
-    // `letter_images` vec before returning it.
-    let (image_width, image_height) = image_buffer.dimensions();
-    let cell_width = image_width / width;
-    let cell_height = image_height / height;
-
-    for row in 0..height {
-        for col in 0..width {
-            let x = col * cell_width;
-            let y = row * cell_height;
-            let sub_image = image_buffer.view(x, y, cell_width, cell_height);
-            letter_images.push(sub_image);
-        }
+/// Print log messages to stderr, at the level chosen by `--verbose` or
+/// `--quiet`. When verbose, the time each step of a scan takes is shown as
+/// the step finishes.
+fn init_logging(verbose: u8, quiet: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => Level::WARN,
+        (false, 0) => Level::INFO,
+        (false, 1) => Level::DEBUG,
+        (false, _) => Level::TRACE,
+    };
+    let logger = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_target(verbose > 1)
+        .with_writer(std::io::stderr);
+    // Warnings are also kept for `scan --json` to report.
+    if verbose > 0 {
+        logger
+            .with_span_events(FmtSpan::CLOSE)
+            .with_timer(Uptime::default())
+            .finish()
+            .with(summary::WarningLog)
+            .init();
+    } else {
+        logger
+            .without_time()
+            .finish()
+            .with(summary::WarningLog)
+            .init();
     }
-
-    letter_images
 }
-
-/*
-glyphs
-1 2 3 4 5 6 7 8 9 0 - plus
-! @ # $ % ^ & * ( ) _ 
-, . / ; ' [ ] \ < > ? : " { } | ` ~ 
-
-*/
\ No newline at end of file
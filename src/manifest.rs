@@ -0,0 +1,569 @@
+use anyhow::{bail, Context};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    canvas::{Align, Canvas},
+    duplicates::Fingerprint,
+    glyph_metrics::GlyphMetrics,
+    guides::Guides,
+    lighting::Lighting,
+    quality::{Issue, Quality},
+    skeleton::SkeletonFormat,
+    threshold::Threshold,
+};
+
+/// The name of the manifest file written next to the letter images.
+pub const FILE_NAME: &str = "manifest.json";
+
+/// The name of the manifest file, with `suffix` added to it if the letter
+/// images' names have one.
+pub fn file_name(suffix: Option<&str>) -> String {
+    match suffix {
+        Some(suffix) => format!("manifest{suffix}.json"),
+        None => FILE_NAME.to_string(),
+    }
+}
+
+/// A description of every cell scanned into one output directory, so that
+/// other tools can find each letter image and learn where it came from
+/// without parsing file names.
+#[derive(Debug, Default, Serialize)]
+pub struct Manifest {
+    /// The pages that were scanned, in order.
+    pub pages: Vec<PageEntry>,
+    /// Every cell of every page, in order, whether or not it was saved.
+    pub glyphs: Vec<GlyphEntry>,
+    /// The other images scans saved in the directory, relative to it, like
+    /// `--debug-output` images, threshold sweeps, and `grid-not-found.png`,
+    /// so that `clean` can find them. Those saved by earlier scans are kept
+    /// while they're still there.
+    pub artifacts: Vec<PathBuf>,
+}
+
+/// A scanned page, and how it was processed.
+#[derive(Debug, Serialize)]
+pub struct PageEntry {
+    pub input_file: PathBuf,
+    /// The page of the input file, if it's a PDF.
+    pub pdf_page: Option<u32>,
+    /// The resolution of the input, if it's recorded.
+    pub dpi: Option<f32>,
+    pub rows: u32,
+    pub cols: u32,
+    /// Whether the grid's lines were found. If not, the cells were cut inside
+    /// the margins given for them.
+    pub grid_found: bool,
+    /// How many of the grid's lines weren't found, and were placed where
+    /// they were expected to be instead.
+    pub guessed_lines: u32,
+    /// Where the page's contact sheet was saved, if one was.
+    pub contact_sheet: Option<PathBuf>,
+    pub preprocessing: Preprocessing,
+}
+
+/// The settings used to clean up a page and save its cells.
+#[derive(Debug, Serialize)]
+pub struct Preprocessing {
+    /// The threshold that was asked for: a number, or `"auto"`.
+    #[serde(serialize_with = "serialize_threshold")]
+    pub threshold: Threshold,
+    /// The threshold that was actually used, after choosing one
+    /// automatically or adjusting it for the input's resolution.
+    pub threshold_level: u8,
+    pub adaptive_threshold: Option<u32>,
+    /// The name or path of the profile the page was cleaned up with, if one
+    /// was given.
+    pub profile: Option<String>,
+    pub camera: bool,
+    /// How the page's lighting was evened out, if it was.
+    pub lighting: Option<Lighting>,
+    pub deskew: bool,
+    /// How far the page was turned clockwise to turn it upright, in degrees.
+    pub rotation: u32,
+    /// How far the page was rotated to straighten it, in degrees.
+    pub skew_degrees: Option<f32>,
+    pub min_ink: f32,
+    /// How many pixels of whitespace were left around each letterform, or
+    /// `None` if whole cells were saved.
+    pub padding: Option<u32>,
+    /// Whether fragments of the grid lines were erased from each cell.
+    pub grid_lines_removed: bool,
+    /// Specks of ink smaller than this many pixels were erased.
+    pub despeckle: Option<u32>,
+    /// The radius of the morphological opening applied to each cell.
+    pub open: Option<u8>,
+    /// The radius of the morphological closing applied to each cell.
+    pub close: Option<u8>,
+    /// The width that strokes were thickened or thinned to, in pixels.
+    pub stroke_width: Option<f32>,
+    /// Whether letter images were saved in shades of gray.
+    pub grayscale: bool,
+    /// The color of pen that was read, as a hex code or `"auto"`, if only
+    /// ink of one color was read.
+    pub pen_color: Option<String>,
+    /// The size of the image every letterform was placed on, if any.
+    pub canvas: Option<Canvas>,
+    /// Where each letterform was placed on its canvas.
+    pub align: Option<Align>,
+    /// How much letter images were resized by to reach `--target-height`.
+    pub scale: Option<f32>,
+    /// How many times bigger letter images were made by `--upscale`.
+    pub upscale: Option<f32>,
+    pub pot: bool,
+    /// Whether the paper was made transparent.
+    pub alpha: bool,
+    /// The color ink was drawn in, as a hex code, if it isn't black.
+    pub ink_color: Option<String>,
+    /// Whether an SVG outline was saved next to each letter image.
+    pub vectorize: bool,
+    /// How each letterform's centerline was saved, if it was.
+    pub skeleton: Option<SkeletonFormat>,
+    /// The file extension of the letter images.
+    pub output_format: &'static str,
+}
+
+/// One cell of a page.
+#[derive(Debug, Serialize)]
+pub struct GlyphEntry {
+    /// The position of the glyph's page in [`Manifest::pages`].
+    pub page: usize,
+    /// The cell's position in the charset. When pages are merged, this runs
+    /// on from one page to the next.
+    pub cell: usize,
+    pub row: u32,
+    pub col: u32,
+    /// The character the cell was saved as, or the sequence of characters
+    /// for a ligature, which may have been changed while reviewing.
+    pub character: Option<String>,
+    /// The name of the letter image, relative to the manifest, if it was
+    /// saved.
+    pub file: Option<String>,
+    /// Which alternate of its character the letter image is, like 1 for
+    /// `U+0041_A.alt1.png`, or `None` if it's the first image of it.
+    /// Letter images of the same character are grouped by this.
+    pub alternate: Option<u32>,
+    pub status: GlyphStatus,
+    /// The cell's rectangle on the page, after the page was straightened.
+    pub cell_bounds: Bounds,
+    /// The letterform's rectangle on the page, if the cell was saved and
+    /// isn't empty.
+    pub ink_bounds: Option<Bounds>,
+    /// How much of the cell is covered by ink, as a fraction between 0 and 1.
+    pub ink_coverage: f32,
+    /// Where the template's baseline and x-height guides are in the letter
+    /// image, if they were found in the cell, so that letters can be lined
+    /// up with each other in a font.
+    pub guides: Option<Guides>,
+    /// How the letterform is spaced and where it sits on the baseline, if
+    /// the cell was saved and isn't empty, so that a font built from it
+    /// isn't spaced evenly.
+    pub metrics: Option<GlyphMetrics>,
+    /// How the letter image was padded to a power-of-two size, if it was
+    /// with `--pot`, so that the letterform's texture coordinates can be
+    /// found.
+    pub pot_padding: Option<PotPadding>,
+    /// How wide the letterform's strokes were, in pixels, if strokes were
+    /// normalized.
+    pub stroke_width: Option<f32>,
+    /// Whether the letterform ran into the edge of its cell, and may have
+    /// been cut off.
+    pub clipped: bool,
+    /// The area around the cell that the letter image was cut from, if the
+    /// letterform was followed past the cell's edge with `--expand-clipped`.
+    pub expanded_bounds: Option<Bounds>,
+    /// How well the letterform was captured, if the cell was saved and
+    /// isn't empty.
+    pub quality: Option<Quality>,
+    /// What looks wrong with the letterform, if anything.
+    pub issues: Vec<Issue>,
+    /// The letter image that this letterform looks like a copy of, if any.
+    pub duplicate_of: Option<PathBuf>,
+    /// The letterform's shape, for finding copies of it.
+    #[serde(skip)]
+    pub fingerprint: Option<Fingerprint>,
+}
+
+/// What happened to a cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GlyphStatus {
+    Saved,
+    /// The cell had less ink than `--min-ink`.
+    Empty,
+    /// The cell matched the baseline scan.
+    Unchanged,
+    /// The cell was left out while reviewing.
+    Rejected,
+    /// Another cell was saved under the same name, and that one was kept.
+    Conflict,
+    /// The cell was saved, then replaced by a later cell with the same name.
+    Replaced,
+    /// The letter image couldn't be saved.
+    Failed,
+    /// An earlier scan saved the letter image, and it was kept as it is:
+    /// it's newer than the input, with `--skip-existing`, or its letterform
+    /// hasn't changed, with `--update`.
+    Existing,
+    /// The letterform looked like a copy of an earlier one, so its letter
+    /// image was removed with `--dedup`.
+    Duplicate,
+}
+
+/// Where a letter image sits in its power-of-two padding, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct PotPadding {
+    /// How much paper was added to the left of the letter image.
+    pub left: u32,
+    /// How much paper was added above the letter image.
+    pub top: u32,
+    /// The width of the letter image before it was padded.
+    pub width: u32,
+    /// The height of the letter image before it was padded.
+    pub height: u32,
+}
+
+/// A rectangle, in pixels.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Bounds {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl From<(u32, u32, u32, u32)> for Bounds {
+    fn from((x, y, width, height): (u32, u32, u32, u32)) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+impl Manifest {
+    /// Mark the saved glyph whose file is named `file_stem`, without its
+    /// extension, as replaced by a later one.
+    pub fn replace(&mut self, file_stem: &str) {
+        for glyph in &mut self.glyphs {
+            let stem = glyph
+                .file
+                .as_deref()
+                .and_then(|file| Path::new(file).file_stem())
+                .and_then(|stem| stem.to_str());
+            if glyph.status == GlyphStatus::Saved && stem == Some(file_stem) {
+                glyph.status = GlyphStatus::Replaced;
+                glyph.file = None;
+            }
+        }
+    }
+
+    /// Note that a scan saved the image at `path`, if it's in `dir`, the
+    /// directory the manifest is written to.
+    pub fn add_artifact(&mut self, dir: &Path, path: &Path) {
+        let (Ok(dir), Ok(path)) = (std::fs::canonicalize(dir), std::fs::canonicalize(path)) else {
+            return;
+        };
+        if let Ok(relative) = path.strip_prefix(dir) {
+            if !self.artifacts.iter().any(|artifact| artifact == relative) {
+                self.artifacts.push(relative.to_path_buf());
+            }
+        }
+    }
+
+    /// Keep the artifacts listed by the manifest named `file_name` that an
+    /// earlier scan wrote to `dir`, if there is one, and if they're still
+    /// there.
+    pub fn keep_artifacts(&mut self, dir: &Path, file_name: &str) {
+        let Ok(earlier) = read(&dir.join(file_name)) else {
+            return;
+        };
+        let earlier = earlier["artifacts"].as_array().into_iter().flatten();
+        for artifact in earlier.filter_map(|artifact| artifact.as_str()) {
+            let artifact = Path::new(artifact);
+            let inside = artifact
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+            if inside
+                && dir.join(artifact).is_file()
+                && !self.artifacts.iter().any(|kept| kept == artifact)
+            {
+                self.artifacts.push(artifact.to_path_buf());
+            }
+        }
+    }
+
+    /// Write the manifest into `dir`, named `file_name`.
+    pub fn write(&self, dir: &Path, file_name: &str) -> anyhow::Result<()> {
+        let path = dir.join(file_name);
+        let json = serde_json::to_string_pretty(self).context("serializing the manifest")?;
+        std::fs::write(&path, json).with_context(|| format!("writing {}", path.display()))
+    }
+}
+
+/// Read a manifest written by the `scan` command, without checking what's in
+/// it, so that manifests written by older versions can be read too.
+pub fn read(path: &Path) -> anyhow::Result<serde_json::Value> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("parsing {}", path.display()))
+}
+
+/// Whether the manifest at `path` describes a scan of any pages, rather than
+/// only images like a threshold sweep. A manifest that can't be read counts.
+pub fn holds_scan(path: &Path) -> bool {
+    path.exists()
+        && read(path).map_or(true, |manifest| {
+            manifest["pages"]
+                .as_array()
+                .is_none_or(|pages| !pages.is_empty())
+        })
+}
+
+/// Add `artifacts` to those listed by the manifest at `path`.
+pub fn add_artifacts(path: &Path, artifacts: &[PathBuf]) -> anyhow::Result<()> {
+    let mut manifest = read(path)?;
+    if !manifest["artifacts"].is_array() {
+        manifest["artifacts"] = serde_json::Value::Array(Vec::new());
+    }
+    if let Some(listed) = manifest["artifacts"].as_array_mut() {
+        for artifact in artifacts {
+            let artifact = serde_json::to_value(artifact).context("serializing the manifest")?;
+            if !listed.contains(&artifact) {
+                listed.push(artifact);
+            }
+        }
+    }
+    let json = serde_json::to_string_pretty(&manifest).context("serializing the manifest")?;
+    std::fs::write(path, json).with_context(|| format!("writing {}", path.display()))
+}
+
+/// What changed when one scan's manifest was merged into an earlier one's.
+#[derive(Debug, Default, PartialEq)]
+pub struct Merged {
+    /// The earlier scan's letter images that the later one replaced.
+    pub replaced: Vec<String>,
+    /// The letter images that only the later scan saved.
+    pub added: Vec<String>,
+    /// How many of the earlier scan's letter images were kept as they were.
+    pub kept: usize,
+    /// Letter images replaced by one of a different character, and
+    /// characters that now have letter images under two names, described
+    /// for a warning.
+    pub conflicts: Vec<String>,
+}
+
+/// Merge `later`, the manifest of a scan saved into the same directory as
+/// `earlier`, into it. The later scan's pages and cells are listed after the
+/// earlier scan's, and the earlier cells whose letter images it replaced are
+/// marked as replaced.
+pub fn merge(
+    mut earlier: serde_json::Value,
+    later: serde_json::Value,
+) -> anyhow::Result<(serde_json::Value, Merged)> {
+    use serde_json::Value;
+
+    let (Some(earlier_pages), Some(later_pages)) =
+        (earlier["pages"].as_array(), later["pages"].as_array())
+    else {
+        bail!("the manifests to merge weren't written by the scan command.");
+    };
+    let (page_offset, later_pages) = (earlier_pages.len(), later_pages.clone());
+    let saved = |glyph: &Value| {
+        let file = glyph["file"].as_str()?;
+        let character = glyph["character"].as_str();
+        Some((file.to_string(), character.map(str::to_string)))
+    };
+    let later_glyphs = later["glyphs"].as_array().cloned().unwrap_or_default();
+    // The later scan's letter images, with their characters.
+    let later_files: HashMap<String, Option<String>> =
+        later_glyphs.iter().filter_map(saved).collect();
+
+    let mut report = Merged::default();
+    // The first images of characters that the earlier scan's letter images
+    // are kept for, which the later scan shouldn't save under another name.
+    let mut kept_characters = HashMap::new();
+    let earlier_glyphs = earlier["glyphs"].as_array_mut();
+    for glyph in earlier_glyphs.into_iter().flatten() {
+        let Some((file, character)) = saved(glyph) else {
+            continue;
+        };
+        match later_files.get(&file) {
+            Some(later_character) => {
+                if *later_character != character {
+                    report.conflicts.push(format!(
+                        "{file} was {}, and was replaced by {}.",
+                        describe(character.as_deref()),
+                        describe(later_character.as_deref())
+                    ));
+                }
+                glyph["status"] = serde_json::to_value(GlyphStatus::Replaced)?;
+                glyph["file"] = Value::Null;
+                report.replaced.push(file);
+            }
+            None => {
+                report.kept += 1;
+                if let Some(character) = character.filter(|_| glyph["alternate"].is_null()) {
+                    kept_characters.entry(character).or_insert(file);
+                }
+            }
+        }
+    }
+
+    for mut glyph in later_glyphs {
+        if let Some(page) = glyph["page"].as_u64() {
+            glyph["page"] = (page + page_offset as u64).into();
+        }
+        if let Some((file, character)) = saved(&glyph) {
+            let replaced = report.replaced.contains(&file);
+            if !replaced {
+                report.added.push(file.clone());
+            }
+            let kept = character
+                .as_ref()
+                .and_then(|character| kept_characters.get(character))
+                .filter(|_| !replaced && glyph["alternate"].is_null());
+            if let Some(kept) = kept {
+                report.conflicts.push(format!(
+                    "{} now has two letter images, {kept} and {file}.",
+                    describe(character.as_deref())
+                ));
+            }
+        }
+        if let Some(glyphs) = earlier["glyphs"].as_array_mut() {
+            glyphs.push(glyph);
+        }
+    }
+    if let Some(pages) = earlier["pages"].as_array_mut() {
+        pages.extend(later_pages);
+    }
+    let later_artifacts = later["artifacts"].as_array().cloned().unwrap_or_default();
+    match earlier["artifacts"].as_array_mut() {
+        Some(artifacts) => {
+            for artifact in later_artifacts {
+                if !artifacts.contains(&artifact) {
+                    artifacts.push(artifact);
+                }
+            }
+        }
+        None => earlier["artifacts"] = later_artifacts.into(),
+    }
+
+    Ok((earlier, report))
+}
+
+/// A cell's character, or sequence of characters, for a message.
+fn describe(character: Option<&str>) -> String {
+    match character {
+        Some(character) => format!("{character:?}"),
+        None => "a cell without a character".to_string(),
+    }
+}
+
+/// Read `field` of every glyph in the manifests that the `scan` command
+/// wrote in `dir`, by the path of its letter image. Glyphs without a letter
+/// image or without the field are left out.
+pub fn read_glyph_field<T: DeserializeOwned>(
+    dir: &Path,
+    field: &str,
+) -> anyhow::Result<HashMap<PathBuf, T>> {
+    let mut values = HashMap::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let path = entry?.path();
+        let is_manifest = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("manifest") && name.ends_with(".json"));
+        if !is_manifest || !path.is_file() {
+            continue;
+        }
+        let manifest = read(&path)?;
+        for glyph in manifest["glyphs"].as_array().into_iter().flatten() {
+            let Some(file) = glyph["file"].as_str() else {
+                continue;
+            };
+            if let Ok(value) = T::deserialize(&glyph[field]) {
+                values.insert(dir.join(file), value);
+            }
+        }
+    }
+
+    Ok(values)
+}
+
+fn serialize_threshold<S: serde::Serializer>(
+    threshold: &Threshold,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match threshold {
+        Threshold::Fixed(level) => serializer.serialize_u8(*level),
+        Threshold::Auto => serializer.serialize_str("auto"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn glyph(page: u64, file: Option<&str>, character: Option<&str>) -> serde_json::Value {
+        json!({
+            "page": page,
+            "file": file,
+            "character": character,
+            "alternate": null,
+            "status": if file.is_some() { "saved" } else { "empty" },
+        })
+    }
+
+    #[test]
+    fn merges_a_rescan_into_an_earlier_one() {
+        let earlier = json!({
+            "pages": [{"input_file": "page-1.jpeg"}, {"input_file": "page-2.jpeg"}],
+            "glyphs": [
+                glyph(0, Some("U+0041_A.png"), Some("A")),
+                glyph(0, Some("U+0042_B.png"), Some("B")),
+                glyph(1, Some("letter-2.png"), None),
+                glyph(1, None, Some("D")),
+            ],
+        });
+        let later = json!({
+            "artifacts": ["debug/page-1-overlay.png"],
+            "pages": [{"input_file": "page-2-again.jpeg"}],
+            "glyphs": [
+                glyph(0, Some("U+0042_B.png"), Some("B")),
+                glyph(0, Some("letter-2.png"), Some("C")),
+                glyph(0, Some("D.png"), Some("D")),
+                glyph(0, Some("A.png"), Some("A")),
+            ],
+        });
+
+        let (merged, report) = merge(earlier, later).unwrap();
+        assert_eq!(report.replaced, ["U+0042_B.png", "letter-2.png"]);
+        assert_eq!(report.added, ["D.png", "A.png"]);
+        assert_eq!(report.kept, 1);
+        assert_eq!(report.conflicts.len(), 2);
+        assert!(report.conflicts[0].contains("letter-2.png"));
+        assert!(report.conflicts[1].contains("U+0041_A.png and A.png"));
+
+        assert_eq!(merged["pages"].as_array().unwrap().len(), 3);
+        let glyphs = merged["glyphs"].as_array().unwrap();
+        assert_eq!(glyphs.len(), 8);
+        assert_eq!(glyphs[0]["file"], "U+0041_A.png");
+        assert_eq!(glyphs[1]["status"], "replaced");
+        assert!(glyphs[1]["file"].is_null());
+        assert_eq!(glyphs[4]["page"], 2);
+        assert_eq!(glyphs[4]["file"], "U+0042_B.png");
+        assert_eq!(merged["artifacts"], json!(["debug/page-1-overlay.png"]));
+    }
+
+    #[test]
+    fn refuses_to_merge_other_files() {
+        assert!(merge(json!({"glyphs": []}), json!({"pages": [], "glyphs": []})).is_err());
+    }
+}
@@ -0,0 +1,411 @@
+use anyhow::{bail, Context};
+use clap::Args;
+use image::{GrayImage, Luma};
+use imageproc::region_labelling::{connected_components, Connectivity};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+use crate::{deskew, pipeline, threshold};
+
+/// The sentences printed on a lined template when none are given. Between
+/// them, they use every letter of the alphabet many times over, in many
+/// different pairs.
+pub const PANGRAMS: &[&str] = &[
+    "The quick brown fox jumps over the lazy dog.",
+    "Pack my box with five dozen liquor jugs.",
+    "How vexingly quick daft zebras jump!",
+    "Sphinx of black quartz, judge my vow.",
+    "The five boxing wizards jump quickly.",
+    "Jackdaws love my big sphinx of quartz.",
+    "Waltz, bad nymph, for quick jigs vex.",
+    "Bright vixens jump; dozy fowl quack.",
+];
+/// Pieces of ink smaller than this fraction of the page are specks, and are
+/// left out.
+const MIN_SPECK_AREA: f32 = 0.000002;
+/// Pieces of ink shorter than this fraction of the median piece's height,
+/// like dots and commas, don't start a line of writing of their own.
+const MIN_LINE_PIECE_HEIGHT: f32 = 0.4;
+/// Letters with no ascender or descender, whose height is the x-height.
+const X_HEIGHT_LETTERS: &str = "acemnorsuvwxz";
+/// Pairs whose spacing differs from the usual by less than this fraction of
+/// the x-height aren't kerned.
+const MIN_KERNING: f32 = 0.05;
+
+/// Arguments for the `metrics` command.
+#[derive(Args, Debug)]
+pub struct MetricsArgs {
+    /// A scan of a lined template made by `generate-template --lined`, with
+    /// each of its sentences written out on the line below it.
+    #[arg(short, long)]
+    input_file: PathBuf,
+
+    /// Where to write the measurements, as JSON, for `build-font --metrics`
+    /// and `export --metrics`.
+    #[arg(short, long, default_value = "metrics.json")]
+    output: PathBuf,
+
+    /// The sentences printed on the template, one per line, or the path of a
+    /// text file containing them. By default, the pangrams that
+    /// `generate-template --lined` prints are used.
+    #[arg(long)]
+    text: Option<String>,
+
+    /// Threshold value to use when reading the page, the same as for the
+    /// `scan` command. The default value is 190.
+    #[arg(short, long, default_value = "190")]
+    threshold: threshold::Threshold,
+}
+
+/// How someone spaces their handwriting, measured from a page of it. Every
+/// distance is a fraction of the handwriting's x-height, so it can be used
+/// at any size.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metrics {
+    /// The usual space between the letters of a word.
+    pub letter_spacing: f32,
+    /// The usual space between words.
+    pub word_spacing: f32,
+    /// How much more (positive) or less (negative) space than usual is left
+    /// between pairs of letters, like `"To"`, that are spaced differently.
+    pub kerning: BTreeMap<String, f32>,
+}
+
+impl Metrics {
+    /// Read measurements written by the `metrics` command.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        serde_json::from_str(&contents).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    /// The kerning of each pair of characters, as `(left, right, amount)`.
+    pub fn pairs(&self) -> impl Iterator<Item = (char, char, f32)> + '_ {
+        self.kerning.iter().filter_map(|(pair, amount)| {
+            let mut chars = pair.chars();
+            match (chars.next(), chars.next(), chars.next()) {
+                (Some(left), Some(right), None) => Some((left, right, *amount)),
+                _ => None,
+            }
+        })
+    }
+}
+
+/// The sentences given with `--text`, or the default pangrams.
+pub fn sentences_from_arg(value: Option<&str>) -> anyhow::Result<Vec<String>> {
+    let Some(value) = value else {
+        return Ok(PANGRAMS.iter().map(|s| s.to_string()).collect());
+    };
+    let path = Path::new(value);
+    let text = if path.is_file() {
+        std::fs::read_to_string(path).context("reading text file")?
+    } else {
+        value.to_string()
+    };
+    let sentences: Vec<String> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect();
+    if sentences.is_empty() {
+        bail!("text must hold at least one sentence.");
+    }
+
+    Ok(sentences)
+}
+
+/// Measure the spacing of the handwriting on a scanned lined template.
+pub fn metrics(args: MetricsArgs) -> anyhow::Result<()> {
+    let MetricsArgs {
+        input_file,
+        output,
+        text,
+        threshold,
+    } = args;
+    let sentences = sentences_from_arg(text.as_deref())?;
+
+    let (image, _) = pipeline::open_input(&input_file, None, None)?;
+    let mut page = image.to_luma8();
+    let level = threshold.level(&page);
+    let skew = deskew::estimate_skew(&page, level);
+    if let Some(straightened) = deskew::straighten(&page, skew) {
+        info!("Straightened the scan by {skew:.2}°.");
+        page = straightened;
+    }
+    let page = imageproc::contrast::threshold(&page, level);
+
+    let metrics = measure(&page, &sentences)?;
+    std::fs::write(
+        &output,
+        serde_json::to_string_pretty(&metrics).context("serializing the metrics")?,
+    )
+    .with_context(|| format!("writing {}", output.display()))?;
+    println!(
+        "Letter spacing: {:.3} of the x-height",
+        metrics.letter_spacing
+    );
+    println!("Word spacing: {:.3} of the x-height", metrics.word_spacing);
+    println!("Kerned pairs: {}", metrics.kerning.len());
+    info!("Wrote {}.", output.display());
+
+    Ok(())
+}
+
+/// A piece of ink, or a few that overlap from left to right, like the dot
+/// and stem of an `i`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Blob {
+    left: u32,
+    top: u32,
+    right: u32,
+    bottom: u32,
+}
+
+impl Blob {
+    fn height(&self) -> u32 {
+        self.bottom - self.top + 1
+    }
+
+    fn center_y(&self) -> u32 {
+        (self.top + self.bottom) / 2
+    }
+
+    fn union(self, other: Self) -> Self {
+        Self {
+            left: self.left.min(other.left),
+            top: self.top.min(other.top),
+            right: self.right.max(other.right),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+}
+
+/// Measure the spacing of the handwriting on a thresholded page, given the
+/// sentences written on it, one per line of writing, in order.
+///
+/// Each line is split into words at its widest gaps, one fewer than the
+/// words of its sentence. Words whose pieces of ink match their letters one
+/// for one give the spacing between letters; joined-up words are skipped.
+pub fn measure(page: &GrayImage, sentences: &[String]) -> anyhow::Result<Metrics> {
+    let blobs = find_blobs(page);
+    let lines = group_lines(&blobs);
+    if lines.is_empty() {
+        bail!("no writing was found on the page.");
+    }
+    if lines.len() != sentences.len() {
+        warn!(
+            "found {} lines of writing, but there are {} sentences; they're matched in order.",
+            lines.len(),
+            sentences.len()
+        );
+    }
+
+    let mut letter_gaps = Vec::new();
+    let mut pair_gaps: HashMap<(char, char), Vec<f32>> = HashMap::new();
+    let mut word_gaps = Vec::new();
+    let mut x_heights = Vec::new();
+    let (mut words_matched, mut words_total) = (0, 0);
+    for (line, sentence) in lines.iter().zip(sentences) {
+        let words: Vec<Vec<char>> = sentence
+            .split_whitespace()
+            .map(|word| word.chars().collect())
+            .collect();
+        words_total += words.len();
+        if line.len() < words.len() {
+            continue;
+        }
+        let gaps: Vec<u32> = line
+            .windows(2)
+            .map(|pair| pair[1].left - pair[0].right - 1)
+            .collect();
+        // The widest gaps are between words.
+        let mut widest: Vec<usize> = (0..gaps.len()).collect();
+        widest.sort_by_key(|&i| std::cmp::Reverse(gaps[i]));
+        let mut breaks = widest[..words.len() - 1].to_vec();
+        breaks.sort();
+        word_gaps.extend(breaks.iter().map(|&i| gaps[i] as f32));
+
+        let mut start = 0;
+        for (word, end) in words
+            .iter()
+            .zip(breaks.iter().map(|i| i + 1).chain([line.len()]))
+        {
+            let pieces = &line[start..end];
+            if pieces.len() == word.len() {
+                words_matched += 1;
+                for (k, pair) in word.windows(2).enumerate() {
+                    let gap = gaps[start + k] as f32;
+                    letter_gaps.push(gap);
+                    pair_gaps.entry((pair[0], pair[1])).or_default().push(gap);
+                }
+                for (c, piece) in word.iter().zip(pieces) {
+                    if X_HEIGHT_LETTERS.contains(*c) {
+                        x_heights.push(piece.height() as f32);
+                    }
+                }
+            }
+            start = end;
+        }
+    }
+    info!("{words_matched} of {words_total} words could be split into letters.");
+    if letter_gaps.is_empty() || x_heights.is_empty() {
+        bail!(
+            "none of the words could be split into letters. Write the letters apart, rather \
+             than joined up, and check that the sentences match the page."
+        );
+    }
+
+    let x_height = median(&mut x_heights);
+    let letter_spacing = median(&mut letter_gaps) / x_height;
+    let word_spacing = if word_gaps.is_empty() {
+        letter_spacing
+    } else {
+        median(&mut word_gaps) / x_height
+    };
+    let mut kerning = BTreeMap::new();
+    for ((left, right), gaps) in pair_gaps {
+        let mean = gaps.iter().sum::<f32>() / gaps.len() as f32;
+        let amount = mean / x_height - letter_spacing;
+        if amount.abs() >= MIN_KERNING {
+            kerning.insert(format!("{left}{right}"), amount);
+        }
+    }
+
+    Ok(Metrics {
+        letter_spacing,
+        word_spacing,
+        kerning,
+    })
+}
+
+/// Find the pieces of ink on a page, leaving out specks.
+fn find_blobs(page: &GrayImage) -> Vec<Blob> {
+    let mask = GrayImage::from_fn(page.width(), page.height(), |x, y| {
+        Luma([if page.get_pixel(x, y).0[0] < 128 {
+            255
+        } else {
+            0
+        }])
+    });
+    let labels = connected_components(&mask, Connectivity::Eight, Luma([0]));
+    let mut blobs: HashMap<u32, (Blob, u32)> = HashMap::new();
+    for (x, y, label) in labels.enumerate_pixels() {
+        let label = label.0[0];
+        if label == 0 {
+            continue;
+        }
+        let point = Blob {
+            left: x,
+            top: y,
+            right: x,
+            bottom: y,
+        };
+        let entry = blobs.entry(label).or_insert((point, 0));
+        entry.0 = entry.0.union(point);
+        entry.1 += 1;
+    }
+    let min_area = (page.width() * page.height()) as f32 * MIN_SPECK_AREA;
+    blobs
+        .into_values()
+        .filter(|(_, area)| *area as f32 >= min_area)
+        .map(|(blob, _)| blob)
+        .collect()
+}
+
+/// Sort pieces of ink into lines of writing, from top to bottom. Within each
+/// line, pieces that overlap from left to right are joined, and the rest are
+/// sorted from left to right.
+fn group_lines(blobs: &[Blob]) -> Vec<Vec<Blob>> {
+    let mut heights: Vec<f32> = blobs.iter().map(|blob| blob.height() as f32).collect();
+    if heights.is_empty() {
+        return Vec::new();
+    }
+    let min_height = median(&mut heights) * MIN_LINE_PIECE_HEIGHT;
+
+    // Lines are where the taller pieces of ink overlap from top to bottom.
+    let mut spans: Vec<(u32, u32)> = blobs
+        .iter()
+        .filter(|blob| blob.height() as f32 >= min_height)
+        .map(|blob| (blob.top, blob.bottom))
+        .collect();
+    spans.sort();
+    let mut bands: Vec<(u32, u32)> = Vec::new();
+    for (top, bottom) in spans {
+        match bands.last_mut() {
+            Some(band) if top <= band.1 => band.1 = band.1.max(bottom),
+            _ => bands.push((top, bottom)),
+        }
+    }
+
+    // Every piece, however small, joins the nearest line.
+    let mut lines = vec![Vec::new(); bands.len()];
+    for blob in blobs {
+        let center = blob.center_y();
+        let distance = |&(top, bottom): &(u32, u32)| {
+            if center < top {
+                top - center
+            } else {
+                center.saturating_sub(bottom)
+            }
+        };
+        if let Some((i, _)) = bands
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, band)| distance(band))
+        {
+            lines[i].push(*blob);
+        }
+    }
+    for line in &mut lines {
+        line.sort_by_key(|blob| blob.left);
+        let mut joined: Vec<Blob> = Vec::new();
+        for blob in line.drain(..) {
+            match joined.last_mut() {
+                Some(last) if blob.left <= last.right => *last = last.union(blob),
+                _ => joined.push(blob),
+            }
+        }
+        *line = joined;
+    }
+
+    lines
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_by(f32::total_cmp);
+    values[values.len() / 2]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use imageproc::{drawing::draw_filled_rect_mut, rect::Rect};
+
+    #[test]
+    fn measures_letter_and_word_spacing() {
+        // "mo To" written as blocks 20 pixels tall: the letters of each word
+        // are 6 pixels apart, except `T` and `o`, which are tucked together,
+        // and the words are 30 pixels apart. The `i`-like dot above the `o`
+        // is part of it, not a letter of its own.
+        let mut page = GrayImage::from_pixel(300, 100, Luma([255]));
+        let letters = [(10, 20), (36, 20), (86, 20), (108, 20)];
+        for (x, width) in letters {
+            draw_filled_rect_mut(&mut page, Rect::at(x, 40).of_size(width, 20), Luma([0]));
+        }
+        draw_filled_rect_mut(&mut page, Rect::at(40, 30).of_size(4, 4), Luma([0]));
+        let sentences = vec!["mo To".to_string()];
+
+        let metrics = measure(&page, &sentences).unwrap();
+        assert_eq!(metrics.letter_spacing, 6.0 / 20.0);
+        assert_eq!(metrics.word_spacing, 30.0 / 20.0);
+        assert_eq!(
+            metrics.pairs().collect::<Vec<_>>(),
+            vec![('T', 'o', 2.0 / 20.0 - 6.0 / 20.0)]
+        );
+    }
+}
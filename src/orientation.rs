@@ -0,0 +1,170 @@
+use image::{DynamicImage, GrayImage, Luma};
+use imageproc::{
+    distance_transform::Norm,
+    region_labelling::{connected_components, Connectivity},
+};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Cursor, Seek},
+    path::Path,
+};
+
+/// How much the page is shrunk by when looking for its corner markers.
+const SEARCH_SCALE: u32 = 4;
+
+/// Find which way up a page of the template is, from its solid corner
+/// markers. The template has markers beside three corners of its grid, and
+/// none beside the top right one, so the missing marker shows where the top
+/// right of the page has ended up.
+///
+/// Returns how many degrees the page must be rotated clockwise to be upright:
+/// 0, 90, 180, or 270. Returns `None` if the markers can't be found, such as
+/// on a page that isn't the template.
+pub fn detect(image: &GrayImage, threshold: u8) -> Option<u32> {
+    // A thumbnail averages each block of pixels straight into the small
+    // page, where resizing would first hold a large copy of the whole page.
+    let small = image::imageops::thumbnail(
+        image,
+        (image.width() / SEARCH_SCALE).max(1),
+        (image.height() / SEARCH_SCALE).max(1),
+    );
+    let (width, height) = small.dimensions();
+    let ink = GrayImage::from_fn(width, height, |x, y| {
+        Luma([if small.get_pixel(x, y).0[0] < threshold {
+            255
+        } else {
+            0
+        }])
+    });
+    // Opening removes grid lines and pen strokes, leaving the solid markers.
+    let radius = (width.max(height) / 400).clamp(1, 255) as u8;
+    let solid = imageproc::morphology::open(&ink, Norm::LInf, radius);
+
+    // Find the center of each solid shape.
+    let labels = connected_components(&solid, Connectivity::Eight, Luma([0]));
+    let mut shapes: Vec<(u32, f32, f32)> = Vec::new();
+    for (x, y, label) in labels.enumerate_pixels() {
+        let label = label.0[0] as usize;
+        if label == 0 {
+            continue;
+        }
+        if label > shapes.len() {
+            shapes.resize(label, (0, 0.0, 0.0));
+        }
+        let shape = &mut shapes[label - 1];
+        *shape = (shape.0 + 1, shape.1 + x as f32, shape.2 + y as f32);
+    }
+    shapes.sort_by_key(|(size, _, _)| std::cmp::Reverse(*size));
+    let markers = shapes.get(..3)?;
+    // The markers are all the same size, and much larger than a speck.
+    if markers[2].0 < 4 || markers[2].0 * 3 < markers[0].0 {
+        return None;
+    }
+    let centers: Vec<(f32, f32)> = markers
+        .iter()
+        .map(|(size, x, y)| (x / *size as f32, y / *size as f32))
+        .collect();
+
+    // Each marker sits at a corner of the rectangle around all three.
+    let middle = |values: &mut dyn Iterator<Item = f32>| {
+        let (min, max) = values.fold((f32::MAX, f32::MIN), |(min, max), v| {
+            (min.min(v), max.max(v))
+        });
+        (min + max) / 2.0
+    };
+    let middle_x = middle(&mut centers.iter().map(|(x, _)| *x));
+    let middle_y = middle(&mut centers.iter().map(|(_, y)| *y));
+    let mut corners = [false; 4];
+    for (x, y) in centers {
+        // Clockwise from the top left.
+        let corner = match (x < middle_x, y < middle_y) {
+            (true, true) => 0,
+            (false, true) => 1,
+            (false, false) => 2,
+            (true, false) => 3,
+        };
+        corners[corner] = true;
+    }
+    let missing: Vec<usize> = (0..4).filter(|corner| !corners[*corner]).collect();
+
+    // The missing marker belongs at the top right.
+    match missing[..] {
+        [1] => Some(0),
+        [2] => Some(270),
+        [3] => Some(180),
+        [0] => Some(90),
+        _ => None,
+    }
+}
+
+/// Read the orientation an image file's EXIF metadata says it should be
+/// shown in, as a number from 1 to 8. Photos from phones are often stored
+/// sideways and rely on this to be shown upright. Returns `None` if the file
+/// doesn't record its orientation.
+pub fn read_exif_orientation(path: &Path) -> Option<u32> {
+    let file = File::open(path).ok()?;
+    exif_orientation_in(&mut BufReader::new(file))
+}
+
+/// Like [`read_exif_orientation`], but for an image that's already in
+/// memory.
+pub fn exif_orientation(bytes: &[u8]) -> Option<u32> {
+    exif_orientation_in(&mut Cursor::new(bytes))
+}
+
+fn exif_orientation_in(container: &mut (impl BufRead + Seek)) -> Option<u32> {
+    let exif = exif::Reader::new().read_from_container(container).ok()?;
+    exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+        .value
+        .get_uint(0)
+}
+
+/// Turn and flip an image the way its EXIF `orientation` says to show it.
+pub fn apply_exif_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{charset::Charset, template::render_template};
+
+    #[test]
+    fn detects_every_rotation() {
+        let page = render_template(3, 4, 120, &Charset::default(), None, false);
+        assert_eq!(detect(&page, 128), Some(0));
+        // Turning the page clockwise means it has to be turned back.
+        assert_eq!(detect(&image::imageops::rotate90(&page), 128), Some(270));
+        assert_eq!(detect(&image::imageops::rotate180(&page), 128), Some(180));
+        assert_eq!(detect(&image::imageops::rotate270(&page), 128), Some(90));
+    }
+
+    #[test]
+    fn applies_exif_orientations() {
+        let image =
+            DynamicImage::ImageLuma8(GrayImage::from_fn(3, 2, |x, y| Luma([(y * 3 + x) as u8])));
+        let pixel = |image: &DynamicImage, x, y| image.to_luma8().get_pixel(x, y).0[0];
+        assert_eq!(pixel(&apply_exif_orientation(image.clone(), 1), 0, 0), 0);
+        // A sideways photo, whose top is on the left.
+        let upright = apply_exif_orientation(image.clone(), 6);
+        assert_eq!((upright.width(), upright.height()), (2, 3));
+        assert_eq!(pixel(&upright, 0, 0), 3);
+        assert_eq!(pixel(&apply_exif_orientation(image.clone(), 3), 0, 0), 5);
+        assert_eq!(pixel(&apply_exif_orientation(image, 2), 0, 0), 2);
+    }
+
+    #[test]
+    fn finds_nothing_on_a_blank_page() {
+        let page = GrayImage::from_pixel(400, 300, Luma([255]));
+        assert_eq!(detect(&page, 128), None);
+    }
+}
@@ -0,0 +1,357 @@
+use anyhow::Context;
+use clap::ValueEnum;
+use image::ImageFormat;
+use std::{
+    fmt,
+    io::{Cursor, Write},
+    path::Path,
+    str::FromStr,
+    time::SystemTime,
+};
+
+use crate::charset;
+
+/// The image formats that letter images can be saved in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    Bmp,
+    Tiff,
+    Webp,
+    Jpeg,
+}
+
+impl OutputFormat {
+    pub fn image_format(self) -> ImageFormat {
+        match self {
+            Self::Png => ImageFormat::Png,
+            Self::Bmp => ImageFormat::Bmp,
+            Self::Tiff => ImageFormat::Tiff,
+            Self::Webp => ImageFormat::WebP,
+            Self::Jpeg => ImageFormat::Jpeg,
+        }
+    }
+
+    /// Whether images in this format can be transparent.
+    pub fn supports_alpha(self) -> bool {
+        self != Self::Jpeg
+    }
+
+    /// The file extension for this format, without the leading dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Bmp => "bmp",
+            Self::Tiff => "tiff",
+            Self::Webp => "webp",
+            Self::Jpeg => "jpeg",
+        }
+    }
+}
+
+/// Whether the file at `path` exists and was last changed no earlier than
+/// `than`. If `than` isn't known, any file that exists counts as newer.
+pub fn is_newer(path: &Path, than: Option<SystemTime>) -> bool {
+    let Ok(modified) = std::fs::metadata(path).and_then(|metadata| metadata.modified()) else {
+        return false;
+    };
+    than.is_none_or(|than| modified >= than)
+}
+
+/// When a file was last changed, or `None` if that can't be told.
+pub fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Every file in `dir` and its subdirectories, relative to `dir`, sorted.
+pub fn list_files(dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        let entries = std::fs::read_dir(&current)
+            .with_context(|| format!("reading {}", current.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if let Ok(relative) = path.strip_prefix(dir) {
+                // Zip files and URLs both separate directories with `/`.
+                let parts: Vec<_> = relative
+                    .components()
+                    .map(|part| part.as_os_str().to_string_lossy())
+                    .collect();
+                files.push(parts.join("/"));
+            }
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// Pack a scan's letters, the `files` in `dir`, into a zip file.
+pub fn to_zip(dir: &Path, files: &[String]) -> anyhow::Result<Vec<u8>> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default();
+    for file in files {
+        let contents = std::fs::read(dir.join(file)).with_context(|| format!("reading {file}"))?;
+        zip.start_file(file.as_str(), options)?;
+        zip.write_all(&contents)?;
+    }
+
+    Ok(zip.finish()?.into_inner())
+}
+
+/// The current time in UTC, like `2024-05-01_14-30-00`, for naming a
+/// directory. Names like this sort in the order they were made.
+pub fn timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    format_timestamp(seconds)
+}
+
+/// Format a time, in seconds since 1970 began, as `2024-05-01_14-30-00`.
+fn format_timestamp(seconds: u64) -> String {
+    let (days, time) = (seconds / 86_400, seconds % 86_400);
+    // Count in 400-year eras, which always have the same number of days,
+    // starting on the 1st of March so that leap days come last.
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}_{:02}-{:02}-{:02}",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Which letter image to keep when two would be saved under the same name,
+/// such as when a character is written on two pages.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OnConflict {
+    /// Keep the image that was saved first, and skip the later one.
+    #[default]
+    First,
+    /// Replace the earlier image with the later one.
+    Last,
+    /// Keep both, by adding a number to the later one's name, like
+    /// `U+0041_A-2.png`.
+    Both,
+    /// Keep every image as an alternate of the first, by adding `.alt1`,
+    /// `.alt2`, and so on to the later ones' names, like
+    /// `U+0041_A.alt1.png`. Fonts built from them switch between the
+    /// alternates, so that repeated letters don't look stamped.
+    Alternates,
+}
+
+/// How letter images are named instead of by their character, written with
+/// `--name-format` like `{char}-{row}x{col}-{index}.{ext}`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameFormat(Vec<NamePart>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum NamePart {
+    Text(String),
+    Char,
+    Codepoint,
+    Row,
+    Col,
+    Page,
+    Index,
+    Sample,
+}
+
+/// What a letter image can be named after.
+#[derive(Clone, Copy, Debug)]
+pub struct CellName<'a> {
+    /// The character written in the cell, or the sequence of characters for
+    /// a ligature.
+    pub text: &'a str,
+    pub row: u32,
+    pub col: u32,
+    /// The page's position among the pages that were scanned, counted from 1.
+    pub page: usize,
+    /// The cell's position in the charset.
+    pub index: usize,
+    /// Which sample of its character the cell is, counted from 1 through the
+    /// charset.
+    pub sample: usize,
+}
+
+impl NameFormat {
+    /// The name of a letter image, without its extension.
+    pub fn file_stem(&self, cell: &CellName) -> String {
+        let mut stem = String::new();
+        for part in &self.0 {
+            match part {
+                NamePart::Text(text) => stem.push_str(text),
+                NamePart::Char => {
+                    let names: Vec<String> = cell.text.chars().map(charset::glyph_name).collect();
+                    stem.push_str(&names.join("_"));
+                }
+                NamePart::Codepoint => {
+                    let codepoints: Vec<String> = cell
+                        .text
+                        .chars()
+                        .map(|c| format!("{:04X}", c as u32))
+                        .collect();
+                    stem.push_str(&codepoints.join("+"));
+                }
+                NamePart::Row => stem.push_str(&cell.row.to_string()),
+                NamePart::Col => stem.push_str(&cell.col.to_string()),
+                NamePart::Page => stem.push_str(&cell.page.to_string()),
+                NamePart::Index => stem.push_str(&cell.index.to_string()),
+                NamePart::Sample => stem.push_str(&cell.sample.to_string()),
+            }
+        }
+        stem
+    }
+}
+
+impl FromStr for NameFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // The extension is the output format's, so it's always added.
+        let format = s.strip_suffix(".{ext}").unwrap_or(s);
+        if format.contains(['/', '\\']) {
+            return Err("a name format must not contain a path separator".to_string());
+        }
+        let mut parts = Vec::new();
+        let mut rest = format;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(NamePart::Text(rest[..start].to_string()));
+            }
+            let Some(length) = rest[start..].find('}') else {
+                return Err(format!("{s:?} has a {{ without a }}"));
+            };
+            parts.push(match &rest[start + 1..start + length] {
+                "char" => NamePart::Char,
+                "codepoint" => NamePart::Codepoint,
+                "row" => NamePart::Row,
+                "col" => NamePart::Col,
+                "page" => NamePart::Page,
+                "index" => NamePart::Index,
+                "sample" => NamePart::Sample,
+                "ext" => return Err("{ext} can only come at the end, after a .".to_string()),
+                name => {
+                    return Err(format!(
+                        "{{{name}}} isn't one of {{char}}, {{codepoint}}, {{row}}, {{col}}, \
+                         {{page}}, {{index}}, {{sample}}, or {{ext}}"
+                    ))
+                }
+            });
+            rest = &rest[start + length + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(NamePart::Text(rest.to_string()));
+        }
+        if parts.is_empty() {
+            return Err("a name format can't be empty".to_string());
+        }
+
+        Ok(Self(parts))
+    }
+}
+
+/// The color that ink is drawn in, written as a hex code like `#1a3d8f`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InkColor(pub [u8; 3]);
+
+impl FromStr for InkColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        let invalid = || format!("{s:?} isn't a color like #1a3d8f");
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+
+        Ok(Self([channel(0)?, channel(2)?, channel(4)?]))
+    }
+}
+
+impl fmt::Display for InkColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [red, green, blue] = self.0;
+        write!(f, "#{red:02x}{green:02x}{blue:02x}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ink_colors() {
+        assert_eq!("#1a3d8f".parse(), Ok(InkColor([0x1a, 0x3d, 0x8f])));
+        assert_eq!("FF0000".parse(), Ok(InkColor([255, 0, 0])));
+        assert_eq!(InkColor([0x1a, 0x3d, 0x8f]).to_string(), "#1a3d8f");
+        assert!("#12345".parse::<InkColor>().is_err());
+        assert!("#12345g".parse::<InkColor>().is_err());
+    }
+    #[test]
+    fn names_letter_images() {
+        let format: NameFormat = "{char}-{row}x{col}-{index}.{ext}".parse().unwrap();
+        let cell = CellName {
+            text: "/",
+            row: 2,
+            col: 7,
+            page: 1,
+            index: 31,
+            sample: 1,
+        };
+        assert_eq!(format.file_stem(&cell), "slash-2x7-31");
+        let format: NameFormat = "uni{codepoint}_p{page}s{sample}".parse().unwrap();
+        let cell = CellName {
+            text: "th",
+            page: 2,
+            sample: 3,
+            ..cell
+        };
+        assert_eq!(format.file_stem(&cell), "uni0074+0068_p2s3");
+        assert!("{char".parse::<NameFormat>().is_err());
+        assert!("{letter}".parse::<NameFormat>().is_err());
+        assert!("{ext}-{char}".parse::<NameFormat>().is_err());
+        assert!("glyphs/{char}".parse::<NameFormat>().is_err());
+        assert!(".{ext}".parse::<NameFormat>().is_err());
+    }
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(format_timestamp(0), "1970-01-01_00-00-00");
+        assert_eq!(format_timestamp(951_827_696), "2000-02-29_12-34-56");
+        assert_eq!(format_timestamp(1_714_573_800), "2024-05-01_14-30-00");
+    }
+    #[test]
+    fn compares_modification_times() {
+        let path = std::env::temp_dir().join("handwriting-scan-tool-is-newer.txt");
+        std::fs::write(&path, "").unwrap();
+        let earlier = SystemTime::UNIX_EPOCH;
+        let later = SystemTime::now() + std::time::Duration::from_secs(3600);
+        assert!(is_newer(&path, Some(earlier)));
+        assert!(!is_newer(&path, Some(later)));
+        assert!(is_newer(&path, None));
+        std::fs::remove_file(&path).unwrap();
+        assert!(!is_newer(&path, None));
+    }
+}
@@ -0,0 +1,183 @@
+use ab_glyph::FontVec;
+use image::{GrayImage, Rgb, RgbImage};
+use imageproc::{
+    drawing::{draw_hollow_rect_mut, draw_text_mut},
+    rect::Rect,
+};
+
+use crate::grid::Grid;
+
+/// The color of the grid lines that were found.
+const GRID_COLOR: Rgb<u8> = Rgb([60, 120, 255]);
+/// The color of the rectangle around each letterform.
+const INK_COLOR: Rgb<u8> = Rgb([30, 160, 60]);
+/// The color of the cells' numbers and characters.
+const LABEL_COLOR: Rgb<u8> = Rgb([120, 40, 160]);
+/// Flagged cells are outlined and tinted with this color.
+const FLAGGED_COLOR: Rgb<u8> = Rgb([220, 40, 40]);
+/// Cells that weren't saved are tinted with this color.
+const SKIPPED_TINT: Rgb<u8> = Rgb([170, 170, 170]);
+/// How much of a tinted cell's paper is the tint.
+const TINT_STRENGTH: f32 = 0.35;
+/// The height of the cells' labels, as a fraction of the cell's height.
+const LABEL_SIZE: f32 = 0.18;
+
+/// How a cell turned out, for coloring it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mark {
+    /// Its letterform was saved, and nothing looks wrong with it.
+    Saved,
+    /// It wasn't saved, because it was empty, unchanged, or left out.
+    Skipped,
+    /// Something looks wrong with its letterform, or it couldn't be saved.
+    Flagged,
+}
+
+/// What to draw over one cell of a page.
+#[derive(Clone, Debug)]
+pub struct CellOverlay {
+    /// The cell's rectangle on the page, as `(x, y, width, height)`.
+    pub area: (u32, u32, u32, u32),
+    /// The cell's number, and its character if it has one.
+    pub label: String,
+    /// The rectangle around the cell's letterform on the page, if one was
+    /// found.
+    pub ink_bounds: Option<(u32, u32, u32, u32)>,
+    pub mark: Mark,
+}
+
+/// Draw how a page was read over it: the lines of each grid that was found,
+/// and for each cell, its label, the rectangle around its letterform, and
+/// whether it was skipped or flagged. The page is faded, so that what's drawn
+/// over it stands out. Labels are only drawn if a `font` is given.
+pub fn render(
+    page: &GrayImage,
+    grids: &[Grid],
+    cells: &[CellOverlay],
+    font: Option<&FontVec>,
+) -> RgbImage {
+    let (width, height) = page.dimensions();
+    let mut image = RgbImage::from_fn(width, height, |x, y| {
+        Rgb([page.get_pixel(x, y).0[0] / 2 + 128; 3])
+    });
+    if width == 0 || height == 0 {
+        return image;
+    }
+
+    for cell in cells {
+        let (x, y, cell_width, cell_height) = cell.area;
+        let tint = match cell.mark {
+            Mark::Saved => None,
+            Mark::Skipped => Some(SKIPPED_TINT),
+            Mark::Flagged => Some(FLAGGED_COLOR),
+        };
+        if let Some(tint) = tint {
+            for py in y..(y + cell_height).min(height) {
+                for px in x..(x + cell_width).min(width) {
+                    let pixel = image.get_pixel_mut(px, py);
+                    for (channel, tint) in pixel.0.iter_mut().zip(tint.0) {
+                        *channel = (*channel as f32 * (1.0 - TINT_STRENGTH)
+                            + tint as f32 * TINT_STRENGTH) as u8;
+                    }
+                }
+            }
+        }
+    }
+
+    // The grid lines are drawn two pixels thick, so they show when the page
+    // is shrunk to look at it.
+    for grid in grids {
+        let (Some(&left), Some(&right)) = (grid.columns.first(), grid.columns.last()) else {
+            continue;
+        };
+        let (Some(&top), Some(&bottom)) = (grid.rows.first(), grid.rows.last()) else {
+            continue;
+        };
+        for &column in &grid.columns {
+            for x in column..(column + 2).min(width) {
+                for y in top..=bottom.min(height - 1) {
+                    image.put_pixel(x, y, GRID_COLOR);
+                }
+            }
+        }
+        for &row in &grid.rows {
+            for y in row..(row + 2).min(height) {
+                for x in left..=right.min(width - 1) {
+                    image.put_pixel(x, y, GRID_COLOR);
+                }
+            }
+        }
+    }
+
+    for cell in cells {
+        let (x, y, cell_width, cell_height) = cell.area;
+        if cell.mark == Mark::Flagged && cell_width > 4 && cell_height > 4 {
+            for inset in 2..4 {
+                let rect = Rect::at((x + inset) as i32, (y + inset) as i32)
+                    .of_size(cell_width - inset * 2, cell_height - inset * 2);
+                draw_hollow_rect_mut(&mut image, rect, FLAGGED_COLOR);
+            }
+        }
+        if let Some((ink_x, ink_y, ink_width, ink_height)) = cell.ink_bounds {
+            let rect =
+                Rect::at(ink_x as i32, ink_y as i32).of_size(ink_width.max(1), ink_height.max(1));
+            draw_hollow_rect_mut(&mut image, rect, INK_COLOR);
+        }
+        if let Some(font) = font {
+            let scale = (cell_height as f32 * LABEL_SIZE).max(8.0);
+            draw_text_mut(
+                &mut image,
+                LABEL_COLOR,
+                x as i32 + 4,
+                y as i32 + 4,
+                scale,
+                font,
+                &cell.label,
+            );
+        }
+    }
+
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn draws_the_grid_letterforms_and_flagged_cells() {
+        let page = GrayImage::from_pixel(200, 100, Luma([255]));
+        let grid = Grid {
+            columns: vec![10, 100, 190],
+            rows: vec![10, 90],
+            guessed: 0,
+        };
+        let cells = [
+            CellOverlay {
+                area: (12, 12, 86, 76),
+                label: "0 A".into(),
+                ink_bounds: Some((30, 30, 20, 30)),
+                mark: Mark::Saved,
+            },
+            CellOverlay {
+                area: (102, 12, 86, 76),
+                label: "1 B".into(),
+                ink_bounds: None,
+                mark: Mark::Flagged,
+            },
+        ];
+        let image = render(&page, &[grid], &cells, None);
+
+        assert_eq!(*image.get_pixel(100, 50), GRID_COLOR);
+        assert_eq!(*image.get_pixel(50, 10), GRID_COLOR);
+        assert_eq!(*image.get_pixel(30, 40), INK_COLOR);
+        assert_eq!(*image.get_pixel(104, 50), FLAGGED_COLOR);
+        // The saved cell's paper is left alone, and the flagged one's is
+        // tinted.
+        let paper = *image.get_pixel(70, 70);
+        assert_eq!(paper, Rgb([255, 255, 255]));
+        let tinted = *image.get_pixel(150, 50);
+        assert!(tinted.0[0] > tinted.0[1]);
+    }
+}
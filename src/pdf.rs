@@ -0,0 +1,183 @@
+use anyhow::{bail, Context};
+use image::{DynamicImage, GrayImage, ImageFormat, RgbImage};
+use lopdf::{Document, Object};
+use std::{path::Path, str::FromStr};
+
+/// Whether `path` names a PDF file.
+pub fn is_pdf(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pdf"))
+}
+
+/// The number of pages in a PDF.
+pub fn page_count(path: &Path) -> anyhow::Result<u32> {
+    let document = load(path)?;
+    Ok(document.get_pages().len() as u32)
+}
+
+/// A selection of pages, like `1,3-5`. Pages are numbered from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pages(pub Vec<u32>);
+
+impl FromStr for Pages {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_pages(s).map(Self)
+    }
+}
+
+fn parse_pages(s: &str) -> Result<Vec<u32>, String> {
+    let mut pages = Vec::new();
+    for part in s.split(',') {
+        let part = part.trim();
+        let parse = |n: &str| match n.trim().parse::<u32>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("{part:?} isn't a page number or a range like 3-5")),
+        };
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse(first)?, parse(last)?);
+                if first > last {
+                    return Err(format!("{part:?} is a range that runs backwards"));
+                }
+                pages.extend(first..=last);
+            }
+            None => pages.push(parse(part)?),
+        }
+    }
+
+    Ok(pages)
+}
+
+/// Load the scanned image on a page of a PDF, counting from 1, along with
+/// its resolution if the page records its size.
+///
+/// Scanners save each page as a single image, so the page's largest image is
+/// taken to be the scan. JPEG images and uncompressed or Flate-compressed
+/// grayscale and RGB images are supported. Pages drawn with text and vector
+/// shapes rather than a scanned image can't be loaded.
+pub fn load_page(path: &Path, page: u32) -> anyhow::Result<(DynamicImage, Option<f32>)> {
+    let document = load(path)?;
+    let pages = document.get_pages();
+    let Some(&page_id) = pages.get(&page) else {
+        bail!(
+            "{} has no page {page}; it has {} pages.",
+            path.display(),
+            pages.len()
+        );
+    };
+    let images = document
+        .get_page_images(page_id)
+        .with_context(|| format!("reading the images on page {page}"))?;
+    let Some(scan) = images.iter().max_by_key(|image| image.width * image.height) else {
+        bail!("page {page} has no scanned image; only PDFs made by scanners are supported.");
+    };
+
+    let (width, height) = (scan.width as u32, scan.height as u32);
+    let filters = scan.filters.clone().unwrap_or_default();
+    let image = match filters.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["DCTDecode"] => image::load_from_memory_with_format(scan.content, ImageFormat::Jpeg)
+            .context("decoding the scanned image")?,
+        [] | ["FlateDecode"] => {
+            if scan.origin_dict.has(b"DecodeParms") {
+                bail!("the scanned image on page {page} uses a compression option that isn't supported.");
+            }
+            let pixels = if filters.is_empty() {
+                scan.content.to_vec()
+            } else {
+                miniz_oxide::inflate::decompress_to_vec_zlib(scan.content)
+                    .map_err(|err| anyhow::anyhow!("decompressing the scanned image: {err:?}"))?
+            };
+            raw_image(
+                width,
+                height,
+                scan.color_space.as_deref(),
+                scan.bits_per_component,
+                pixels,
+            )
+            .with_context(|| format!("decoding the scanned image on page {page}"))?
+        }
+        _ => bail!(
+            "the scanned image on page {page} is compressed with {}, which isn't supported.",
+            filters.join(", ")
+        ),
+    };
+
+    // The page's size is recorded in points, which are 1/72 of an inch.
+    // Images are often stored sideways and rotated onto the page, so the
+    // image's long side is matched with the page's.
+    let dpi = page_size(&document, page_id).map(|(page_width, page_height)| {
+        image.width().max(image.height()) as f32 * 72.0 / page_width.max(page_height)
+    });
+
+    Ok((image, dpi))
+}
+
+fn load(path: &Path) -> anyhow::Result<Document> {
+    Document::load(path).with_context(|| format!("opening {}", path.display()))
+}
+
+fn page_size(document: &Document, page_id: lopdf::ObjectId) -> Option<(f32, f32)> {
+    let page = document.get_dictionary(page_id).ok()?;
+    let media_box = page.get(b"MediaBox").and_then(Object::as_array).ok()?;
+    let [left, bottom, right, top] = media_box.as_slice() else {
+        return None;
+    };
+    let width = (right.as_float().ok()? - left.as_float().ok()?).abs();
+    let height = (top.as_float().ok()? - bottom.as_float().ok()?).abs();
+
+    (width > 0.0 && height > 0.0).then_some((width, height))
+}
+
+fn raw_image(
+    width: u32,
+    height: u32,
+    color_space: Option<&str>,
+    bits_per_component: Option<i64>,
+    pixels: Vec<u8>,
+) -> anyhow::Result<DynamicImage> {
+    match (color_space, bits_per_component) {
+        (Some("DeviceGray"), Some(8)) => GrayImage::from_raw(width, height, pixels)
+            .map(DynamicImage::ImageLuma8)
+            .context("the image is smaller than its recorded size"),
+        (Some("DeviceRGB"), Some(8)) => RgbImage::from_raw(width, height, pixels)
+            .map(DynamicImage::ImageRgb8)
+            .context("the image is smaller than its recorded size"),
+        (Some("DeviceGray"), Some(1)) => {
+            // Each row is padded to a whole byte, and a set bit is white.
+            let row_bytes = width.div_ceil(8) as usize;
+            if pixels.len() < row_bytes * height as usize {
+                bail!("the image is smaller than its recorded size");
+            }
+            Ok(DynamicImage::ImageLuma8(GrayImage::from_fn(
+                width,
+                height,
+                |x, y| {
+                    let byte = pixels[y as usize * row_bytes + x as usize / 8];
+                    let bit = (byte >> (7 - x % 8)) & 1;
+                    image::Luma([bit * 255])
+                },
+            )))
+        }
+        (color_space, bits) => bail!(
+            "images in the {} color space with {} bits per component aren't supported",
+            color_space.unwrap_or("unknown"),
+            bits.map_or("unknown".to_string(), |bits| bits.to_string())
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_page_selections() {
+        assert_eq!(parse_pages("2"), Ok(vec![2]));
+        assert_eq!(parse_pages("1, 3-5"), Ok(vec![1, 3, 4, 5]));
+        assert!(parse_pages("0").is_err());
+        assert!(parse_pages("5-3").is_err());
+        assert!(parse_pages("a").is_err());
+    }
+}
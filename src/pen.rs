@@ -0,0 +1,196 @@
+use image::{GrayImage, Rgb, RgbImage};
+use rayon::prelude::*;
+use std::{fmt, str::FromStr};
+
+use crate::output::InkColor;
+
+/// The threshold for pages made by [`separate`], where the pen's ink is dark
+/// and everything else is light.
+pub const THRESHOLD: u8 = 128;
+
+/// Pixels less colorful than this are treated as black, white, or gray when
+/// looking for the pen's color.
+const MIN_CHROMA: f32 = 24.0;
+
+/// The fraction of a page that must be colorful for its pen color to be
+/// found automatically.
+const MIN_COLORED_FRACTION: f32 = 0.001;
+
+/// Only every this many pixels, across and down, are looked at when finding
+/// the pen's color.
+const SAMPLE_STEP: usize = 2;
+
+/// How many shades of hue are told apart when finding the pen's color.
+const HUE_BINS: usize = 36;
+
+/// The color of the pen a template was filled in with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PenColor {
+    /// Find the most common color on the page.
+    Auto,
+    Color(InkColor),
+}
+
+impl FromStr for PenColor {
+    type Err = String;
+
+    /// Parse `auto`, a color name like `blue`, or a hex code like `#1a3d8f`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let color = match s.to_ascii_lowercase().as_str() {
+            "auto" => return Ok(Self::Auto),
+            "blue" => [30, 60, 200],
+            "red" => [200, 30, 30],
+            "green" => [30, 150, 60],
+            "purple" | "violet" => [120, 40, 170],
+            _ => {
+                return s.parse().map(Self::Color).map_err(|_| {
+                    format!("{s:?} isn't \"auto\", a color name like \"blue\", or a hex code")
+                })
+            }
+        };
+
+        Ok(Self::Color(InkColor(color)))
+    }
+}
+
+impl fmt::Display for PenColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Color(color) => write!(f, "{color}"),
+        }
+    }
+}
+
+/// The hue of the pen's ink, as an angle in radians, or `None` if `color`
+/// is `auto` and nothing on the page is colorful enough to be the pen.
+pub fn hue(image: &RgbImage, color: PenColor) -> Option<f32> {
+    let InkColor(color) = match color {
+        PenColor::Color(color) => color,
+        PenColor::Auto => return most_common_hue(image),
+    };
+    let (a, b) = chroma(&Rgb(color));
+    Some(b.atan2(a))
+}
+
+/// Find the most common hue among the colorful pixels of a page, which on a
+/// template filled in with colored pen is the pen's.
+fn most_common_hue(image: &RgbImage) -> Option<f32> {
+    // Every pixel isn't needed to find the most common color. Pixels are
+    // skipped rather than averaged, so that thin strokes keep their color.
+    let mut bins = [0u32; HUE_BINS];
+    let (mut sampled, mut colored) = (0, 0);
+    for y in (0..image.height()).step_by(SAMPLE_STEP) {
+        for x in (0..image.width()).step_by(SAMPLE_STEP) {
+            sampled += 1;
+            let (a, b) = chroma(image.get_pixel(x, y));
+            if a.hypot(b) < MIN_CHROMA {
+                continue;
+            }
+            let turn = (b.atan2(a) / std::f32::consts::TAU).rem_euclid(1.0);
+            bins[(turn * HUE_BINS as f32) as usize % HUE_BINS] += 1;
+            colored += 1;
+        }
+    }
+    if (colored as f32) < sampled as f32 * MIN_COLORED_FRACTION {
+        return None;
+    }
+    let (bin, _) = bins.iter().enumerate().max_by_key(|(_, count)| **count)?;
+
+    Some((bin as f32 + 0.5) / HUE_BINS as f32 * std::f32::consts::TAU)
+}
+
+/// Make a grayscale page showing only ink of the given `hue`, as dark on
+/// light. Black, white, and gray, like the printed grid and the paper, come
+/// out white, and so do colors far from the pen's.
+pub fn separate(image: &RgbImage, hue: f32) -> GrayImage {
+    let (sin, cos) = hue.sin_cos();
+    // How strongly each pixel is the pen's color.
+    let strength: Vec<f32> = image
+        .par_pixels()
+        .map(|pixel| {
+            let (a, b) = chroma(pixel);
+            (a * cos + b * sin).max(0.0)
+        })
+        .collect();
+
+    // Scale so that the pen's strongest strokes are solid black, ignoring the
+    // odd stray pixel.
+    let mut inked: Vec<f32> = strength.iter().copied().filter(|s| *s > 0.0).collect();
+    let scale = if inked.is_empty() {
+        1.0
+    } else {
+        let strong = inked.len() * 99 / 100;
+        let (_, strong, _) = inked.select_nth_unstable_by(strong, f32::total_cmp);
+        255.0 / strong.max(MIN_CHROMA)
+    };
+
+    GrayImage::from_vec(
+        image.width(),
+        image.height(),
+        strength
+            .into_iter()
+            .map(|strength| 255 - (strength * scale).min(255.0) as u8)
+            .collect(),
+    )
+    .expect("one value per pixel")
+}
+
+/// A pixel's color without its brightness, as a point on the plane of
+/// colors with the same brightness. Black, white, and grays are at the
+/// origin.
+fn chroma(Rgb([red, green, blue]): &Rgb<u8>) -> (f32, f32) {
+    let (red, green, blue) = (*red as f32, *green as f32, *blue as f32);
+    (
+        (red - green) / std::f32::consts::SQRT_2,
+        (red + green - 2.0 * blue) / 6f32.sqrt(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    /// A white page with a gray line and a blue stroke.
+    fn page() -> RgbImage {
+        RgbImage::from_fn(40, 40, |x, y| match (x, y) {
+            (10, _) => Rgb([120, 120, 120]),
+            (_, 20) => Rgb([40, 70, 190]),
+            _ => Rgb([250, 250, 250]),
+        })
+    }
+
+    #[test]
+    fn parses_pen_colors() {
+        assert_eq!("auto".parse(), Ok(PenColor::Auto));
+        assert_eq!("Blue".parse(), Ok(PenColor::Color(InkColor([30, 60, 200]))));
+        assert_eq!(
+            "#ff0000".parse(),
+            Ok(PenColor::Color(InkColor([255, 0, 0])))
+        );
+        assert!("plaid".parse::<PenColor>().is_err());
+    }
+
+    #[test]
+    fn separates_the_pen_from_the_grid() {
+        let page = page();
+        let hue = hue(&page, PenColor::Auto).unwrap();
+        let blue = super::hue(&page, "blue".parse().unwrap()).unwrap();
+        assert!(
+            (hue - blue).cos() > 0.95,
+            "found hue {hue}, expected {blue}"
+        );
+
+        let separated = separate(&page, hue);
+        assert!(separated.get_pixel(30, 20).0[0] < THRESHOLD);
+        assert_eq!(separated.get_pixel(10, 5), &Luma([255]));
+        assert_eq!(separated.get_pixel(30, 5), &Luma([255]));
+    }
+
+    #[test]
+    fn finds_no_pen_on_a_gray_page() {
+        let page = RgbImage::from_pixel(40, 40, Rgb([200, 200, 200]));
+        assert_eq!(hue(&page, PenColor::Auto), None);
+    }
+}
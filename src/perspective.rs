@@ -0,0 +1,121 @@
+use image::{GrayImage, Luma};
+use imageproc::{
+    distance_transform::Norm,
+    geometric_transformations::{warp_into, Interpolation, Projection},
+    region_labelling::{connected_components, Connectivity},
+};
+
+/// The margin left around the grid after correcting perspective, as a
+/// fraction of the grid's width.
+const MARGIN: f32 = 0.05;
+
+/// The corners of a quadrilateral, as `(x, y)` points: top left, top right,
+/// bottom right, then bottom left.
+pub type Corners = [(f32, f32); 4];
+
+/// Find the four outer corners of the template's grid in a photo of the page.
+///
+/// The grid is taken to be the largest connected shape of ink, once the solid
+/// corner markers have been removed (they touch the grid's corners, and would
+/// otherwise be mistaken for them). Returns `None` if nothing resembling a
+/// grid is found.
+pub fn find_grid_corners(image: &GrayImage, threshold: u8) -> Option<Corners> {
+    let (width, height) = image.dimensions();
+    // Ink is the foreground for the morphology and labelling operations.
+    let ink = GrayImage::from_fn(width, height, |x, y| {
+        Luma([if image.get_pixel(x, y).0[0] < threshold {
+            255
+        } else {
+            0
+        }])
+    });
+
+    // Opening removes anything thinner than the kernel, such as grid lines and
+    // pen strokes, leaving only solid shapes like the corner markers.
+    let marker_radius = (width.max(height) / 300).clamp(2, 255) as u8;
+    let markers = imageproc::morphology::open(&ink, Norm::LInf, marker_radius);
+    let markers = imageproc::morphology::dilate(&markers, Norm::LInf, 2);
+    let lines = GrayImage::from_fn(width, height, |x, y| {
+        let is_line = ink.get_pixel(x, y).0[0] > 0 && markers.get_pixel(x, y).0[0] == 0;
+        Luma([if is_line { 255 } else { 0 }])
+    });
+
+    let labels = connected_components(&lines, Connectivity::Eight, Luma([0]));
+    let mut sizes = Vec::new();
+    for label in labels.pixels() {
+        let label = label.0[0] as usize;
+        if label >= sizes.len() {
+            sizes.resize(label + 1, 0u32);
+        }
+        sizes[label] += 1;
+    }
+    let (grid_label, grid_size) = sizes
+        .iter()
+        .enumerate()
+        .skip(1)
+        .max_by_key(|(_, size)| **size)?;
+    // A grid covers far more of the page than a stray blot does.
+    if *grid_size < (width + height) {
+        return None;
+    }
+
+    // The corners are the grid's most extreme points along the diagonals.
+    let mut top_left = (f32::MAX, (0.0, 0.0));
+    let mut top_right = (f32::MIN, (0.0, 0.0));
+    let mut bottom_right = (f32::MIN, (0.0, 0.0));
+    let mut bottom_left = (f32::MIN, (0.0, 0.0));
+    for (x, y, label) in labels.enumerate_pixels() {
+        if label.0[0] as usize != grid_label {
+            continue;
+        }
+        let (x, y) = (x as f32, y as f32);
+        if x + y < top_left.0 {
+            top_left = (x + y, (x, y));
+        }
+        if x - y > top_right.0 {
+            top_right = (x - y, (x, y));
+        }
+        if x + y > bottom_right.0 {
+            bottom_right = (x + y, (x, y));
+        }
+        if y - x > bottom_left.0 {
+            bottom_left = (y - x, (x, y));
+        }
+    }
+
+    Some([top_left.1, top_right.1, bottom_right.1, bottom_left.1])
+}
+
+/// Warp a photo of a page so that the quadrilateral `corners` becomes an
+/// upright rectangle, with a small margin of paper around it.
+pub fn correct_perspective(image: &GrayImage, corners: Corners) -> Option<GrayImage> {
+    let distance = |a: (f32, f32), b: (f32, f32)| (a.0 - b.0).hypot(a.1 - b.1);
+    let [top_left, top_right, bottom_right, bottom_left] = corners;
+    let grid_width = distance(top_left, top_right).max(distance(bottom_left, bottom_right));
+    let grid_height = distance(top_left, bottom_left).max(distance(top_right, bottom_right));
+    if grid_width < 1.0 || grid_height < 1.0 {
+        return None;
+    }
+
+    let margin = grid_width * MARGIN;
+    let target = [
+        (margin, margin),
+        (margin + grid_width, margin),
+        (margin + grid_width, margin + grid_height),
+        (margin, margin + grid_height),
+    ];
+    let projection = Projection::from_control_points(corners, target)?;
+    let mut corrected = GrayImage::new(
+        (grid_width + margin * 2.0).ceil() as u32,
+        (grid_height + margin * 2.0).ceil() as u32,
+    );
+    warp_into(
+        image,
+        &projection,
+        Interpolation::Bilinear,
+        Luma([255]),
+        &mut corrected,
+    );
+
+    Some(corrected)
+}
@@ -0,0 +1,781 @@
+use anyhow::{bail, Context};
+use image::{
+    imageops, DynamicImage, GenericImageView, GrayImage, ImageBuffer, Pixel, Rgb, RgbImage, Rgba,
+    RgbaImage, SubImage,
+};
+use rayon::prelude::*;
+use std::{ops::Deref, path::Path};
+use tracing::{debug, debug_span, error, info, warn};
+
+use crate::{
+    deskew, dpi, glyph, grid, heif,
+    lighting::Lighting,
+    orientation,
+    output::{InkColor, OutputFormat},
+    pdf,
+    pen::{self, PenColor},
+    perspective,
+    profile::Profile,
+    skeleton::{self, SkeletonFormat},
+    threshold, vectorize,
+};
+
+/// The settings that control how a page is cleaned up before its cells are
+/// cut out.
+#[derive(Clone, Copy, Debug)]
+pub struct PreprocessOptions {
+    pub threshold: threshold::Threshold,
+    /// Threshold each pixel against the square of this many pixels around it,
+    /// instead of using one threshold for the whole page.
+    pub adaptive_window: Option<u32>,
+    /// Turn the page upright, by its corner markers, or so that it's wider
+    /// than it's tall if they can't be found.
+    pub orient: bool,
+    /// Even out the page's lighting before anything else, so that a dim
+    /// side doesn't threshold to solid black.
+    pub lighting: Option<Lighting>,
+    /// Straighten the page if it's rotated.
+    pub deskew: bool,
+    /// Correct the perspective of a photo of the page.
+    pub camera: bool,
+    /// Also make a grayscale copy of the page, thresholded softly so that
+    /// the edges of strokes stay smooth.
+    pub grayscale: bool,
+    /// Also make a copy of the page with only the ink of this color, which
+    /// leaves out the printed grid.
+    pub pen_color: Option<PenColor>,
+    /// Also keep a copy of the straightened page from before it's
+    /// thresholded, where the template's light guide lines can still be seen.
+    pub straightened: bool,
+}
+
+/// Open an input image, or a page of a PDF (the first page, if `pdf_page`
+/// isn't given), along with its resolution if it's recorded. Images are
+/// turned upright if their EXIF metadata says they're stored sideways. Pages of PDFs are
+/// resampled to `pdf_dpi`, if given. HEIC and AVIF images are opened with
+/// [`heif::open`].
+pub fn open_input(
+    path: &Path,
+    pdf_page: Option<u32>,
+    pdf_dpi: Option<u32>,
+) -> anyhow::Result<(DynamicImage, Option<f32>)> {
+    let _span = debug_span!("load").entered();
+    if heif::is_heif(path) {
+        return Ok((heif::open(path)?, dpi::read_dpi(path)));
+    }
+    if !pdf::is_pdf(path) {
+        let mut image = image::open(path).with_context(|| format!("opening {}", path.display()))?;
+        if let Some(exif_orientation) = orientation::read_exif_orientation(path) {
+            debug!(
+                "{} has EXIF orientation {exif_orientation}.",
+                path.display()
+            );
+            image = orientation::apply_exif_orientation(image, exif_orientation);
+        }
+        return Ok((image, dpi::read_dpi(path)));
+    }
+
+    let (image, dpi) = pdf::load_page(path, pdf_page.unwrap_or(1))?;
+    match (pdf_dpi, dpi) {
+        (Some(target), Some(dpi)) if (target as f32 - dpi).abs() >= 1.0 => {
+            let scale = target as f32 / dpi;
+            let image = image.resize_exact(
+                (image.width() as f32 * scale).round().max(1.0) as u32,
+                (image.height() as f32 * scale).round().max(1.0) as u32,
+                image::imageops::FilterType::CatmullRom,
+            );
+            Ok((image, Some(target as f32)))
+        }
+        (Some(_), None) => {
+            warn!(
+                "{} doesn't record its page size; ignoring pdf_dpi.",
+                path.display()
+            );
+            Ok((image, None))
+        }
+        _ => Ok((image, dpi)),
+    }
+}
+
+/// What was learned about a page while preprocessing it.
+#[derive(Clone, Debug)]
+pub struct PreprocessReport {
+    /// The threshold that was used.
+    pub threshold: u8,
+    /// How far the page was turned clockwise to turn it upright, in degrees:
+    /// 0, 90, 180, or 270.
+    pub rotation: u32,
+    /// How far the page was rotated to straighten it, in degrees, if it was.
+    pub skew: Option<f32>,
+    /// The page thresholded softly, if [`PreprocessOptions::grayscale`] was
+    /// set. It lines up pixel for pixel with the thresholded page.
+    pub grayscale: Option<GrayImage>,
+    /// The page thresholded with only the pen's ink, if
+    /// [`PreprocessOptions::pen_color`] was set and the pen's color was found.
+    /// Cells should be cut from this instead, since it doesn't have the grid
+    /// lines, but the grid can only be found on the page itself.
+    pub pen: Option<GrayImage>,
+    /// The straightened page before it was thresholded, if
+    /// [`PreprocessOptions::straightened`] was set. It lines up pixel for
+    /// pixel with the thresholded page.
+    pub straightened: Option<GrayImage>,
+}
+
+/// Straighten, sharpen, and threshold a scanned page.
+pub fn preprocess(
+    image: DynamicImage,
+    options: PreprocessOptions,
+) -> anyhow::Result<(GrayImage, PreprocessReport)> {
+    preprocess_with_profile(image, options, &Profile::default())
+}
+
+/// Like [`preprocess`], but once the page is straightened, put it through
+/// the page stages of `profile` instead of sharpening and thresholding it.
+pub fn preprocess_with_profile(
+    image: DynamicImage,
+    options: PreprocessOptions,
+    profile: &Profile,
+) -> anyhow::Result<(GrayImage, PreprocessReport)> {
+    let PreprocessOptions {
+        threshold,
+        adaptive_window,
+        orient,
+        lighting,
+        deskew,
+        camera,
+        grayscale,
+        pen_color,
+        straightened: keep_straightened,
+    } = options;
+    let mut luma = image.to_luma8();
+    if let Some(lighting) = lighting {
+        luma = debug_span!("lighting").in_scope(|| lighting.apply(&luma));
+    }
+    let threshold = match threshold {
+        threshold::Threshold::Fixed(threshold) => threshold,
+        threshold::Threshold::Auto => {
+            let threshold = threshold.level(&luma);
+            info!("Using threshold {threshold}.");
+            threshold
+        }
+    };
+    let rotation = debug_span!("orientation").in_scope(|| {
+        if !orient {
+            return 0;
+        }
+        orientation::detect(&luma, threshold).unwrap_or_else(|| {
+            debug!("Couldn't find the template's corner markers.");
+            // The template is wider than it's tall.
+            if luma.height() > luma.width() {
+                270
+            } else {
+                0
+            }
+        })
+    });
+    // The page's colors are only needed to find a pen's ink. Otherwise
+    // they're let go of here, since they take three times the memory of the
+    // grayscale page.
+    let colors = match pen_color {
+        Some(_) => Some(image.into_rgb8()),
+        None => {
+            drop(image);
+            None
+        }
+    };
+    let mut image = rotate(luma, rotation);
+    let colors = colors.map(|colors| rotate(colors, rotation));
+    if rotation != 0 {
+        info!("Rotated the page by {rotation}° to turn it upright.");
+    }
+    let mut pen = pen_color.zip(colors).and_then(|(color, colors)| {
+        let _span = debug_span!("pen").entered();
+        let hue = pen::hue(&colors, color);
+        if hue.is_none() {
+            warn!("couldn't find any colored ink; reading the whole page instead.");
+        }
+        hue.map(|hue| pen::separate(&colors, hue))
+    });
+    if camera {
+        let _span = debug_span!("perspective").entered();
+        let Some(corners) = perspective::find_grid_corners(&image, threshold) else {
+            bail!("couldn't find the corners of the grid in the photo.");
+        };
+        image = perspective::correct_perspective(&image, corners)
+            .context("correcting the photo's perspective")?;
+        pen = pen.and_then(|pen| perspective::correct_perspective(&pen, corners));
+        info!("Corrected the photo's perspective.");
+    }
+    let mut report = PreprocessReport {
+        threshold,
+        rotation,
+        skew: None,
+        grayscale: None,
+        pen: None,
+        straightened: None,
+    };
+    if deskew {
+        let _span = debug_span!("deskew").entered();
+        let skew = deskew::estimate_skew(&image, threshold);
+        if let Some(straightened) = deskew::straighten(&image, skew) {
+            info!("Straightened the scan by {skew:.2}°.");
+            image = straightened;
+            report.skew = Some(skew);
+            pen = pen.map(|pen| deskew::straighten(&pen, skew).unwrap_or(pen));
+        }
+    }
+    if grayscale {
+        let _span = debug_span!("grayscale").entered();
+        report.grayscale = Some(match (&pen, adaptive_window) {
+            (Some(pen), _) => threshold::soft_threshold(pen, pen::THRESHOLD),
+            (None, Some(window)) => threshold::soft_adaptive_threshold(&image, window),
+            (None, None) => threshold::soft_threshold(&image, threshold),
+        });
+    }
+    if keep_straightened {
+        report.straightened = Some(image.clone());
+    }
+    let image = profile.apply_to_page(image, threshold, adaptive_window);
+    report.pen = pen.map(|pen| imageproc::contrast::threshold(&pen, pen::THRESHOLD));
+
+    Ok((image, report))
+}
+
+/// Turn a page clockwise by `rotation` degrees, letting go of the page it was
+/// turned from.
+fn rotate<P: Pixel + 'static>(
+    image: ImageBuffer<P, Vec<P::Subpixel>>,
+    rotation: u32,
+) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    match rotation {
+        90 => imageops::rotate90(&image),
+        180 => imageops::rotate180(&image),
+        270 => imageops::rotate270(&image),
+        _ => image,
+    }
+}
+
+/// The kernel that pages are sharpened with, row by row.
+pub const SHARPEN_KERNEL: [f32; 9] = [0.0, -1.0, 0.0, -1.0, 5.0, -1.0, 0.0, -1.0, 0.0];
+
+/// Sharpen a page with [`SHARPEN_KERNEL`].
+pub fn sharpen(image: &GrayImage) -> GrayImage {
+    convolve(image, SHARPEN_KERNEL)
+}
+
+/// How many rows of a page the stages that work in place process at a time.
+/// Each band copies only its own rows, and the rows around them, to read from,
+/// so a large page isn't copied whole.
+pub const BAND_HEIGHT: usize = 64;
+
+/// Convolve a page with a 3 × 3 kernel, given row by row. Like
+/// [`image::imageops::filter3x3`], the pixels along the edges, which the
+/// kernel doesn't fit around, are left black.
+pub fn convolve(image: &GrayImage, kernel: [f32; 9]) -> GrayImage {
+    let mut convolved = image.clone();
+    convolve_in_place(&mut convolved, kernel);
+    convolved
+}
+
+/// Convolve a page with a 3 × 3 kernel like [`convolve`], but without
+/// allocating a second page, one band of [`BAND_HEIGHT`] rows at a time in
+/// parallel.
+pub fn convolve_in_place(image: &mut GrayImage, kernel: [f32; 9]) {
+    let (width, height) = image.dimensions();
+    if width < 3 || height < 3 {
+        image.fill(0);
+        return;
+    }
+    let (width, height) = (width as usize, height as usize);
+    // The rows just above and below each band, saved before the bands next
+    // to it change them.
+    let row = |y: usize| image.as_raw()[y * width..(y + 1) * width].to_vec();
+    let neighbors: Vec<(Vec<u8>, Vec<u8>)> = (0..height.div_ceil(BAND_HEIGHT))
+        .map(|band| {
+            let first = band * BAND_HEIGHT;
+            let last = (first + BAND_HEIGHT).min(height) - 1;
+            let above = if first > 0 {
+                row(first - 1)
+            } else {
+                Vec::new()
+            };
+            let below = if last + 1 < height {
+                row(last + 1)
+            } else {
+                Vec::new()
+            };
+            (above, below)
+        })
+        .collect();
+
+    image
+        .par_chunks_mut(width * BAND_HEIGHT)
+        .zip(neighbors)
+        .enumerate()
+        .for_each(|(band, (rows, (above, below)))| {
+            let source: Vec<u8> = [&above[..], rows, &below[..]].concat();
+            let at = |x: usize, y: usize| source[y * width + x] as f32;
+            // Where the band's first row is in `source`.
+            let offset = usize::from(!above.is_empty());
+            for (n, row) in rows.chunks_mut(width).enumerate() {
+                let y = band * BAND_HEIGHT + n;
+                if y == 0 || y == height - 1 {
+                    row.fill(0);
+                    continue;
+                }
+                let y = offset + n;
+                for (x, pixel) in row.iter_mut().enumerate().take(width - 1).skip(1) {
+                    let mut value = 0.0;
+                    for (i, weight) in kernel.iter().enumerate() {
+                        if *weight != 0.0 {
+                            value += weight * at(x + i % 3 - 1, y + i / 3 - 1);
+                        }
+                    }
+                    *pixel = value.round().clamp(0.0, 255.0) as u8;
+                }
+                row[0] = 0;
+                row[width - 1] = 0;
+            }
+        });
+}
+
+/// Find the template's grid in a preprocessed page. If it can't be found,
+/// fall back to where the grid sits on the bundled template.
+pub fn locate_grid(image: &GrayImage, cols: u32, rows: u32) -> grid::Grid {
+    let detected = debug_span!("grid").in_scope(|| grid::detect_grid(image, cols, rows));
+    detected.unwrap_or_else(|| fallback_grid(image, cols, rows, grid::Margins::default()))
+}
+
+/// Where the grid sits inside `margins`, or on the bundled template where
+/// they aren't given, for a page whose grid lines couldn't be found.
+pub fn fallback_grid(
+    image: &GrayImage,
+    cols: u32,
+    rows: u32,
+    margins: grid::Margins,
+) -> grid::Grid {
+    if margins == grid::Margins::default() {
+        warn!("couldn't find the grid lines; assuming the default template margins.");
+    } else {
+        warn!("couldn't find the grid lines; assuming the given margins.");
+    }
+    let (width, height) = image.dimensions();
+    grid::Grid::from_margins(width, height, cols, rows, margins)
+}
+
+/// Cut a page into its cells along the grid's lines, from left to right and
+/// then top to bottom. The cells are views into the page, so their offsets
+/// give their positions on it.
+pub fn cut_cells<'a, P, Container>(
+    image_buffer: &'a ImageBuffer<P, Container>,
+    grid: &grid::Grid,
+) -> Vec<SubImage<&'a ImageBuffer<P, Container>>>
+where
+    P: Pixel,
+    Container: Deref<Target = [P::Subpixel]>,
+{
+    cut_areas(image_buffer, &grid.cells())
+}
+
+/// Cut a page into the rectangles in `areas`, each given as `(x, y, width,
+/// height)`.
+pub fn cut_areas<'a, P, Container>(
+    image_buffer: &'a ImageBuffer<P, Container>,
+    areas: &[(u32, u32, u32, u32)],
+) -> Vec<SubImage<&'a ImageBuffer<P, Container>>>
+where
+    P: Pixel,
+    Container: Deref<Target = [P::Subpixel]>,
+{
+    areas
+        .iter()
+        .map(|&(x, y, width, height)| image_buffer.view(x, y, width, height))
+        .collect()
+}
+
+/// The settings for [`process_bytes`].
+#[derive(Clone, Copy, Debug)]
+pub struct ProcessOptions {
+    pub preprocess: PreprocessOptions,
+    /// The number of rows and columns of cells on the template.
+    pub rows: u32,
+    pub cols: u32,
+    /// Cells with less of their area covered by ink than this, between 0
+    /// and 1, are taken to be empty and left out.
+    pub min_ink: f32,
+    /// Crop each letterform to its ink, leaving this many pixels of
+    /// whitespace around it. If `None`, whole cells are kept.
+    pub padding: Option<u32>,
+}
+
+/// A letterform cut out of a page by [`process_bytes`].
+#[derive(Clone, Debug)]
+pub struct Glyph {
+    /// The cell's position on the grid, counting from left to right, then
+    /// top to bottom. This is also its position in the charset.
+    pub cell: usize,
+    pub row: u32,
+    pub col: u32,
+    /// How much of the cell is covered by ink, as a fraction between 0 and 1.
+    pub ink_coverage: f32,
+    /// The letterform, in black on white.
+    pub image: GrayImage,
+}
+
+impl Glyph {
+    /// Encode the letterform as a PNG file.
+    pub fn to_png(&self) -> anyhow::Result<Vec<u8>> {
+        let mut png = Vec::new();
+        self.image
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .context("encoding the letterform")?;
+        Ok(png)
+    }
+}
+
+/// Scan a page that's already in memory, such as an upload in a browser, and
+/// return the letterform in each cell that isn't empty. `bytes` can hold an
+/// image in any format the `image` crate reads, but not a PDF. Nothing is
+/// read from or written to disk, so this works in WebAssembly too.
+pub fn process_bytes(bytes: &[u8], options: ProcessOptions) -> anyhow::Result<Vec<Glyph>> {
+    let ProcessOptions {
+        preprocess: preprocess_options,
+        rows,
+        cols,
+        min_ink,
+        padding,
+    } = options;
+    if rows == 0 || cols == 0 {
+        bail!("rows and cols must be at least 1.");
+    }
+    let mut image = image::load_from_memory(bytes).context("reading the image")?;
+    if let Some(exif_orientation) = orientation::exif_orientation(bytes) {
+        image = orientation::apply_exif_orientation(image, exif_orientation);
+    }
+
+    let (page, report) = preprocess(image, preprocess_options)?;
+    let grid = locate_grid(&page, cols, rows);
+    if grid.cells().iter().any(|(_, _, w, h)| *w == 0 || *h == 0) {
+        bail!("the image is too small to hold {cols} × {rows} cells.");
+    }
+    let cells = cut_cells(report.pen.as_ref().unwrap_or(&page), &grid);
+    let glyphs = cells
+        .par_iter()
+        .enumerate()
+        .filter_map(|(i, cell)| {
+            let ink_coverage = glyph::ink_coverage(&**cell);
+            if ink_coverage < min_ink {
+                return None;
+            }
+            let mut image = cell.to_image();
+            glyph::remove_grid_lines(&mut image);
+            if let Some(padding) = padding {
+                if let Some(bounds) = glyph::ink_bounds(&image) {
+                    image = glyph::crop_to(&image, bounds, padding);
+                }
+            }
+            Some(Glyph {
+                cell: i,
+                row: i as u32 / cols,
+                col: i as u32 % cols,
+                ink_coverage,
+                image,
+            })
+        })
+        .collect();
+
+    Ok(glyphs)
+}
+
+/// How letter images are saved.
+#[derive(Clone, Copy, Debug)]
+pub struct SaveOptions {
+    pub output_format: OutputFormat,
+    /// If an image can't be saved in `output_format`, save it as a PNG.
+    pub fallback_to_png: bool,
+    /// Also trace each image into outlines, and save them as an SVG file.
+    pub vectorize: bool,
+    /// How closely traced outlines follow the image, in pixels.
+    pub simplify: f32,
+    /// Thin the image to its centerline, or save its centerline's strokes
+    /// next to it.
+    pub skeleton: Option<SkeletonFormat>,
+    /// Make the paper transparent.
+    pub alpha: bool,
+    /// Draw the ink in this color, instead of black.
+    pub ink_color: Option<InkColor>,
+}
+
+/// Save a letter image as `file_stem` in `output_dir`, along with its traced
+/// outlines if they were asked for. Returns the name of the saved image.
+pub fn save_glyph(
+    letter_image: &GrayImage,
+    output_dir: &Path,
+    file_stem: &str,
+    options: SaveOptions,
+) -> anyhow::Result<String> {
+    let SaveOptions {
+        output_format,
+        fallback_to_png,
+        vectorize,
+        simplify,
+        skeleton,
+        alpha,
+        ink_color,
+    } = options;
+
+    if vectorize {
+        let svg_file = output_dir.join(format!("{file_stem}.svg"));
+        let outlines = vectorize::trace(letter_image, simplify);
+        let svg = vectorize::to_svg(&outlines, letter_image.width(), letter_image.height());
+        std::fs::write(&svg_file, svg).with_context(|| format!("saving {}", svg_file.display()))?;
+    }
+    let thinned;
+    let letter_image = match skeleton {
+        Some(SkeletonFormat::Image) => {
+            thinned = skeleton::thin(letter_image);
+            &thinned
+        }
+        Some(format) => {
+            let (width, height) = letter_image.dimensions();
+            let strokes = skeleton::trace(letter_image, simplify);
+            let contents = match format {
+                SkeletonFormat::Svg => skeleton::to_svg(&strokes, width, height),
+                _ => skeleton::to_json(&strokes, width, height),
+            };
+            let extension = format.extension().unwrap_or_default();
+            let strokes_file = output_dir.join(format!("{file_stem}.{extension}"));
+            std::fs::write(&strokes_file, contents)
+                .with_context(|| format!("saving {}", strokes_file.display()))?;
+            letter_image
+        }
+        None => letter_image,
+    };
+    let letter_image = colorize(letter_image, alpha, ink_color);
+    let file_name = format!("{file_stem}.{}", output_format.extension());
+    let output_file = output_dir.join(&file_name);
+    let Err(err) = letter_image.save_with_format(&output_file, output_format.image_format()) else {
+        return Ok(file_name);
+    };
+    if !fallback_to_png || output_format == OutputFormat::Png {
+        return Err(err).with_context(|| format!("saving {}", output_file.display()));
+    }
+    error!("Failed to save {}: {err}", output_file.display());
+    let png_name = format!("{file_stem}.png");
+    let png_file = output_dir.join(&png_name);
+    letter_image
+        .save(&png_file)
+        .with_context(|| format!("saving {}", png_file.display()))?;
+    info!("Saved {} instead.", png_file.display());
+
+    Ok(png_name)
+}
+
+/// Save many letter images at once, in parallel, each as the file stem it's
+/// paired with, like [`save_glyph`]. Returns, in the same order, the name of
+/// each saved image, or why it couldn't be saved.
+pub fn save_glyphs(
+    glyphs: &[(&GrayImage, &str)],
+    output_dir: &Path,
+    options: SaveOptions,
+) -> Vec<anyhow::Result<String>> {
+    glyphs
+        .par_iter()
+        .map(|&(letter_image, file_stem)| save_glyph(letter_image, output_dir, file_stem, options))
+        .collect()
+}
+
+/// Turn a letter image into the colors it's saved in. Paper becomes
+/// transparent if `alpha` is set, and ink is drawn in `ink_color`. Gray
+/// pixels along the edges of strokes are blended between the two.
+pub fn colorize(
+    letter_image: &GrayImage,
+    alpha: bool,
+    ink_color: Option<InkColor>,
+) -> DynamicImage {
+    let [red, green, blue] = ink_color.map_or([0; 3], |InkColor(color)| color);
+    match (alpha, ink_color) {
+        (false, None) => DynamicImage::ImageLuma8(letter_image.clone()),
+        (true, _) => DynamicImage::ImageRgba8(RgbaImage::from_fn(
+            letter_image.width(),
+            letter_image.height(),
+            |x, y| Rgba([red, green, blue, 255 - letter_image.get_pixel(x, y).0[0]]),
+        )),
+        (false, Some(_)) => DynamicImage::ImageRgb8(RgbImage::from_fn(
+            letter_image.width(),
+            letter_image.height(),
+            |x, y| {
+                let paper = letter_image.get_pixel(x, y).0[0] as u32;
+                let blend = |ink: u8| ((ink as u32 * (255 - paper) + 255 * paper) / 255) as u8;
+                Rgb([blend(red), blend(green), blend(blue)])
+            },
+        )),
+    }
+}
+
+/// Undo [`colorize`]: lay a saved letter image on white paper and make it
+/// gray, so that its ink can be compared with a letter image's.
+pub fn flatten(image: &DynamicImage) -> GrayImage {
+    let image = image.to_luma_alpha8();
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        let [ink, alpha] = image.get_pixel(x, y).0;
+        image::Luma([255 - ((255 - ink as u32) * alpha as u32 / 255) as u8])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{charset::Charset, template::render_template, threshold::Threshold};
+
+    #[test]
+    fn scans_a_generated_template() {
+        let page = render_template(3, 4, 120, &Charset::default(), None, false);
+        let options = PreprocessOptions {
+            threshold: Threshold::Fixed(190),
+            adaptive_window: None,
+            orient: true,
+            lighting: None,
+            deskew: false,
+            camera: false,
+            grayscale: true,
+            pen_color: None,
+            straightened: false,
+        };
+        let (image, report) = preprocess(DynamicImage::ImageLuma8(page), options).unwrap();
+        assert_eq!(report.threshold, 190);
+        assert_eq!(report.grayscale.unwrap().dimensions(), image.dimensions());
+
+        let grid = locate_grid(&image, 4, 3);
+        assert_eq!(grid.columns.len(), 5);
+        assert_eq!(grid.rows.len(), 4);
+        let cells = cut_cells(&image, &grid);
+        assert_eq!(cells.len(), 12);
+        for cell in &cells {
+            let (width, height) = cell.dimensions();
+            assert!((118..=122).contains(&width), "cell is {width} wide");
+            assert!((118..=122).contains(&height), "cell is {height} tall");
+        }
+    }
+
+    #[test]
+    fn processes_an_image_in_memory() {
+        let mut page = render_template(2, 2, 120, &Charset::default(), None, false);
+        let grid = locate_grid(&page, 2, 2);
+        // Write a blob of ink in the last cell, above its baseline.
+        let (x, y, width, height) = grid.cells()[3];
+        for dy in 0..height / 4 {
+            for dx in 0..width / 3 {
+                page.put_pixel(x + width / 3 + dx, y + height / 4 + dy, image::Luma([0]));
+            }
+        }
+        let mut bytes = Vec::new();
+        page.write_to(
+            &mut std::io::Cursor::new(&mut bytes),
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+
+        let options = ProcessOptions {
+            preprocess: PreprocessOptions {
+                // Low enough that sharpening doesn't turn the faint
+                // baselines into ink.
+                threshold: Threshold::Fixed(128),
+                adaptive_window: None,
+                orient: true,
+                lighting: None,
+                deskew: false,
+                camera: false,
+                grayscale: false,
+                pen_color: None,
+                straightened: false,
+            },
+            rows: 2,
+            cols: 2,
+            min_ink: 0.05,
+            padding: Some(2),
+        };
+        let glyphs = process_bytes(&bytes, options).unwrap();
+        assert_eq!(glyphs.len(), 1);
+        assert_eq!((glyphs[0].cell, glyphs[0].row, glyphs[0].col), (3, 1, 1));
+        let (glyph_width, glyph_height) = glyphs[0].image.dimensions();
+        assert!(glyph_width < width / 2 && glyph_height < height / 2);
+    }
+
+    #[test]
+    fn sharpens_like_filter3x3() {
+        // Tall enough to be sharpened in several bands.
+        let image = GrayImage::from_fn(37, BAND_HEIGHT as u32 * 2 + 23, |x, y| {
+            image::Luma([((x * 37 + y * 91) % 256) as u8])
+        });
+        let expected =
+            image::imageops::filter3x3(&image, &[0.0, -1.0, 0.0, -1.0, 5.0, -1.0, 0.0, -1.0, 0.0]);
+        assert_eq!(sharpen(&image), expected);
+    }
+
+    #[test]
+    fn flattens_colorized_letter_images() {
+        let letter_image = GrayImage::from_fn(4, 1, |x, _| image::Luma([x as u8 * 85]));
+        let ink = Some(InkColor([0, 0, 0]));
+        for (alpha, ink_color) in [(false, None), (true, None), (true, ink)] {
+            let colorized = colorize(&letter_image, alpha, ink_color);
+            assert_eq!(flatten(&colorized), letter_image);
+        }
+    }
+
+    #[test]
+    fn saves_glyphs_by_name() {
+        let dir = std::env::temp_dir().join(format!("pipeline-save-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let options = SaveOptions {
+            output_format: OutputFormat::Png,
+            fallback_to_png: false,
+            vectorize: true,
+            simplify: 1.0,
+            skeleton: Some(SkeletonFormat::Json),
+            alpha: false,
+            ink_color: None,
+        };
+        let image = GrayImage::from_pixel(8, 8, image::Luma([0]));
+
+        let file = save_glyph(&image, &dir, "U+0041_A", options).unwrap();
+        assert_eq!(file, "U+0041_A.png");
+        assert!(dir.join("U+0041_A.png").is_file());
+        assert!(dir.join("U+0041_A.svg").is_file());
+        assert!(dir.join("U+0041_A.strokes.json").is_file());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn saves_many_glyphs_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = SaveOptions {
+            output_format: OutputFormat::Png,
+            fallback_to_png: false,
+            vectorize: false,
+            simplify: 1.0,
+            skeleton: None,
+            alpha: false,
+            ink_color: None,
+        };
+        let images: Vec<GrayImage> = (1..=3)
+            .map(|size| GrayImage::from_pixel(size, size, image::Luma([0])))
+            .collect();
+        let stems = ["a", "b", "c"];
+        let glyphs: Vec<(&GrayImage, &str)> = images.iter().zip(stems).collect();
+
+        let files = save_glyphs(&glyphs, dir.path(), options);
+        let files: Vec<String> = files.into_iter().map(Result::unwrap).collect();
+        assert_eq!(files, ["a.png", "b.png", "c.png"]);
+        for (file, size) in files.iter().zip(1..) {
+            let saved = image::open(dir.path().join(file)).unwrap();
+            assert_eq!(saved.dimensions(), (size, size));
+        }
+
+        // A stem that can't be written fails on its own.
+        let glyphs = [(&images[0], "d"), (&images[1], "missing/e")];
+        let files = save_glyphs(&glyphs, dir.path(), options);
+        assert_eq!(files[0].as_deref().unwrap(), "d.png");
+        assert!(files[1].is_err());
+    }
+}
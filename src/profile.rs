@@ -0,0 +1,326 @@
+use anyhow::{bail, Context};
+use image::GrayImage;
+use serde::Deserialize;
+use std::path::Path;
+use tracing::debug_span;
+
+use crate::{cleanup, glyph::PAPER, lighting, pipeline, threshold::Threshold};
+
+/// The profiles built into the app, by name. Each is a TOML file in the
+/// `profiles` directory of the repository, which makes a good starting point
+/// for your own.
+pub const PRESETS: &[(&str, &str)] = &[
+    (
+        "scanner-300dpi",
+        include_str!("../profiles/scanner-300dpi.toml"),
+    ),
+    ("phone-photo", include_str!("../profiles/phone-photo.toml")),
+    ("pencil", include_str!("../profiles/pencil.toml")),
+];
+
+/// The steps a page and its cells are put through, in order, loaded from a
+/// TOML file with `--profile`. Without one, a page is sharpened and then
+/// thresholded, and its cells are left as they are.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// What's done to the whole page, once it's been turned upright and
+    /// straightened. If none of these steps thresholds the page, it's
+    /// thresholded at the end.
+    #[serde(default = "default_page_stages")]
+    pub page: Vec<PageStage>,
+    /// What's done to each cell once it's cut out, before its letterform is
+    /// found. These come before `--despeckle`, `--open`, and `--close`.
+    #[serde(default)]
+    pub cell: Vec<CellStage>,
+}
+
+/// A step that's applied to a whole page.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum PageStage {
+    /// Blur the page, which smooths out paper grain and camera noise.
+    Blur { sigma: f32 },
+    /// Replace each pixel with the median of the square `radius` pixels
+    /// around it, which removes specks without softening edges as much.
+    Median { radius: u32 },
+    /// Make the paper equally bright all over, by dividing out how brightly
+    /// each part of it is lit, like `--lighting flatten`.
+    Flatten,
+    /// Stretch the contrast of each of `tiles` × `tiles` parts of the page
+    /// on its own, like `--lighting clahe`. The default is 8 tiles, with a
+    /// `clip_limit` of 2; a higher limit stretches further.
+    Clahe {
+        #[serde(default = "default_clahe_tiles")]
+        tiles: u32,
+        #[serde(default = "default_clahe_clip_limit")]
+        clip_limit: f32,
+    },
+    /// Convolve the page with a 3 × 3 kernel, given row by row. The default
+    /// kernel sharpens.
+    Sharpen {
+        #[serde(default = "default_kernel")]
+        kernel: [f32; 9],
+    },
+    /// Turn the page into black ink on white paper. Either `level` or
+    /// `window` may be given; without either, `--threshold` and
+    /// `--adaptive-threshold` are used.
+    Threshold {
+        /// A brightness between 0 and 255, or `"auto"`.
+        level: Option<Threshold>,
+        /// Threshold each pixel against the square of this many pixels
+        /// around it, like `--adaptive-threshold`.
+        window: Option<u32>,
+    },
+}
+
+/// A step that's applied to each cell.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum CellStage {
+    /// Erase specks of ink smaller than this many pixels, like
+    /// `--despeckle`.
+    Despeckle { min_size: u32 },
+    /// A morphological opening, like `--open`.
+    Open { radius: u8 },
+    /// A morphological closing, like `--close`.
+    Close { radius: u8 },
+    /// Erase this fraction of the cell's width and height along each edge,
+    /// for templates whose lines leave a wider border than usual.
+    Crop { margin: f32 },
+}
+
+fn default_kernel() -> [f32; 9] {
+    pipeline::SHARPEN_KERNEL
+}
+
+fn default_clahe_tiles() -> u32 {
+    lighting::CLAHE_TILES
+}
+
+fn default_clahe_clip_limit() -> f32 {
+    lighting::CLAHE_CLIP_LIMIT
+}
+
+fn default_page_stages() -> Vec<PageStage> {
+    vec![
+        PageStage::Sharpen {
+            kernel: default_kernel(),
+        },
+        PageStage::Threshold {
+            level: None,
+            window: None,
+        },
+    ]
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            page: default_page_stages(),
+            cell: Vec::new(),
+        }
+    }
+}
+
+impl Profile {
+    /// Load a profile from `--profile`: the name of a preset, or the path of
+    /// a TOML file.
+    pub fn from_arg(value: &str) -> anyhow::Result<Self> {
+        if let Some((_, contents)) = PRESETS.iter().find(|(name, _)| *name == value) {
+            return Self::parse(contents).with_context(|| format!("parsing the {value} preset"));
+        }
+        let path = Path::new(value);
+        if !path.is_file() {
+            let names: Vec<&str> = PRESETS.iter().map(|(name, _)| *name).collect();
+            bail!(
+                "{value} isn't a profile file or one of the presets: {}.",
+                names.join(", ")
+            );
+        }
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    /// Read a profile from the contents of a TOML file, and check that its
+    /// settings make sense.
+    pub fn parse(contents: &str) -> anyhow::Result<Self> {
+        let mut profile: Self = toml::from_str(contents)?;
+        for stage in &profile.page {
+            match *stage {
+                PageStage::Blur { sigma } if sigma <= 0.0 => bail!("blur sigma must be above 0."),
+                PageStage::Clahe { tiles: 0, .. } => bail!("clahe tiles must be at least 1."),
+                PageStage::Clahe { clip_limit, .. } if clip_limit <= 0.0 => {
+                    bail!("clahe clip_limit must be above 0.")
+                }
+                PageStage::Threshold {
+                    level: Some(_),
+                    window: Some(_),
+                } => bail!("a threshold stage takes a level or a window, not both."),
+                PageStage::Threshold {
+                    window: Some(0), ..
+                } => bail!("threshold window must be at least 1 pixel."),
+                _ => {}
+            }
+        }
+        for stage in &profile.cell {
+            if let CellStage::Crop { margin } = *stage {
+                if !(0.0..0.5).contains(&margin) {
+                    bail!("crop margin must be at least 0 and less than 0.5.");
+                }
+            }
+        }
+        if !profile
+            .page
+            .iter()
+            .any(|stage| matches!(stage, PageStage::Threshold { .. }))
+        {
+            profile.page.push(PageStage::Threshold {
+                level: None,
+                window: None,
+            });
+        }
+
+        Ok(profile)
+    }
+
+    /// Put a page through the profile's page stages. A threshold stage with
+    /// no settings of its own thresholds at `level`, or adaptively with
+    /// `window` if it's given.
+    pub fn apply_to_page(&self, page: GrayImage, level: u8, window: Option<u32>) -> GrayImage {
+        let mut page = page;
+        for stage in &self.page {
+            // Sharpening and fixed thresholds change the page in place, so
+            // that a large page isn't held twice.
+            page = match *stage {
+                PageStage::Blur { sigma } => debug_span!("blur")
+                    .in_scope(|| imageproc::filter::gaussian_blur_f32(&page, sigma)),
+                PageStage::Flatten => debug_span!("flatten").in_scope(|| lighting::flatten(&page)),
+                PageStage::Clahe { tiles, clip_limit } => {
+                    debug_span!("clahe").in_scope(|| lighting::clahe(&page, tiles, clip_limit))
+                }
+                PageStage::Median { radius } => debug_span!("median")
+                    .in_scope(|| imageproc::filter::median_filter(&page, radius, radius)),
+                PageStage::Sharpen { kernel } => debug_span!("sharpen").in_scope(|| {
+                    pipeline::convolve_in_place(&mut page, kernel);
+                    page
+                }),
+                PageStage::Threshold {
+                    level: stage_level,
+                    window: stage_window,
+                } => debug_span!("threshold").in_scope(|| {
+                    match (
+                        stage_level,
+                        stage_window.or(window.filter(|_| stage_level.is_none())),
+                    ) {
+                        (_, Some(window)) => {
+                            return crate::threshold::adaptive_threshold(&page, window)
+                        }
+                        (Some(stage_level), None) => {
+                            let stage_level = stage_level.level(&page);
+                            imageproc::contrast::threshold_mut(&mut page, stage_level);
+                        }
+                        (None, None) => imageproc::contrast::threshold_mut(&mut page, level),
+                    }
+                    page
+                }),
+            };
+        }
+
+        page
+    }
+
+    /// Put a cell through the profile's cell stages.
+    pub fn apply_to_cell(&self, cell: &mut GrayImage) {
+        for stage in &self.cell {
+            match *stage {
+                CellStage::Despeckle { min_size } => cleanup::despeckle(cell, min_size),
+                CellStage::Open { radius } => cleanup::open(cell, radius),
+                CellStage::Close { radius } => cleanup::close(cell, radius),
+                CellStage::Crop { margin } => crop(cell, margin),
+            }
+        }
+    }
+}
+
+/// Erase `margin` of a cell's width and height along each of its edges.
+fn crop(cell: &mut GrayImage, margin: f32) {
+    let (width, height) = cell.dimensions();
+    let inset_x = (width as f32 * margin).round() as u32;
+    let inset_y = (height as f32 * margin).round() as u32;
+    for (x, y, pixel) in cell.enumerate_pixels_mut() {
+        let inside =
+            (inset_x..width - inset_x).contains(&x) && (inset_y..height - inset_y).contains(&y);
+        if !inside {
+            *pixel = PAPER;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_presets_and_profiles() {
+        for (name, _) in PRESETS {
+            Profile::from_arg(name).unwrap();
+        }
+        assert_eq!(
+            Profile::from_arg("scanner-300dpi").unwrap(),
+            Profile::default()
+        );
+
+        let profile = Profile::parse(
+            r#"
+            [[page]]
+            kind = "blur"
+            sigma = 1.5
+
+            [[cell]]
+            kind = "crop"
+            margin = 0.1
+            "#,
+        )
+        .unwrap();
+        // A threshold is added, since the page would be left gray otherwise.
+        assert_eq!(
+            profile.page,
+            vec![
+                PageStage::Blur { sigma: 1.5 },
+                PageStage::Threshold {
+                    level: None,
+                    window: None
+                }
+            ]
+        );
+        assert_eq!(profile.cell, vec![CellStage::Crop { margin: 0.1 }]);
+
+        let profile =
+            Profile::parse("[[page]]\nkind = \"flatten\"\n[[page]]\nkind = \"clahe\"").unwrap();
+        assert_eq!(profile.page[0], PageStage::Flatten);
+        assert_eq!(
+            profile.page[1],
+            PageStage::Clahe {
+                tiles: 8,
+                clip_limit: 2.0
+            }
+        );
+        assert!(Profile::parse("[[page]]\nkind = \"clahe\"\ntiles = 0").is_err());
+
+        assert!(Profile::parse("[[page]]\nkind = \"emboss\"").is_err());
+        assert!(Profile::parse("[[page]]\nkind = \"threshold\"\nlevel = 300").is_err());
+        assert!(Profile::from_arg("no-such-preset").is_err());
+    }
+
+    #[test]
+    fn crops_the_edges_of_cells() {
+        let mut cell = GrayImage::from_pixel(10, 10, image::Luma([0]));
+        crop(&mut cell, 0.2);
+        assert_eq!(cell.get_pixel(1, 5).0[0], 255);
+        assert_eq!(cell.get_pixel(5, 8).0[0], 255);
+        assert_eq!(cell.get_pixel(2, 2).0[0], 0);
+        assert_eq!(cell.get_pixel(7, 7).0[0], 0);
+    }
+}
@@ -0,0 +1,206 @@
+use image::{GenericImageView, GrayImage, Luma};
+use imageproc::{
+    distance_transform::Norm,
+    morphology::dilate,
+    region_labelling::{connected_components, Connectivity},
+};
+use serde::Serialize;
+use std::{collections::HashMap, fmt};
+
+/// Letterforms whose ink is, on average, lighter than this are faint. Ink
+/// that's solid black is 1, and ink that's barely darker than the threshold
+/// is 0.
+const MIN_CONTRAST: f32 = 0.35;
+/// Letterforms in more pieces than this are probably broken. Some
+/// characters, like `%` and `÷`, have three.
+const MAX_COMPONENTS: u32 = 3;
+/// Pieces of ink smaller than this fraction of the cell are specks, and
+/// aren't counted as pieces of the letterform.
+const MIN_COMPONENT_AREA: f32 = 0.0005;
+/// Letterforms whose edges fade from ink to paper over more than this
+/// fraction of the cell's height are blurry. That's 4.5 pixels in a cell
+/// 225 pixels tall, the size of a cell at 300 DPI.
+const MAX_EDGE_WIDTH: f32 = 0.02;
+/// Letterforms covering more than this fraction of their cell are probably
+/// smudged or scribbled over.
+const MAX_INK_DENSITY: f32 = 0.35;
+
+/// How well a letterform was captured.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Quality {
+    /// How dark the ink is, on average, from 0 (barely darker than the
+    /// threshold) to 1 (solid black).
+    pub contrast: f32,
+    /// How much of the cell is covered by ink, from 0 to 1.
+    pub ink_density: f32,
+    /// How many separate pieces of ink make up the letterform, not counting
+    /// specks.
+    pub components: u32,
+    /// How many pixels wide the blurry edge between ink and paper is, on
+    /// average.
+    pub edge_width: f32,
+}
+
+/// Something that looks wrong with a letterform.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Issue {
+    Faint,
+    Broken,
+    Blurry,
+    Smudged,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Faint => "looks faint",
+            Self::Broken => "looks broken into pieces",
+            Self::Blurry => "looks blurry",
+            Self::Smudged => "looks smudged",
+        })
+    }
+}
+
+/// Measure a letterform from its cell, thresholded and cleaned up (`ink`),
+/// and the same cell thresholded softly (`soft`), as made by
+/// [`crate::threshold::soft_threshold`]. Returns `None` if the cell has no
+/// ink.
+pub fn measure<I>(ink: &GrayImage, soft: &I) -> Option<Quality>
+where
+    I: GenericImageView<Pixel = Luma<u8>>,
+{
+    let (width, height) = ink.dimensions();
+    let is_ink = |x: u32, y: u32| ink.get_pixel(x, y).0[0] < 128;
+    let mut ink_pixels = 0u32;
+    let mut darkness = 0.0;
+    let mut edge = 0u32;
+    for (x, y, pixel) in ink.enumerate_pixels() {
+        if pixel.0[0] >= 128 {
+            continue;
+        }
+        ink_pixels += 1;
+        darkness += 1.0 - soft.get_pixel(x, y).0[0] as f32 / 255.0;
+        let neighbors = [
+            (x.wrapping_sub(1), y),
+            (x + 1, y),
+            (x, y.wrapping_sub(1)),
+            (x, y + 1),
+        ];
+        if neighbors
+            .iter()
+            .any(|&(x, y)| x < width && y < height && !is_ink(x, y))
+        {
+            edge += 1;
+        }
+    }
+    if ink_pixels == 0 {
+        return None;
+    }
+    // Pixels near the strokes that are neither solid ink nor paper make up
+    // their blurry edges. Spread along the strokes' outlines, they say how
+    // wide those edges are. Pixels farther away are left out, since they're
+    // more likely what's left of the grid's lines.
+    let mask = ink_mask(ink);
+    let reach = (height as f32 * MAX_EDGE_WIDTH * 2.0).ceil().min(255.0) as u8;
+    let near = dilate(&mask, Norm::LInf, reach);
+    let gray = near
+        .enumerate_pixels()
+        .filter(|(x, y, near)| {
+            let pixel = soft.get_pixel(*x, *y).0[0];
+            near.0[0] != 0 && pixel > 0 && pixel < 255
+        })
+        .count();
+
+    Some(Quality {
+        contrast: darkness / ink_pixels as f32,
+        ink_density: ink_pixels as f32 / (width * height) as f32,
+        components: components(&mask),
+        edge_width: gray as f32 / edge.max(1) as f32,
+    })
+}
+
+/// A mask of a cell's ink, which is white where the ink is.
+fn ink_mask(ink: &GrayImage) -> GrayImage {
+    GrayImage::from_fn(ink.width(), ink.height(), |x, y| {
+        Luma([if ink.get_pixel(x, y).0[0] < 128 {
+            255
+        } else {
+            0
+        }])
+    })
+}
+
+/// Count the pieces of ink in a cell's ink mask, ignoring specks.
+fn components(mask: &GrayImage) -> u32 {
+    let (width, height) = mask.dimensions();
+    let labels = connected_components(mask, Connectivity::Eight, Luma([0]));
+    let mut sizes = HashMap::new();
+    for label in labels.pixels().filter(|label| label.0[0] != 0) {
+        *sizes.entry(label.0[0]).or_insert(0u32) += 1;
+    }
+    let min_area = (width * height) as f32 * MIN_COMPONENT_AREA;
+    sizes
+        .values()
+        .filter(|size| **size as f32 >= min_area)
+        .count() as u32
+}
+
+/// What looks wrong with a letterform in a cell `cell_height` pixels tall.
+pub fn issues(quality: &Quality, cell_height: u32) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    if quality.contrast < MIN_CONTRAST {
+        issues.push(Issue::Faint);
+    }
+    if quality.components > MAX_COMPONENTS {
+        issues.push(Issue::Broken);
+    }
+    if quality.edge_width > cell_height as f32 * MAX_EDGE_WIDTH {
+        issues.push(Issue::Blurry);
+    }
+    if quality.ink_density > MAX_INK_DENSITY {
+        issues.push(Issue::Smudged);
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use imageproc::{drawing::draw_filled_rect_mut, rect::Rect};
+
+    #[test]
+    fn finds_faint_and_broken_letterforms() {
+        // A solid black bar, cut cleanly from the paper.
+        let mut soft = GrayImage::from_pixel(100, 100, Luma([255]));
+        draw_filled_rect_mut(&mut soft, Rect::at(20, 45).of_size(60, 10), Luma([0]));
+        let ink = soft.clone();
+        let quality = measure(&ink, &soft).unwrap();
+        assert_eq!(quality.contrast, 1.0);
+        assert_eq!(quality.components, 1);
+        assert_eq!(quality.edge_width, 0.0);
+        assert_eq!(issues(&quality, 100), vec![]);
+
+        // The same bar, barely darker than the threshold, in five pieces.
+        let mut soft = GrayImage::from_pixel(100, 100, Luma([255]));
+        for i in 0..5 {
+            draw_filled_rect_mut(
+                &mut soft,
+                Rect::at(10 + i * 18, 45).of_size(10, 10),
+                Luma([220]),
+            );
+        }
+        let ink = imageproc::contrast::threshold(&soft, 250);
+        let quality = measure(&ink, &soft).unwrap();
+        assert_eq!(quality.components, 5);
+        assert_eq!(
+            issues(&quality, 100),
+            vec![Issue::Faint, Issue::Broken, Issue::Blurry]
+        );
+
+        assert_eq!(
+            measure(&GrayImage::from_pixel(10, 10, Luma([255])), &soft),
+            None
+        );
+    }
+}
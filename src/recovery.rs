@@ -0,0 +1,206 @@
+use image::{imageops, DynamicImage, GrayImage, Rgb, RgbImage};
+use std::{fmt, path::PathBuf};
+
+use crate::{descriptor::Located, grid, pipeline::PreprocessOptions, threshold::Threshold};
+
+/// The color of the lines that were found on a page, in a debug image.
+const FOUND_COLOR: Rgb<u8> = Rgb([220, 40, 40]);
+/// The color of the grid that was expected, in a debug image.
+const EXPECTED_COLOR: Rgb<u8> = Rgb([60, 120, 255]);
+/// The widest a debug image is made, in pixels.
+const MAX_DEBUG_WIDTH: u32 = 1600;
+/// The fraction of a page's darkest pixels, and of its lightest, that
+/// stretching its contrast ignores, so that a few specks don't decide it.
+const STRETCH_CLIP: f32 = 0.01;
+
+/// Another way to read a page whose grid couldn't be found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Attempt {
+    /// Turn the page clockwise by this many degrees first, in case its
+    /// corner markers were missed.
+    Rotate(u32),
+    /// Swap black and white, for scans of white ink on black paper.
+    Invert,
+    /// Stretch the page's contrast and choose its threshold automatically,
+    /// for faded or underexposed scans.
+    StretchContrast,
+}
+
+impl Attempt {
+    /// Every attempt, in the order they're tried.
+    pub const ALL: [Self; 5] = [
+        Self::Rotate(90),
+        Self::Rotate(180),
+        Self::Rotate(270),
+        Self::Invert,
+        Self::StretchContrast,
+    ];
+
+    /// Change a page, as it was loaded, the way this attempt reads it.
+    pub fn apply(self, image: DynamicImage) -> DynamicImage {
+        match self {
+            Self::Rotate(90) => image.rotate90(),
+            Self::Rotate(180) => image.rotate180(),
+            Self::Rotate(_) => image.rotate270(),
+            Self::Invert => {
+                let mut image = image;
+                image.invert();
+                image
+            }
+            Self::StretchContrast => DynamicImage::ImageLuma8(stretch_contrast(image.to_luma8())),
+        }
+    }
+
+    /// The options to preprocess a page changed by this attempt with,
+    /// instead of `options`.
+    pub fn options(self, options: PreprocessOptions) -> PreprocessOptions {
+        match self {
+            // Turning the page upright would undo turning it.
+            Self::Rotate(_) => PreprocessOptions {
+                orient: false,
+                ..options
+            },
+            Self::Invert => options,
+            Self::StretchContrast => PreprocessOptions {
+                threshold: Threshold::Auto,
+                ..options
+            },
+        }
+    }
+}
+
+impl fmt::Display for Attempt {
+    /// Describes the page, as in "the grid was found by reading the page ...".
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rotate(degrees) => write!(f, "turned {degrees}°"),
+            Self::Invert => f.write_str("with its colors inverted"),
+            Self::StretchContrast => f.write_str("with its contrast stretched"),
+        }
+    }
+}
+
+/// The error that stops a scan when a page's grid can't be found, however the
+/// page is read, and it has no margins to be cut inside instead. Scans
+/// stopped by it exit with [`crate::summary::Status::NoGrid`].
+#[derive(Debug)]
+pub struct GridNotFound {
+    /// Where an image of the lines that were found on the page was saved, if
+    /// it could be.
+    pub debug_image: Option<PathBuf>,
+}
+
+impl fmt::Display for GridNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(
+            "couldn't find the template's grid, even with the page turned each way, with its \
+             colors inverted, or with its contrast stretched.",
+        )?;
+        if let Some(debug_image) = &self.debug_image {
+            write!(
+                f,
+                " {} shows the lines that were found in red, and where the grid was expected \
+                 in blue.",
+                debug_image.display()
+            )?;
+        }
+        f.write_str(
+            " To cut the page anyway, pass its margins with --margin-x and --margin-y, or \
+             describe its layout with --template.",
+        )
+    }
+}
+
+impl std::error::Error for GridNotFound {}
+
+/// Stretch a page's brightness so that its darkest and lightest pixels, but
+/// for a few, become black and white.
+pub fn stretch_contrast(mut image: GrayImage) -> GrayImage {
+    let histogram = imageproc::stats::cumulative_histogram(&image).channels[0];
+    let total = histogram[255] as f32;
+    let level = |fraction: f32| {
+        histogram
+            .iter()
+            .position(|count| *count as f32 >= total * fraction)
+            .unwrap_or(255) as u8
+    };
+    let (lower, upper) = (level(STRETCH_CLIP), level(1.0 - STRETCH_CLIP));
+    if upper > lower {
+        imageproc::contrast::stretch_contrast_mut(&mut image, lower, upper);
+    }
+    image
+}
+
+/// Draw a thresholded page whose grid couldn't be found, shrunk to at most
+/// [`MAX_DEBUG_WIDTH`] pixels wide, with the lines that could be grid lines
+/// in red and the grid that was expected in blue.
+pub fn debug_image(page: &GrayImage, located: &Located) -> RgbImage {
+    let (page_width, page_height) = page.dimensions();
+    let scale = (MAX_DEBUG_WIDTH as f32 / page_width as f32).min(1.0);
+    let size = |length: u32| ((length as f32 * scale).round() as u32).max(1);
+    let (width, height) = (size(page_width), size(page_height));
+    let small = imageops::thumbnail(page, width, height);
+    // The page is faded, so that the lines stand out.
+    let mut image = RgbImage::from_fn(width, height, |x, y| {
+        Rgb([small.get_pixel(x, y).0[0] / 2 + 128; 3])
+    });
+
+    let at = |position: u32, extent: u32| ((position as f32 * scale) as u32).min(extent - 1);
+    let mut vertical = |x: u32, color: Rgb<u8>| {
+        let x = at(x, width);
+        for y in 0..height {
+            image.put_pixel(x, y, color);
+        }
+    };
+    let (columns, rows) = grid::find_grid_lines(page);
+    for grid in &located.grids {
+        grid.columns
+            .iter()
+            .for_each(|x| vertical(*x, EXPECTED_COLOR));
+    }
+    columns.iter().for_each(|x| vertical(*x, FOUND_COLOR));
+    let mut horizontal = |y: u32, color: Rgb<u8>| {
+        let y = at(y, height);
+        for x in 0..width {
+            image.put_pixel(x, y, color);
+        }
+    };
+    for grid in &located.grids {
+        grid.rows
+            .iter()
+            .for_each(|y| horizontal(*y, EXPECTED_COLOR));
+    }
+    rows.iter().for_each(|y| horizontal(*y, FOUND_COLOR));
+
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn stretches_a_faded_page() {
+        let page = GrayImage::from_fn(100, 1, |x, _| Luma([100 + x as u8]));
+        let stretched = stretch_contrast(page);
+        assert_eq!(stretched.get_pixel(0, 0).0[0], 0);
+        assert_eq!(stretched.get_pixel(99, 0).0[0], 255);
+        assert!(stretched.get_pixel(50, 0).0[0].abs_diff(128) < 8);
+    }
+
+    #[test]
+    fn finds_the_grid_of_an_inverted_page() {
+        use crate::{charset::Charset, template::render_template};
+        let page = render_template(2, 3, 100, &Charset::default(), None, false);
+        let mut inverted = DynamicImage::ImageLuma8(page);
+        inverted.invert();
+        let found = |image: &DynamicImage| {
+            let page = imageproc::contrast::threshold(&image.to_luma8(), 128);
+            grid::detect_grid(&page, 3, 2).is_some()
+        };
+        assert!(!found(&inverted));
+        let restored = Attempt::Invert.apply(inverted);
+        assert!(found(&restored));
+    }
+}
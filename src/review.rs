@@ -0,0 +1,155 @@
+use image::{GrayImage, Luma};
+use std::io::Write;
+
+use crate::glyph;
+
+/// The largest preview drawn for a cell, in characters. Each character shows
+/// two pixels, one above the other.
+const PREVIEW_COLUMNS: u32 = 48;
+const PREVIEW_ROWS: u32 = 20;
+
+/// What to do with a cell after reviewing it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Save the cell under this character, or sequence of characters, or by
+    /// cell number if there's none.
+    Accept(Option<String>),
+    Reject,
+}
+
+/// Show each cell in `cells` in the terminal and ask whether to keep it, one
+/// at a time. `labels` holds the character each cell will be saved as, and
+/// `cell_names` how to refer to each cell. Returns a decision for every cell,
+/// or `None` if the review was abandoned and nothing should be saved.
+pub fn review(
+    cells: &[(usize, GrayImage)],
+    labels: &[Option<String>],
+    cell_names: &[String],
+) -> Option<Vec<Decision>> {
+    let mut decisions: Vec<Decision> = labels
+        .iter()
+        .map(|label| Decision::Accept(label.clone()))
+        .collect();
+    println!();
+    println!("Reviewing {} letterforms. For each one, type:", cells.len());
+    println!("  y        to keep it (the default)");
+    println!("  n        to leave it out");
+    println!("  l <char> to save it as a different character, or a ligature like `l th`");
+    println!("  b        to go back to the previous letterform");
+    println!("  a        to keep it and every letterform after it");
+    println!("  q        to stop without saving anything");
+
+    let mut position = 0;
+    while let Some((i, cell)) = cells.get(position) {
+        let i = *i;
+        println!();
+        println!("{}", preview(cell));
+        let label = match &decisions[i] {
+            Decision::Accept(Some(text)) => format!("{text:?}"),
+            Decision::Accept(None) => "no character".to_string(),
+            Decision::Reject => "left out".to_string(),
+        };
+        print!(
+            "[{}/{}] {} ({label}) > ",
+            position + 1,
+            cells.len(),
+            cell_names[i]
+        );
+        std::io::stdout().flush().ok();
+
+        let mut input = String::new();
+        if std::io::stdin().read_line(&mut input).ok()? == 0 {
+            // Stdin was closed, so there's nobody left to ask.
+            return None;
+        }
+        let input = input.trim();
+        match input
+            .split_once(' ')
+            .map_or((input, ""), |(command, rest)| (command, rest.trim()))
+        {
+            ("" | "y", _) => {
+                if decisions[i] == Decision::Reject {
+                    decisions[i] = Decision::Accept(labels[i].clone());
+                }
+                position += 1;
+            }
+            ("n", _) => {
+                decisions[i] = Decision::Reject;
+                position += 1;
+            }
+            ("l", "") => println!("Type a character after `l`, like `l A`."),
+            ("l", label) if label.contains(char::is_whitespace) => {
+                println!("A ligature can't contain spaces.")
+            }
+            ("l", label) => {
+                decisions[i] = Decision::Accept(Some(label.to_string()));
+                position += 1;
+            }
+            ("b", _) => position = position.saturating_sub(1),
+            ("a", _) => break,
+            ("q", _) => return None,
+            _ => println!("Invalid input."),
+        }
+    }
+
+    Some(decisions)
+}
+
+/// Draw a cell's letterform with block characters, scaled down to fit the
+/// terminal.
+pub fn preview(cell: &GrayImage) -> String {
+    let letterform = match glyph::ink_bounds(cell) {
+        Some(bounds) => glyph::crop_to(cell, bounds, 2),
+        None => cell.clone(),
+    };
+    let (width, height) = letterform.dimensions();
+    // Terminal characters are about twice as tall as they are wide, which the
+    // half-block characters make up for.
+    let scale = (PREVIEW_COLUMNS as f32 / width as f32)
+        .min((PREVIEW_ROWS * 2) as f32 / height as f32)
+        .min(1.0);
+    let columns = ((width as f32 * scale).round() as u32).max(1);
+    let rows = ((height as f32 * scale / 2.0).round() as u32).max(1);
+
+    let is_ink = |x: u32, y: u32| {
+        // Average the pixels covered by this half of the character.
+        let mut sum = 0u32;
+        let mut count = 0u32;
+        for py in span(y, rows * 2, height) {
+            for px in span(x, columns, width) {
+                let Luma([value]) = *letterform.get_pixel(px, py);
+                sum += value as u32;
+                count += 1;
+            }
+        }
+        count > 0 && sum / count < 160
+    };
+
+    let mut preview = String::new();
+    for row in 0..rows {
+        preview.push_str("  ");
+        for column in 0..columns {
+            preview.push(
+                match (is_ink(column, row * 2), is_ink(column, row * 2 + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                },
+            );
+        }
+        if row + 1 < rows {
+            preview.push('\n');
+        }
+    }
+
+    preview
+}
+
+/// The pixels covered by the `i`th of `steps` equal steps across `extent`
+/// pixels. Every step covers at least one pixel.
+fn span(i: u32, steps: u32, extent: u32) -> std::ops::Range<u32> {
+    let start = i * extent / steps;
+    let end = ((i + 1) * extent / steps).max(start + 1);
+    start.min(extent)..end.min(extent)
+}
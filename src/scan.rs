@@ -0,0 +1,2227 @@
+use anyhow::{bail, Context};
+use clap::{Args, Parser};
+use image::{GenericImageView, GrayImage};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::{debug, debug_span, error, info, warn};
+
+use crate::{
+    canvas, charset, contact_sheet,
+    descriptor::{self, Descriptor},
+    dpi, duplicates, glyph, grid, guide_dots, inputs, lighting, manifest, output, overlay, pdf,
+    pen, pipeline,
+    profile::Profile,
+    recovery::GridNotFound,
+    review, skeleton, stages,
+    summary::{self, Status, Summary},
+    template, threshold, watch,
+};
+
+/// Arguments for the `scan` command.
+#[derive(Args, Debug)]
+pub struct ScanArgs {
+    /// The image or PDF file that will be scanned. Pass several files, a
+    /// directory, or a pattern like `scans/page-*.jpeg` to scan many pages in
+    /// one run; each page's letters will be saved in a subdirectory of the
+    /// output directory, named after the page's file. Every page of a PDF is
+    /// scanned, unless `--pages` is given. Pass `-` to scan an image or PDF
+    /// piped to stdin.
+    ///
+    /// See
+    /// https://github.com/image-rs/image/blob/main/README.md#supported-image-formats
+    /// for a list of supported image formats.
+    ///
+    /// An example handwriting scan image is included in this app's repository.
+    /// The file is named `example-handwriting-scan.jpeg`.
+    #[arg(short, long, num_args = 1.., required_unless_present = "watch")]
+    input_file: Vec<PathBuf>,
+
+    /// Instead of scanning the input files, watch this directory and scan
+    /// every image or PDF that appears in it, as soon as it has finished
+    /// being written. Each one's letters are saved in a subdirectory of the
+    /// output directory, named after the file. Files that are already in the
+    /// directory are left alone. The images are saved without asking.
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["input_file", "baseline", "review", "merge", "dry_run"]
+    )]
+    watch: Option<PathBuf>,
+
+    /// When watching a directory, check it for new files every this many
+    /// seconds, rather than waiting to be told about them. This is needed
+    /// for network shares, where new files written by other computers
+    /// otherwise go unnoticed.
+    #[arg(long, value_name = "SECONDS", requires = "watch")]
+    poll_interval: Option<f32>,
+
+    /// The directory that the letter images will be written to. If not
+    /// provided, the images will be written to the current working directory.
+    /// Pass `-` to write a zip file of the letter images and the manifest to
+    /// stdout instead.
+    #[arg(short, long)]
+    output_dir: Option<PathBuf>,
+
+    /// The pages to scan from PDF input files, like `1,3-4`. By default,
+    /// every page is scanned.
+    #[arg(long, alias = "page")]
+    pages: Option<pdf::Pages>,
+
+    /// Resample pages from PDF input files to this resolution, in dots per
+    /// inch. By default, each page's scan is used at its own resolution.
+    #[arg(long)]
+    pdf_dpi: Option<u32>,
+
+    /// The number of rows of cells on the template. The default value is 9,
+    /// which matches the bundled template.
+    #[arg(long, default_value = "9")]
+    rows: u32,
+
+    /// The number of columns of cells on the template. The default value is
+    /// 12, which matches the bundled template. Cells don't need to be
+    /// square; the grid lines are found wherever they are.
+    #[arg(long, default_value = "12")]
+    cols: u32,
+
+    /// Ignore the QR code that templates made by `generate-template` carry.
+    /// By default, a page with one is scanned with the rows, columns, and
+    /// charset it records, in place of `--rows`, `--cols`, and `--charset`.
+    #[arg(long, default_value_t = false)]
+    no_layout_code: bool,
+
+    /// The space to the left and right of the grid, as a fraction of the
+    /// page's width, like `0.066`, or in pixels, like `120px`. This is only
+    /// used for pages whose grid lines can't be found, which otherwise stop
+    /// the scan.
+    #[arg(long, value_name = "MARGIN")]
+    margin_x: Option<grid::Margin>,
+
+    /// The space above and below the grid, as a fraction of the page's
+    /// height, like `0.079`, or in pixels, like `120px`. Like `--margin-x`,
+    /// this is only used for pages whose grid lines can't be found.
+    #[arg(long, value_name = "MARGIN")]
+    margin_y: Option<grid::Margin>,
+
+    /// The layout of the template: the name of a built-in template
+    /// (`standard` or `symbols`), or the path of a TOML file describing its
+    /// grids, their margins, which cells hold labels or are left empty, and
+    /// the charset. Use this in place of `--rows`, `--cols`, `--margin-x`,
+    /// and `--margin-y`. The QR code of a template made by
+    /// `generate-template` isn't read.
+    #[arg(
+        long,
+        value_name = "NAME_OR_FILE",
+        conflicts_with_all = ["rows", "cols", "margin_x", "margin_y"]
+    )]
+    template: Option<String>,
+
+    /// Scan a page that isn't a template, like old handwriting on ordinary
+    /// grid paper, by cutting this rectangle of it into `--rows` × `--cols`
+    /// even cells, without looking for grid lines. Give it in pixels of the
+    /// input image as `x,y,width,height`, like `120,340,1800,2400`, or pass
+    /// `page` with `--gui` to draw it by hand by dragging across the page.
+    #[arg(
+        long,
+        value_name = "RECTANGLE",
+        conflicts_with_all = ["template", "margin_x", "margin_y"]
+    )]
+    region: Option<descriptor::Selection>,
+
+    /// By default, the app will ask for confirmation before saving the images.
+    /// If you want to skip confirmation, pass this flag.
+    #[arg(short, long, default_value_t = false)]
+    yes: bool,
+
+    /// Instead of asking once whether to save every image, show each
+    /// letterform in the terminal and decide whether to keep it, leave it out,
+    /// or save it as a different character.
+    #[arg(long, default_value_t = false, conflicts_with = "yes")]
+    review: bool,
+
+    /// Before the cells are cut out, open a window showing the page with
+    /// the grid that was found over it. Drag the grid's corners to move it,
+    /// change the threshold with the slider below the page or the arrow
+    /// keys, and click cells to leave them out. Press S to save, or Escape
+    /// to exit without saving. Only available when built with the `gui`
+    /// feature.
+    #[cfg(feature = "gui")]
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["watch", "review", "json", "threshold_sweep", "adaptive_threshold"]
+    )]
+    gui: bool,
+
+    /// Before asking whether to save the images, save a single image showing
+    /// every cell in its place on the grid, labeled with its number and
+    /// character, to this path. Cells that won't be saved are tinted blue.
+    /// When scanning several pages, the page's number is added to the name.
+    #[arg(long)]
+    contact_sheet: Option<PathBuf>,
+
+    /// Save images of how each page was read into this directory, to see
+    /// which step went wrong when letters come out badly: the page once it's
+    /// turned upright and straightened, once it's thresholded, and an
+    /// overlay of the straightened page with the grid that was found, each
+    /// cell's number and character, the rectangle around each letterform,
+    /// and the cells that were flagged. Pages are saved as
+    /// `page-1-deskewed.png`, `page-1-thresholded.png`, and
+    /// `page-1-overlay.png`, numbered in the order they're scanned.
+    #[arg(long, value_name = "DIR")]
+    debug_output: Option<PathBuf>,
+
+    /// Scan the pages and print a report on every cell: whether a
+    /// letterform was found in it, how much ink it holds, and whether the
+    /// letterform runs into the cell's edge. Nothing is saved.
+    #[arg(long, default_value_t = false, conflicts_with_all = ["review", "contact_sheet"])]
+    dry_run: bool,
+
+    /// Never ask anything, and print a JSON summary of the scan to stdout:
+    /// each page's grid and cells, the letter images written, and any
+    /// warnings. The exit code tells how the scan went, like without it: 0
+    /// if it went well, 3 if only some of the grid was found or some images
+    /// couldn't be saved, 4 if no grid was found, and 1 if it failed.
+    #[arg(long, default_value_t = false, conflicts_with_all = ["review", "dry_run", "watch"])]
+    json: bool,
+
+    /// Threshold value to use during processing. The default value is 190.
+    /// This value should be between 0 and 255, or `auto` to pick a value for
+    /// each scan using Otsu's method.
+    #[arg(short, long, default_value = "190")]
+    threshold: threshold::Threshold,
+
+    /// Instead of saving letters, threshold each page at every value in this
+    /// range, like `150..220:10`, and save an image comparing a few of its
+    /// cells at each threshold, one row per threshold, to
+    /// `threshold-sweep.png` in the output directory. Cells that would be
+    /// skipped as empty at a threshold are tinted blue.
+    #[arg(
+        long,
+        value_name = "FROM..TO:STEP",
+        conflicts_with_all = ["review", "dry_run", "json", "watch", "adaptive_threshold"]
+    )]
+    threshold_sweep: Option<threshold::Sweep>,
+
+    /// How many cells to compare in a threshold sweep. They're picked evenly
+    /// from the cells with a letterform in them. Pass the number of cells in
+    /// the grid, or more, to compare every cell.
+    #[arg(long, default_value = "8", requires = "threshold_sweep")]
+    sweep_cells: usize,
+
+    /// Threshold each pixel against the average brightness of the square of
+    /// this many pixels around it, instead of using one threshold for the
+    /// whole page. This handles unevenly lit scans, where a single threshold
+    /// would turn the shadowed side of the page black. A window a little
+    /// larger than a pen stroke is thick works well; try 51 for a 300 DPI
+    /// scan.
+    #[arg(long)]
+    adaptive_threshold: Option<u32>,
+
+    /// The steps to clean up each page and its cells with: the name of a
+    /// built-in profile (`scanner-300dpi`, `phone-photo`, or `pencil`), or
+    /// the path of a TOML file describing them. By default, each page is
+    /// sharpened and then thresholded, like `scanner-300dpi`. A threshold step
+    /// without settings of its own uses `--threshold` and
+    /// `--adaptive-threshold`.
+    #[arg(long, value_name = "NAME_OR_FILE")]
+    profile: Option<String>,
+
+    /// Treat the threshold as tuned for a 300 DPI scan, and adjust it to suit
+    /// the resolution recorded in the input file's metadata. This lets one
+    /// tuned threshold be reused across scanner settings.
+    ///
+    /// The threshold is lowered by 8 for every doubling of the resolution
+    /// above 300 DPI, and raised by 8 for every halving below it. If the
+    /// input file doesn't record its resolution, the threshold is used as-is.
+    #[arg(long, default_value_t = false)]
+    scale_threshold_with_dpi: bool,
+
+    /// A previous scan of the same template. When provided, each cell is
+    /// compared with the same cell of the baseline scan, and only the cells
+    /// that changed are saved. The baseline is aligned to the input image
+    /// before comparing, so the two scans don't need to line up exactly.
+    #[arg(short, long)]
+    baseline: Option<PathBuf>,
+
+    /// How similar a cell must be to the same cell of the baseline scan, or a
+    /// letterform to the one saved before with `--update`, to be considered
+    /// unchanged. The default value is 0.75. This value should be
+    /// between 0 (always unchanged) and 1 (only identical cells are
+    /// unchanged).
+    #[arg(long, default_value = "0.75")]
+    baseline_similarity: f32,
+
+    /// The position of a dot printed inside every cell of the template, which
+    /// should be removed from the letterforms. Positions are written as `x,y`
+    /// or `x,y,radius`, where each value is a fraction of the cell's size; for
+    /// example, `0.5,0.8,0.03` is a dot centered horizontally, near the bottom
+    /// of the cell, with a radius of 3% of the cell's width. The default radius
+    /// is 2% of the cell's width.
+    ///
+    /// Pass this option once for every dot.
+    #[arg(long)]
+    guide_dots: Vec<guide_dots::GuideDot>,
+
+    /// Pad each letter image up to the next power-of-two width and height,
+    /// keeping the letterform centered. Some graphics engines require this of
+    /// textures. The manifest records how much paper was added around each
+    /// letter image, so its texture coordinates can be found.
+    #[arg(long, default_value_t = false)]
+    pot: bool,
+
+    /// If a letter image can't be saved in the requested format, save it as a
+    /// PNG instead.
+    #[arg(long, default_value_t = false)]
+    fallback_to_png: bool,
+
+    /// Exit with an error if any letter image couldn't be saved. By default,
+    /// failures are reported and the remaining images are still saved.
+    #[arg(long, default_value_t = false)]
+    strict: bool,
+
+    /// By default, the app will detect how far the scan is rotated and
+    /// straighten it before cutting out the letters. If you want to skip
+    /// straightening, pass this flag.
+    #[arg(long, default_value_t = false)]
+    no_deskew: bool,
+
+    /// The input is a photo of the page rather than a flatbed scan. The
+    /// corners of the grid will be located, and the photo will be warped so
+    /// that the grid is a rectangle again, before anything else is done.
+    #[arg(long, default_value_t = false)]
+    camera: bool,
+
+    /// Even out a page that's lit more brightly on one side than the other,
+    /// like a phone photo, before it's thresholded, so that its dim side
+    /// doesn't turn solid black. `flatten` measures how brightly each part
+    /// of the paper is lit and divides it out; `clahe` stretches the
+    /// contrast of each part of the page on its own, which brings out faint
+    /// strokes, and works best with `--adaptive-threshold`.
+    #[arg(long, value_enum)]
+    lighting: Option<lighting::Lighting>,
+
+    /// Cells with less ink than this are considered empty, and aren't saved.
+    /// Ink is measured as a fraction of the cell's area, ignoring the grid
+    /// lines around its edges. The default value is 0.003, which keeps even
+    /// small punctuation marks. Pass 0 to save every cell.
+    #[arg(long, default_value = "0.003")]
+    min_ink: f32,
+
+    /// By default, each letter image is cropped to the letterform, so that
+    /// the grid lines and whitespace around it are removed. If you want to
+    /// save whole cells instead, pass this flag.
+    #[arg(long, default_value_t = false)]
+    no_crop: bool,
+
+    /// By default, fragments of the grid lines left along the edges of each
+    /// cell are erased, so they don't show up in the letter images. If your
+    /// letterforms are written right up against the grid lines and are being
+    /// clipped, pass this flag to keep them.
+    #[arg(long, default_value_t = false)]
+    keep_grid_lines: bool,
+
+    /// Letterforms that run into the edge of their cell, like descenders
+    /// crossing the grid line, may be cut off, and are warned about. Pass this
+    /// flag to follow them up to a quarter of a cell into the neighboring
+    /// cells instead, so their letter images are cut from the larger area.
+    #[arg(long, default_value_t = false)]
+    expand_clipped: bool,
+
+    /// Erase specks of ink smaller than this many pixels from each letter
+    /// image, such as dust and paper texture. Try a value like 20 for a 300
+    /// DPI scan.
+    #[arg(long)]
+    despeckle: Option<u32>,
+
+    /// Smooth each letter image by removing bits of ink thinner than about
+    /// twice this many pixels, such as specks and rough edges. This is done
+    /// before `--close`. Try a value like 1.
+    #[arg(long)]
+    open: Option<u8>,
+
+    /// Smooth each letter image by filling gaps in the ink narrower than
+    /// about twice this many pixels, such as pinholes and breaks in a
+    /// stroke. This is done after `--open`. Try a value like 1.
+    #[arg(long)]
+    close: Option<u8>,
+
+    /// Thicken or thin the strokes of each letterform so that they're all
+    /// about this many pixels wide, to even out differences in pen pressure.
+    /// If no width is given, the median stroke width of the page is used.
+    #[arg(long, value_name = "PIXELS", num_args = 0..=1)]
+    normalize_strokes: Option<Option<f32>>,
+
+    /// The color of the pen the template was filled in with, like `blue`,
+    /// `red`, or a hex code like `#1a3d8f`, or `auto` to find it on its own.
+    /// Only ink of that color is saved, which leaves out the printed grid
+    /// and guides, even where letters touch them.
+    #[arg(long, value_name = "COLOR")]
+    pen_color: Option<pen::PenColor>,
+
+    /// Keep the smooth, anti-aliased edges of strokes, by saving letter
+    /// images in shades of gray instead of only black and white. Cleaning
+    /// up and cropping work the same way, but the edges of what's left are
+    /// taken from the scan.
+    #[arg(long, default_value_t = false, conflicts_with = "normalize_strokes")]
+    grayscale: bool,
+
+    /// Place every letterform on an image of this size, like `128x128`,
+    /// instead of cropping it to its own size. Font tools and training
+    /// pipelines often want every image to be the same size.
+    #[arg(long, value_name = "WxH", conflicts_with_all = ["no_crop", "pot"])]
+    canvas: Option<canvas::Canvas>,
+
+    /// Where to place each letterform on its canvas, when `--canvas` is
+    /// given: in the center, or lined up on the template's baseline guide.
+    #[arg(long, value_enum, default_value_t, requires = "canvas")]
+    align: canvas::Align,
+
+    /// Scale letter images so that a whole cell of the template would be
+    /// this many pixels tall, whatever resolution the page was scanned at.
+    /// Letters keep their sizes relative to each other, and pages scanned at
+    /// 150, 300, or 600 DPI give letters of the same size. The resolution is
+    /// read from the input file, or worked out from the size of the grid.
+    #[arg(long, value_name = "PIXELS")]
+    target_height: Option<u32>,
+
+    /// Make letter images this many times bigger, after any
+    /// `--target-height` scaling, for tracing at a high resolution.
+    /// Black-and-white letter images are enlarged in a way that keeps their
+    /// edges sharp and smooths diagonal strokes; ones in shades of gray are
+    /// resampled with a Lanczos filter.
+    #[arg(long, value_name = "FACTOR")]
+    upscale: Option<f32>,
+
+    /// The width and height of the template's cells on paper, in inches, for
+    /// working out the scan's resolution when the input file doesn't record
+    /// it. The default value is 0.75, the size `generate-template` uses.
+    #[arg(long, default_value_t = dpi::DEFAULT_CELL_SIZE)]
+    cell_size: f32,
+
+    /// How many pixels of whitespace to leave around each letterform when
+    /// cropping or centering it on a canvas. The default value is 10.
+    #[arg(long, default_value = "10")]
+    padding: u32,
+
+    /// The characters written on the template, in grid order (left to right,
+    /// top to bottom). This may be the characters themselves, or the path of
+    /// a text file containing them. A space marks a cell with no character,
+    /// and line breaks are ignored, so each row may be written on its own
+    /// line.
+    ///
+    /// When provided, letter images are named after their characters, like
+    /// `U+0041_A.png` or `U+0021_exclam.png`. Otherwise, they're named by
+    /// cell, like `letter-0.png`.
+    ///
+    /// To write a ligature or other joined sequence in one cell, put it in
+    /// braces, like `{th}` or `{ffi}`. Its image is named after all of its
+    /// characters, like `U+0074+0068_t_h.png`.
+    ///
+    /// The `greek`, `cyrillic`, and `hebrew` charsets are built in, like
+    /// `--charset greek`.
+    #[arg(short, long)]
+    charset: Option<String>,
+
+    /// Fill each row of cells from right to left, for scripts like Hebrew
+    /// and Arabic: the charset's first character is in the top row's
+    /// rightmost cell. Templates made by `generate-template --right-to-left`
+    /// with a charset don't need this, as their layout code records where
+    /// each character is.
+    #[arg(long, default_value_t = false)]
+    right_to_left: bool,
+
+    /// The image format that letter images are saved in. The default is PNG,
+    /// which is lossless; JPEG adds blurry artifacts around every stroke.
+    #[arg(short = 'f', long, value_enum, default_value_t)]
+    output_format: output::OutputFormat,
+
+    /// Save letter images with a transparent background, so they can be
+    /// placed over other artwork. Every format except JPEG supports this.
+    #[arg(long, default_value_t = false)]
+    alpha: bool,
+
+    /// The color to draw ink in, as a hex code like `#1a3d8f`. The default
+    /// is black.
+    #[arg(long, value_name = "COLOR")]
+    ink_color: Option<output::InkColor>,
+
+    /// When scanning several pages, save every page's letters together in
+    /// the output directory, as one set of glyphs. The charset then runs on
+    /// from one page to the next: the first page's cells take the first
+    /// characters of the charset, the second page's cells take the ones after
+    /// them, and so on. Pad the charset with spaces to leave cells empty.
+    #[arg(long, default_value_t = false)]
+    merge: bool,
+
+    /// Add the letters of this scan to the glyph set an earlier scan saved
+    /// in this directory, instead of saving a new one, as when redoing one
+    /// bad page. Letter images the new scan saves replace the earlier ones
+    /// of the same name, every other letter image is kept, and the
+    /// directory's manifest is updated to list them all. The letter images
+    /// that were replaced or added are listed at the end, along with any
+    /// that were replaced by a different character, or whose character now
+    /// has two letter images.
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["output_dir", "archive", "watch", "timestamp", "skip_existing", "dry_run"]
+    )]
+    merge_into: Option<PathBuf>,
+
+    /// Letterforms at least this alike, between 0 and 1, are taken to be
+    /// copies of each other, as when a cell is filled in twice or a page is
+    /// scanned twice. Every page scanned together is checked, and copies are
+    /// warned about. The default value is 0.9.
+    #[arg(long, default_value = "0.9")]
+    duplicate_similarity: f32,
+
+    /// Remove letterforms that look like copies of earlier ones, keeping
+    /// only the first. Their letter images are deleted, and the manifest
+    /// marks them as duplicates.
+    #[arg(long, default_value_t = false)]
+    dedup: bool,
+
+    /// What to do when two cells would be saved under the same name, because
+    /// the same character appears in the charset twice, or on two merged
+    /// pages. The default is to keep the first one. To keep them all as
+    /// alternates of each other, pass `alternates`.
+    #[arg(long, value_enum, default_value_t)]
+    on_conflict: output::OnConflict,
+
+    /// Replace letter images and manifests left in the output directory by
+    /// an earlier scan. Without this, nothing is saved if an earlier scan's
+    /// files would be replaced.
+    #[arg(long, default_value_t = false)]
+    force: bool,
+
+    /// Leave alone letter images that an earlier scan saved, as long as
+    /// they're newer than the input file, and only extract the rest. Pages
+    /// whose manifest is newer than the input file aren't scanned at all,
+    /// unless the pages are being merged. Older letter images are replaced.
+    /// This makes re-running a batch after adding a page quick.
+    #[arg(long, default_value_t = false, conflicts_with = "force")]
+    skip_existing: bool,
+
+    /// Compare each letterform with the letter image an earlier scan saved
+    /// under the same name, and only write the ones that changed, so that
+    /// rescanning a page after rewriting a few letters only touches their
+    /// files. The letter images that were updated or added are listed at the
+    /// end.
+    #[arg(long, default_value_t = false, conflicts_with_all = ["skip_existing", "archive"])]
+    update: bool,
+
+    /// Name letter images after this pattern instead of their character, like
+    /// `{char}-{row}x{col}-{index}.{ext}`. It can hold `{char}`, the
+    /// character's name, like `A` or `slash`; `{codepoint}`, its Unicode
+    /// codepoint in hex, like `0041`; `{row}` and `{col}`, the cell's place in
+    /// the grid, counted from 0; `{page}`, the page's place among the pages
+    /// scanned, counted from 1; `{index}`, the cell's place in the charset,
+    /// counted from 0; `{sample}`, which sample of its character the cell is,
+    /// counted from 1; and `{ext}`, the image's extension, which is added
+    /// anyway. Cells without a character are still named like `letter-12`.
+    #[arg(long, value_name = "FORMAT")]
+    name_format: Option<output::NameFormat>,
+
+    /// Add this to the end of the name of every letter image and of the
+    /// manifest, like `-v2`, so that a new scan can be saved next to an
+    /// earlier one.
+    #[arg(long)]
+    suffix: Option<String>,
+
+    /// Save into a new subdirectory of the output directory named after the
+    /// current time, like `2024-05-01_14-30-00`, so that every run is kept.
+    #[arg(long, default_value_t = false)]
+    timestamp: bool,
+
+    /// Write the letter images and the manifest into this zip file instead
+    /// of a directory, so that a page's letters can be passed around as one
+    /// file. Pages of several input files are kept in folders named after
+    /// them, like in an output directory.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["output_dir", "watch", "dry_run", "timestamp"]
+    )]
+    archive: Option<PathBuf>,
+
+    /// Also trace each letterform into smooth outlines, and save them as an
+    /// SVG file next to the letter image. Font editors can import these
+    /// directly, without autotracing.
+    #[arg(long, default_value_t = false)]
+    vectorize: bool,
+
+    /// How closely traced outlines follow the edges of each letterform, in
+    /// pixels. Larger values make smoother outlines with fewer points, but
+    /// lose detail. The default value is 1.
+    #[arg(long, default_value = "1.0")]
+    simplify: f32,
+
+    /// Thin each letterform down to the centerline of its strokes, for
+    /// plotters and stroke fonts, which draw with a pen rather than filling
+    /// outlines. `image` thins the letter images themselves; `json` saves
+    /// each letterform's strokes as polylines next to its letter image, like
+    /// `U+0041_A.strokes.json`, with how wide the stroke is at each point;
+    /// `svg` saves them as unfilled paths, like `U+0041_A.strokes.svg`.
+    /// `--simplify` sets how closely the strokes follow the centerline.
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    skeleton: Option<skeleton::SkeletonFormat>,
+}
+
+/// Reads the arguments of the `scan` command on their own.
+#[derive(Debug, Parser)]
+#[command(name = "scan", no_binary_name = true, args_override_self = true)]
+struct ScanCli {
+    #[command(flatten)]
+    args: ScanArgs,
+}
+
+impl ScanArgs {
+    /// Read the arguments of the `scan` command from `args`, without the
+    /// command's name, as though they were typed after `scan`. When an
+    /// argument is given more than once, the last one wins, so that settings
+    /// can be added after a list of defaults.
+    pub fn try_parse_from<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        ScanCli::try_parse_from(args).map(|cli| cli.args)
+    }
+}
+
+/// Scan pages of handwriting, and save the letters as individual images.
+/// Before each page's letters are saved, `confirm` is asked whether to save
+/// them, unless `--yes` or `--review` decide instead; the command line asks on
+/// stdin. Returns how the scan went, which decides the app's exit code.
+pub fn scan(args: ScanArgs, confirm: &dyn Fn() -> anyhow::Result<bool>) -> anyhow::Result<Status> {
+    if !args.json {
+        return match scan_all(args, confirm) {
+            Ok(summary) => Ok(summary.status),
+            Err(err) if err.is::<GridNotFound>() => {
+                error!("{err:#}");
+                Ok(Status::NoGrid)
+            }
+            Err(err) => Err(err),
+        };
+    }
+
+    summary::collect_warnings();
+    let (mut summary, result) = match scan_all(args, confirm) {
+        Ok(summary) => {
+            let status = summary.status;
+            (summary, Ok(status))
+        }
+        Err(err) if err.is::<GridNotFound>() => {
+            let summary = Summary {
+                status: Status::NoGrid,
+                error: Some(format!("{err:#}")),
+                ..Summary::default()
+            };
+            (summary, Ok(Status::NoGrid))
+        }
+        Err(err) => {
+            let summary = Summary {
+                status: Status::Error,
+                error: Some(format!("{err:#}")),
+                ..Summary::default()
+            };
+            (summary, Err(err))
+        }
+    };
+    summary.warnings = summary::take_warnings();
+    let json = serde_json::to_string_pretty(&summary).context("serializing the summary")?;
+    println!("{json}");
+
+    result
+}
+
+/// Scan every page, and describe what was found and saved.
+fn scan_all(
+    mut args: ScanArgs,
+    confirm: &dyn Fn() -> anyhow::Result<bool>,
+) -> anyhow::Result<Summary> {
+    // `-` stands for stdin or stdout. What's piped in is saved to a file, and
+    // letters for stdout or an archive are saved to a directory first, so
+    // that they're scanned and saved like any others.
+    let is_dash = |path: &Path| path.as_os_str() == "-";
+    let reads_stdin = args.input_file.iter().any(|file| is_dash(file));
+    let writes_stdout = args.output_dir.as_deref().is_some_and(is_dash);
+    let writes_zip = writes_stdout || args.archive.is_some();
+    let pipe_dir = (reads_stdin || writes_zip)
+        .then(tempfile::tempdir)
+        .transpose()
+        .context("making a temporary directory")?;
+    if let Some(pipe_dir) = pipe_dir.as_ref().filter(|_| reads_stdin) {
+        if args.input_file.iter().filter(|file| is_dash(file)).count() > 1 {
+            bail!("stdin can only be read once, so pass - as an input file only once.");
+        }
+        if args.review {
+            bail!("--review reads its answers from stdin, so the input can't be piped in.");
+        }
+        let piped = inputs::save_stdin(pipe_dir.path())?;
+        for file in args.input_file.iter_mut().filter(|file| is_dash(file)) {
+            *file = piped.clone();
+        }
+        // Nobody can answer on stdin.
+        args.yes = true;
+    }
+    if writes_stdout {
+        if args.json {
+            bail!("--json and --output-dir - both write to stdout, so pass only one.");
+        }
+        if args.review || args.dry_run {
+            bail!("--review and --dry-run write to stdout, so they can't be used with --output-dir -.");
+        }
+        if args.watch.is_some() {
+            bail!("--watch never finishes, so its letters can't be written to stdout.");
+        }
+        args.yes = true;
+    }
+    if let Some(archive) = &args.archive {
+        if archive.is_dir() {
+            bail!("archive path must be a file, not a directory.");
+        }
+        if archive.exists() && !args.force {
+            bail!(
+                "{} already exists. Pass --force to replace it.",
+                archive.display()
+            );
+        }
+    }
+
+    // validate input files
+    let input_files = inputs::expand_inputs(&args.input_file)?;
+    if let Some(dir) = &args.watch {
+        if !dir.is_dir() {
+            bail!("watch path doesn't exist or is not a directory.");
+        }
+        // Nobody is there to answer when a file turns up.
+        args.yes = true;
+    }
+    if args.json {
+        args.yes = true;
+    }
+    // Merged letters are saved into the earlier scan's directory, as one set
+    // of glyphs.
+    if let Some(dir) = &args.merge_into {
+        args.output_dir = Some(dir.clone());
+        args.merge = true;
+    }
+    if args.poll_interval.is_some_and(|interval| interval <= 0.0) {
+        bail!("poll_interval must be greater than 0.");
+    }
+
+    // validate output directory
+    let output_dir = match (&args.output_dir, &pipe_dir) {
+        (_, Some(pipe_dir)) if writes_zip => pipe_dir.path().join("letters"),
+        (Some(dir), _) => dir.clone(),
+        (None, _) => std::env::current_dir()?,
+    };
+    if output_dir.is_file() {
+        bail!("output_dir path must be a directory.");
+    }
+    let output_dir = if args.timestamp {
+        output_dir.join(output::timestamp())
+    } else {
+        output_dir
+    };
+    if let Some(suffix) = &args.suffix {
+        if suffix.contains(['/', '\\']) {
+            bail!("suffix must not contain a path separator.");
+        }
+    }
+    // The earlier scan's manifest is read before the new scan's replaces it.
+    let earlier = match &args.merge_into {
+        Some(dir) => {
+            let path = dir.join(manifest::file_name(args.suffix.as_deref()));
+            if !path.is_file() {
+                bail!(
+                    "{} doesn't hold a scan to merge into. Pass --output-dir to start a new one.",
+                    dir.display()
+                );
+            }
+            Some(manifest::read(&path)?)
+        }
+        None => None,
+    };
+
+    // validate grid size
+    if args.rows == 0 || args.cols == 0 {
+        bail!("rows and cols must be at least 1.");
+    }
+
+    // `--region page` only gives the window a rectangle to start from; on its
+    // own, it would cut the whole page, margins and all.
+    #[cfg(feature = "gui")]
+    let gui = args.gui;
+    #[cfg(not(feature = "gui"))]
+    let gui = false;
+    if args.region == Some(descriptor::Selection::Page) && !gui {
+        if cfg!(feature = "gui") {
+            bail!("--region page needs --gui, to draw the region by hand.");
+        }
+        bail!(
+            "--region page needs --gui, which this build doesn't have; pass the region as \
+             x,y,width,height instead."
+        );
+    }
+
+    // validate baseline
+    if let Some(baseline) = &args.baseline {
+        if !baseline.is_file() {
+            bail!("baseline path doesn't exist or is not a file.");
+        }
+        if input_files.len() > 1 || input_files.iter().any(|file| pdf::is_pdf(file)) {
+            bail!("baseline can only be used when scanning a single image file.");
+        }
+    }
+    if !(0.0..=1.0).contains(&args.baseline_similarity) {
+        bail!("baseline_similarity must be between 0 and 1.");
+    }
+
+    if args.alpha && !args.output_format.supports_alpha() {
+        bail!(
+            "{} images can't be transparent; pick another output format.",
+            args.output_format.extension()
+        );
+    }
+
+    if args.target_height == Some(0) {
+        bail!("target_height must be at least 1.");
+    }
+    if args
+        .upscale
+        .is_some_and(|factor| factor.is_nan() || factor < 1.0)
+    {
+        bail!("upscale must be at least 1.");
+    }
+    if args.cell_size <= 0.0 {
+        bail!("cell_size must be greater than 0.");
+    }
+
+    if !(0.0..=1.0).contains(&args.min_ink) {
+        bail!("min_ink must be between 0 and 1.");
+    }
+    if !(0.0..=1.0).contains(&args.duplicate_similarity) {
+        bail!("duplicate_similarity must be between 0 and 1.");
+    }
+    if args.adaptive_threshold == Some(0) {
+        bail!("adaptive_threshold window must be at least 1 pixel.");
+    }
+    if args.simplify < 0.0 {
+        bail!("simplify must not be negative.");
+    }
+    if let Some(contact_sheet) = &args.contact_sheet {
+        if image::ImageFormat::from_path(contact_sheet).is_err() {
+            bail!("contact_sheet must be an image file, like contact-sheet.png.");
+        }
+    }
+
+    let mut descriptor = match &args.template {
+        Some(template) => Descriptor::from_arg(template)?,
+        None => {
+            let margins = grid::Margins {
+                x: args.margin_x,
+                y: args.margin_y,
+            };
+            Descriptor::grid(args.rows, args.cols, margins)
+        }
+    };
+    descriptor.right_to_left |= args.right_to_left;
+    let charset = match (&args.charset, &descriptor.charset) {
+        (Some(charset), _) => charset::Charset::from_arg(charset)?,
+        (None, Some(charset)) => charset::Charset::parse(charset),
+        (None, None) => charset::Charset::default(),
+    };
+    let profile = match &args.profile {
+        Some(profile) => Profile::from_arg(profile)?,
+        None => Profile::default(),
+    };
+
+    if let Some(dir) = &args.watch {
+        let poll_interval = args.poll_interval.map(Duration::from_secs_f32);
+        return watch::watch(dir, poll_interval, |input_file| {
+            let name = input_file
+                .file_stem()
+                .map_or("scan".into(), |stem| stem.to_string_lossy());
+            let output_dir = inputs::unused_output_dir(&name, &output_dir);
+            info!("Scanning {}.", input_file.display());
+            let pages = list_pages(&[input_file.to_path_buf()], &args)?;
+            scan_pages(
+                &args,
+                &pages,
+                &output_dir,
+                &descriptor,
+                &charset,
+                &profile,
+                confirm,
+            )
+            .map(|_| ())
+            .with_context(|| format!("scanning {}", input_file.display()))
+        })
+        .map(|_| Summary::default());
+    }
+
+    let pages = list_pages(&input_files, &args)?;
+    let mut summary = scan_pages(
+        &args,
+        &pages,
+        &output_dir,
+        &descriptor,
+        &charset,
+        &profile,
+        confirm,
+    )?;
+    if let Some(earlier) = earlier {
+        merge_manifest(&output_dir, args.suffix.as_deref(), earlier)?;
+    }
+    if !writes_zip || !output_dir.is_dir() {
+        return Ok(summary);
+    }
+    let files = output::list_files(&output_dir)?;
+    let zip = output::to_zip(&output_dir, &files)?;
+    match &args.archive {
+        Some(archive) => {
+            if let Some(parent) = archive.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("creating {}", parent.display()))?;
+            }
+            std::fs::write(archive, zip)
+                .with_context(|| format!("writing {}", archive.display()))?;
+            info!("Wrote {} files to {}.", files.len(), archive.display());
+        }
+        None => std::io::stdout()
+            .lock()
+            .write_all(&zip)
+            .context("writing to stdout")?,
+    }
+    // The directory the letters were saved in is about to be deleted, so the
+    // summary names them by where they are in the zip file instead.
+    for page in &mut summary.pages {
+        page.output_dir = relative_to(&page.output_dir, &output_dir);
+    }
+    for file in &mut summary.files {
+        *file = relative_to(file, &output_dir);
+    }
+
+    Ok(summary)
+}
+
+/// `path` relative to `dir`, or `path` itself if it isn't in `dir`.
+fn relative_to(path: &Path, dir: &Path) -> PathBuf {
+    path.strip_prefix(dir).unwrap_or(path).to_path_buf()
+}
+
+/// List the pages to scan from `input_files`: every image, and each
+/// selected page of every PDF.
+fn list_pages(
+    input_files: &[PathBuf],
+    args: &ScanArgs,
+) -> anyhow::Result<Vec<(PathBuf, Option<u32>)>> {
+    // Each page of a PDF is scanned on its own.
+    let mut pages = Vec::new();
+    for input_file in input_files {
+        if !pdf::is_pdf(input_file) {
+            pages.push((input_file.clone(), None));
+            continue;
+        }
+        let page_count = pdf::page_count(input_file)?;
+        let selected = match &args.pages {
+            Some(pdf::Pages(selected)) => selected.clone(),
+            None => (1..=page_count).collect(),
+        };
+        for page in selected {
+            if page > page_count {
+                bail!(
+                    "{} has no page {page}; it has {page_count} pages.",
+                    input_file.display()
+                );
+            }
+            pages.push((input_file.clone(), Some(page)));
+        }
+    }
+    if pages.is_empty() {
+        bail!("there are no pages to scan.");
+    }
+
+    Ok(pages)
+}
+
+/// Scan every page in `pages`, and save their letters in `output_dir`.
+fn scan_pages(
+    args: &ScanArgs,
+    pages: &[(PathBuf, Option<u32>)],
+    output_dir: &Path,
+    descriptor: &Descriptor,
+    charset: &charset::Charset,
+    profile: &Profile,
+    confirm: &dyn Fn() -> anyhow::Result<bool>,
+) -> anyhow::Result<Summary> {
+    let cells_per_page = descriptor.cell_count();
+    let cell_count = if args.merge {
+        cells_per_page * pages.len()
+    } else {
+        cells_per_page
+    };
+    if !charset.is_empty() && charset.len() != cell_count {
+        warn!(
+            "the charset has {} characters, but the template has {cell_count} cells.",
+            charset.len()
+        );
+    }
+
+    // When scanning several pages, each one's letters are saved in a
+    // subdirectory named after the page, unless they're being merged.
+    let mut saved = HashSet::new();
+    let mut manifest = manifest::Manifest::default();
+    let mut summary = Summary::default();
+    let manifest_name = manifest::file_name(args.suffix.as_deref());
+    if pages.len() == 1 || args.merge {
+        check_overwrite(args, output_dir, &manifest_name)?;
+    }
+    if pages.len() == 1 {
+        let (input_file, pdf_page) = &pages[0];
+        if already_scanned(args, input_file, output_dir, &manifest_name) {
+            info!(
+                "{} was already scanned into {}; skipping it.",
+                input_file.display(),
+                output_dir.display()
+            );
+            return Ok(summary);
+        }
+        let page = Page {
+            input_file,
+            pdf_page: *pdf_page,
+            output_dir,
+            number: 1,
+            first_cell: 0,
+            contact_sheet: args.contact_sheet.clone(),
+            confirm,
+        };
+        manifest.keep_artifacts(output_dir, &manifest_name);
+        scan_file(
+            args,
+            &page,
+            descriptor,
+            charset,
+            profile,
+            &mut saved,
+            &mut manifest,
+        )
+        .map_err(|err| record_grid_not_found(err, &mut manifest, output_dir, args))?;
+        write_manifest(&manifest, output_dir, args.suffix.as_deref())?;
+        let mut scanned = vec![(output_dir.to_path_buf(), manifest)];
+        check_duplicates(args, &mut scanned)?;
+        for (output_dir, manifest) in &scanned {
+            summary.add(manifest, output_dir);
+        }
+        return Ok(summary);
+    }
+    let output_dirs = if args.merge {
+        vec![output_dir.to_path_buf(); pages.len()]
+    } else {
+        let names: Vec<String> = pages
+            .iter()
+            .map(|(input_file, pdf_page)| {
+                let stem = input_file
+                    .file_stem()
+                    .map_or("scan".into(), |stem| stem.to_string_lossy());
+                match pdf_page {
+                    Some(page) => format!("{stem}-page-{page}"),
+                    None => stem.into_owned(),
+                }
+            })
+            .collect();
+        let output_dirs = inputs::output_dirs(&names, output_dir);
+        for output_dir in &output_dirs {
+            check_overwrite(args, output_dir, &manifest_name)?;
+        }
+        output_dirs
+    };
+    // Every page's manifest, kept to look for copies across pages.
+    let mut scanned = Vec::new();
+    for (i, ((input_file, pdf_page), output_dir)) in pages.iter().zip(&output_dirs).enumerate() {
+        let name = match pdf_page {
+            Some(page) => format!("page {page} of {}", input_file.display()),
+            None => input_file.display().to_string(),
+        };
+        if !args.merge && already_scanned(args, input_file, output_dir, &manifest_name) {
+            info!(
+                "[{}/{}] {name} was already scanned; skipping it.",
+                i + 1,
+                pages.len()
+            );
+            continue;
+        }
+        info!("[{}/{}] {name}", i + 1, pages.len());
+        let page = Page {
+            input_file,
+            pdf_page: *pdf_page,
+            output_dir,
+            number: i + 1,
+            first_cell: if args.merge { i * cells_per_page } else { 0 },
+            contact_sheet: args.contact_sheet.as_ref().map(|path| {
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                let extension = path.extension().unwrap_or_default().to_string_lossy();
+                path.with_file_name(format!("{stem}-{}.{extension}", i + 1))
+            }),
+            confirm,
+        };
+        if !args.merge {
+            saved.clear();
+        }
+        if !args.merge || i == 0 {
+            manifest.keep_artifacts(output_dir, &manifest_name);
+        }
+        scan_file(
+            args,
+            &page,
+            descriptor,
+            charset,
+            profile,
+            &mut saved,
+            &mut manifest,
+        )
+        .map_err(|err| record_grid_not_found(err, &mut manifest, output_dir, args))
+        .with_context(|| format!("scanning {name}"))?;
+        if !args.merge {
+            write_manifest(&manifest, output_dir, args.suffix.as_deref())?;
+            scanned.push((output_dir.clone(), std::mem::take(&mut manifest)));
+        }
+    }
+    if args.merge {
+        write_manifest(&manifest, output_dir, args.suffix.as_deref())?;
+        scanned.push((output_dir.to_path_buf(), manifest));
+    }
+    check_duplicates(args, &mut scanned)?;
+    for (output_dir, manifest) in &scanned {
+        summary.add(manifest, output_dir);
+    }
+
+    Ok(summary)
+}
+
+/// Note the image of the lines that were found on a page whose grid wasn't,
+/// if `err` is why it stopped, in the manifest of `output_dir`, so that
+/// `clean` can find it. Returns `err`.
+fn record_grid_not_found(
+    err: anyhow::Error,
+    manifest: &mut manifest::Manifest,
+    output_dir: &Path,
+    args: &ScanArgs,
+) -> anyhow::Error {
+    if let Some(GridNotFound {
+        debug_image: Some(path),
+    }) = err.downcast_ref()
+    {
+        manifest.add_artifact(output_dir, path);
+        if let Err(write_err) = write_manifest(manifest, output_dir, args.suffix.as_deref()) {
+            warn!(
+                "couldn't note {} in the manifest: {write_err:#}",
+                path.display()
+            );
+        }
+    }
+
+    err
+}
+
+/// Stop before scanning if an earlier scan's manifest is in `output_dir`,
+/// since its letter images would most likely be replaced too, unless
+/// `--force`, or an option that keeps them, was given.
+fn check_overwrite(args: &ScanArgs, output_dir: &Path, manifest_name: &str) -> anyhow::Result<()> {
+    let manifest_path = output_dir.join(manifest_name);
+    if !args.force
+        && !args.dry_run
+        && args.threshold_sweep.is_none()
+        && !args.skip_existing
+        && !args.update
+        && args.merge_into.is_none()
+        && manifest::holds_scan(&manifest_path)
+    {
+        bail!(
+            "{} already holds a scan. Pass --force to replace it, --suffix to save \
+             next to it, or --timestamp to save in a new subdirectory.",
+            output_dir.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Whether `--skip-existing` was given and `output_dir` holds a manifest
+/// that's newer than `input_file`, meaning the whole page was saved since
+/// the input last changed.
+fn already_scanned(
+    args: &ScanArgs,
+    input_file: &Path,
+    output_dir: &Path,
+    manifest_name: &str,
+) -> bool {
+    let manifest_path = output_dir.join(manifest_name);
+    args.skip_existing
+        && manifest::holds_scan(&manifest_path)
+        && output::is_newer(&manifest_path, output::modified(input_file))
+}
+
+/// Write the manifest of the pages and other images saved into
+/// `output_dir`, if any were.
+fn write_manifest(
+    manifest: &manifest::Manifest,
+    output_dir: &Path,
+    suffix: Option<&str>,
+) -> anyhow::Result<()> {
+    if manifest.pages.is_empty() && manifest.artifacts.is_empty() {
+        return Ok(());
+    }
+    let file_name = manifest::file_name(suffix);
+    // Images saved without scanning any pages, like a threshold sweep, are
+    // added to an earlier scan's manifest rather than replacing it.
+    let path = output_dir.join(&file_name);
+    if manifest.pages.is_empty() && manifest::holds_scan(&path) {
+        return manifest::add_artifacts(&path, &manifest.artifacts);
+    }
+    manifest.write(output_dir, &file_name)?;
+    info!("Wrote {}.", output_dir.join(file_name).display());
+
+    Ok(())
+}
+
+/// Merge the manifest that was just written into `output_dir` with
+/// `earlier`, the one an earlier scan left there, and report which letter
+/// images were replaced and added, and any conflicts between them.
+fn merge_manifest(
+    output_dir: &Path,
+    suffix: Option<&str>,
+    earlier: serde_json::Value,
+) -> anyhow::Result<()> {
+    let path = output_dir.join(manifest::file_name(suffix));
+    let later = manifest::read(&path)?;
+    if later == earlier {
+        info!(
+            "No letters were saved, so {} is unchanged.",
+            output_dir.display()
+        );
+        return Ok(());
+    }
+    let (merged, report) = manifest::merge(earlier, later)?;
+    let json = serde_json::to_string_pretty(&merged).context("serializing the manifest")?;
+    std::fs::write(&path, json).with_context(|| format!("writing {}", path.display()))?;
+
+    for conflict in &report.conflicts {
+        warn!("{conflict}");
+    }
+    if !report.replaced.is_empty() {
+        info!(
+            "Replaced {} letter images: {}",
+            report.replaced.len(),
+            report.replaced.join(", ")
+        );
+    }
+    if !report.added.is_empty() {
+        info!(
+            "Added {} new letter images: {}",
+            report.added.len(),
+            report.added.join(", ")
+        );
+    }
+    info!(
+        "Kept {} letter images from the earlier scan; {} now lists them all.",
+        report.kept,
+        path.display()
+    );
+
+    Ok(())
+}
+
+/// Look for letterforms that are copies of earlier ones, on any of the pages
+/// that were `scanned`, each with the directory its manifest was written
+/// to. Copies are warned about and noted in the manifests, which are written
+/// again, and with `--dedup`, their letter images are deleted.
+fn check_duplicates(
+    args: &ScanArgs,
+    scanned: &mut [(PathBuf, manifest::Manifest)],
+) -> anyhow::Result<()> {
+    let _span = debug_span!("duplicates").entered();
+    // Every saved letterform, by its manifest and its position in it.
+    let mut glyphs = Vec::new();
+    // The page of each of those letterforms, by its manifest and its
+    // position in it.
+    let mut pages = Vec::new();
+    let mut fingerprints = Vec::new();
+    for (m, (_, manifest)) in scanned.iter().enumerate() {
+        for (g, glyph) in manifest.glyphs.iter().enumerate() {
+            if glyph.status == manifest::GlyphStatus::Saved && glyph.fingerprint.is_some() {
+                glyphs.push((m, g));
+                pages.push((m, glyph.page));
+                fingerprints.push(glyph.fingerprint.clone());
+            }
+        }
+    }
+    let found = duplicates::find(&fingerprints, args.duplicate_similarity);
+    if found.is_empty() {
+        return Ok(());
+    }
+
+    // A page with many copies of another was most likely scanned twice, which
+    // is said once rather than for every letterform.
+    let page_of = |i: usize| pages[i];
+    let mut copies_between: HashMap<_, usize> = HashMap::new();
+    for duplicate in &found {
+        *copies_between
+            .entry((page_of(duplicate.original), page_of(duplicate.copy)))
+            .or_default() += 1;
+    }
+    let mut rescans = HashSet::new();
+    for (&(original, copy), &count) in &copies_between {
+        let letterforms = (0..glyphs.len()).filter(|i| page_of(*i) == copy).count();
+        if original != copy && count > 1 && count * 2 >= letterforms {
+            rescans.insert((original, copy));
+        }
+    }
+    let page_names: HashMap<_, _> = pages
+        .iter()
+        .map(|&(m, page)| {
+            let entry = &scanned[m].1.pages[page];
+            let name = match entry.pdf_page {
+                Some(pdf_page) => format!("page {pdf_page} of {}", entry.input_file.display()),
+                None => entry.input_file.display().to_string(),
+            };
+            ((m, page), name)
+        })
+        .collect();
+    let describe_page = |page| &page_names[&page];
+    for &(original, copy) in &rescans {
+        warn!(
+            "{} looks like a rescan of {}: {} of its letterforms match.",
+            describe_page(copy),
+            describe_page(original),
+            copies_between[&(original, copy)]
+        );
+    }
+
+    // Each copy points to the first letterform of its kind, even when the
+    // one it's most alike is a copy itself.
+    let mut originals = HashMap::new();
+    let mut changed = HashSet::new();
+    for duplicate in &found {
+        let original = *originals
+            .get(&duplicate.original)
+            .unwrap_or(&duplicate.original);
+        originals.insert(duplicate.copy, original);
+        let (original_m, original_g) = glyphs[original];
+        let (copy_m, copy_g) = glyphs[duplicate.copy];
+        let original_glyph = &scanned[original_m].1.glyphs[original_g];
+        let original_name = stages::cell_name(
+            original_glyph.row,
+            original_glyph.col,
+            original_glyph.character.as_deref(),
+        );
+        let original_file = original_glyph
+            .file
+            .as_ref()
+            .map(|file| scanned[original_m].0.join(file));
+
+        let (output_dir, manifest) = &mut scanned[copy_m];
+        let glyph = &mut manifest.glyphs[copy_g];
+        if !rescans.contains(&(page_of(duplicate.original), page_of(duplicate.copy))) {
+            let name = stages::cell_name(glyph.row, glyph.col, glyph.character.as_deref());
+            let place = if page_of(original) == page_of(duplicate.copy) {
+                "on the same page".to_string()
+            } else {
+                format!("on {}", describe_page(page_of(original)))
+            };
+            warn!(
+                "{name} looks like a copy of {original_name} {place} ({:.0}% alike); it may \
+                 have been filled in twice.",
+                duplicate.similarity * 100.0
+            );
+        }
+        glyph.duplicate_of = original_file;
+        if args.dedup {
+            if let Some(file) = glyph.file.take() {
+                let path = output_dir.join(&file);
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("removing {}", path.display()))?;
+                let outline = path.with_extension("svg");
+                if args.vectorize && outline.exists() {
+                    std::fs::remove_file(&outline)
+                        .with_context(|| format!("removing {}", outline.display()))?;
+                }
+                if let Some(extension) = args.skeleton.and_then(|skeleton| skeleton.extension()) {
+                    let strokes = path.with_extension(extension);
+                    if strokes.exists() {
+                        std::fs::remove_file(&strokes)
+                            .with_context(|| format!("removing {}", strokes.display()))?;
+                    }
+                }
+            }
+            glyph.status = manifest::GlyphStatus::Duplicate;
+        }
+        changed.insert(copy_m);
+    }
+    if args.dedup {
+        info!("Removed {} copies of earlier letterforms.", found.len());
+    }
+    for m in changed {
+        let (output_dir, manifest) = &scanned[m];
+        write_manifest(manifest, output_dir, args.suffix.as_deref())?;
+    }
+
+    Ok(())
+}
+
+/// Pick `count` cells spread evenly through the grid from those that aren't
+/// `empty`, or from every cell if they all are.
+fn pick_cells(empty: &[bool], count: usize) -> Vec<usize> {
+    let mut candidates: Vec<usize> = (0..empty.len()).filter(|i| !empty[*i]).collect();
+    if candidates.is_empty() {
+        candidates = (0..empty.len()).collect();
+    }
+    if count >= candidates.len() {
+        return candidates;
+    }
+    (0..count)
+        .map(|k| candidates[k * candidates.len() / count])
+        .collect()
+}
+
+/// What a threshold sweep compares.
+struct Sweep<'a> {
+    levels: &'a [u8],
+    /// The positions of the cells to compare.
+    cells: &'a [usize],
+    /// The characters of every cell of the page.
+    labels: &'a [Option<String>],
+    descriptor: &'a Descriptor,
+}
+
+/// Threshold the `original` page at each of the sweep's levels, cut it into
+/// cells the way `scan` does, and save the sweep's cells at every level side
+/// by side, one row per level, to `path`.
+fn sweep_thresholds(
+    sweep: &Sweep,
+    original: &image::DynamicImage,
+    options: pipeline::PreprocessOptions,
+    profile: &Profile,
+    min_ink: f32,
+    clean: &stages::CleanOptions,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let _span = debug_span!("threshold_sweep").entered();
+    info!("Thresholding the page at {} values...", sweep.levels.len());
+    let mut tiles = Vec::new();
+    let mut tile_labels = Vec::new();
+    let mut skipped = Vec::new();
+    for &level in sweep.levels {
+        let options = pipeline::PreprocessOptions {
+            threshold: threshold::Threshold::Fixed(level),
+            grayscale: false,
+            straightened: false,
+            ..options
+        };
+        let (page, report) = pipeline::preprocess_with_profile(original.clone(), options, profile)?;
+        let located = sweep.descriptor.locate(&page);
+        stages::warn_missing_grids(sweep.descriptor, &located.missing, Some(level));
+        let areas: Vec<_> = sweep
+            .descriptor
+            .cells(&located.grids)
+            .iter()
+            .map(|cell| cell.area)
+            .collect();
+        let cells = pipeline::cut_areas(report.pen.as_ref().unwrap_or(&page), &areas);
+        for &i in sweep.cells {
+            let Some(cell) = cells.get(i) else {
+                continue;
+            };
+            let cleaned = clean.clean(cell);
+            skipped.push(glyph::ink_coverage(&cleaned) < min_ink);
+            tiles.push(cleaned);
+            tile_labels.push(match &sweep.labels[i] {
+                Some(label) => format!("{level}: {i} {label}"),
+                None => format!("{level}: {i}"),
+            });
+        }
+    }
+
+    let font = template::system_font();
+    if font.is_none() {
+        warn!("no font was found for the threshold sweep's labels.");
+    }
+    let views: Vec<_> = tiles
+        .iter()
+        .map(|tile| tile.view(0, 0, tile.width(), tile.height()))
+        .collect();
+    let cols = sweep.cells.len().max(1) as u32;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).context("creating output dir")?;
+    }
+    contact_sheet::render(&views, cols, &tile_labels, &skipped, font.as_ref())
+        .save(path)
+        .with_context(|| format!("saving {}", path.display()))?;
+    info!("Saved a threshold sweep to {}.", path.display());
+
+    Ok(())
+}
+
+/// One page to scan, and where its letters go.
+struct Page<'a> {
+    input_file: &'a Path,
+    /// The page to scan, if the input file is a PDF.
+    pdf_page: Option<u32>,
+    output_dir: &'a Path,
+    /// The page's position among the pages being scanned, counted from 1.
+    number: usize,
+    /// The position in the charset of the page's first cell.
+    first_cell: usize,
+    /// Where to save the page's contact sheet, if anywhere.
+    contact_sheet: Option<PathBuf>,
+    /// Asks whether to save the page's letters.
+    confirm: &'a dyn Fn() -> anyhow::Result<bool>,
+}
+
+/// Scan one page, and save its letters once the page's `confirm` says to,
+/// unless `--yes`, `--review`, or `--gui` decide instead. The names of the letter
+/// images that have been saved so far are kept in `saved`, so that
+/// conflicting names can be resolved, and every cell is described in
+/// `manifest`.
+fn scan_file(
+    args: &ScanArgs,
+    page: &Page,
+    descriptor: &Descriptor,
+    charset: &charset::Charset,
+    profile: &Profile,
+    saved: &mut HashSet<String>,
+    manifest: &mut manifest::Manifest,
+) -> anyhow::Result<()> {
+    let &Page {
+        input_file,
+        pdf_page,
+        output_dir,
+        number,
+        first_cell,
+        ref contact_sheet,
+        confirm,
+    } = page;
+
+    let load_options = stages::LoadOptions {
+        input_file,
+        pdf_page,
+        pdf_dpi: args.pdf_dpi,
+        region: args.region,
+        preprocess: pipeline::PreprocessOptions {
+            threshold: args.threshold,
+            adaptive_window: args.adaptive_threshold,
+            // A region is measured on the page the way it was scanned, so the
+            // page isn't turned.
+            orient: args.region.is_none(),
+            lighting: args.lighting,
+            deskew: !args.no_deskew,
+            camera: args.camera,
+            // The softly thresholded page is also used to judge how well each
+            // letterform was captured.
+            grayscale: true,
+            pen_color: args.pen_color,
+            // The template's guide lines are too light to survive
+            // thresholding.
+            straightened: true,
+        },
+        scale_threshold_with_dpi: args.scale_threshold_with_dpi,
+        // A threshold sweep thresholds the page again for every value.
+        keep_original: args.threshold_sweep.is_some(),
+        profile,
+    };
+    let loaded = stages::load(&load_options)?;
+    if let Some(dir) = &args.debug_output {
+        for path in save_debug_stages(dir, number, &loaded.report, &loaded.image)? {
+            manifest.add_artifact(output_dir, &path);
+        }
+    }
+    let region_descriptor = loaded.region.map(|bounds| {
+        let mut region_descriptor = Descriptor::fixed(args.rows, args.cols, bounds);
+        region_descriptor.charset = descriptor.charset.clone();
+        region_descriptor.right_to_left = descriptor.right_to_left;
+        region_descriptor
+    });
+    let locate_options = stages::LocateOptions {
+        descriptor: region_descriptor.as_ref().unwrap_or(descriptor),
+        // A template descriptor or region describes the page instead of its
+        // layout code.
+        layout_code: !args.no_layout_code && args.template.is_none() && args.region.is_none(),
+        margins: grid::Margins {
+            x: args.margin_x,
+            y: args.margin_y,
+        },
+        charset,
+        first_cell,
+        output_dir,
+    };
+    let located = stages::locate(loaded, &load_options, &locate_options)?;
+    if let (Some(dir), Some(_)) = (&args.debug_output, located.attempt) {
+        for path in save_debug_stages(dir, number, &located.page.report, &located.page.image)? {
+            manifest.add_artifact(output_dir, &path);
+        }
+    }
+    let threshold = located.page.options.threshold;
+    // With `--gui`, the grid and threshold are fixed by hand before the cells
+    // are cut out, and cells can be left out.
+    #[cfg(feature = "gui")]
+    let (located, threshold, rejected_by_hand) = if args.gui {
+        let mut located = located;
+        let [grid] = &located.located.grids[..] else {
+            bail!("--gui can only adjust templates with one region.");
+        };
+        // The window labels every cell of the grid, including labels and
+        // skipped cells.
+        let mut grid_labels = vec![None; grid.cells().len()];
+        for (cell, label) in located.cells.iter().zip(located.labels()) {
+            grid_labels[cell.index] = label;
+        }
+        let page = &located.page;
+        let Some(adjusted) = adjust_by_hand(
+            grid.clone(),
+            page.image.clone(),
+            page.report.clone(),
+            profile,
+            &grid_labels,
+        )?
+        else {
+            info!("Very well. Exiting without saving...");
+            return Ok(());
+        };
+        located.located.grids = vec![adjusted.grid];
+        located.cells = located.descriptor.cells(&located.located.grids);
+        located.page.image = adjusted.image;
+        located.page.report = adjusted.report;
+        let rejected = located
+            .cells
+            .iter()
+            .map(|cell| adjusted.rejected[cell.index])
+            .collect();
+        (
+            located,
+            adjusted
+                .threshold
+                .map_or(threshold, threshold::Threshold::Fixed),
+            Some(rejected),
+        )
+    } else {
+        (located, threshold, None)
+    };
+    #[cfg(not(feature = "gui"))]
+    let rejected_by_hand: Option<Vec<bool>> = None;
+    let glyph_scale = args
+        .target_height
+        .and_then(|target_height| stages::glyph_scale(&located, target_height, args.cell_size));
+
+    let cut_options = stages::CutOptions {
+        min_ink: args.min_ink,
+        baseline: args.baseline.as_deref(),
+        baseline_similarity: args.baseline_similarity,
+        pdf_dpi: args.pdf_dpi,
+        profile,
+    };
+    let mut cut = stages::cut(&located, &cut_options)?;
+    let page_image = located
+        .page
+        .report
+        .straightened
+        .as_ref()
+        .unwrap_or(&located.page.image);
+
+    if args.dry_run {
+        if let Some(dir) = &args.debug_output {
+            let cells: Vec<overlay::CellOverlay> = cut
+                .letter_images
+                .iter()
+                .enumerate()
+                .map(|(i, letter_image)| {
+                    let (x, y) = letter_image.offsets();
+                    let bounds = glyph::ink_bounds(&letter_image.to_image());
+                    let mark = if cut.empty[i] || cut.unchanged[i] {
+                        overlay::Mark::Skipped
+                    } else if bounds.is_some_and(|bounds| {
+                        glyph::touches_edge(letter_image.dimensions(), bounds)
+                    }) {
+                        overlay::Mark::Flagged
+                    } else {
+                        overlay::Mark::Saved
+                    };
+                    overlay::CellOverlay {
+                        area: located.cells[i].area,
+                        label: overlay_label(i, cut.labels[i].as_deref()),
+                        ink_bounds: bounds.map(|(ink_x, ink_y, width, height)| {
+                            (x + ink_x, y + ink_y, width, height)
+                        }),
+                        mark,
+                    }
+                })
+                .collect();
+            let path = save_overlay(dir, number, page_image, &located.located.grids, &cells)?;
+            manifest.add_artifact(output_dir, &path);
+        }
+        print_report(&cut, &located.cells, &args.guide_dots);
+        return Ok(());
+    }
+    let clean = stages::CleanOptions {
+        guide_dots: &args.guide_dots,
+        keep_grid_lines: args.keep_grid_lines,
+        profile,
+        despeckle: args.despeckle,
+        open: args.open,
+        close: args.close,
+    };
+    if let (Some(sweep), Some(original)) = (args.threshold_sweep, &located.page.original) {
+        let picked = pick_cells(&cut.empty, args.sweep_cells);
+        let path = output_dir.join("threshold-sweep.png");
+        let sweep = Sweep {
+            levels: &sweep.levels(),
+            cells: &picked,
+            labels: &cut.labels,
+            descriptor: &located.descriptor,
+        };
+        sweep_thresholds(
+            &sweep,
+            original,
+            located.page.options,
+            profile,
+            args.min_ink,
+            &clean,
+            &path,
+        )?;
+        manifest.add_artifact(output_dir, &path);
+        return Ok(());
+    }
+    let adjusted_by_hand = rejected_by_hand.is_some();
+    if let Some(rejected_by_hand) = rejected_by_hand {
+        cut.rejected = rejected_by_hand;
+    }
+    let (rows, cols) = located.descriptor.size();
+    if let Some(contact_sheet) = contact_sheet {
+        let font = template::system_font();
+        if font.is_none() {
+            warn!("no font was found for the contact sheet's labels.");
+        }
+        let sheet_labels: Vec<String> = cut
+            .labels
+            .iter()
+            .enumerate()
+            .map(|(i, label)| match label {
+                Some(label) => format!("{i} {label}"),
+                None => i.to_string(),
+            })
+            .collect();
+        let skipped: Vec<bool> = cut
+            .empty
+            .iter()
+            .zip(&cut.unchanged)
+            .map(|(e, u)| *e || *u)
+            .collect();
+        contact_sheet::render(
+            &cut.letter_images,
+            cols,
+            &sheet_labels,
+            &skipped,
+            font.as_ref(),
+        )
+        .save(contact_sheet)
+        .with_context(|| format!("saving {}", contact_sheet.display()))?;
+        info!("Saved a contact sheet to {}.", contact_sheet.display());
+    }
+    let confirmation = if args.review {
+        let cells: Vec<(usize, GrayImage)> = cut
+            .letter_images
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !cut.empty[*i] && !cut.unchanged[*i])
+            .map(|(i, letter_image)| (i, clean.clean(letter_image)))
+            .collect();
+        let cell_names: Vec<String> = located
+            .cells
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| format!("cell {i} (row {}, column {})", cell.row, cell.col))
+            .collect();
+        match review::review(&cells, &cut.labels, &cell_names) {
+            Some(decisions) => {
+                for (i, decision) in decisions.into_iter().enumerate() {
+                    match decision {
+                        review::Decision::Accept(c) => cut.labels[i] = c,
+                        review::Decision::Reject => cut.rejected[i] = true,
+                    }
+                }
+                true
+            }
+            None => false,
+        }
+    } else if args.yes || adjusted_by_hand {
+        true
+    } else {
+        confirm()?
+    };
+    if !confirmation {
+        info!("Very well. Exiting without saving...");
+        return Ok(());
+    }
+
+    let _span = debug_span!("save").entered();
+    info!("Saving images...");
+    let report = &located.page.report;
+    let entry = manifest::PageEntry {
+        input_file: input_file.to_path_buf(),
+        pdf_page,
+        dpi: located.page.dpi,
+        rows,
+        cols,
+        grid_found: located.located.missing.is_empty(),
+        guessed_lines: located.located.guessed(),
+        // Kept whole, so it can be found again by `clean` from anywhere.
+        contact_sheet: contact_sheet
+            .as_ref()
+            .and_then(|path| std::fs::canonicalize(path).ok()),
+        preprocessing: manifest::Preprocessing {
+            threshold,
+            threshold_level: report.threshold,
+            adaptive_threshold: args.adaptive_threshold,
+            profile: args.profile.clone(),
+            camera: args.camera,
+            lighting: args.lighting,
+            deskew: !args.no_deskew,
+            rotation: report.rotation,
+            skew_degrees: report.skew,
+            min_ink: args.min_ink,
+            padding: (!args.no_crop).then_some(args.padding),
+            grid_lines_removed: !args.keep_grid_lines,
+            despeckle: args.despeckle,
+            open: args.open,
+            close: args.close,
+            stroke_width: None,
+            grayscale: args.grayscale,
+            pen_color: args.pen_color.map(|color| color.to_string()),
+            canvas: args.canvas,
+            align: args.canvas.map(|_| args.align),
+            scale: glyph_scale,
+            upscale: args.upscale,
+            pot: args.pot,
+            alpha: args.alpha,
+            ink_color: args.ink_color.map(|color| color.to_string()),
+            vectorize: args.vectorize,
+            skeleton: args.skeleton,
+            output_format: args.output_format.extension(),
+        },
+    };
+    let save_options = stages::SaveOptions {
+        output_dir,
+        input_file,
+        number,
+        first_cell,
+        files: pipeline::SaveOptions {
+            output_format: args.output_format,
+            fallback_to_png: args.fallback_to_png,
+            vectorize: args.vectorize,
+            simplify: args.simplify,
+            skeleton: args.skeleton,
+            alpha: args.alpha,
+            ink_color: args.ink_color,
+        },
+        clean,
+        name_format: args.name_format.as_ref(),
+        suffix: args.suffix.as_deref(),
+        on_conflict: args.on_conflict,
+        force: args.force,
+        skip_existing: args.skip_existing,
+        update: args.update,
+        update_similarity: args.baseline_similarity,
+        merging: args.merge_into.is_some(),
+        expand_clipped: args.expand_clipped,
+        normalize_strokes: args.normalize_strokes,
+        grayscale: args.grayscale,
+        scale: glyph_scale,
+        upscale: args.upscale,
+        canvas: args.canvas,
+        align: args.align,
+        crop: !args.no_crop,
+        padding: args.padding,
+        pot: args.pot,
+    };
+    let failures = stages::save(&located, &cut, entry, &save_options, saved, manifest)?;
+
+    // `save` adds an entry to the manifest for each of the page's cells, so
+    // the page's entries are the last ones.
+    let start = manifest.glyphs.len().checked_sub(located.cells.len());
+    if start.is_none() && args.debug_output.is_some() {
+        warn!("page {number}'s overlay wasn't saved: the manifest is missing some of its letters");
+    }
+    if let (Some(dir), Some(start)) = (&args.debug_output, start) {
+        let entries = &manifest.glyphs[start..];
+        let cells: Vec<overlay::CellOverlay> = entries
+            .iter()
+            .zip(&located.cells)
+            .enumerate()
+            .map(|(i, (entry, cell))| {
+                use manifest::GlyphStatus;
+                let mark = match entry.status {
+                    GlyphStatus::Empty
+                    | GlyphStatus::Unchanged
+                    | GlyphStatus::Rejected
+                    | GlyphStatus::Replaced => overlay::Mark::Skipped,
+                    GlyphStatus::Conflict | GlyphStatus::Failed => overlay::Mark::Flagged,
+                    GlyphStatus::Saved | GlyphStatus::Existing | GlyphStatus::Duplicate
+                        if entry.clipped || !entry.issues.is_empty() =>
+                    {
+                        overlay::Mark::Flagged
+                    }
+                    GlyphStatus::Saved | GlyphStatus::Existing | GlyphStatus::Duplicate => {
+                        overlay::Mark::Saved
+                    }
+                };
+                overlay::CellOverlay {
+                    area: cell.area,
+                    label: overlay_label(i, entry.character.as_deref()),
+                    ink_bounds: entry
+                        .ink_bounds
+                        .map(|bounds| (bounds.x, bounds.y, bounds.width, bounds.height)),
+                    mark,
+                }
+            })
+            .collect();
+        let path = save_overlay(dir, number, page_image, &located.located.grids, &cells)?;
+        manifest.add_artifact(output_dir, &path);
+    }
+    if failures.is_empty() {
+        info!("Images saved successfully.");
+    } else {
+        let failures: Vec<String> = failures.iter().map(|i| i.to_string()).collect();
+        let message = format!(
+            "{} letter images couldn't be saved: {}",
+            failures.len(),
+            failures.join(", ")
+        );
+        if args.strict {
+            bail!(message);
+        }
+        error!("{message}");
+    }
+
+    Ok(())
+}
+
+/// Save the page as it was after each step of reading it into
+/// `--debug-output`: turned upright and straightened, thresholded, and with
+/// only the pen's ink, if it was read. Page `number`'s images replace any
+/// saved for it before, so a page whose grid was only found by reading it
+/// another way is saved as it was read then. Returns where they were saved.
+fn save_debug_stages(
+    dir: &Path,
+    number: usize,
+    report: &pipeline::PreprocessReport,
+    thresholded: &GrayImage,
+) -> anyhow::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    let stages = [
+        ("deskewed", report.straightened.as_ref()),
+        ("thresholded", Some(thresholded)),
+        ("pen", report.pen.as_ref()),
+    ];
+    let mut saved = Vec::new();
+    for (stage, image) in stages {
+        let Some(image) = image else {
+            continue;
+        };
+        let path = dir.join(format!("page-{number}-{stage}.png"));
+        image
+            .save(&path)
+            .with_context(|| format!("saving {}", path.display()))?;
+        saved.push(path);
+    }
+    debug!("Saved page {number}'s stages to {}.", dir.display());
+
+    Ok(saved)
+}
+
+/// Save an overlay of how page `number` was read into `--debug-output`.
+/// Returns where it was saved.
+fn save_overlay(
+    dir: &Path,
+    number: usize,
+    page: &GrayImage,
+    grids: &[grid::Grid],
+    cells: &[overlay::CellOverlay],
+) -> anyhow::Result<PathBuf> {
+    let font = template::system_font();
+    if font.is_none() {
+        warn!("no font was found for the overlay's labels.");
+    }
+    let path = dir.join(format!("page-{number}-overlay.png"));
+    overlay::render(page, grids, cells, font.as_ref())
+        .save(&path)
+        .with_context(|| format!("saving {}", path.display()))?;
+    info!(
+        "Saved an overlay of how the page was read to {}.",
+        path.display()
+    );
+
+    Ok(path)
+}
+
+/// A cell's label on the overlay: its number, and its character if it has
+/// one.
+fn overlay_label(i: usize, character: Option<&str>) -> String {
+    match character {
+        Some(character) => format!("{i} {character}"),
+        None => i.to_string(),
+    }
+}
+
+/// A page whose grid and threshold were fixed by hand.
+#[cfg(feature = "gui")]
+struct AdjustedPage {
+    grid: grid::Grid,
+    /// The page, thresholded again if the threshold was changed.
+    image: GrayImage,
+    report: pipeline::PreprocessReport,
+    /// The new threshold, if it was changed.
+    threshold: Option<u8>,
+    rejected: Vec<bool>,
+}
+
+/// Open a window for fixing the grid that was found on a page, and the
+/// threshold it was thresholded at, by hand. Returns the page once they're
+/// saved, or `None` if the window was closed without saving.
+#[cfg(feature = "gui")]
+fn adjust_by_hand(
+    grid: grid::Grid,
+    image: GrayImage,
+    mut report: pipeline::PreprocessReport,
+    profile: &Profile,
+    labels: &[Option<String>],
+) -> anyhow::Result<Option<AdjustedPage>> {
+    let straightened = report
+        .straightened
+        .clone()
+        .context("the page wasn't kept before it was thresholded")?;
+    info!("Adjust the grid in the window, then press S to save.");
+    let Some(adjustment) = crate::gui::adjust(&straightened, &grid, report.threshold, labels)?
+    else {
+        return Ok(None);
+    };
+    let mut image = image;
+    let changed = adjustment.threshold != report.threshold;
+    if changed {
+        // The page is thresholded the same way it was the first time, from
+        // the straightened page, so that it still lines up with the grid.
+        let level = adjustment.threshold;
+        info!("Using threshold {level} instead of {}.", report.threshold);
+        image = profile.apply_to_page(straightened.clone(), level, None);
+        if report.pen.is_none() {
+            report.grayscale = report
+                .grayscale
+                .as_ref()
+                .map(|_| threshold::soft_threshold(&straightened, level));
+        }
+        report.threshold = level;
+    }
+
+    Ok(Some(AdjustedPage {
+        grid: adjustment.grid,
+        image,
+        report,
+        threshold: changed.then_some(adjustment.threshold),
+        rejected: adjustment.rejected,
+    }))
+}
+
+/// Print a line about every cell of a page, for `--dry-run`.
+fn print_report(
+    cut: &stages::CutPage,
+    cells: &[descriptor::Cell],
+    guide_dots: &[guide_dots::GuideDot],
+) {
+    let stages::CutPage {
+        letter_images,
+        coverage,
+        empty,
+        unchanged,
+        labels,
+        ..
+    } = cut;
+    println!();
+    println!("cell  row  col  char     ink  status");
+    let mut clipped = 0;
+    for (i, letter_image) in letter_images.iter().enumerate() {
+        let label = labels[i].as_deref().unwrap_or("-");
+        let status = if empty[i] {
+            "empty".to_string()
+        } else if unchanged[i] {
+            "unchanged from the baseline".to_string()
+        } else {
+            let mut cell = letter_image.to_image();
+            guide_dots::mask_guide_dots(&mut cell, guide_dots);
+            match glyph::ink_bounds(&cell) {
+                Some(bounds) if glyph::touches_edge(cell.dimensions(), bounds) => {
+                    clipped += 1;
+                    "letterform; warning: it runs into the cell's edge".to_string()
+                }
+                _ => "letterform".to_string(),
+            }
+        };
+        println!(
+            "{i:>4} {:>4} {:>4}  {label:<4} {:>6.2}%  {status}",
+            cells[i].row,
+            cells[i].col,
+            coverage[i] * 100.0
+        );
+    }
+    println!();
+    println!(
+        "{} letterforms, {} empty cells, {clipped} letterforms running into the cell's edge. Nothing was saved.",
+        empty.iter().filter(|empty| !**empty).count(),
+        empty.iter().filter(|empty| **empty).count(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::SystemTime;
+
+    fn args(extra: &[&str]) -> ScanArgs {
+        let mut args = vec!["--input-file", "page.png"];
+        args.extend(extra);
+        ScanArgs::try_parse_from(args).unwrap()
+    }
+
+    fn write_json(path: &Path, json: &serde_json::Value) {
+        std::fs::write(path, json.to_string()).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_replace_an_earlier_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(check_overwrite(&args(&[]), dir.path(), manifest::FILE_NAME).is_ok());
+
+        // A threshold sweep's manifest lists no pages, so it's not a scan.
+        let path = dir.path().join(manifest::FILE_NAME);
+        write_json(
+            &path,
+            &json!({"pages": [], "glyphs": [], "artifacts": ["sweep.png"]}),
+        );
+        assert!(check_overwrite(&args(&[]), dir.path(), manifest::FILE_NAME).is_ok());
+
+        write_json(
+            &path,
+            &json!({"pages": [{"input_file": "page.png"}], "glyphs": []}),
+        );
+        let err = check_overwrite(&args(&[]), dir.path(), manifest::FILE_NAME).unwrap_err();
+        assert!(err.to_string().contains("already holds a scan"));
+        for extra in [
+            &["--force"][..],
+            &["--skip-existing"],
+            &["--update"],
+            &["--dry-run"],
+        ] {
+            assert!(check_overwrite(&args(extra), dir.path(), manifest::FILE_NAME).is_ok());
+        }
+        // Saving next to the earlier scan, with a suffix, doesn't replace it.
+        assert!(check_overwrite(&args(&[]), dir.path(), "manifest-2.json").is_ok());
+    }
+
+    #[test]
+    fn skips_pages_scanned_since_they_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let input_file = dir.path().join("page.png");
+        std::fs::write(&input_file, b"").unwrap();
+        let path = dir.path().join(manifest::FILE_NAME);
+        write_json(
+            &path,
+            &json!({"pages": [{"input_file": "page.png"}], "glyphs": []}),
+        );
+        let set_modified = |path: &Path, time: SystemTime| {
+            std::fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        };
+        let earlier = SystemTime::now() - Duration::from_secs(60);
+        set_modified(&input_file, earlier);
+
+        let skip_existing = args(&["--skip-existing"]);
+        assert!(already_scanned(
+            &skip_existing,
+            &input_file,
+            dir.path(),
+            manifest::FILE_NAME
+        ));
+        assert!(!already_scanned(
+            &args(&[]),
+            &input_file,
+            dir.path(),
+            manifest::FILE_NAME
+        ));
+        // The page changed after it was scanned.
+        set_modified(&path, earlier - Duration::from_secs(60));
+        assert!(!already_scanned(
+            &skip_existing,
+            &input_file,
+            dir.path(),
+            manifest::FILE_NAME
+        ));
+    }
+
+    #[test]
+    fn merges_a_rescan_into_the_earlier_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(manifest::FILE_NAME);
+        let glyph = |page: u64, file: &str, character: &str| {
+            json!({
+                "page": page,
+                "file": file,
+                "character": character,
+                "alternate": null,
+                "status": "saved",
+            })
+        };
+        let earlier = json!({
+            "pages": [{"input_file": "page-1.png"}],
+            "glyphs": [glyph(0, "A.png", "A"), glyph(0, "B.png", "B")],
+        });
+
+        // A rescan that saved nothing leaves the manifest alone.
+        write_json(&path, &earlier);
+        merge_manifest(dir.path(), None, earlier.clone()).unwrap();
+        assert_eq!(manifest::read(&path).unwrap(), earlier);
+
+        write_json(
+            &path,
+            &json!({
+                "pages": [{"input_file": "page-1-again.png"}],
+                "glyphs": [glyph(0, "B.png", "B"), glyph(0, "C.png", "C")],
+            }),
+        );
+        merge_manifest(dir.path(), None, earlier).unwrap();
+        let merged = manifest::read(&path).unwrap();
+        assert_eq!(merged["pages"].as_array().unwrap().len(), 2);
+        let files: Vec<&serde_json::Value> = merged["glyphs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|glyph| &glyph["file"])
+            .collect();
+        assert_eq!(
+            files,
+            [
+                &json!("A.png"),
+                &json!(null),
+                &json!("B.png"),
+                &json!("C.png")
+            ]
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use image::{GrayImage, Luma, RgbaImage};
+use imageproc::distance_transform::euclidean_squared_distance_transform;
+
+/// The value of a distance field on the outline of a letterform, from 0 to 1.
+pub const EDGE: f32 = 0.5;
+
+/// Which pixels of a letter image are ink, as a mask that's white where the
+/// ink is. Letter images with transparent paper have their ink wherever
+/// they're opaque, whatever color it was drawn in; other letter images have
+/// it wherever they're dark.
+pub fn ink_mask(image: &RgbaImage) -> GrayImage {
+    let transparent = image.pixels().any(|pixel| pixel.0[3] < 255);
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        let [red, green, blue, alpha] = image.get_pixel(x, y).0;
+        let luma = (red as u32 * 299 + green as u32 * 587 + blue as u32 * 114) / 1000;
+        let ink = alpha >= 128 && (transparent || luma < 128);
+        Luma([if ink { 255 } else { 0 }])
+    })
+}
+
+/// Turn an ink mask, as made by [`ink_mask`], into a signed distance field
+/// with `spread` pixels of paper added on every side.
+///
+/// Each pixel holds how far it is from the letterform's outline: [`EDGE`]
+/// (128) on the outline, rising to 255 `spread` pixels inside the ink, and
+/// falling to 0 `spread` pixels outside it. Drawn with a threshold at the
+/// edge value, the letterform stays crisp however much it's scaled.
+pub fn signed_distance_field(mask: &GrayImage, spread: u32) -> GrayImage {
+    let (width, height) = mask.dimensions();
+    let mut ink = GrayImage::new(width + spread * 2, height + spread * 2);
+    image::imageops::replace(&mut ink, mask, spread as i64, spread as i64);
+    let paper = GrayImage::from_fn(ink.width(), ink.height(), |x, y| {
+        Luma([255 - ink.get_pixel(x, y).0[0]])
+    });
+    // How far each pixel is from the nearest ink, and from the nearest paper.
+    let to_ink = euclidean_squared_distance_transform(&ink);
+    let to_paper = euclidean_squared_distance_transform(&paper);
+
+    let spread = spread.max(1) as f64;
+    GrayImage::from_fn(ink.width(), ink.height(), |x, y| {
+        // Distances are measured between pixels' centers, so the outline is
+        // half a pixel from the pixels on either side of it.
+        let distance = if ink.get_pixel(x, y).0[0] != 0 {
+            to_paper.get_pixel(x, y).0[0].sqrt() - 0.5
+        } else {
+            0.5 - to_ink.get_pixel(x, y).0[0].sqrt()
+        };
+        let value = EDGE as f64 + distance / (spread * 2.0);
+        Luma([(value.clamp(0.0, 1.0) * 255.0).round() as u8])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use imageproc::{drawing::draw_filled_rect_mut, rect::Rect};
+
+    #[test]
+    fn measures_distance_from_the_outline() {
+        let mut image = RgbaImage::from_pixel(20, 20, image::Rgba([255, 255, 255, 255]));
+        draw_filled_rect_mut(
+            &mut image,
+            Rect::at(5, 5).of_size(10, 10),
+            image::Rgba([0, 0, 0, 255]),
+        );
+        let field = signed_distance_field(&ink_mask(&image), 4);
+        assert_eq!(field.dimensions(), (28, 28));
+        // The middle of the square is deep inside, the corners of the field
+        // are far outside, and the pixels on either side of the outline are
+        // close to the edge value.
+        assert_eq!(field.get_pixel(14, 14).0[0], 255);
+        assert_eq!(field.get_pixel(0, 0).0[0], 0);
+        let inside = field.get_pixel(9, 14).0[0];
+        let outside = field.get_pixel(8, 14).0[0];
+        assert!(inside > 128 && inside < 150);
+        assert!(outside < 128 && outside > 105);
+    }
+}
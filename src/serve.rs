@@ -0,0 +1,357 @@
+use anyhow::{bail, Context};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::Args;
+use std::{collections::BTreeMap, io::Read, path::Path};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{error, info};
+
+use crate::{inputs, manifest, output, scan, summary::Status};
+
+/// The settings that may be changed for each upload, in the query string of
+/// the request, like `/scan?rows=6&cols=8`. Anything else is left to the
+/// server's own settings, so that an upload can't read or write files on
+/// the server.
+const QUERY_SETTINGS: &[&str] = &[
+    "rows",
+    "cols",
+    "threshold",
+    "adaptive-threshold",
+    "min-ink",
+    "padding",
+    "output-format",
+];
+
+/// Arguments for the `serve` command.
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// The address to listen on. The default only accepts connections from
+    /// this computer; use `0.0.0.0:8080` to accept them from anywhere.
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    address: String,
+
+    /// The largest upload accepted, in megabytes.
+    #[arg(long, default_value = "50")]
+    max_upload: u32,
+
+    /// Arguments for the `scan` command, used for every upload, like
+    /// `-- --charset "abc" --padding 4`. `--input-file` and `--output-dir`
+    /// are set for each upload, and `--yes` is always passed.
+    #[arg(last = true)]
+    scan_args: Vec<String>,
+}
+
+/// How the letters of a scan are sent back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ResponseFormat {
+    Json,
+    Zip,
+}
+
+/// Listen for scans uploaded over HTTP, and send back their letters.
+///
+/// `POST /scan` with an image or PDF as the request's body scans it with the
+/// server's settings, changed by any of [`QUERY_SETTINGS`] in the query
+/// string. The letters are sent back as JSON by default: the manifest, and
+/// every file as base64. Add `format=zip` to the query string to get a zip
+/// file of the letter images and the manifest instead. Uploads are scanned
+/// one at a time. A scan whose letters are worth checking says it's
+/// `partial`, and a page whose grid can't be found is sent back as a 422.
+pub fn serve(args: ServeArgs) -> anyhow::Result<()> {
+    // Check the scan arguments before listening, so that a typo shows up
+    // right away rather than on the first upload.
+    scan_args(
+        &args.scan_args,
+        &[],
+        Path::new("upload.png"),
+        Path::new("."),
+    )
+    .context("reading the scan arguments")?;
+
+    let server = Server::http(&args.address)
+        .map_err(|err| anyhow::anyhow!("listening on {}: {err}", args.address))?;
+    info!(
+        "Listening on http://{}. POST a scan to /scan to extract its letters.",
+        args.address
+    );
+
+    let max_upload = args.max_upload as u64 * 1024 * 1024;
+    for request in server.incoming_requests() {
+        respond(request, &args, max_upload);
+    }
+
+    Ok(())
+}
+
+/// Scan the upload in `request`, and send back its letters, or what went
+/// wrong. How the scan went is sent in the `X-Scan-Status` header, as
+/// `complete` or `partial`, so that letters worth checking can be told apart.
+fn respond(mut request: Request, args: &ServeArgs, max_upload: u64) {
+    let (status, content_type, body, scan_status) = match handle(&mut request, args, max_upload) {
+        Ok((content_type, body, scan_status)) => (200, content_type, body, Some(scan_status)),
+        Err(err) => {
+            let status = err.downcast_ref::<HttpError>().map_or(422, |err| err.0);
+            error!("{} {}: {err:#}", request.method(), request.url());
+            let body = serde_json::json!({ "error": format!("{err:#}") });
+            (
+                status,
+                "application/json",
+                body.to_string().into_bytes(),
+                None,
+            )
+        }
+    };
+    let header =
+        Header::from_bytes("Content-Type", content_type).expect("content types are valid headers");
+    let mut response = Response::from_data(body)
+        .with_status_code(status)
+        .with_header(header);
+    if let Some(scan_status) = scan_status {
+        let name = status_name(scan_status);
+        response.add_header(
+            Header::from_bytes("X-Scan-Status", name).expect("statuses are valid headers"),
+        );
+    }
+    if let Err(err) = request.respond(response) {
+        error!("sending a response: {err}");
+    }
+}
+
+/// How a scan went, as it's named in responses: `complete` or `partial`.
+fn status_name(status: Status) -> &'static str {
+    match status {
+        Status::Complete => "complete",
+        Status::Partial => "partial",
+        Status::Error => "error",
+        Status::NoGrid => "no_grid",
+    }
+}
+
+/// An error in the request itself, with the HTTP status code to send back.
+#[derive(Debug)]
+struct HttpError(u16, String);
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.1)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+/// Scan the upload in `request`, and return the response's content type and
+/// body, and how the scan went. A page whose grid couldn't be found is an
+/// error, since none of its letters can be trusted.
+fn handle(
+    request: &mut Request,
+    args: &ServeArgs,
+    max_upload: u64,
+) -> anyhow::Result<(&'static str, Vec<u8>, Status)> {
+    let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+    if path != "/scan" {
+        bail!(HttpError(404, "not found; POST scans to /scan.".into()));
+    }
+    if *request.method() != Method::Post {
+        bail!(HttpError(405, "scans must be sent with POST.".into()));
+    }
+
+    let (format, settings) = parse_query(query)?;
+
+    let mut upload = Vec::new();
+    request
+        .as_reader()
+        .take(max_upload + 1)
+        .read_to_end(&mut upload)
+        .context("reading the upload")?;
+    if upload.len() as u64 > max_upload {
+        bail!(HttpError(413, "the upload is too large.".into()));
+    }
+    let Some(extension) = inputs::sniff_extension(&upload) else {
+        bail!(HttpError(415, "the upload isn't an image or a PDF.".into()));
+    };
+    info!("Scanning an upload of {} bytes.", upload.len());
+
+    let dir = tempfile::tempdir().context("making a temporary directory")?;
+    let input_file = dir.path().join(format!("upload.{extension}"));
+    std::fs::write(&input_file, &upload).context("saving the upload")?;
+    let output_dir = dir.path().join("letters");
+    std::fs::create_dir(&output_dir).context("making the output directory")?;
+
+    let scan_args =
+        scan_args(&args.scan_args, &settings, &input_file, &output_dir).map_err(|err| {
+            // Only the first line says what's wrong; the rest is advice for
+            // the command line.
+            let message = err.to_string();
+            let first_line = message.lines().next().unwrap_or_default();
+            HttpError(400, first_line.trim_start_matches("error: ").to_string())
+        })?;
+    // `--yes` is always passed, so nobody is asked.
+    let status = scan::scan(scan_args, &|| Ok(true))?;
+    if status == Status::NoGrid {
+        bail!(HttpError(
+            422,
+            "couldn't find the template's grid on the page. Check that the whole grid is in \
+             view, or pass its rows and cols."
+                .into()
+        ));
+    }
+
+    let files = output::list_files(&output_dir)?;
+    let (content_type, body) = match format {
+        ResponseFormat::Json => ("application/json", to_json(&output_dir, &files, status)?),
+        ResponseFormat::Zip => ("application/zip", output::to_zip(&output_dir, &files)?),
+    };
+
+    Ok((content_type, body, status))
+}
+
+/// Read the response format and the scan settings from a query string.
+fn parse_query(query: &str) -> anyhow::Result<(ResponseFormat, Vec<(String, String)>)> {
+    let mut format = ResponseFormat::Json;
+    let mut settings = Vec::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let key = key.replace('_', "-");
+        match (key.as_str(), value) {
+            ("format", "json") => format = ResponseFormat::Json,
+            ("format", "zip") => format = ResponseFormat::Zip,
+            ("format", _) => bail!(HttpError(400, "format must be json or zip.".into())),
+            (key, value) if QUERY_SETTINGS.contains(&key) => {
+                settings.push((key.to_string(), decode_query_value(value)));
+            }
+            _ => bail!(HttpError(
+                400,
+                format!(
+                    "{key} can't be changed; only format, {} can.",
+                    QUERY_SETTINGS.join(", ")
+                )
+            )),
+        }
+    }
+
+    Ok((format, settings))
+}
+
+/// Undo the percent-encoding of a query string value.
+fn decode_query_value(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = tail.get(..2).and_then(|hex| std::str::from_utf8(hex).ok());
+        match (byte, hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            (b'+', _) => {
+                bytes.push(b' ');
+                rest = tail;
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Build the arguments of the `scan` command for one upload: the server's
+/// own, then the upload's settings, then where to read and write.
+fn scan_args(
+    server_args: &[String],
+    settings: &[(String, String)],
+    input_file: &Path,
+    output_dir: &Path,
+) -> Result<scan::ScanArgs, clap::Error> {
+    let mut args = server_args.to_vec();
+    for (key, value) in settings {
+        args.push(format!("--{key}={value}"));
+    }
+    args.extend([
+        "--yes".to_string(),
+        format!("--input-file={}", input_file.display()),
+        format!("--output-dir={}", output_dir.display()),
+    ]);
+    scan::ScanArgs::try_parse_from(args)
+}
+
+/// Describe a scan's letters as JSON: how it went, its manifest, and every
+/// file it saved, encoded as base64.
+fn to_json(dir: &Path, files: &[String], status: Status) -> anyhow::Result<Vec<u8>> {
+    let manifest = match std::fs::read(dir.join(manifest::FILE_NAME)) {
+        Ok(manifest) => serde_json::from_slice(&manifest).context("reading the manifest")?,
+        Err(_) => serde_json::Value::Null,
+    };
+    let mut encoded = BTreeMap::new();
+    for file in files {
+        let contents = std::fs::read(dir.join(file)).with_context(|| format!("reading {file}"))?;
+        encoded.insert(file, STANDARD.encode(contents));
+    }
+    let json = serde_json::json!({
+        "status": status_name(status),
+        "manifest": manifest,
+        "files": encoded,
+    });
+
+    Ok(json.to_string().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn reads_settings_from_the_query() {
+        let (format, settings) = parse_query("format=zip&rows=6&min_ink=0.02").unwrap();
+        assert_eq!(format, ResponseFormat::Zip);
+        assert_eq!(
+            settings,
+            [
+                ("rows".to_string(), "6".to_string()),
+                ("min-ink".to_string(), "0.02".to_string())
+            ]
+        );
+        assert!(parse_query("output-dir=/etc").is_err());
+        assert_eq!(decode_query_value("%23ff0000+x"), "#ff0000 x");
+    }
+
+    #[test]
+    fn refuses_a_page_without_a_grid() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        let args = ServeArgs {
+            address: address.to_string(),
+            max_upload: 50,
+            scan_args: Vec::new(),
+        };
+        let responder = std::thread::spawn(move || {
+            let request = server.incoming_requests().next().unwrap();
+            respond(request, &args, 50 * 1024 * 1024);
+        });
+
+        let mut upload = Vec::new();
+        image::GrayImage::from_pixel(600, 800, image::Luma([255]))
+            .write_to(
+                &mut std::io::Cursor::new(&mut upload),
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "POST /scan HTTP/1.1\r\nHost: {address}\r\nContent-Type: image/png\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            upload.len()
+        )
+        .unwrap();
+        stream.write_all(&upload).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        responder.join().unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 422"), "{response}");
+        assert!(response.contains("couldn't find the template's grid"));
+    }
+}
@@ -0,0 +1,442 @@
+use clap::ValueEnum;
+use image::{GrayImage, Luma};
+use imageproc::distance_transform::euclidean_squared_distance_transform;
+use serde::Serialize;
+
+use crate::vectorize;
+
+/// The offsets of a pixel's eight neighbors, clockwise from the one above
+/// it.
+const NEIGHBORS: [(i32, i32); 8] = [
+    (0, -1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+];
+
+/// How a letterform's centerline is saved, with `--skeleton`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkeletonFormat {
+    /// Thin the letter image itself to lines one pixel wide.
+    Image,
+    /// Save the centerline's strokes as polylines in a JSON file next to the
+    /// letter image, with how wide the stroke is at each point.
+    Json,
+    /// Save the centerline's strokes as unfilled paths in an SVG file next
+    /// to the letter image, for plotters and stroke fonts.
+    Svg,
+}
+
+impl SkeletonFormat {
+    /// The extension of the file saved next to each letter image, which
+    /// replaces the letter image's own, if one is.
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Self::Image => None,
+            Self::Json => Some("strokes.json"),
+            Self::Svg => Some("strokes.svg"),
+        }
+    }
+}
+
+/// A point along a stroke's centerline, in pixel coordinates with the
+/// origin at the top left of the image.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct StrokePoint {
+    pub x: f32,
+    pub y: f32,
+    /// How wide the stroke is here, in pixels, which is wider where the pen
+    /// was pressed harder.
+    pub width: f32,
+}
+
+/// One line of a letterform's centerline, from an end of a stroke or a
+/// place where strokes meet to the next.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Stroke {
+    pub points: Vec<StrokePoint>,
+    /// Whether the stroke runs around a loop back to where it started, like
+    /// an `o`.
+    pub closed: bool,
+}
+
+/// The strokes of a letter image, as saved with `--skeleton json`.
+#[derive(Debug, Serialize)]
+struct Strokes<'a> {
+    width: u32,
+    height: u32,
+    strokes: &'a [Stroke],
+}
+
+/// Thin the ink of a letter image down to its centerline, one pixel wide,
+/// with the Zhang–Suen algorithm. The centerline is black on white, like a
+/// letter image.
+pub fn thin(image: &GrayImage) -> GrayImage {
+    let skeleton = Skeleton::new(image);
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        Luma([if skeleton.is_set(x as i32, y as i32) {
+            0
+        } else {
+            255
+        }])
+    })
+}
+
+/// Trace the centerline of a letter image into strokes. Each stroke is
+/// simplified like [`vectorize::trace`]'s outlines, dropping points closer
+/// than `tolerance` pixels to the line between their neighbors. Short spurs
+/// left by thinning the ends and corners of thick strokes are left out.
+pub fn trace(image: &GrayImage, tolerance: f32) -> Vec<Stroke> {
+    let skeleton = Skeleton::new(image);
+    let paper = GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        Luma([if image.get_pixel(x, y).0[0] < 128 {
+            0
+        } else {
+            255
+        }])
+    });
+    let to_paper = euclidean_squared_distance_transform(&paper);
+    // The centerline is as far from the paper on one side as on the other.
+    let width_at = |(x, y): (i32, i32)| {
+        (to_paper.get_pixel(x as u32, y as u32).0[0].sqrt() as f32 * 2.0 - 1.0).max(1.0)
+    };
+
+    let mut strokes = Vec::new();
+    for (pixels, closed) in skeleton.paths() {
+        let length = pixels.len();
+        let ends_at_junction = |pixel: (i32, i32)| skeleton.degree(pixel) > 2;
+        let (first, last) = (pixels[0], pixels[length - 1]);
+        // A spur runs from a loose end to a junction, and is shorter than
+        // the stroke is wide there.
+        let spur = !closed
+            && ends_at_junction(first) != ends_at_junction(last)
+            && (length as f32) < width_at(if ends_at_junction(first) { first } else { last });
+        if spur {
+            continue;
+        }
+
+        let centers: Vec<vectorize::Point> = pixels
+            .iter()
+            .map(|&(x, y)| (x as f32 + 0.5, y as f32 + 0.5))
+            .collect();
+        let simplified = vectorize::simplify_open(&centers, tolerance);
+        let points = simplified
+            .into_iter()
+            .map(|(x, y)| StrokePoint {
+                x,
+                y,
+                width: width_at((x as i32, y as i32)),
+            })
+            .collect();
+        strokes.push(Stroke { points, closed });
+    }
+
+    strokes
+}
+
+/// Write strokes as JSON, along with the size of the image they were traced
+/// from.
+pub fn to_json(strokes: &[Stroke], width: u32, height: u32) -> String {
+    let strokes = Strokes {
+        width,
+        height,
+        strokes,
+    };
+    serde_json::to_string_pretty(&strokes).expect("strokes can always be serialized")
+}
+
+/// Draw strokes as an SVG image of the given size, each as an unfilled path
+/// as wide as the letterform's strokes are on average.
+pub fn to_svg(strokes: &[Stroke], width: u32, height: u32) -> String {
+    let widths: Vec<f32> = strokes
+        .iter()
+        .flat_map(|stroke| stroke.points.iter().map(|point| point.width))
+        .collect();
+    let stroke_width = if widths.is_empty() {
+        1.0
+    } else {
+        widths.iter().sum::<f32>() / widths.len() as f32
+    };
+    let mut path = String::new();
+    for stroke in strokes {
+        for (i, point) in stroke.points.iter().enumerate() {
+            let command = if i == 0 { 'M' } else { 'L' };
+            path.push_str(&format!("{command}{:.1} {:.1}", point.x, point.y));
+        }
+        // A dot, like the one over an `i`, is drawn as a line of no length.
+        if stroke.points.len() == 1 {
+            path.push_str("l0 0");
+        }
+        if stroke.closed {
+            path.push('Z');
+        }
+    }
+
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\">\n\
+         <path fill=\"none\" stroke=\"black\" stroke-width=\"{stroke_width:.1}\" stroke-linecap=\"round\" stroke-linejoin=\"round\" d=\"{path}\"/>\n\
+         </svg>\n"
+    )
+}
+
+/// The centerline pixels of a letter image.
+struct Skeleton {
+    width: i32,
+    height: i32,
+    pixels: Vec<bool>,
+}
+
+impl Skeleton {
+    fn new(image: &GrayImage) -> Self {
+        let (width, height) = (image.width() as i32, image.height() as i32);
+        let pixels = image.pixels().map(|pixel| pixel.0[0] < 128).collect();
+        let mut skeleton = Self {
+            width,
+            height,
+            pixels,
+        };
+        skeleton.thin();
+        skeleton.remove_redundant();
+        skeleton
+    }
+
+    fn is_set(&self, x: i32, y: i32) -> bool {
+        (0..self.width).contains(&x)
+            && (0..self.height).contains(&y)
+            && self.pixels[(y * self.width + x) as usize]
+    }
+
+    fn set(&mut self, (x, y): (i32, i32), value: bool) {
+        self.pixels[(y * self.width + x) as usize] = value;
+    }
+
+    /// Which of a pixel's neighbors are set, clockwise from the one above.
+    fn neighbors(&self, (x, y): (i32, i32)) -> [bool; 8] {
+        NEIGHBORS.map(|(dx, dy)| self.is_set(x + dx, y + dy))
+    }
+
+    fn degree(&self, pixel: (i32, i32)) -> usize {
+        self.neighbors(pixel).iter().filter(|set| **set).count()
+    }
+
+    fn set_pixels(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        (0..self.height)
+            .flat_map(move |y| (0..self.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| self.is_set(x, y))
+    }
+
+    /// Peel pixels off the edges of the ink, from alternate sides, until
+    /// only lines one pixel wide are left.
+    fn thin(&mut self) {
+        loop {
+            let mut changed = false;
+            for step in 0..2 {
+                let removed: Vec<(i32, i32)> = self
+                    .set_pixels()
+                    .filter(|&pixel| {
+                        let [n, ne, e, se, s, sw, w, nw] = self.neighbors(pixel);
+                        let ring = [n, ne, e, se, s, sw, w, nw, n];
+                        let count = self.degree(pixel);
+                        let transitions =
+                            ring.windows(2).filter(|pair| !pair[0] && pair[1]).count();
+                        let sides = if step == 0 {
+                            !(e && s && (n || w))
+                        } else {
+                            !(n && w && (e || s))
+                        };
+                        (2..=6).contains(&count) && transitions == 1 && sides
+                    })
+                    .collect();
+                changed |= !removed.is_empty();
+                for pixel in removed {
+                    self.set(pixel, false);
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    /// Remove pixels that thinning leaves on the inside corners of
+    /// staircases and where strokes meet, which don't join anything that
+    /// their neighbors don't already join, so that every line is one pixel
+    /// wide and only branches where strokes really meet.
+    fn remove_redundant(&mut self) {
+        loop {
+            let mut changed = false;
+            let pixels: Vec<_> = self.set_pixels().collect();
+            for pixel in pixels {
+                let neighbors = self.neighbors(pixel);
+                let ends_a_line = neighbors.iter().filter(|set| **set).count() <= 1;
+                // Without the pixel, its neighbors must still be joined to
+                // each other, and no hole may open up.
+                if !ends_a_line
+                    && components(neighbors, true) == 1
+                    && components(neighbors, false) == 1
+                {
+                    self.set(pixel, false);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    /// Split the centerline into paths of pixels: from each end or junction
+    /// to the next, and then around each loop that has neither. Returns each
+    /// path with whether it's a loop.
+    fn paths(&self) -> Vec<(Vec<(i32, i32)>, bool)> {
+        let index = |(x, y): (i32, i32)| (y * self.width + x) as usize;
+        let neighbors = |(x, y): (i32, i32)| {
+            NEIGHBORS
+                .iter()
+                .map(move |(dx, dy)| (x + dx, y + dy))
+                .filter(|&(x, y)| self.is_set(x, y))
+        };
+        let mut walked = vec![false; self.pixels.len()];
+        let mut paths = Vec::new();
+        // Follow pixels that continue a line until reaching one that doesn't.
+        let walk = |start: (i32, i32), next: (i32, i32), walked: &mut Vec<bool>| {
+            let mut path = vec![start, next];
+            let (mut previous, mut current) = (start, next);
+            while self.degree(current) == 2 && !walked[index(current)] {
+                walked[index(current)] = true;
+                let Some(following) = neighbors(current).find(|pixel| *pixel != previous) else {
+                    break;
+                };
+                path.push(following);
+                (previous, current) = (current, following);
+                if following == start {
+                    break;
+                }
+            }
+            path
+        };
+
+        let nodes: Vec<_> = self
+            .set_pixels()
+            .filter(|pixel| self.degree(*pixel) != 2)
+            .collect();
+        for &node in &nodes {
+            if self.degree(node) == 0 {
+                paths.push((vec![node], false));
+            }
+            for next in neighbors(node) {
+                // Each line between two nodes is walked from one end only,
+                // and junctions next to each other are one place where
+                // strokes meet.
+                let next_is_node = self.degree(next) != 2;
+                let both_junctions = self.degree(node) > 2 && self.degree(next) > 2;
+                if walked[index(next)]
+                    || both_junctions
+                    || (next_is_node && index(next) < index(node))
+                {
+                    continue;
+                }
+                paths.push((walk(node, next, &mut walked), false));
+            }
+        }
+        for pixel in self.set_pixels() {
+            if walked[index(pixel)] || self.degree(pixel) != 2 {
+                continue;
+            }
+            walked[index(pixel)] = true;
+            let Some(next) = neighbors(pixel).next() else {
+                continue;
+            };
+            let mut path = walk(pixel, next, &mut walked);
+            let closed = path.last() == Some(&pixel);
+            if closed {
+                path.pop();
+            }
+            paths.push((path, closed));
+        }
+
+        paths
+    }
+}
+
+/// Count the separate groups of a pixel's neighbors that are ink, when
+/// `ink`, or paper. Ink joins diagonally, but paper only joins across edges,
+/// and only paper beside the pixel's edges counts.
+fn components(neighbors: [bool; 8], ink: bool) -> usize {
+    let mut group: [usize; 8] = std::array::from_fn(|i| i);
+    fn root(group: &mut [usize; 8], mut i: usize) -> usize {
+        while group[i] != i {
+            i = group[i];
+        }
+        i
+    }
+    for i in 0..8 {
+        let mut join = |j: usize| {
+            if neighbors[i] == ink && neighbors[j] == ink {
+                let (a, b) = (root(&mut group, i), root(&mut group, j));
+                group[a] = b;
+            }
+        };
+        join((i + 1) % 8);
+        // Neighbors above, beside, and below the pixel touch diagonally.
+        if ink && i % 2 == 0 {
+            join((i + 2) % 8);
+        }
+    }
+
+    let mut roots: Vec<usize> = (0..8)
+        .filter(|&i| neighbors[i] == ink && (ink || i % 2 == 0))
+        .map(|i| root(&mut group, i))
+        .collect();
+    roots.sort_unstable();
+    roots.dedup();
+    roots.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use imageproc::{drawing::draw_filled_rect_mut, rect::Rect};
+
+    #[test]
+    fn traces_the_centerlines_of_strokes() {
+        // A thick T: a bar across the top, and a stem down from its middle.
+        let mut image = GrayImage::from_pixel(60, 60, Luma([255]));
+        draw_filled_rect_mut(&mut image, Rect::at(5, 5).of_size(50, 7), Luma([0]));
+        draw_filled_rect_mut(&mut image, Rect::at(27, 5).of_size(7, 50), Luma([0]));
+
+        let thinned = thin(&image);
+        let ink = thinned.pixels().filter(|pixel| pixel.0[0] == 0).count();
+        assert!((80..110).contains(&ink), "{ink} pixels of centerline");
+
+        let strokes = trace(&image, 1.0);
+        assert_eq!(strokes.len(), 3, "{strokes:?}");
+        for stroke in &strokes {
+            assert!(!stroke.closed);
+            for point in &stroke.points {
+                assert!((4.0..=9.0).contains(&point.width), "{point:?}");
+            }
+        }
+        let stem = strokes
+            .iter()
+            .find(|stroke| stroke.points.iter().any(|point| point.y > 50.0))
+            .unwrap();
+        assert!(stem.points.iter().all(|point| (point.x - 31.0).abs() < 2.0));
+    }
+
+    #[test]
+    fn traces_loops() {
+        let mut image = GrayImage::from_pixel(40, 40, Luma([255]));
+        draw_filled_rect_mut(&mut image, Rect::at(5, 5).of_size(30, 30), Luma([0]));
+        draw_filled_rect_mut(&mut image, Rect::at(10, 10).of_size(20, 20), Luma([255]));
+        let strokes = trace(&image, 1.0);
+        assert_eq!(strokes.len(), 1, "{strokes:?}");
+        assert!(strokes[0].closed);
+        assert!(to_svg(&strokes, 40, 40).contains("Z\""));
+    }
+}
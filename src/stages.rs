@@ -0,0 +1,1296 @@
+//! The stages of scanning a page, which `scan` runs one after another:
+//! [`load`] the page and clean it up, [`locate`] its template's grid, [`cut`]
+//! it into cells, and [`save`] each cell's letter image. Each stage takes its
+//! settings in an options struct, so that other tools can run them too, and
+//! look at or change the page between them, the way `scan` does to let a
+//! page's cells be reviewed before they're saved.
+
+use anyhow::{bail, Context};
+use image::{DynamicImage, GenericImageView, GrayImage, SubImage};
+use rayon::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+use tracing::{debug, debug_span, error, info, warn};
+
+use crate::{
+    baseline, canvas,
+    charset::Charset,
+    cleanup, compare,
+    descriptor::{self, Descriptor, Selection},
+    diff, dpi, duplicates, glyph, glyph_metrics, glyph_set, grid, guide_dots,
+    guides::{self, Guides},
+    layout_code, manifest, output, pipeline,
+    profile::Profile,
+    quality,
+    recovery::{self, GridNotFound},
+    template, threshold, upscale,
+};
+
+/// The settings for [`load`].
+#[derive(Clone, Copy, Debug)]
+pub struct LoadOptions<'a> {
+    pub input_file: &'a Path,
+    /// The page to load, if the input file is a PDF.
+    pub pdf_page: Option<u32>,
+    /// Resample pages of PDFs to this resolution, in dots per inch.
+    pub pdf_dpi: Option<u32>,
+    /// The rectangle of the page that holds the cells, if the page isn't a
+    /// template.
+    pub region: Option<Selection>,
+    pub preprocess: pipeline::PreprocessOptions,
+    /// Scale a fixed threshold with the page's resolution.
+    pub scale_threshold_with_dpi: bool,
+    /// Keep the page as it was opened, to threshold it again later.
+    pub keep_original: bool,
+    pub profile: &'a Profile,
+}
+
+/// A page that's been opened and cleaned up by [`load`].
+#[derive(Clone, Debug)]
+pub struct LoadedPage {
+    /// The page, thresholded.
+    pub image: GrayImage,
+    pub report: pipeline::PreprocessReport,
+    /// The settings the page was cleaned up with, with the threshold scaled to
+    /// its resolution.
+    pub options: pipeline::PreprocessOptions,
+    /// The page's resolution, in dots per inch, if it's recorded.
+    pub dpi: Option<f32>,
+    /// The bounds of [`LoadOptions::region`] on the page, as fractions of its
+    /// width and height: `[left, top, right, bottom]`.
+    pub region: Option<[f32; 4]>,
+    /// The page as it was opened, if [`LoadOptions::keep_original`] was set.
+    pub original: Option<DynamicImage>,
+}
+
+/// Open a page and clean it up with [`pipeline::preprocess_with_profile`].
+pub fn load(options: &LoadOptions) -> anyhow::Result<LoadedPage> {
+    info!("Loading image...");
+    let (image, input_dpi) =
+        pipeline::open_input(options.input_file, options.pdf_page, options.pdf_dpi)?;
+    // A region is measured on the input image, so it's placed once the image
+    // is open.
+    let region = match options.region {
+        Some(selection) => Some(selection.bounds(image.width(), image.height())?),
+        None => None,
+    };
+
+    let threshold = match options.preprocess.threshold {
+        threshold::Threshold::Fixed(threshold) if options.scale_threshold_with_dpi => {
+            threshold::Threshold::Fixed(match input_dpi {
+                Some(dpi) => {
+                    let scaled = dpi::scale_threshold(threshold, dpi);
+                    info!(
+                        "Input is {dpi:.0} DPI; using threshold {scaled} instead of {threshold}."
+                    );
+                    scaled
+                }
+                None => {
+                    warn!(
+                        "input_file doesn't record its DPI; using threshold {threshold} unscaled."
+                    );
+                    threshold
+                }
+            })
+        }
+        threshold::Threshold::Auto if options.scale_threshold_with_dpi => {
+            warn!("the threshold is chosen automatically; ignoring scale_threshold_with_dpi.");
+            threshold::Threshold::Auto
+        }
+        threshold => threshold,
+    };
+    let preprocess = pipeline::PreprocessOptions {
+        threshold,
+        ..options.preprocess
+    };
+
+    info!("Scanning handwriting...");
+    let original = options.keep_original.then(|| image.clone());
+    let (image, report) = pipeline::preprocess_with_profile(image, preprocess, options.profile)?;
+
+    Ok(LoadedPage {
+        image,
+        report,
+        options: preprocess,
+        dpi: input_dpi,
+        region,
+        original,
+    })
+}
+
+/// The settings for [`locate`].
+#[derive(Clone, Copy, Debug)]
+pub struct LocateOptions<'a> {
+    /// The template that's expected on the page.
+    pub descriptor: &'a Descriptor,
+    /// Read the template's layout code, and use the grid it describes instead
+    /// of `descriptor`.
+    pub layout_code: bool,
+    /// The margins around the grid of a layout code.
+    pub margins: grid::Margins,
+    /// The characters of every cell, unless the layout code has its own.
+    pub charset: &'a Charset,
+    /// The position in `charset` of the page's first cell.
+    pub first_cell: usize,
+    /// Where an image of the lines that were found is saved, if the grid
+    /// can't be found.
+    pub output_dir: &'a Path,
+}
+
+/// A page whose template's grid was found by [`locate`].
+#[derive(Clone, Debug)]
+pub struct LocatedPage {
+    /// The page, read again another way if its grid was only found then.
+    pub page: LoadedPage,
+    /// The template that was found on the page.
+    pub descriptor: Descriptor,
+    /// The way the page was read again to find its grid, if it was.
+    pub attempt: Option<recovery::Attempt>,
+    pub located: descriptor::Located,
+    /// The template's cells that hold letters.
+    pub cells: Vec<descriptor::Cell>,
+    /// The characters of the page's cells.
+    pub charset: Charset,
+    /// The position in `charset` of the page's first cell.
+    pub charset_offset: usize,
+}
+
+impl LocatedPage {
+    /// The character in each cell, if the charset has one for it.
+    pub fn labels(&self) -> Vec<Option<String>> {
+        (0..self.cells.len())
+            .map(|i| self.charset.text(self.charset_offset + i).map(String::from))
+            .collect()
+    }
+}
+
+/// Find the template's grid on a page. If it can't be found, the page is
+/// opened again with `load`'s options and read each of the ways in
+/// [`recovery::Attempt::ALL`], and if it still can't be found and the
+/// template has no margins to cut inside instead, [`GridNotFound`] is
+/// returned.
+pub fn locate(
+    page: LoadedPage,
+    load: &LoadOptions,
+    options: &LocateOptions,
+) -> anyhow::Result<LocatedPage> {
+    let layout = if options.layout_code {
+        debug_span!("layout_code").in_scope(|| layout_code::read(&page.image))
+    } else {
+        None
+    };
+    let descriptor = match &layout {
+        Some(layout) => {
+            info!(
+                "Read the template's layout code: {} × {} cells{}.",
+                layout.cols,
+                layout.rows,
+                if layout.charset.is_some() {
+                    ", with a charset"
+                } else {
+                    ""
+                }
+            );
+            Descriptor::grid(layout.rows, layout.cols, options.margins)
+        }
+        None => options.descriptor.clone(),
+    };
+    // A charset from the layout code is the page's own, so it starts at the
+    // page's first cell.
+    let (charset, charset_offset) = match layout.and_then(|layout| layout.charset) {
+        Some(charset) => (Charset::parse(&charset), 0),
+        None => (options.charset.clone(), options.first_cell),
+    };
+
+    let located = descriptor.locate(&page.image);
+    let (page, located, attempt) = if located
+        .missing
+        .iter()
+        .any(|&i| !descriptor.regions[i].is_placed())
+    {
+        let (attempt, image, report, located) = debug_span!("recovery")
+            .in_scope(|| recover_grid(load, options, &descriptor, (&page.image, &located)))?;
+        let page = LoadedPage {
+            image,
+            report,
+            options: attempt.options(page.options),
+            // The page is thresholded again the way its grid was found.
+            original: page.original.map(|original| attempt.apply(original)),
+            ..page
+        };
+        (page, located, Some(attempt))
+    } else {
+        (page, located, None)
+    };
+    warn_missing_grids(&descriptor, &located.missing, None);
+    if located.missing.is_empty() && located.guessed() > 0 {
+        warn!(
+            "{} of the grid's lines couldn't be found, and were placed where they were expected.",
+            located.guessed()
+        );
+    }
+    let cells = descriptor.cells(&located.grids);
+    if cells
+        .iter()
+        .any(|cell| cell.area.2 == 0 || cell.area.3 == 0)
+    {
+        let (rows, cols) = descriptor.size();
+        bail!("the image is too small to hold {cols} × {rows} cells.");
+    }
+
+    Ok(LocatedPage {
+        page,
+        descriptor,
+        attempt,
+        located,
+        cells,
+        charset,
+        charset_offset,
+    })
+}
+
+/// Read a page whose grid couldn't be found again, each of the ways in
+/// [`recovery::Attempt::ALL`], until its grid is found. Returns the attempt
+/// that found it, the page, read that way, and the grids found on it. If the
+/// grid is never found, an image of what was found on the `first` try, the
+/// thresholded page and its grids, is saved, and [`GridNotFound`] is
+/// returned.
+fn recover_grid(
+    load: &LoadOptions,
+    options: &LocateOptions,
+    descriptor: &Descriptor,
+    first: (&GrayImage, &descriptor::Located),
+) -> anyhow::Result<(
+    recovery::Attempt,
+    GrayImage,
+    pipeline::PreprocessReport,
+    descriptor::Located,
+)> {
+    info!("Couldn't find the grid lines; trying to read the page other ways...");
+    // The page is loaded again, rather than kept, since it's rarely needed.
+    let (original, _) = pipeline::open_input(load.input_file, load.pdf_page, load.pdf_dpi)?;
+    for attempt in recovery::Attempt::ALL {
+        let image = attempt.apply(original.clone());
+        let preprocess = attempt.options(load.preprocess);
+        let (image, report) = pipeline::preprocess_with_profile(image, preprocess, load.profile)?;
+        let located = descriptor.locate(&image);
+        if located
+            .missing
+            .iter()
+            .all(|&i| descriptor.regions[i].is_placed())
+        {
+            info!("Found the grid by reading the page {attempt}.");
+            return Ok((attempt, image, report, located));
+        }
+        debug!("Couldn't find the grid by reading the page {attempt}.");
+    }
+
+    let (image, located) = first;
+    let path = options.output_dir.join("grid-not-found.png");
+    let saved = std::fs::create_dir_all(options.output_dir)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| Ok(recovery::debug_image(image, located).save(&path)?));
+    if let Err(err) = &saved {
+        warn!("couldn't save {}: {err:#}", path.display());
+    }
+    Err(GridNotFound {
+        debug_image: saved.ok().map(|_| path),
+    }
+    .into())
+}
+
+/// Warn that the grid lines of the `missing` regions of a template couldn't be
+/// found on a page, thresholded at `level` if it's one of several.
+pub fn warn_missing_grids(descriptor: &Descriptor, missing: &[usize], level: Option<u8>) {
+    if missing.is_empty() {
+        return;
+    }
+    let at = level
+        .map(|level| format!(" at threshold {level}"))
+        .unwrap_or_default();
+    if let [region] = &descriptor.regions[..] {
+        let margins = match (region.bounds, region.margin_x, region.margin_y) {
+            (None, None, None) => "the default template margins",
+            _ => "the given margins",
+        };
+        warn!("couldn't find the grid lines{at}; assuming {margins}.");
+    } else {
+        let regions: Vec<String> = missing.iter().map(|i| (i + 1).to_string()).collect();
+        warn!(
+            "couldn't find the grid lines of regions {}{at}; assuming their margins.",
+            regions.join(", ")
+        );
+    }
+}
+
+/// How much bigger to make letter images so that a cell `cell_size` inches
+/// tall comes out `target_height` pixels tall, judged from the page's
+/// resolution, or from the size of its cells if it isn't recorded. Returns
+/// `None` if neither says how big the page is.
+pub fn glyph_scale(page: &LocatedPage, target_height: u32, cell_size: f32) -> Option<f32> {
+    // Once a photo's perspective is corrected, the resolution it records no
+    // longer applies.
+    let page_dpi = page
+        .page
+        .dpi
+        .filter(|_| !page.page.options.camera)
+        .or_else(|| {
+            let cell_heights: Vec<u32> = page.cells.iter().map(|cell| cell.area.3).collect();
+            let dpi = dpi::estimate_from_cells(&cell_heights, cell_size)?;
+            info!("The page looks like it was scanned at about {dpi:.0} DPI.");
+            Some(dpi)
+        });
+    page_dpi.map(|dpi| target_height as f32 / (dpi * cell_size))
+}
+
+/// The settings for [`cut`].
+#[derive(Clone, Copy, Debug)]
+pub struct CutOptions<'a> {
+    /// How much of a cell must be ink for it to hold a letterform, from 0 to
+    /// 1.
+    pub min_ink: f32,
+    /// An earlier scan of the same page. Letterforms that look the same on it
+    /// are marked unchanged.
+    pub baseline: Option<&'a Path>,
+    /// How alike a cell must be to the baseline's to be unchanged, from 0 to
+    /// 1.
+    pub baseline_similarity: f32,
+    pub pdf_dpi: Option<u32>,
+    pub profile: &'a Profile,
+}
+
+/// A page's cells, cut out by [`cut`].
+#[derive(Clone)]
+pub struct CutPage<'a> {
+    /// Each cell, as a view into the page.
+    pub letter_images: Vec<SubImage<&'a GrayImage>>,
+    /// How much of each cell is ink, from 0 to 1.
+    pub coverage: Vec<f32>,
+    /// Which cells have too little ink to hold a letterform.
+    pub empty: Vec<bool>,
+    /// Which cells look the same as the baseline's.
+    pub unchanged: Vec<bool>,
+    /// The character in each cell. It can be changed before the cells are
+    /// saved.
+    pub labels: Vec<Option<String>>,
+    /// Which cells were left out by hand, and won't be saved.
+    pub rejected: Vec<bool>,
+}
+
+/// Cut a page into its cells, and find which of them are empty, or unchanged
+/// from a baseline scan.
+pub fn cut<'a>(page: &'a LocatedPage, options: &CutOptions) -> anyhow::Result<CutPage<'a>> {
+    let cut_span = debug_span!("cut").entered();
+    let image = &page.page.image;
+    let areas: Vec<(u32, u32, u32, u32)> = page.cells.iter().map(|cell| cell.area).collect();
+    let letter_images = pipeline::cut_areas(page.page.report.pen.as_ref().unwrap_or(image), &areas);
+    let coverage: Vec<f32> = letter_images
+        .par_iter()
+        .map(|letter_image| glyph::ink_coverage(&**letter_image))
+        .collect();
+    drop(cut_span);
+    let empty: Vec<bool> = coverage
+        .iter()
+        .map(|coverage| *coverage < options.min_ink)
+        .collect();
+    info!(
+        "Scan complete; {} letterforms were detected.",
+        empty.iter().filter(|empty| !**empty).count()
+    );
+    if empty.contains(&true) {
+        let empty = indices_where(&empty);
+        info!(
+            "{} cells are empty and will be skipped: {}",
+            empty.len(),
+            empty.join(", ")
+        );
+    }
+
+    let mut unchanged = vec![false; letter_images.len()];
+    if let Some(baseline) = options.baseline {
+        let _span = debug_span!("baseline").entered();
+        info!("Comparing with the baseline scan...");
+        let (baseline_image, _) = pipeline::open_input(baseline, None, options.pdf_dpi)?;
+        let preprocess = pipeline::PreprocessOptions {
+            grayscale: false,
+            pen_color: None,
+            straightened: false,
+            ..page.page.options
+        };
+        let (mut baseline_image, _) =
+            pipeline::preprocess_with_profile(baseline_image, preprocess, options.profile)?;
+        if baseline_image.dimensions() != image.dimensions() {
+            baseline_image = image::imageops::resize(
+                &baseline_image,
+                image.width(),
+                image.height(),
+                image::imageops::FilterType::Triangle,
+            );
+        }
+        let (dx, dy) = baseline::estimate_offset(image, &baseline_image);
+        for (i, letter_image) in letter_images.iter().enumerate() {
+            if empty[i] {
+                continue;
+            }
+            let (x, y) = letter_image.offsets();
+            let similarity = baseline::cell_similarity(
+                &**letter_image,
+                &baseline_image,
+                x as i64 + dx,
+                y as i64 + dy,
+            );
+            unchanged[i] = similarity >= options.baseline_similarity;
+        }
+        let skipped = indices_where(&unchanged);
+        info!(
+            "{} letterforms are unchanged from the baseline and will be skipped: {}",
+            skipped.len(),
+            skipped.join(", ")
+        );
+    }
+
+    Ok(CutPage {
+        rejected: vec![false; letter_images.len()],
+        labels: page.labels(),
+        letter_images,
+        coverage,
+        empty,
+        unchanged,
+    })
+}
+
+/// How a cell is cleaned up before its letterform is found.
+#[derive(Clone, Copy, Debug)]
+pub struct CleanOptions<'a> {
+    /// The guide dots printed in every cell, which are masked out.
+    pub guide_dots: &'a [guide_dots::GuideDot],
+    /// Leave the grid lines along the cell's edges.
+    pub keep_grid_lines: bool,
+    /// The profile whose cell stages are applied.
+    pub profile: &'a Profile,
+    /// Remove specks of ink smaller than this many pixels.
+    pub despeckle: Option<u32>,
+    /// Open, then close, the ink with these radii, in pixels.
+    pub open: Option<u8>,
+    pub close: Option<u8>,
+}
+
+impl CleanOptions<'_> {
+    /// Everything that's done to a cell before its letterform is found.
+    pub fn clean(&self, letter_image: &SubImage<&GrayImage>) -> GrayImage {
+        let mut letter_image = letter_image.to_image();
+        guide_dots::mask_guide_dots(&mut letter_image, self.guide_dots);
+        if !self.keep_grid_lines {
+            glyph::remove_grid_lines(&mut letter_image);
+        }
+        self.profile.apply_to_cell(&mut letter_image);
+        if let Some(min_size) = self.despeckle {
+            cleanup::despeckle(&mut letter_image, min_size);
+        }
+        if let Some(radius) = self.open {
+            cleanup::open(&mut letter_image, radius);
+        }
+        if let Some(radius) = self.close {
+            cleanup::close(&mut letter_image, radius);
+        }
+        letter_image
+    }
+}
+
+/// The settings for [`save`].
+#[derive(Clone, Copy, Debug)]
+pub struct SaveOptions<'a> {
+    pub output_dir: &'a Path,
+    /// The page's file, which letter images kept with `skip_existing` must be
+    /// newer than.
+    pub input_file: &'a Path,
+    /// The page's position among the pages being scanned, counted from 1.
+    pub number: usize,
+    /// The position among every page's cells of the page's first cell.
+    pub first_cell: usize,
+    pub files: pipeline::SaveOptions,
+    pub clean: CleanOptions<'a>,
+    /// Name letter images with this, instead of by their characters.
+    pub name_format: Option<&'a output::NameFormat>,
+    /// Add this to the end of every letter image's name.
+    pub suffix: Option<&'a str>,
+    /// What to do when two cells would be saved with the same name.
+    pub on_conflict: output::OnConflict,
+    /// Replace letter images that are already in `output_dir`.
+    pub force: bool,
+    /// Keep letter images in `output_dir` that are newer than the page.
+    pub skip_existing: bool,
+    /// Keep letter images in `output_dir` that look like the new ones, and
+    /// replace the rest.
+    pub update: bool,
+    /// How alike letterforms must be for `update` to keep them, from 0 to 1.
+    pub update_similarity: f32,
+    /// The letter images are being added to an earlier scan's, so the ones
+    /// already in `output_dir` are expected.
+    pub merging: bool,
+    /// Follow letterforms that run into the edges of their cells past them.
+    pub expand_clipped: bool,
+    /// Make every letterform's strokes this many pixels wide, or as wide as
+    /// the page's middling strokes if it's `Some(None)`.
+    pub normalize_strokes: Option<Option<f32>>,
+    /// Keep the shades of gray along the edges of strokes.
+    pub grayscale: bool,
+    /// Make letter images this many times bigger, to reach a target height.
+    pub scale: Option<f32>,
+    /// Make letter images this many times bigger, smoothing their edges.
+    pub upscale: Option<f32>,
+    /// Place each letterform on a canvas of this size instead of cropping.
+    pub canvas: Option<canvas::Canvas>,
+    pub align: canvas::Align,
+    /// Crop each letter image to its letterform.
+    pub crop: bool,
+    /// The space left around a letterform when it's cropped or placed.
+    pub padding: u32,
+    /// Pad letter images to powers of two wide and tall.
+    pub pot: bool,
+}
+
+/// Save the letter images of every cell of a page that holds a letterform, and
+/// add the page's `entry`, and an entry for every cell, to `manifest`. The
+/// names of the letter images that have been saved so far are kept in
+/// `saved`, so that conflicting names can be resolved. Returns the cells whose
+/// letter images couldn't be saved.
+pub fn save(
+    page: &LocatedPage,
+    cut: &CutPage,
+    mut entry: manifest::PageEntry,
+    options: &SaveOptions,
+    saved: &mut HashSet<String>,
+    manifest: &mut manifest::Manifest,
+) -> anyhow::Result<Vec<usize>> {
+    let &SaveOptions {
+        output_dir,
+        input_file,
+        number,
+        first_cell,
+        files,
+        clean,
+        name_format,
+        suffix,
+        on_conflict,
+        force,
+        skip_existing,
+        update,
+        update_similarity,
+        merging,
+        expand_clipped,
+        normalize_strokes,
+        grayscale,
+        scale,
+        upscale,
+        canvas,
+        align,
+        crop,
+        padding,
+        pot,
+    } = options;
+    let CutPage {
+        letter_images,
+        labels,
+        ..
+    } = cut;
+    let letter_cells = &page.cells;
+    let report = &page.page.report;
+    let output_format = files.output_format;
+    std::fs::create_dir_all(output_dir).context("creating output dir")?;
+
+    // Names are chosen in order, so that conflicts are resolved the same way
+    // every time, and then the images are saved in parallel.
+    let mut statuses = vec![manifest::GlyphStatus::Saved; letter_images.len()];
+    let mut file_stems: Vec<Option<String>> = vec![None; letter_images.len()];
+    // Every file that saving a letter image can write.
+    let mut extensions = vec![output_format.extension()];
+    if files.fallback_to_png {
+        extensions.push("png");
+    }
+    if files.vectorize {
+        extensions.push("svg");
+    }
+    extensions.extend(files.skeleton.and_then(|skeleton| skeleton.extension()));
+    let mut existing = Vec::new();
+    // Letter images kept from an earlier scan, with `skip_existing`.
+    let mut kept_files: Vec<Option<String>> = vec![None; letter_images.len()];
+    let input_modified = skip_existing
+        .then(|| output::modified(input_file))
+        .flatten();
+    for i in 0..letter_images.len() {
+        let cell = first_cell + i;
+        statuses[i] = if cut.empty[i] {
+            manifest::GlyphStatus::Empty
+        } else if cut.unchanged[i] {
+            manifest::GlyphStatus::Unchanged
+        } else if cut.rejected[i] {
+            manifest::GlyphStatus::Rejected
+        } else {
+            manifest::GlyphStatus::Saved
+        };
+        if statuses[i] != manifest::GlyphStatus::Saved {
+            continue;
+        }
+        let file_stem = match (&labels[i], name_format) {
+            (Some(text), Some(name_format)) => {
+                // Earlier pages' samples count too, if they're merged.
+                let sample = (0..page.charset_offset)
+                    .filter(|&j| page.charset.text(j) == Some(text))
+                    .count()
+                    + labels[..=i]
+                        .iter()
+                        .filter(|label| label.as_ref() == Some(text))
+                        .count();
+                name_format.file_stem(&output::CellName {
+                    text,
+                    row: letter_cells[i].row,
+                    col: letter_cells[i].col,
+                    page: number,
+                    index: cell,
+                    sample,
+                })
+            }
+            (Some(text), None) => crate::charset::text_file_stem(text),
+            (None, _) => format!("letter-{cell}"),
+        };
+        let file_stem = match suffix {
+            Some(suffix) => format!("{file_stem}{suffix}"),
+            None => file_stem,
+        };
+        let file_stem = if saved.contains(&file_stem) {
+            match on_conflict {
+                output::OnConflict::First => {
+                    warn!("{file_stem} was already saved; skipping cell {i}.");
+                    statuses[i] = manifest::GlyphStatus::Conflict;
+                    continue;
+                }
+                output::OnConflict::Last => {
+                    warn!("replacing {file_stem} with cell {i}.");
+                    manifest.replace(&file_stem);
+                    // An earlier cell of this page isn't saved at all.
+                    if let Some(j) = file_stems
+                        .iter()
+                        .position(|stem| stem.as_ref() == Some(&file_stem))
+                    {
+                        file_stems[j] = None;
+                        statuses[j] = manifest::GlyphStatus::Replaced;
+                    }
+                    file_stem
+                }
+                output::OnConflict::Both => (2..)
+                    .map(|n| format!("{file_stem}-{n}"))
+                    .find(|stem| !saved.contains(stem))
+                    .expect("there's always an unused name"),
+                output::OnConflict::Alternates => (1..)
+                    .map(|n| format!("{file_stem}.alt{n}"))
+                    .find(|stem| !saved.contains(stem))
+                    .expect("there's always an unused name"),
+            }
+        } else {
+            file_stem
+        };
+        // Names already in `saved` were written by this run.
+        let kept_file = format!("{file_stem}.{}", output_format.extension());
+        if skip_existing
+            && !saved.contains(&file_stem)
+            && output::is_newer(&output_dir.join(&kept_file), input_modified)
+        {
+            statuses[i] = manifest::GlyphStatus::Existing;
+            kept_files[i] = Some(kept_file);
+            saved.insert(file_stem);
+            continue;
+        }
+        if !force && !skip_existing && !update && !merging && !saved.contains(&file_stem) {
+            existing.extend(
+                extensions
+                    .iter()
+                    .map(|extension| format!("{file_stem}.{extension}"))
+                    .filter(|file| output_dir.join(file).exists()),
+            );
+        }
+        saved.insert(file_stem.clone());
+        file_stems[i] = Some(file_stem);
+    }
+    if let Some(first) = existing.first() {
+        bail!(
+            "{} files in {} would be replaced, like {first}. Pass --force to replace \
+             them, --suffix to save next to them, or --timestamp to save in a new \
+             subdirectory.",
+            existing.len(),
+            output_dir.display()
+        );
+    }
+
+    let mut cleaned: Vec<Option<GrayImage>> = letter_images
+        .par_iter()
+        .zip(&file_stems)
+        .map(|(letter_image, file_stem)| file_stem.as_ref().map(|_| clean.clean(letter_image)))
+        .collect();
+    // Letterforms that run into the edge of their cell may have been cut off.
+    // With `expand_clipped`, they're followed past it.
+    let clipped: Vec<bool> = cleaned
+        .par_iter()
+        .map(|cell| {
+            cell.as_ref().is_some_and(|cell| {
+                glyph::ink_bounds(cell)
+                    .is_some_and(|bounds| glyph::touches_edge(cell.dimensions(), bounds))
+            })
+        })
+        .collect();
+    let page_image = report.pen.as_ref().unwrap_or(&page.page.image);
+    let grid_cells: Vec<(u32, u32, u32, u32)> = letter_cells.iter().map(|cell| cell.area).collect();
+    let mut areas = grid_cells.clone();
+    if expand_clipped {
+        cleaned
+            .par_iter_mut()
+            .zip(&mut areas)
+            .zip(&clipped)
+            .filter(|(_, clipped)| **clipped)
+            .for_each(|((cell, area), _)| {
+                let Some(cell) = cell else { return };
+                let Some(bounds) = glyph::ink_bounds(cell) else {
+                    return;
+                };
+                let expanded = glyph::expand_cell(page_image.dimensions(), *area, bounds);
+                *cell = glyph::follow_past_edges(page_image, *area, cell, expanded);
+                *area = expanded;
+            });
+    }
+    let stroke_widths: Vec<Option<f32>> = match normalize_strokes {
+        Some(_) => cleaned
+            .par_iter()
+            .map(|cell| cell.as_ref().and_then(cleanup::stroke_width))
+            .collect(),
+        None => vec![None; cleaned.len()],
+    };
+    let stroke_target = match normalize_strokes {
+        Some(Some(target)) => Some(target),
+        Some(None) => {
+            let mut widths: Vec<f32> = stroke_widths.iter().flatten().copied().collect();
+            widths.sort_by(f32::total_cmp);
+            widths.get(widths.len() / 2).copied()
+        }
+        None => None,
+    };
+    if let Some(target) = stroke_target {
+        info!("Normalizing strokes to {target} pixels wide.");
+        entry.preprocessing.stroke_width = Some(target);
+    }
+
+    // How many times bigger letter images are made than their cells.
+    let resize = scale.unwrap_or(1.0) * upscale.unwrap_or(1.0);
+    // The template's guide lines, found in each cell that's saved.
+    let cell_guides: Vec<Option<Guides>> = match &report.straightened {
+        Some(page) => grid_cells
+            .par_iter()
+            .zip(&file_stems)
+            .map(|(&(x, y, width, height), file_stem)| {
+                file_stem.as_ref()?;
+                guides::find(&*page.view(x, y, width, height), report.threshold)
+            })
+            .collect(),
+        None => vec![None; grid_cells.len()],
+    };
+    let found = cell_guides.iter().flatten().count();
+    if found > 0 {
+        info!(
+            "Found the template's baseline guide in {found} of {} cells.",
+            file_stems.iter().flatten().count()
+        );
+    }
+
+    let finished: Vec<_> = cleaned
+        .into_par_iter()
+        .zip(&file_stems)
+        .zip(&stroke_widths)
+        .enumerate()
+        .map(|(i, ((letter_image, file_stem), stroke_width))| {
+            let (mut letter_image, file_stem) = (letter_image?, file_stem.as_ref()?);
+            let (x, y, width, height) = areas[i];
+            // The guides are followed through every step that moves or resizes
+            // the letter image.
+            let mut guides = cell_guides[i]
+                .map(|guides| guides.transform(1.0, grid_cells[i].1 as f32 - y as f32));
+            let soft = report
+                .grayscale
+                .as_ref()
+                .map(|soft| soft.view(x, y, width, height));
+            let quality = soft
+                .as_ref()
+                .and_then(|soft| quality::measure(&letter_image, &**soft));
+            let fingerprint = duplicates::Fingerprint::of(&letter_image);
+            if let (Some(width), Some(target)) = (*stroke_width, stroke_target) {
+                cleanup::normalize_stroke_width(&mut letter_image, width, target);
+            }
+            if let (true, Some(soft)) = (grayscale, &soft) {
+                letter_image = glyph::restore_grayscale(&letter_image, &**soft);
+            }
+            if let Some(scale) = scale {
+                letter_image = glyph::scale(&letter_image, scale, grayscale);
+            }
+            if let Some(factor) = upscale {
+                letter_image = upscale::upscale(&letter_image, factor, grayscale);
+            }
+            guides = guides.map(|guides| guides.transform(resize, 0.0));
+            let bounds = glyph::ink_bounds(&letter_image);
+            // Where the cell's guide lines are, or, if they weren't found,
+            // where the template puts them.
+            let cell_top = (grid_cells[i].1 as f32 - y as f32) * resize;
+            let cell_height = grid_cells[i].3 as f32 * resize;
+            let baseline = guides.map_or(
+                cell_top + cell_height * template::BASELINE_POSITION,
+                |guides| guides.baseline,
+            );
+            let x_height = guides.and_then(|guides| guides.x_height).unwrap_or(
+                baseline
+                    - cell_height * (template::BASELINE_POSITION - template::X_HEIGHT_POSITION),
+            );
+            let metrics = bounds
+                .map(|bounds| glyph_metrics::measure(&letter_image, bounds, baseline, x_height));
+            let still_clipped =
+                bounds.is_some_and(|bounds| glyph::touches_edge(letter_image.dimensions(), bounds));
+            if let Some(canvas) = canvas {
+                (letter_image, guides) =
+                    canvas::place(&letter_image, bounds, canvas, align, padding, guides);
+            } else if let (true, Some(bounds)) = (crop, bounds) {
+                letter_image = glyph::crop_to(&letter_image, bounds, padding);
+                guides =
+                    guides.map(|guides| guides.transform(1.0, padding as f32 - bounds.1 as f32));
+            }
+            let pot_padding = pot.then(|| {
+                let (width, height) = letter_image.dimensions();
+                let (padded, (left, top)) = glyph::pad_to_power_of_two(&letter_image);
+                letter_image = padded;
+                guides = guides.map(|guides| guides.transform(1.0, top as f32));
+                manifest::PotPadding {
+                    left,
+                    top,
+                    width,
+                    height,
+                }
+            });
+            // With `update`, a letterform that looks like the one an earlier
+            // scan saved is left as it is.
+            let previous = format!("{file_stem}.{}", output_format.extension());
+            let change = update.then(|| match image::open(output_dir.join(&previous)) {
+                Ok(before) => {
+                    let before =
+                        compare::normalize_glyph(&pipeline::flatten(&before), 128, UPDATE_SIZE);
+                    let after = compare::normalize_glyph(&letter_image, 128, UPDATE_SIZE);
+                    let change = diff::measure_change(file_stem, &before, &after);
+                    if change.iou >= update_similarity {
+                        Update::Same
+                    } else {
+                        Update::Changed
+                    }
+                }
+                Err(_) => Update::Added,
+            });
+            Some(Finished {
+                letter_image,
+                bounds,
+                quality,
+                still_clipped,
+                fingerprint,
+                guides,
+                metrics,
+                pot_padding,
+                change,
+            })
+        })
+        .collect();
+    let to_save: Vec<(usize, &GrayImage, &str)> = finished
+        .iter()
+        .zip(&file_stems)
+        .enumerate()
+        .filter_map(|(i, (finished, file_stem))| {
+            let (finished, file_stem) = (finished.as_ref()?, file_stem.as_deref()?);
+            (finished.change != Some(Update::Same)).then_some((
+                i,
+                &finished.letter_image,
+                file_stem,
+            ))
+        })
+        .collect();
+    let glyphs: Vec<(&GrayImage, &str)> = to_save
+        .iter()
+        .map(|&(_, letter_image, file_stem)| (letter_image, file_stem))
+        .collect();
+    let mut saved_files: Vec<Option<anyhow::Result<String>>> =
+        (0..letter_images.len()).map(|_| None).collect();
+    for ((i, ..), saved_file) in to_save
+        .iter()
+        .zip(pipeline::save_glyphs(&glyphs, output_dir, files))
+    {
+        saved_files[*i] = Some(saved_file);
+    }
+
+    manifest.pages.push(entry);
+    let mut failures = Vec::new();
+    let mut updates: HashMap<Update, Vec<String>> = HashMap::new();
+    for (i, ((letter_image, finished), saved_file)) in letter_images
+        .iter()
+        .zip(finished)
+        .zip(saved_files)
+        .enumerate()
+    {
+        let (x, y) = letter_image.offsets();
+        let mut entry = manifest::GlyphEntry {
+            page: manifest.pages.len() - 1,
+            cell: first_cell + i,
+            row: letter_cells[i].row,
+            col: letter_cells[i].col,
+            character: labels[i].clone(),
+            file: kept_files[i].take(),
+            alternate: None,
+            status: statuses[i],
+            cell_bounds: (x, y, letter_image.width(), letter_image.height()).into(),
+            ink_bounds: None,
+            ink_coverage: cut.coverage[i],
+            guides: None,
+            metrics: None,
+            pot_padding: None,
+            stroke_width: stroke_widths[i],
+            clipped: clipped[i],
+            expanded_bounds: None,
+            quality: None,
+            issues: Vec::new(),
+            duplicate_of: None,
+            fingerprint: None,
+        };
+        if let Some(finished) = finished {
+            entry.fingerprint = finished.fingerprint;
+            entry.guides = finished.guides;
+            entry.metrics = finished.metrics;
+            entry.pot_padding = finished.pot_padding;
+            let name = cell_name(entry.row, entry.col, entry.character.as_deref());
+            if let Some(quality) = finished.quality {
+                entry.issues = quality::issues(&quality, letter_image.height());
+                for issue in &entry.issues {
+                    warn!("{name} {issue}; consider rescanning.");
+                }
+                entry.quality = Some(quality);
+            }
+            let (x, y, width, height) = areas[i];
+            if (x, y, width, height) != grid_cells[i] {
+                entry.expanded_bounds = Some((x, y, width, height).into());
+            }
+            if finished.still_clipped && !expand_clipped {
+                warn!(
+                    "{name} runs into the edge of its cell and may be cut off; pass \
+                     --expand-clipped to follow it past the edge."
+                );
+            } else if finished.still_clipped {
+                warn!("{name} runs past the area around its cell and may still be cut off.");
+            }
+            // The letterform was found in the resized letter image, so its
+            // rectangle is sized back down to the page.
+            let on_page = |length: u32| (length as f32 / resize).round() as u32;
+            entry.ink_bounds = finished.bounds.map(|(ink_x, ink_y, width, height)| {
+                let (ink_x, ink_y) = (on_page(ink_x), on_page(ink_y));
+                (x + ink_x, y + ink_y, on_page(width), on_page(height)).into()
+            });
+            let change = finished.change;
+            let saved_file = match saved_file {
+                Some(saved_file) => saved_file,
+                // Letter images that look the same are kept as they are.
+                None => Ok(format!(
+                    "{}.{}",
+                    file_stems[i].as_deref().unwrap_or_default(),
+                    output_format.extension()
+                )),
+            };
+            match saved_file {
+                Ok(file) => {
+                    if let Some(change) = change {
+                        updates.entry(change).or_default().push(file.clone());
+                    }
+                    if change == Some(Update::Same) {
+                        entry.status = manifest::GlyphStatus::Existing;
+                    }
+                    entry.file = Some(file);
+                }
+                Err(err) => {
+                    error!("{err:#}");
+                    failures.push(i);
+                    entry.status = manifest::GlyphStatus::Failed;
+                }
+            }
+        }
+        entry.alternate = entry
+            .file
+            .as_deref()
+            .and_then(|file| glyph_set::alternate(Path::new(file)));
+        manifest.glyphs.push(entry);
+    }
+    if update {
+        report_updates(&updates);
+    }
+
+    Ok(failures)
+}
+
+/// A letter image that's ready to be saved, and what was learned about it on
+/// the way.
+struct Finished {
+    letter_image: GrayImage,
+    bounds: Option<(u32, u32, u32, u32)>,
+    quality: Option<quality::Quality>,
+    still_clipped: bool,
+    fingerprint: Option<duplicates::Fingerprint>,
+    guides: Option<Guides>,
+    metrics: Option<glyph_metrics::GlyphMetrics>,
+    pot_padding: Option<manifest::PotPadding>,
+    change: Option<Update>,
+}
+
+/// The width and height that letterforms are scaled to before they're
+/// compared with `--update`, like `diff`'s default `--size`.
+const UPDATE_SIZE: u32 = 256;
+
+/// How a letterform compares with the letter image an earlier scan saved
+/// under the same name, with `--update`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Update {
+    /// There was no letter image, so one was added.
+    Added,
+    /// The letterform changed, so its letter image was replaced.
+    Changed,
+    /// The letterform looks the same, so its letter image was kept.
+    Same,
+}
+
+/// List the letter images that `--update` replaced and added, by name.
+fn report_updates(updates: &HashMap<Update, Vec<String>>) {
+    let files = |update: Update| updates.get(&update).map_or(&[][..], Vec::as_slice);
+    let (changed, added) = (files(Update::Changed), files(Update::Added));
+    if changed.is_empty() && added.is_empty() {
+        info!("No letterforms changed since the last scan.");
+        return;
+    }
+    if !changed.is_empty() {
+        info!(
+            "Updated {} letter images that changed: {}",
+            changed.len(),
+            changed.join(", ")
+        );
+    }
+    if !added.is_empty() {
+        info!(
+            "Added {} new letter images: {}",
+            added.len(),
+            added.join(", ")
+        );
+    }
+    info!(
+        "Left {} letter images as they were.",
+        files(Update::Same).len()
+    );
+}
+
+/// How a cell is named in warnings, like `cell (3,7) 'Q'`.
+pub fn cell_name(row: u32, col: u32, character: Option<&str>) -> String {
+    match character {
+        Some(character) => format!("cell ({row},{col}) '{character}'"),
+        None => format!("cell ({row},{col})"),
+    }
+}
+
+/// List the indices of the cells for which `flags` is set.
+fn indices_where(flags: &[bool]) -> Vec<String> {
+    flags
+        .iter()
+        .enumerate()
+        .filter(|(_, flag)| **flag)
+        .map(|(i, _)| i.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{canvas::Align, charset, output::OutputFormat, template::render_template};
+
+    #[test]
+    fn runs_each_stage_on_a_generated_template() {
+        let charset = Charset::parse("abcdef");
+        let mut page = render_template(2, 3, 120, &Charset::default(), None, false);
+        let grid = pipeline::locate_grid(&page, 3, 2);
+        // Write a blob of ink in the second and last cells, above their
+        // baselines.
+        for i in [1, 5] {
+            let (x, y, width, height) = grid.cells()[i];
+            for dy in 0..height / 4 {
+                for dx in 0..width / 3 {
+                    page.put_pixel(x + width / 3 + dx, y + height / 4 + dy, image::Luma([0]));
+                }
+            }
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let input_file = dir.path().join("page.png");
+        page.save(&input_file).unwrap();
+        let output_dir = dir.path().join("letters");
+        let profile = Profile::default();
+
+        let load_options = LoadOptions {
+            input_file: &input_file,
+            pdf_page: None,
+            pdf_dpi: None,
+            region: None,
+            preprocess: pipeline::PreprocessOptions {
+                threshold: threshold::Threshold::Fixed(128),
+                adaptive_window: None,
+                // The page is upright, but has too little writing to tell.
+                orient: false,
+                lighting: None,
+                deskew: false,
+                camera: false,
+                grayscale: true,
+                pen_color: None,
+                straightened: true,
+            },
+            scale_threshold_with_dpi: false,
+            keep_original: false,
+            profile: &profile,
+        };
+        let loaded = load(&load_options).unwrap();
+        let descriptor = Descriptor::grid(2, 3, grid::Margins { x: None, y: None });
+        let locate_options = LocateOptions {
+            descriptor: &descriptor,
+            layout_code: false,
+            margins: grid::Margins { x: None, y: None },
+            charset: &charset,
+            first_cell: 0,
+            output_dir: &output_dir,
+        };
+        let located = locate(loaded, &load_options, &locate_options).unwrap();
+        assert!(located.located.missing.is_empty());
+        assert_eq!(located.cells.len(), 6);
+
+        let cut_options = CutOptions {
+            min_ink: 0.05,
+            baseline: None,
+            baseline_similarity: 0.9,
+            pdf_dpi: None,
+            profile: &profile,
+        };
+        let cut = cut(&located, &cut_options).unwrap();
+        assert_eq!(cut.empty, [true, false, true, true, true, false]);
+        assert_eq!(cut.labels[5].as_deref(), Some("f"));
+
+        let save_options = SaveOptions {
+            output_dir: &output_dir,
+            input_file: &input_file,
+            number: 1,
+            first_cell: 0,
+            files: pipeline::SaveOptions {
+                output_format: OutputFormat::Png,
+                fallback_to_png: false,
+                vectorize: false,
+                simplify: 1.0,
+                skeleton: None,
+                alpha: false,
+                ink_color: None,
+            },
+            clean: CleanOptions {
+                guide_dots: &[],
+                keep_grid_lines: false,
+                profile: &profile,
+                despeckle: None,
+                open: None,
+                close: None,
+            },
+            name_format: None,
+            suffix: None,
+            on_conflict: output::OnConflict::First,
+            force: false,
+            skip_existing: false,
+            update: false,
+            update_similarity: 0.9,
+            merging: false,
+            expand_clipped: false,
+            normalize_strokes: None,
+            grayscale: false,
+            scale: None,
+            upscale: None,
+            canvas: None,
+            align: Align::default(),
+            crop: true,
+            padding: 2,
+            pot: false,
+        };
+        let mut manifest = manifest::Manifest::default();
+        let failures = save(
+            &located,
+            &cut,
+            page_entry(&input_file),
+            &save_options,
+            &mut HashSet::new(),
+            &mut manifest,
+        )
+        .unwrap();
+        assert!(failures.is_empty());
+        assert_eq!(manifest.pages.len(), 1);
+        let files: Vec<_> = manifest
+            .glyphs
+            .iter()
+            .filter_map(|glyph| glyph.file.as_deref())
+            .collect();
+        let expected = [
+            charset::text_file_stem("b") + ".png",
+            charset::text_file_stem("f") + ".png",
+        ];
+        assert_eq!(files, expected);
+        for file in files {
+            let (width, height) = image::open(output_dir.join(file)).unwrap().dimensions();
+            assert!(width < 60 && height < 60, "{file} is {width} × {height}");
+        }
+    }
+
+    /// The manifest's entry for a page that was read with default settings.
+    fn page_entry(input_file: &Path) -> manifest::PageEntry {
+        manifest::PageEntry {
+            input_file: input_file.to_path_buf(),
+            pdf_page: None,
+            dpi: None,
+            rows: 2,
+            cols: 3,
+            grid_found: true,
+            guessed_lines: 0,
+            contact_sheet: None,
+            preprocessing: manifest::Preprocessing {
+                threshold: threshold::Threshold::Fixed(128),
+                threshold_level: 128,
+                adaptive_threshold: None,
+                profile: None,
+                camera: false,
+                lighting: None,
+                deskew: false,
+                rotation: 0,
+                skew_degrees: None,
+                min_ink: 0.05,
+                padding: Some(2),
+                grid_lines_removed: true,
+                despeckle: None,
+                open: None,
+                close: None,
+                stroke_width: None,
+                grayscale: false,
+                pen_color: None,
+                canvas: None,
+                align: None,
+                scale: None,
+                upscale: None,
+                pot: false,
+                alpha: false,
+                ink_color: None,
+                vectorize: false,
+                skeleton: None,
+                output_format: "png",
+            },
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use serde::Serialize;
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+use tracing::{field::Field, Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+use crate::manifest::{GlyphStatus, Manifest};
+
+/// How a scan went, which decides the app's exit code.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Every page's grid was found, and every letter image was saved.
+    #[default]
+    Complete,
+    /// Something went wrong before the scan finished.
+    Error,
+    /// Letter images were saved, but some of the grid's lines had to be
+    /// guessed, some pages' grids weren't found, or some images couldn't be
+    /// saved. They're worth checking.
+    Partial,
+    /// A page's grid couldn't be found, so the scan stopped, or no page's
+    /// grid was found, and the letter images were cut inside the margins
+    /// given for them.
+    NoGrid,
+}
+
+impl Status {
+    /// The exit code for the status. 1 is left for other errors, and 2 for
+    /// mistakes in the command line, like clap uses.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Self::Complete => 0,
+            Self::Error => 1,
+            Self::Partial => 3,
+            Self::NoGrid => 4,
+        }
+    }
+}
+
+/// What a scan did, printed as JSON by `scan --json`.
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub status: Status,
+    /// What went wrong, if the scan stopped with an error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Every page that was scanned, in order.
+    pub pages: Vec<PageSummary>,
+    /// Every letter image that was written.
+    pub files: Vec<PathBuf>,
+    /// The warnings logged while scanning.
+    pub warnings: Vec<String>,
+}
+
+/// What was found on one page.
+#[derive(Debug, Serialize)]
+pub struct PageSummary {
+    pub input_file: PathBuf,
+    pub pdf_page: Option<u32>,
+    pub output_dir: PathBuf,
+    pub grid_found: bool,
+    /// How many of the grid's lines were guessed, rather than found.
+    pub guessed_lines: u32,
+    pub cells: usize,
+    /// How many cells have a letterform in them.
+    pub detected: usize,
+    pub saved: usize,
+    pub failed: usize,
+}
+
+impl Summary {
+    /// Add the pages and letter images described in a manifest written into
+    /// `output_dir`.
+    pub fn add(&mut self, manifest: &Manifest, output_dir: &Path) {
+        for (i, page) in manifest.pages.iter().enumerate() {
+            let glyphs: Vec<_> = manifest.glyphs.iter().filter(|g| g.page == i).collect();
+            let count = |status: GlyphStatus| glyphs.iter().filter(|g| g.status == status).count();
+            self.pages.push(PageSummary {
+                input_file: page.input_file.clone(),
+                pdf_page: page.pdf_page,
+                output_dir: output_dir.to_path_buf(),
+                grid_found: page.grid_found,
+                guessed_lines: page.guessed_lines,
+                cells: glyphs.len(),
+                detected: glyphs.len() - count(GlyphStatus::Empty),
+                saved: count(GlyphStatus::Saved),
+                failed: count(GlyphStatus::Failed),
+            });
+        }
+        self.files.extend(
+            manifest
+                .glyphs
+                .iter()
+                .filter(|glyph| glyph.status == GlyphStatus::Saved)
+                .filter_map(|glyph| glyph.file.as_ref())
+                .map(|file| output_dir.join(file)),
+        );
+        self.status = self.judge();
+    }
+
+    /// Work out the status of the pages so far.
+    fn judge(&self) -> Status {
+        let missing_grids = self.pages.iter().filter(|page| !page.grid_found).count();
+        if missing_grids > 0 && missing_grids == self.pages.len() {
+            Status::NoGrid
+        } else if missing_grids > 0
+            || self
+                .pages
+                .iter()
+                .any(|page| page.guessed_lines > 0 || page.failed > 0)
+        {
+            Status::Partial
+        } else {
+            Status::Complete
+        }
+    }
+}
+
+/// Whether warnings are being kept for [`take_warnings`].
+static COLLECTING: AtomicBool = AtomicBool::new(false);
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Start keeping the messages of warnings and errors that are logged, for
+/// [`take_warnings`]. [`WarningLog`] must be part of the logger.
+pub fn collect_warnings() {
+    COLLECTING.store(true, Ordering::Relaxed);
+}
+
+/// Take the warnings kept since [`collect_warnings`] was called, or since
+/// this was last called.
+pub fn take_warnings() -> Vec<String> {
+    std::mem::take(&mut WARNINGS.lock().unwrap_or_else(|err| err.into_inner()))
+}
+
+/// A logging layer that keeps the messages of warnings and errors, once
+/// [`collect_warnings`] has been called.
+pub struct WarningLog;
+
+impl<S: Subscriber> Layer<S> for WarningLog {
+    fn on_event(&self, event: &Event<'_>, _context: Context<'_, S>) {
+        if *event.metadata().level() > Level::WARN || !COLLECTING.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut message = String::new();
+        event.record(&mut |field: &Field, value: &dyn Debug| {
+            if field.name() == "message" {
+                message = format!("{value:?}");
+            }
+        });
+        WARNINGS
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(grid_found: bool, guessed_lines: u32) -> PageSummary {
+        PageSummary {
+            input_file: PathBuf::from("page.png"),
+            pdf_page: None,
+            output_dir: PathBuf::from("out"),
+            grid_found,
+            guessed_lines,
+            cells: 4,
+            detected: 2,
+            saved: 2,
+            failed: 0,
+        }
+    }
+
+    #[test]
+    fn judges_how_a_scan_went() {
+        let mut summary = Summary::default();
+        summary.pages.push(page(true, 0));
+        assert_eq!(summary.judge(), Status::Complete);
+        summary.pages.push(page(true, 2));
+        assert_eq!(summary.judge(), Status::Partial);
+        summary.pages = vec![page(false, 23), page(true, 0)];
+        assert_eq!(summary.judge(), Status::Partial);
+        summary.pages = vec![page(false, 23)];
+        assert_eq!(summary.judge(), Status::NoGrid);
+        assert_eq!(Status::NoGrid.exit_code(), 4);
+    }
+}
@@ -0,0 +1,564 @@
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont};
+use anyhow::{bail, Context};
+use clap::Args;
+use image::{GrayImage, Luma};
+use imageproc::{drawing::draw_filled_rect_mut, rect::Rect};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::{
+    charset::{display_text, Charset},
+    layout_code, metrics,
+};
+
+/// Fonts that are tried, in order, for the guide characters when no font is
+/// given, and for labels on other generated images.
+const SYSTEM_FONTS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+const TITLE: &str = "Write a letter you want to scan in each grid cell";
+const LINED_TITLE: &str = "Write each sentence on the line below it, in your usual hand";
+/// The size of a lined template, in inches: US Letter, upright.
+const LINED_PAGE_SIZE: (f32, f32) = (8.5, 11.0);
+/// The space around a lined template's lines, in inches.
+const LINED_MARGIN: f32 = 0.75;
+/// The distance from one sentence to the next on a lined template, in
+/// inches.
+const LINE_PITCH: f32 = 1.1;
+const LINE_COLOR: Luma<u8> = Luma([0]);
+/// The color of the baseline guides. Like the guide characters, it's light
+/// enough to be treated as paper when scanned at the default threshold.
+const BASELINE_COLOR: Luma<u8> = Luma([225]);
+const GUIDE_CHARACTER_COLOR: Luma<u8> = Luma([225]);
+/// How far down each cell its baseline guide is, as a fraction of the cell's
+/// height.
+pub const BASELINE_POSITION: f32 = 0.65;
+/// How far down each cell its x-height guide is, as a fraction of the cell's
+/// height. It's dashed, so it can be told apart from the baseline.
+pub const X_HEIGHT_POSITION: f32 = 0.4;
+
+/// Arguments for the `generate-template` command.
+#[derive(Args, Debug)]
+pub struct TemplateArgs {
+    /// Where to write the template. The file name should end in `.pdf`, or in
+    /// the extension of an image format, such as `.png`.
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// The number of rows of cells. The default value is 9.
+    #[arg(long, default_value = "9")]
+    rows: u32,
+
+    /// The number of columns of cells. The default value is 12.
+    #[arg(long, default_value = "12")]
+    cols: u32,
+
+    /// The width and height of each cell, in inches. The default value is
+    /// 0.75, which fits the default grid on a landscape letter or A4 page.
+    #[arg(long, default_value = "0.75")]
+    cell_size: f32,
+
+    /// The resolution of the template, in dots per inch. The default value is
+    /// 300.
+    #[arg(long, default_value = "300")]
+    dpi: u32,
+
+    /// The characters to write on the template, in grid order (left to
+    /// right, top to bottom), or the path of a text file containing them.
+    /// Each character is printed faintly in its cell as a guide. A space
+    /// marks a cell with no character. The `greek`, `cyrillic`, and `hebrew`
+    /// charsets are built in, like `--charset greek`.
+    ///
+    /// A charset with more characters than the grid has cells is continued
+    /// on more pages: a PDF gets a page for each, and an image is saved as
+    /// several files, numbered like `template-2.png`.
+    #[arg(short, long)]
+    charset: Option<String>,
+
+    /// Lay the charset out from right to left, for scripts like Hebrew and
+    /// Arabic: its first character is printed in the top row's rightmost
+    /// cell.
+    #[arg(long, default_value_t = false, requires = "charset")]
+    right_to_left: bool,
+
+    /// The TrueType/OpenType font that the title and guide characters are
+    /// printed in. If not provided, a common system font is used.
+    #[arg(long)]
+    font: Option<PathBuf>,
+
+    /// Leave out the QR code that records the template's rows, columns, and
+    /// charset. Without the code, they must be passed to the `scan` command.
+    #[arg(long, default_value_t = false)]
+    no_layout_code: bool,
+
+    /// Make a lined page to write whole sentences on, instead of a grid.
+    /// Each sentence is printed faintly above a line to copy it onto. Scan
+    /// the filled-in page with the `metrics` command to measure how you
+    /// space your letters and words.
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["rows", "cols", "cell_size", "charset", "right_to_left", "no_layout_code"]
+    )]
+    lined: bool,
+
+    /// The sentences to print on a lined page, one per line, or the path of
+    /// a text file containing them. By default, a few pangrams are printed,
+    /// which use every letter of the alphabet.
+    #[arg(long, requires = "lined")]
+    text: Option<String>,
+}
+
+/// Render a printable template, and save it as an image or a PDF.
+pub fn generate_template(args: TemplateArgs) -> anyhow::Result<()> {
+    let TemplateArgs {
+        output,
+        rows,
+        cols,
+        cell_size,
+        dpi,
+        charset,
+        right_to_left,
+        font,
+        no_layout_code,
+        lined,
+        text,
+    } = args;
+    if rows == 0 || cols == 0 {
+        bail!("rows and cols must be at least 1.");
+    }
+    if cell_size <= 0.0 {
+        bail!("cell_size must be greater than 0.");
+    }
+    if !(72..=1200).contains(&dpi) {
+        bail!("dpi must be between 72 and 1200.");
+    }
+    check_output(&output)?;
+    let font = match font {
+        Some(path) => Some(load_font(&path)?),
+        None => {
+            let font = system_font();
+            if font.is_none() {
+                warn!("no font was found for the title and guide characters; pass --font to print them.");
+            }
+            font
+        }
+    };
+    if lined {
+        let sentences = metrics::sentences_from_arg(text.as_deref())?;
+        let template = render_lined_template(&sentences, dpi, font.as_ref());
+        save(&template, &output, dpi)?;
+        info!(
+            "Saved a lined template to {}. Print it at 100% scale.",
+            output.display()
+        );
+        return Ok(());
+    }
+    let charset = match charset {
+        Some(charset) => Charset::from_arg(&charset)?,
+        None => Charset::default(),
+    };
+    if let Some(font) = &font {
+        let missing: String = (0..charset.len())
+            .filter_map(|i| charset.text(i))
+            .flat_map(str::chars)
+            .filter(|c| font.glyph_id(*c).0 == 0)
+            .take(5)
+            .collect();
+        if !missing.is_empty() {
+            warn!(
+                "the font has no glyphs for some of the charset's characters, like {missing}; \
+                 pass --font with one that does to print them."
+            );
+        }
+    }
+
+    let cell_size = (cell_size * dpi as f32).round().max(8.0) as u32;
+    let pages: Vec<GrayImage> = charset
+        .pages((rows * cols) as usize)
+        .iter()
+        .map(|charset| {
+            if right_to_left {
+                charset.right_to_left(cols as usize)
+            } else {
+                charset.clone()
+            }
+        })
+        .chain(charset.is_empty().then(Charset::default))
+        .map(|charset| {
+            render_template(
+                rows,
+                cols,
+                cell_size,
+                &charset,
+                font.as_ref(),
+                !no_layout_code,
+            )
+        })
+        .collect();
+    save_pages(&pages, &output, dpi)?;
+    if pages.len() > 1 {
+        info!(
+            "The charset has {} characters, so the template has {} pages. Scan them together \
+             with --merge to save their letters in one directory.",
+            charset.len(),
+            pages.len()
+        );
+    }
+    info!(
+        "Saved a {cols} × {rows} template to {}. Print it at 100% scale.",
+        output.display()
+    );
+
+    Ok(())
+}
+
+/// Check that a template can be saved to `output`: it must be a PDF or an
+/// image file. Returns whether it's a PDF.
+pub fn check_output(output: &Path) -> anyhow::Result<bool> {
+    let is_pdf = output
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pdf"));
+    if !is_pdf && image::ImageFormat::from_path(output).is_err() {
+        bail!("output must be a .pdf file or an image file.");
+    }
+
+    Ok(is_pdf)
+}
+
+/// Save a rendered template as a PDF or an image, depending on `output`'s
+/// extension. A PDF prints at `dpi` dots per inch.
+pub fn save(template: &GrayImage, output: &Path, dpi: u32) -> anyhow::Result<()> {
+    save_pages(std::slice::from_ref(template), output, dpi)
+}
+
+/// Save the pages of a rendered template, like [`save`]. A PDF gets a page
+/// for each; as images, they're numbered after the first, like
+/// `template-2.png`.
+pub fn save_pages(pages: &[GrayImage], output: &Path, dpi: u32) -> anyhow::Result<()> {
+    if check_output(output)? {
+        return std::fs::write(output, pdf(pages, dpi))
+            .with_context(|| format!("writing {}", output.display()));
+    }
+    for (i, page) in pages.iter().enumerate() {
+        let path = match i {
+            0 => output.to_path_buf(),
+            _ => page_path(output, i + 1),
+        };
+        page.save(&path)
+            .with_context(|| format!("writing {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// The path of the page `number` of a template saved as images to `output`,
+/// like `template-2.png`.
+fn page_path(output: &Path, number: usize) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{stem}-{number}");
+    if let Some(extension) = output.extension() {
+        name = format!("{name}.{}", extension.to_string_lossy());
+    }
+    output.with_file_name(name)
+}
+
+/// Load the first of [`SYSTEM_FONTS`] that's installed.
+pub fn system_font() -> Option<FontVec> {
+    SYSTEM_FONTS
+        .iter()
+        .find_map(|path| load_font(Path::new(path)).ok())
+}
+
+fn load_font(path: &Path) -> anyhow::Result<FontVec> {
+    let bytes = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    FontVec::try_from_vec(bytes).with_context(|| format!("parsing {}", path.display()))
+}
+
+/// Draw a template in the same layout as the bundled one: a grid with a
+/// margin of one cell around it, baseline and x-height guides in each cell,
+/// and solid markers beside three of the grid's corners. With `layout_code`,
+/// a QR code recording the rows, columns, and charset is printed below the
+/// grid, so that scans of the template can be read without being told them.
+pub fn render_template(
+    rows: u32,
+    cols: u32,
+    cell_size: u32,
+    charset: &Charset,
+    font: Option<&FontVec>,
+    layout_code: bool,
+) -> GrayImage {
+    let margin = cell_size;
+    let (width, height) = (cols * cell_size + margin * 2, rows * cell_size + margin * 2);
+    let mut template = GrayImage::from_pixel(width, height, Luma([255]));
+    let line_width = (cell_size / 80).max(1);
+    let marker_size = cell_size / 4;
+
+    for row in 0..rows {
+        for col in 0..cols {
+            let (x, y) = (margin + col * cell_size, margin + row * cell_size);
+            let baseline = y + (cell_size as f32 * BASELINE_POSITION) as u32;
+            draw_filled_rect_mut(
+                &mut template,
+                Rect::at(x as i32, baseline as i32).of_size(cell_size, line_width),
+                BASELINE_COLOR,
+            );
+            let x_height = y + (cell_size as f32 * X_HEIGHT_POSITION) as u32;
+            let dash = (cell_size / 20).max(1);
+            for dash_x in (x..x + cell_size).step_by(dash as usize * 2) {
+                let length = dash.min(x + cell_size - dash_x);
+                draw_filled_rect_mut(
+                    &mut template,
+                    Rect::at(dash_x as i32, x_height as i32).of_size(length, line_width),
+                    BASELINE_COLOR,
+                );
+            }
+            let text = charset.text((row * cols + col) as usize).map(display_text);
+            if let (Some(font), Some(text)) = (font, text.as_deref()) {
+                let mut scale = PxScale::from(cell_size as f32 * 0.45);
+                let mut advance = text_width(font, scale, text);
+                // Sequences like ligatures are shrunk to fit in their cell.
+                let widest = cell_size as f32 * 0.85;
+                if advance > widest {
+                    scale = PxScale::from(scale.x * widest / advance);
+                    advance = widest;
+                }
+                let mut origin = (
+                    x as f32 + (cell_size as f32 - advance) / 2.0,
+                    baseline as f32,
+                );
+                for c in text.chars() {
+                    draw_text(&mut template, font, scale, origin, c, GUIDE_CHARACTER_COLOR);
+                    origin.0 += font.as_scaled(scale).h_advance(font.glyph_id(c));
+                }
+            }
+        }
+    }
+
+    for i in 0..=cols {
+        let x = margin + i * cell_size;
+        let rect = Rect::at(x as i32, margin as i32).of_size(line_width, rows * cell_size);
+        draw_filled_rect_mut(&mut template, rect, LINE_COLOR);
+    }
+    for i in 0..=rows {
+        let y = margin + i * cell_size;
+        let rect =
+            Rect::at(margin as i32, y as i32).of_size(cols * cell_size + line_width, line_width);
+        draw_filled_rect_mut(&mut template, rect, LINE_COLOR);
+    }
+
+    // The markers sit outside the top left, bottom left, and bottom right
+    // corners, touching the grid.
+    let (left, top) = (margin - marker_size, margin - marker_size);
+    let (right, bottom) = (margin + cols * cell_size, margin + rows * cell_size);
+    for (x, y) in [(left, top), (left, bottom), (right, bottom)] {
+        let rect = Rect::at(x as i32, y as i32).of_size(marker_size, marker_size);
+        draw_filled_rect_mut(&mut template, rect, LINE_COLOR);
+    }
+
+    if layout_code {
+        let charset = (!charset.is_empty()).then(|| charset.to_string());
+        let layout = layout_code::Layout::new(rows, cols, charset);
+        // The code sits in the middle of the margin below the grid, clear of
+        // the markers and the grid lines.
+        let max_size = margin - marker_size / 2;
+        match layout_code::render(&layout, max_size) {
+            Some(code) => {
+                let x = (width - code.width()) / 2;
+                let y = bottom + line_width + (margin - line_width - code.height()) / 2;
+                image::imageops::replace(&mut template, &code, x as i64, y as i64);
+            }
+            None => warn!("the cells are too small to fit a readable layout code; leaving it out."),
+        }
+    }
+
+    if let Some(font) = font {
+        let scale = PxScale::from(marker_size as f32 * 0.5);
+        let scaled = font.as_scaled(scale);
+        let mut x = (width as f32 - text_width(font, scale, TITLE)) / 2.0;
+        let y = margin as f32 - marker_size as f32 / 2.0;
+        for c in TITLE.chars() {
+            draw_text(&mut template, font, scale, (x, y), c, LINE_COLOR);
+            x += scaled.h_advance(font.glyph_id(c));
+        }
+    }
+
+    template
+}
+
+/// Draw a lined template: each of `sentences` printed faintly, with a line
+/// below it to copy it onto. Sentences that don't fit on the page are left
+/// out. Everything is printed light enough to be read as paper, so only the
+/// handwriting is left once the page is scanned.
+pub fn render_lined_template(sentences: &[String], dpi: u32, font: Option<&FontVec>) -> GrayImage {
+    let inches = |length: f32| (length * dpi as f32).round() as u32;
+    let (width, height) = (inches(LINED_PAGE_SIZE.0), inches(LINED_PAGE_SIZE.1));
+    let mut template = GrayImage::from_pixel(width, height, Luma([255]));
+    let margin = inches(LINED_MARGIN);
+    let line_width = (dpi / 150).max(1);
+    let fits = ((LINED_PAGE_SIZE.1 - LINED_MARGIN * 2.0) / LINE_PITCH) as usize;
+    if sentences.len() > fits {
+        warn!(
+            "only {fits} sentences fit on the page; the other {} are left out.",
+            sentences.len() - fits
+        );
+    }
+
+    let guide_scale = PxScale::from(inches(0.22) as f32);
+    for (i, sentence) in sentences.iter().take(fits).enumerate() {
+        let top = margin + inches(LINE_PITCH * i as f32);
+        let guide_baseline = top + inches(0.25);
+        let writing_baseline = top + inches(0.9);
+        if let Some(font) = font {
+            let mut x = margin as f32;
+            for c in sentence.chars() {
+                draw_text(
+                    &mut template,
+                    font,
+                    guide_scale,
+                    (x, guide_baseline as f32),
+                    c,
+                    GUIDE_CHARACTER_COLOR,
+                );
+                x += font.as_scaled(guide_scale).h_advance(font.glyph_id(c));
+            }
+        }
+        let rect = Rect::at(margin as i32, writing_baseline as i32)
+            .of_size(width - margin * 2, line_width);
+        draw_filled_rect_mut(&mut template, rect, BASELINE_COLOR);
+    }
+
+    if let Some(font) = font {
+        let scale = PxScale::from(inches(0.18) as f32);
+        let mut x = (width as f32 - text_width(font, scale, LINED_TITLE)) / 2.0;
+        let y = margin as f32 / 2.0;
+        for c in LINED_TITLE.chars() {
+            draw_text(&mut template, font, scale, (x, y), c, GUIDE_CHARACTER_COLOR);
+            x += font.as_scaled(scale).h_advance(font.glyph_id(c));
+        }
+    }
+
+    template
+}
+
+/// How wide `text` is when printed at `scale`.
+fn text_width(font: &FontVec, scale: PxScale, text: &str) -> f32 {
+    let scaled = font.as_scaled(scale);
+    text.chars()
+        .map(|c| scaled.h_advance(font.glyph_id(c)))
+        .sum()
+}
+
+/// Draw one character with its baseline starting at `origin`.
+fn draw_text(
+    image: &mut GrayImage,
+    font: &FontVec,
+    scale: PxScale,
+    origin: (f32, f32),
+    c: char,
+    color: Luma<u8>,
+) {
+    let glyph = font
+        .glyph_id(c)
+        .with_scale_and_position(scale, ab_glyph::point(origin.0, origin.1));
+    let Some(outline) = font.outline_glyph(glyph) else {
+        return;
+    };
+    let bounds = outline.px_bounds();
+    outline.draw(|x, y, coverage| {
+        let (x, y) = (
+            x as i64 + bounds.min.x as i64,
+            y as i64 + bounds.min.y as i64,
+        );
+        if x < 0 || y < 0 {
+            return;
+        }
+        if let Some(pixel) = image.get_pixel_mut_checked(x as u32, y as u32) {
+            let shade = 255.0 - coverage.clamp(0.0, 1.0) * (255.0 - color.0[0] as f32);
+            pixel.0[0] = pixel.0[0].min(shade as u8);
+        }
+    });
+}
+
+/// Build a PDF with a page showing each of `images` at its actual size.
+fn pdf(images: &[GrayImage], dpi: u32) -> Vec<u8> {
+    // Each page takes three objects, after the catalog and the page tree:
+    // the page, its image, and its contents.
+    let kids: Vec<String> = (0..images.len())
+        .map(|i| format!("{} 0 R", 3 + i * 3))
+        .collect();
+    let mut objects: Vec<Vec<u8>> = vec![
+        b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            images.len()
+        )
+        .into_bytes(),
+    ];
+    for (i, image) in images.iter().enumerate() {
+        let (width, height) = image.dimensions();
+        let page_width = width as f32 * 72.0 / dpi as f32;
+        let page_height = height as f32 * 72.0 / dpi as f32;
+        let pixels = miniz_oxide::deflate::compress_to_vec_zlib(image.as_raw(), 6);
+        let contents = format!("q {page_width:.2} 0 0 {page_height:.2} 0 0 cm /Im0 Do Q\n");
+        let (image_object, contents_object) = (4 + i * 3, 5 + i * 3);
+        objects.push(
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {page_width:.2} {page_height:.2}] \
+                 /Resources << /XObject << /Im0 {image_object} 0 R >> >> \
+                 /Contents {contents_object} 0 R >>"
+            )
+            .into_bytes(),
+        );
+        objects.push(
+            [
+                format!(
+                    "<< /Type /XObject /Subtype /Image /Width {width} /Height {height} \
+                 /ColorSpace /DeviceGray /BitsPerComponent 8 /Filter /FlateDecode \
+                 /Length {} >>\nstream\n",
+                    pixels.len()
+                )
+                .as_bytes(),
+                &pixels,
+                b"\nendstream",
+            ]
+            .concat(),
+        );
+        objects.push(
+            format!(
+                "<< /Length {} >>\nstream\n{contents}endstream",
+                contents.len()
+            )
+            .into_bytes(),
+        );
+    }
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend(format!("{} 0 obj\n", i + 1).as_bytes());
+        pdf.extend(object);
+        pdf.extend(b"\nendobj\n");
+    }
+    let xref = pdf.len();
+    pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        pdf.extend(format!("{offset:010} 00000 n \n").as_bytes());
+    }
+    pdf.extend(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            objects.len() + 1
+        )
+        .as_bytes(),
+    );
+
+    pdf
+}
@@ -0,0 +1,285 @@
+use image::{GenericImageView, GrayImage};
+use imageproc::integral_image::{integral_image, sum_image_pixels};
+use rayon::prelude::*;
+use serde::{Deserialize, Deserializer};
+use std::{fmt, str::FromStr};
+
+use crate::pipeline::BAND_HEIGHT;
+
+/// How much darker than its surroundings a pixel must be to count as ink when
+/// thresholding adaptively. Without this, the grain of blank paper would be
+/// half ink.
+const ADAPTIVE_OFFSET: u32 = 12;
+
+/// The brightness that separates ink from paper.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Threshold {
+    /// Pixels brighter than this value are paper, and the rest are ink.
+    Fixed(u8),
+    /// Choose a value for each page using Otsu's method.
+    Auto,
+}
+
+impl Threshold {
+    /// The threshold value to use for `image`.
+    pub fn level(self, image: &GrayImage) -> u8 {
+        match self {
+            Self::Fixed(level) => level,
+            Self::Auto => imageproc::contrast::otsu_level(image),
+        }
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Self::Auto);
+        }
+        s.parse()
+            .map(Self::Fixed)
+            .map_err(|_| format!("{s:?} isn't a number between 0 and 255, or \"auto\""))
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fixed(level) => write!(f, "{level}"),
+            Self::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+/// Thresholds in profiles are written like on the command line: a number, or
+/// `"auto"`.
+impl<'de> Deserialize<'de> for Threshold {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Level {
+            Number(u8),
+            Text(String),
+        }
+
+        match Level::deserialize(deserializer)? {
+            Level::Number(level) => Ok(Self::Fixed(level)),
+            Level::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// A range of thresholds to try, written like `150..220:10`: every tenth
+/// value from 150 to 220. The step can be left off, and is 10 by default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sweep {
+    pub from: u8,
+    pub to: u8,
+    pub step: u8,
+}
+
+impl Sweep {
+    /// Every threshold in the range, in order. The last one is `to`, even if
+    /// the steps don't land on it.
+    pub fn levels(self) -> Vec<u8> {
+        let mut levels: Vec<u8> = (self.from..=self.to).step_by(self.step as usize).collect();
+        if levels.last() != Some(&self.to) {
+            levels.push(self.to);
+        }
+        levels
+    }
+}
+
+impl FromStr for Sweep {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, step) = s.split_once(':').unwrap_or((s, "10"));
+        let (from, to) = range
+            .split_once("..")
+            .ok_or_else(|| format!("{s:?} isn't a range like 150..220:10"))?;
+        let parse = |value: &str| {
+            value
+                .trim()
+                .parse::<u8>()
+                .map_err(|_| format!("{value:?} isn't a number between 0 and 255"))
+        };
+        let (from, to, step) = (parse(from)?, parse(to)?, parse(step)?);
+        if from > to {
+            return Err(format!(
+                "{s:?} runs backwards; start with the lower threshold"
+            ));
+        }
+        if step == 0 {
+            return Err("the step must be at least 1".to_string());
+        }
+        Ok(Self { from, to, step })
+    }
+}
+
+/// How much darker than the threshold, in brightness, a pixel must be to be
+/// solid ink when thresholding softly. Pixels in between are shades of gray.
+const SOFTNESS: f32 = 64.0;
+
+/// Like thresholding at `level`, but keeps the smooth edges of strokes:
+/// pixels brighter than `level` are paper, pixels much darker are solid ink,
+/// and the pixels in between are shades of gray.
+pub fn soft_threshold(image: &GrayImage, level: u8) -> GrayImage {
+    let mut thresholded = image.clone();
+    thresholded
+        .par_iter_mut()
+        .for_each(|pixel| *pixel = soften(*pixel as f32, level as f32));
+    thresholded
+}
+
+/// Like [`adaptive_threshold`], but keeps the smooth edges of strokes, like
+/// [`soft_threshold`]. This also evens out the background, since each pixel is
+/// compared with its own surroundings.
+pub fn soft_adaptive_threshold(image: &GrayImage, window: u32) -> GrayImage {
+    map_with_local_mean(image, window, |value, mean| {
+        soften(value as f32, mean as f32 - ADAPTIVE_OFFSET as f32)
+    })
+}
+
+fn soften(value: f32, level: f32) -> u8 {
+    if value > level {
+        255
+    } else {
+        ((value - (level - SOFTNESS)) / SOFTNESS * 255.0).clamp(0.0, 255.0) as u8
+    }
+}
+
+/// Make a new image from each pixel of `image` and the average brightness of
+/// the `window` × `window` square around it. The averages are summed up one
+/// band of [`BAND_HEIGHT`] rows at a time in parallel, so only a band's worth
+/// of sums is held at once instead of the whole page's.
+fn map_with_local_mean(
+    image: &GrayImage,
+    window: u32,
+    f: impl Fn(u8, u32) -> u8 + Sync,
+) -> GrayImage {
+    let (width, height) = image.dimensions();
+    let mut mapped = GrayImage::new(width, height);
+    if width == 0 || height == 0 {
+        return mapped;
+    }
+    let radius = (window / 2).max(1);
+    mapped
+        .par_chunks_mut(width as usize * BAND_HEIGHT)
+        .enumerate()
+        .for_each(|(band, rows)| {
+            let first = (band * BAND_HEIGHT) as u32;
+            let last = first + (rows.len() / width as usize) as u32 - 1;
+            // The band's rows, and the rows around them that its windows reach.
+            let top = first.saturating_sub(radius);
+            let bottom = (last + radius).min(height - 1);
+            let strip = image.view(0, top, width, bottom - top + 1).to_image();
+            let integral = integral_image::<_, u32>(&strip);
+            for (y, row) in (first..=last).zip(rows.chunks_mut(width as usize)) {
+                let window_top = y.saturating_sub(radius);
+                let window_bottom = (y + radius).min(height - 1);
+                for (x, pixel) in (0..width).zip(row) {
+                    let left = x.saturating_sub(radius);
+                    let right = (x + radius).min(width - 1);
+                    let area = (right - left + 1) * (window_bottom - window_top + 1);
+                    let sum = sum_image_pixels(
+                        &integral,
+                        left,
+                        window_top - top,
+                        right,
+                        window_bottom - top,
+                    )[0];
+                    *pixel = f(image.get_pixel(x, y).0[0], sum / area);
+                }
+            }
+        });
+    mapped
+}
+
+/// Threshold each pixel against the average brightness of the `window` ×
+/// `window` square around it, instead of against one value for the whole
+/// page. This keeps glyphs in shadowed parts of a page from turning solid
+/// black.
+pub fn adaptive_threshold(image: &GrayImage, window: u32) -> GrayImage {
+    map_with_local_mean(image, window, |value, mean| {
+        if value as u32 + ADAPTIVE_OFFSET < mean {
+            0
+        } else {
+            255
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn parses_numbers_and_auto() {
+        assert_eq!("190".parse(), Ok(Threshold::Fixed(190)));
+        assert_eq!("Auto".parse(), Ok(Threshold::Auto));
+        assert!("256".parse::<Threshold>().is_err());
+        assert!("dark".parse::<Threshold>().is_err());
+    }
+
+    #[test]
+    fn parses_sweeps() {
+        let sweep: Sweep = "150..220:10".parse().unwrap();
+        assert_eq!(sweep.levels(), vec![150, 160, 170, 180, 190, 200, 210, 220]);
+        let sweep: Sweep = "180..205".parse().unwrap();
+        assert_eq!(sweep.levels(), vec![180, 190, 200, 205]);
+        assert!("220..150".parse::<Sweep>().is_err());
+        assert!("150..220:0".parse::<Sweep>().is_err());
+        assert!("190".parse::<Sweep>().is_err());
+    }
+
+    #[test]
+    fn adaptive_threshold_averages_across_bands() {
+        let image = GrayImage::from_fn(7, BAND_HEIGHT as u32 * 2 + 9, |x, y| {
+            Luma([((x * 53 + y * 29) % 256) as u8])
+        });
+        let (width, height) = image.dimensions();
+        let thresholded = adaptive_threshold(&image, 5);
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let (left, right) = (x.saturating_sub(2), (x + 2).min(width - 1));
+            let (top, bottom) = (y.saturating_sub(2), (y + 2).min(height - 1));
+            let window: Vec<u32> = (top..=bottom)
+                .flat_map(|y| (left..=right).map(move |x| (x, y)))
+                .map(|(x, y)| image.get_pixel(x, y).0[0] as u32)
+                .collect();
+            let mean = window.iter().sum::<u32>() / window.len() as u32;
+            let expected = if pixel.0[0] as u32 + ADAPTIVE_OFFSET < mean {
+                0
+            } else {
+                255
+            };
+            assert_eq!(thresholded.get_pixel(x, y).0[0], expected, "at {x}, {y}");
+        }
+    }
+
+    #[test]
+    fn adaptive_threshold_ignores_uneven_lighting() {
+        // A dark stroke on paper that fades from white to gray.
+        let image = GrayImage::from_fn(60, 20, |x, y| {
+            let paper = 250 - x as u8 * 2;
+            Luma([if y == 10 { paper - 100 } else { paper }])
+        });
+        let thresholded = adaptive_threshold(&image, 9);
+        for x in 0..60 {
+            assert_eq!(thresholded.get_pixel(x, 10).0[0], 0);
+            assert_eq!(thresholded.get_pixel(x, 3).0[0], 255);
+        }
+    }
+
+    #[test]
+    fn soft_threshold_keeps_edges_gray() {
+        let image = GrayImage::from_fn(4, 1, |x, _| Luma([[20, 150, 200, 250][x as usize]]));
+        let thresholded = soft_threshold(&image, 190);
+        let values: Vec<u8> = thresholded.pixels().map(|pixel| pixel.0[0]).collect();
+        assert_eq!(values[0], 0);
+        assert!(values[1] > 0 && values[1] < 255);
+        assert_eq!(values[2..], [255, 255]);
+    }
+}
@@ -0,0 +1,797 @@
+use std::collections::BTreeMap;
+use tracing::warn;
+
+/// A point on a glyph's outline, in font units with y pointing up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Point {
+    pub x: i16,
+    pub y: i16,
+    /// Whether the outline passes through this point. Points that aren't on
+    /// the curve are the control points of quadratic Bézier curves.
+    pub on_curve: bool,
+}
+
+/// A glyph's outlines and metrics. Outer contours must run clockwise, and
+/// the contours of holes counterclockwise.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Glyph {
+    pub contours: Vec<Vec<Point>>,
+    pub advance_width: u16,
+}
+
+/// Font-wide names and metrics.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FontInfo {
+    pub family_name: String,
+    pub units_per_em: u16,
+    pub ascender: i16,
+    /// The distance below the baseline that glyphs may reach, as a negative
+    /// number.
+    pub descender: i16,
+    pub x_height: i16,
+    pub cap_height: i16,
+}
+
+/// Glyphs that replace others, by index into the font's glyphs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Substitutions {
+    /// Glyphs that may be swapped for each glyph, through the `rand` and
+    /// `salt` features.
+    pub alternates: Vec<(u16, Vec<u16>)>,
+    /// Sequences of glyphs that are joined into one glyph, through the
+    /// `liga` feature.
+    pub ligatures: Vec<(Vec<u16>, u16)>,
+}
+
+/// Serialize a TrueType font. Only what's needed for a simple, unhinted font
+/// of outlines is written: the glyphs, their horizontal metrics, and a
+/// Unicode character map.
+///
+/// The first glyph must be the `.notdef` glyph, and `cmap` maps characters to
+/// indices into `glyphs`. Any `substitutions` are written as OpenType
+/// features, and any `kerning` of pairs of glyphs, as `(left, right,
+/// amount)` in font units, as a `kern` table.
+pub fn write_font(
+    info: &FontInfo,
+    glyphs: &[Glyph],
+    cmap: &[(char, u16)],
+    substitutions: &Substitutions,
+    kerning: &[(u16, u16, i16)],
+) -> Vec<u8> {
+    let mut cmap = cmap.to_vec();
+    cmap.sort();
+    let bounds: Vec<Option<Bounds>> = glyphs.iter().map(Bounds::of).collect();
+    let font_bounds = bounds
+        .iter()
+        .flatten()
+        .fold(None, |acc: Option<Bounds>, b| {
+            Some(acc.map_or(*b, |acc| acc.union(b)))
+        })
+        .unwrap_or_default();
+
+    let (glyf, loca) = write_glyf(glyphs, &bounds);
+    let mut tables: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"OS/2", write_os2(info, glyphs, &cmap)),
+        (b"cmap", write_cmap(&cmap)),
+        (b"glyf", glyf),
+        (b"head", write_head(info, &font_bounds)),
+        (b"hhea", write_hhea(info, glyphs, &bounds, &font_bounds)),
+        (b"hmtx", write_hmtx(glyphs, &bounds)),
+        (b"loca", loca),
+        (b"maxp", write_maxp(glyphs)),
+        (b"name", write_name(info)),
+        (b"post", write_post()),
+    ];
+    if !kerning.is_empty() {
+        // Tables are sorted by tag, and `kern` comes after `hmtx`.
+        let at = tables
+            .iter()
+            .position(|(tag, _)| *tag == b"loca")
+            .unwrap_or(tables.len());
+        tables.insert(at, (b"kern", write_kern(kerning)));
+    }
+    if !substitutions.alternates.is_empty() || !substitutions.ligatures.is_empty() {
+        // Tables are sorted by tag, and `GSUB` comes first.
+        tables.insert(0, (b"GSUB", write_gsub(substitutions)));
+    }
+
+    // The table directory, followed by each table padded to four bytes.
+    let mut font = Vec::new();
+    let num_tables = tables.len() as u16;
+    let entry_selector = 15 - num_tables.leading_zeros() as u16;
+    let search_range: u16 = (1 << entry_selector) * 16;
+    font.extend(0x0001_0000u32.to_be_bytes());
+    font.extend(num_tables.to_be_bytes());
+    font.extend(search_range.to_be_bytes());
+    font.extend(entry_selector.to_be_bytes());
+    font.extend((num_tables * 16 - search_range).to_be_bytes());
+
+    let mut offset = 12 + tables.len() * 16;
+    let mut head_offset = 0;
+    for (tag, data) in &tables {
+        if *tag == b"head" {
+            head_offset = offset;
+        }
+        font.extend(*tag);
+        font.extend(checksum(data).to_be_bytes());
+        font.extend((offset as u32).to_be_bytes());
+        font.extend((data.len() as u32).to_be_bytes());
+        offset += data.len().next_multiple_of(4);
+    }
+    for (_, data) in &tables {
+        font.extend(data);
+        font.resize(font.len().next_multiple_of(4), 0);
+    }
+
+    // The `head` table records a checksum of the whole font.
+    let adjustment = 0xB1B0_AFBAu32.wrapping_sub(checksum(&font));
+    font[head_offset + 8..head_offset + 12].copy_from_slice(&adjustment.to_be_bytes());
+
+    font
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Bounds {
+    x_min: i16,
+    y_min: i16,
+    x_max: i16,
+    y_max: i16,
+}
+
+impl Bounds {
+    fn of(glyph: &Glyph) -> Option<Self> {
+        let mut points = glyph.contours.iter().flatten();
+        let first = points.next()?;
+        let mut bounds = Self {
+            x_min: first.x,
+            y_min: first.y,
+            x_max: first.x,
+            y_max: first.y,
+        };
+        for point in points {
+            bounds.x_min = bounds.x_min.min(point.x);
+            bounds.y_min = bounds.y_min.min(point.y);
+            bounds.x_max = bounds.x_max.max(point.x);
+            bounds.y_max = bounds.y_max.max(point.y);
+        }
+
+        Some(bounds)
+    }
+
+    fn union(self, other: &Self) -> Self {
+        Self {
+            x_min: self.x_min.min(other.x_min),
+            y_min: self.y_min.min(other.y_min),
+            x_max: self.x_max.max(other.x_max),
+            y_max: self.y_max.max(other.y_max),
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+fn write_head(info: &FontInfo, bounds: &Bounds) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend(0x0001_0000u32.to_be_bytes()); // version
+    data.extend(0x0001_0000u32.to_be_bytes()); // fontRevision
+    data.extend(0u32.to_be_bytes()); // checkSumAdjustment, filled in later
+    data.extend(0x5F0F_3CF5u32.to_be_bytes()); // magicNumber
+    data.extend(0x0003u16.to_be_bytes()); // flags: baseline and left sidebearing at 0
+    data.extend(info.units_per_em.to_be_bytes());
+    data.extend(0i64.to_be_bytes()); // created
+    data.extend(0i64.to_be_bytes()); // modified
+    data.extend(bounds.x_min.to_be_bytes());
+    data.extend(bounds.y_min.to_be_bytes());
+    data.extend(bounds.x_max.to_be_bytes());
+    data.extend(bounds.y_max.to_be_bytes());
+    data.extend(0u16.to_be_bytes()); // macStyle
+    data.extend(8u16.to_be_bytes()); // lowestRecPPEM
+    data.extend(2i16.to_be_bytes()); // fontDirectionHint
+    data.extend(1i16.to_be_bytes()); // indexToLocFormat: 32-bit offsets
+    data.extend(0i16.to_be_bytes()); // glyphDataFormat
+
+    data
+}
+
+fn write_hhea(
+    info: &FontInfo,
+    glyphs: &[Glyph],
+    bounds: &[Option<Bounds>],
+    font_bounds: &Bounds,
+) -> Vec<u8> {
+    let advance_width_max = glyphs.iter().map(|g| g.advance_width).max().unwrap_or(0);
+    let (mut min_lsb, mut min_rsb, mut max_extent) = (i16::MAX, i16::MAX, i16::MIN);
+    for (glyph, bounds) in glyphs.iter().zip(bounds) {
+        if let Some(bounds) = bounds {
+            min_lsb = min_lsb.min(bounds.x_min);
+            min_rsb = min_rsb.min(glyph.advance_width as i16 - bounds.x_max);
+            max_extent = max_extent.max(bounds.x_max);
+        }
+    }
+    if max_extent == i16::MIN {
+        (min_lsb, min_rsb, max_extent) = (0, 0, 0);
+    }
+
+    let mut data = Vec::new();
+    data.extend(0x0001_0000u32.to_be_bytes()); // version
+    data.extend(info.ascender.max(font_bounds.y_max).to_be_bytes());
+    data.extend(info.descender.min(font_bounds.y_min).to_be_bytes());
+    data.extend(0i16.to_be_bytes()); // lineGap
+    data.extend(advance_width_max.to_be_bytes());
+    data.extend(min_lsb.to_be_bytes());
+    data.extend(min_rsb.to_be_bytes());
+    data.extend(max_extent.to_be_bytes());
+    data.extend(1i16.to_be_bytes()); // caretSlopeRise
+    data.extend(0i16.to_be_bytes()); // caretSlopeRun
+    data.extend([0; 10]); // caretOffset and four reserved fields
+    data.extend(0i16.to_be_bytes()); // metricDataFormat
+    data.extend((glyphs.len() as u16).to_be_bytes()); // numberOfHMetrics
+
+    data
+}
+
+fn write_hmtx(glyphs: &[Glyph], bounds: &[Option<Bounds>]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (glyph, bounds) in glyphs.iter().zip(bounds) {
+        data.extend(glyph.advance_width.to_be_bytes());
+        data.extend(bounds.map_or(0, |b| b.x_min).to_be_bytes());
+    }
+
+    data
+}
+
+fn write_maxp(glyphs: &[Glyph]) -> Vec<u8> {
+    let max_points = glyphs
+        .iter()
+        .map(|g| g.contours.iter().map(Vec::len).sum::<usize>())
+        .max()
+        .unwrap_or(0) as u16;
+    let max_contours = glyphs.iter().map(|g| g.contours.len()).max().unwrap_or(0) as u16;
+
+    let mut data = Vec::new();
+    data.extend(0x0001_0000u32.to_be_bytes()); // version
+    data.extend((glyphs.len() as u16).to_be_bytes());
+    data.extend(max_points.to_be_bytes());
+    data.extend(max_contours.to_be_bytes());
+    data.extend(0u16.to_be_bytes()); // maxCompositePoints
+    data.extend(0u16.to_be_bytes()); // maxCompositeContours
+    data.extend(2u16.to_be_bytes()); // maxZones
+    data.extend([0; 18]); // the rest only matter for hinted fonts
+
+    data
+}
+
+fn write_os2(info: &FontInfo, glyphs: &[Glyph], cmap: &[(char, u16)]) -> Vec<u8> {
+    let widths: Vec<u32> = glyphs
+        .iter()
+        .filter(|g| g.advance_width > 0)
+        .map(|g| g.advance_width as u32)
+        .collect();
+    let average_width = widths.iter().sum::<u32>() / (widths.len() as u32).max(1);
+    let first_char = cmap.first().map_or(0, |(c, _)| (*c as u32).min(0xFFFF)) as u16;
+    let last_char = cmap.last().map_or(0, |(c, _)| (*c as u32).min(0xFFFF)) as u16;
+    let em = info.units_per_em as i16;
+
+    let mut data = Vec::new();
+    data.extend(4u16.to_be_bytes()); // version
+    data.extend((average_width as i16).to_be_bytes());
+    data.extend(400u16.to_be_bytes()); // usWeightClass: regular
+    data.extend(5u16.to_be_bytes()); // usWidthClass: medium
+    data.extend(0u16.to_be_bytes()); // fsType: installable
+                                     // Subscript and superscript sizes and offsets.
+    for value in [
+        em * 2 / 3,
+        em * 2 / 3,
+        0,
+        em / 7,
+        em * 2 / 3,
+        em * 2 / 3,
+        0,
+        em / 2,
+    ] {
+        data.extend(value.to_be_bytes());
+    }
+    data.extend((em / 20).to_be_bytes()); // yStrikeoutSize
+    data.extend((info.x_height / 2).to_be_bytes()); // yStrikeoutPosition
+    data.extend(0i16.to_be_bytes()); // sFamilyClass
+    data.extend([0; 10]); // panose
+    data.extend([0; 16]); // ulUnicodeRange1-4
+    data.extend(b"NONE"); // achVendID
+    data.extend(0x0040u16.to_be_bytes()); // fsSelection: regular
+    data.extend(first_char.to_be_bytes());
+    data.extend(last_char.to_be_bytes());
+    data.extend(info.ascender.to_be_bytes()); // sTypoAscender
+    data.extend(info.descender.to_be_bytes()); // sTypoDescender
+    data.extend((em / 5).to_be_bytes()); // sTypoLineGap
+    data.extend((info.ascender.max(0) as u16).to_be_bytes()); // usWinAscent
+    data.extend((info.descender.unsigned_abs()).to_be_bytes()); // usWinDescent
+    data.extend(1u32.to_be_bytes()); // ulCodePageRange1: Latin 1
+    data.extend(0u32.to_be_bytes()); // ulCodePageRange2
+    data.extend(info.x_height.to_be_bytes());
+    data.extend(info.cap_height.to_be_bytes());
+    data.extend(0u16.to_be_bytes()); // usDefaultChar
+    data.extend(0x20u16.to_be_bytes()); // usBreakChar
+    data.extend(1u16.to_be_bytes()); // usMaxContext
+
+    data
+}
+
+fn write_cmap(cmap: &[(char, u16)]) -> Vec<u8> {
+    // Format 12 covers every plane, for characters like emoji, with a group
+    // for each run of characters.
+    let groups = runs(cmap.iter().map(|(c, glyph)| (*c as u32, *glyph)));
+    let mut format12 = Vec::new();
+    format12.extend(12u16.to_be_bytes());
+    format12.extend(0u16.to_be_bytes()); // reserved
+    format12.extend((16 + groups.len() as u32 * 12).to_be_bytes()); // length
+    format12.extend(0u32.to_be_bytes()); // language
+    format12.extend((groups.len() as u32).to_be_bytes());
+    for (start, end, glyph) in &groups {
+        format12.extend(start.to_be_bytes()); // startCharCode
+        format12.extend(end.to_be_bytes()); // endCharCode
+        format12.extend((*glyph as u32).to_be_bytes()); // startGlyphID
+    }
+
+    // Unicode BMP and Windows BMP share the format 4 table, and Windows full
+    // repertoire uses the format 12 table. Without a format 4 table, Unicode
+    // full repertoire uses the format 12 table instead.
+    let format4 = write_format4(cmap);
+    let format12_offset = match &format4 {
+        Some(format4) => 4 + 3 * 8 + format4.len() as u32,
+        None => 4 + 2 * 8,
+    };
+    let records = match &format4 {
+        Some(_) => vec![
+            (0u16, 3u16, 4 + 3 * 8),
+            (3, 1, 4 + 3 * 8),
+            (3, 10, format12_offset),
+        ],
+        None => {
+            warn!(
+                "the font has too many characters for a format 4 character map, so only a \
+                 format 12 one is written; some older programs can't read it."
+            );
+            vec![(0, 4, format12_offset), (3, 10, format12_offset)]
+        }
+    };
+    let mut data = Vec::new();
+    data.extend(0u16.to_be_bytes()); // version
+    data.extend((records.len() as u16).to_be_bytes()); // numTables
+    for (platform, encoding, offset) in records {
+        data.extend(platform.to_be_bytes());
+        data.extend(encoding.to_be_bytes());
+        data.extend(offset.to_be_bytes());
+    }
+    data.extend(format4.into_iter().flatten());
+    data.extend(format12);
+
+    data
+}
+
+/// The format 4 character map of the Basic Multilingual Plane, with a
+/// segment for each run of characters, plus the final segment that every
+/// format 4 table ends with. Returns `None` if there are too many runs for
+/// its length to be written.
+fn write_format4(cmap: &[(char, u16)]) -> Option<Vec<u8>> {
+    let bmp = cmap
+        .iter()
+        .filter(|(c, _)| (*c as u32) < 0xFFFF)
+        .map(|(c, glyph)| (*c as u32, *glyph));
+    let segments = runs(bmp);
+    let segment_count = u16::try_from(segments.len() + 1).ok()?;
+    let length = u16::try_from(16 + (segments.len() + 1) * 8).ok()?;
+    let entry_selector = 15 - segment_count.leading_zeros() as u16;
+    let search_range: u16 = 2 * (1 << entry_selector);
+    let mut format4 = Vec::new();
+    format4.extend(4u16.to_be_bytes());
+    format4.extend(length.to_be_bytes());
+    format4.extend(0u16.to_be_bytes()); // language
+    format4.extend((segment_count * 2).to_be_bytes());
+    format4.extend(search_range.to_be_bytes());
+    format4.extend(entry_selector.to_be_bytes());
+    format4.extend((segment_count * 2 - search_range).to_be_bytes());
+    for (_, end, _) in &segments {
+        format4.extend((*end as u16).to_be_bytes()); // endCode
+    }
+    format4.extend(0xFFFFu16.to_be_bytes());
+    format4.extend(0u16.to_be_bytes()); // reservedPad
+    for (start, _, _) in &segments {
+        format4.extend((*start as u16).to_be_bytes()); // startCode
+    }
+    format4.extend(0xFFFFu16.to_be_bytes());
+    for (start, _, glyph) in &segments {
+        format4.extend(glyph.wrapping_sub(*start as u16).to_be_bytes()); // idDelta
+    }
+    format4.extend(1u16.to_be_bytes());
+    format4.extend(vec![0; segment_count as usize * 2]); // idRangeOffset
+
+    Some(format4)
+}
+
+/// Join sorted characters and their glyphs into runs of consecutive
+/// characters whose glyphs are consecutive too, as `(first, last, glyph)`,
+/// where `glyph` is the first character's glyph.
+fn runs(cmap: impl Iterator<Item = (u32, u16)>) -> Vec<(u32, u32, u16)> {
+    let mut runs: Vec<(u32, u32, u16)> = Vec::new();
+    for (c, glyph) in cmap {
+        match runs.last_mut() {
+            Some((start, end, first))
+                if c == *end + 1 && glyph as u32 == *first as u32 + (c - *start) =>
+            {
+                *end = c;
+            }
+            _ => runs.push((c, c, glyph)),
+        }
+    }
+
+    runs
+}
+
+fn write_glyf(glyphs: &[Glyph], bounds: &[Option<Bounds>]) -> (Vec<u8>, Vec<u8>) {
+    let mut glyf = Vec::new();
+    let mut loca = Vec::new();
+    for (glyph, bounds) in glyphs.iter().zip(bounds) {
+        loca.extend((glyf.len() as u32).to_be_bytes());
+        // Glyphs without outlines, like the space, take up no room at all.
+        let Some(bounds) = bounds else {
+            continue;
+        };
+
+        glyf.extend((glyph.contours.len() as i16).to_be_bytes());
+        glyf.extend(bounds.x_min.to_be_bytes());
+        glyf.extend(bounds.y_min.to_be_bytes());
+        glyf.extend(bounds.x_max.to_be_bytes());
+        glyf.extend(bounds.y_max.to_be_bytes());
+        let mut end_point = 0u16;
+        for contour in &glyph.contours {
+            end_point += contour.len() as u16;
+            glyf.extend((end_point - 1).to_be_bytes());
+        }
+        glyf.extend(0u16.to_be_bytes()); // instructionLength
+
+        // Every coordinate is written as a 16-bit delta from the previous
+        // point, so the only flag needed is whether the point is on the curve.
+        let points: Vec<&Point> = glyph.contours.iter().flatten().collect();
+        for point in &points {
+            glyf.push(point.on_curve as u8);
+        }
+        let mut previous = 0i16;
+        for point in &points {
+            glyf.extend(point.x.wrapping_sub(previous).to_be_bytes());
+            previous = point.x;
+        }
+        let mut previous = 0i16;
+        for point in &points {
+            glyf.extend(point.y.wrapping_sub(previous).to_be_bytes());
+            previous = point.y;
+        }
+        glyf.resize(glyf.len().next_multiple_of(4), 0);
+    }
+    loca.extend((glyf.len() as u32).to_be_bytes());
+
+    (glyf, loca)
+}
+
+fn write_name(info: &FontInfo) -> Vec<u8> {
+    let postscript_name: String = info
+        .family_name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    let postscript_name = format!("{postscript_name}-Regular");
+    let records = [
+        (1u16, info.family_name.clone()),
+        (2, "Regular".to_string()),
+        (3, postscript_name.clone()),
+        (4, format!("{} Regular", info.family_name)),
+        (5, "Version 1.000".to_string()),
+        (6, postscript_name),
+    ];
+
+    let mut strings = Vec::new();
+    let mut data = Vec::new();
+    data.extend(0u16.to_be_bytes()); // format
+    data.extend((records.len() as u16).to_be_bytes());
+    data.extend((6 + records.len() as u16 * 12).to_be_bytes()); // stringOffset
+    for (name_id, value) in &records {
+        let encoded: Vec<u8> = value.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        data.extend(3u16.to_be_bytes()); // platformID: Windows
+        data.extend(1u16.to_be_bytes()); // encodingID: Unicode BMP
+        data.extend(0x0409u16.to_be_bytes()); // languageID: English (US)
+        data.extend(name_id.to_be_bytes());
+        data.extend((encoded.len() as u16).to_be_bytes());
+        data.extend((strings.len() as u16).to_be_bytes());
+        strings.extend(encoded);
+    }
+    data.extend(strings);
+
+    data
+}
+
+fn write_post() -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend(0x0003_0000u32.to_be_bytes()); // version 3: no glyph names
+    data.extend(0u32.to_be_bytes()); // italicAngle
+    data.extend((-100i16).to_be_bytes()); // underlinePosition
+    data.extend(50i16.to_be_bytes()); // underlineThickness
+    data.extend(0u32.to_be_bytes()); // isFixedPitch
+    data.extend([0; 16]); // memory usage hints
+
+    data
+}
+
+/// A glyph substitution table: a lookup that joins sequences of glyphs into
+/// ligatures, used by the `liga` feature, and a lookup that swaps glyphs
+/// for their alternates, used by both the `rand` (randomize) and `salt`
+/// (stylistic alternates) features. Every script uses every feature.
+fn write_gsub(substitutions: &Substitutions) -> Vec<u8> {
+    // Each lookup's type and subtable, and each feature's tag and lookup.
+    let mut lookups: Vec<(u16, Vec<u8>)> = Vec::new();
+    let mut features: Vec<(&[u8; 4], u16)> = Vec::new();
+    if !substitutions.ligatures.is_empty() {
+        features.push((b"liga", lookups.len() as u16));
+        lookups.push((4, write_ligature_subtable(&substitutions.ligatures)));
+    }
+    if !substitutions.alternates.is_empty() {
+        let index = lookups.len() as u16;
+        features.extend([(b"rand", index), (b"salt", index)]);
+        lookups.push((3, write_alternate_subtable(&substitutions.alternates)));
+    }
+    let feature_count = features.len() as u16;
+
+    // The script list: `DFLT` and `latn` share one script table, whose
+    // default language system turns on every feature.
+    let mut scripts = Vec::new();
+    scripts.extend(2u16.to_be_bytes()); // scriptCount
+    for tag in [b"DFLT", b"latn"] {
+        scripts.extend(tag);
+        scripts.extend(14u16.to_be_bytes()); // scriptOffset
+    }
+    scripts.extend(4u16.to_be_bytes()); // defaultLangSysOffset
+    scripts.extend(0u16.to_be_bytes()); // langSysCount
+    scripts.extend(0u16.to_be_bytes()); // lookupOrderOffset
+    scripts.extend(0xFFFFu16.to_be_bytes()); // requiredFeatureIndex
+    scripts.extend(feature_count.to_be_bytes());
+    for index in 0..feature_count {
+        scripts.extend(index.to_be_bytes());
+    }
+
+    // The feature list, sorted by tag, followed by each feature's table.
+    let mut feature_list = Vec::new();
+    feature_list.extend(feature_count.to_be_bytes());
+    for (i, (tag, _)) in features.iter().enumerate() {
+        feature_list.extend(*tag);
+        let offset = 2 + 6 * feature_count + 6 * i as u16;
+        feature_list.extend(offset.to_be_bytes());
+    }
+    for (_, lookup) in &features {
+        feature_list.extend(0u16.to_be_bytes()); // featureParamsOffset
+        feature_list.extend(1u16.to_be_bytes()); // lookupIndexCount
+        feature_list.extend(lookup.to_be_bytes());
+    }
+
+    // The lookup list, followed by each lookup with its one subtable.
+    let mut lookup_list = Vec::new();
+    lookup_list.extend((lookups.len() as u16).to_be_bytes());
+    let mut offset = 2 + 2 * lookups.len() as u16;
+    for (_, subtable) in &lookups {
+        lookup_list.extend(offset.to_be_bytes());
+        offset += 8 + subtable.len() as u16;
+    }
+    for (lookup_type, subtable) in lookups {
+        lookup_list.extend(lookup_type.to_be_bytes());
+        lookup_list.extend(0u16.to_be_bytes()); // lookupFlag
+        lookup_list.extend(1u16.to_be_bytes()); // subTableCount
+        lookup_list.extend(8u16.to_be_bytes()); // subtableOffset
+        lookup_list.extend(subtable);
+    }
+
+    let mut data = Vec::new();
+    data.extend(0x0001_0000u32.to_be_bytes()); // version 1.0
+    let script_list_offset = 10u16;
+    let feature_list_offset = script_list_offset + scripts.len() as u16;
+    let lookup_list_offset = feature_list_offset + feature_list.len() as u16;
+    data.extend(script_list_offset.to_be_bytes());
+    data.extend(feature_list_offset.to_be_bytes());
+    data.extend(lookup_list_offset.to_be_bytes());
+    data.extend(scripts);
+    data.extend(feature_list);
+    data.extend(lookup_list);
+
+    data
+}
+
+/// A kerning table with one subtable of pairs, sorted by their glyphs, as
+/// most renderers read for fonts without positioning features.
+fn write_kern(kerning: &[(u16, u16, i16)]) -> Vec<u8> {
+    let mut pairs = kerning.to_vec();
+    pairs.sort();
+    let count = pairs.len() as u16;
+    let entry_selector = 15 - count.leading_zeros() as u16;
+    let search_range: u16 = (1 << entry_selector) * 6;
+
+    let mut data = Vec::new();
+    data.extend(0u16.to_be_bytes()); // version
+    data.extend(1u16.to_be_bytes()); // nTables
+    data.extend(0u16.to_be_bytes()); // subtable version
+    data.extend((14 + 6 * count).to_be_bytes()); // length
+    data.extend(1u16.to_be_bytes()); // coverage: horizontal, format 0
+    data.extend(count.to_be_bytes());
+    data.extend(search_range.to_be_bytes());
+    data.extend(entry_selector.to_be_bytes());
+    data.extend((count * 6 - search_range).to_be_bytes()); // rangeShift
+    for (left, right, amount) in pairs {
+        data.extend(left.to_be_bytes());
+        data.extend(right.to_be_bytes());
+        data.extend(amount.to_be_bytes());
+    }
+
+    data
+}
+
+/// A coverage table listing `glyphs`, which must be sorted.
+fn write_coverage(glyphs: &[u16]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend(1u16.to_be_bytes()); // coverageFormat
+    data.extend((glyphs.len() as u16).to_be_bytes());
+    for glyph in glyphs {
+        data.extend(glyph.to_be_bytes());
+    }
+
+    data
+}
+
+/// An alternate substitution subtable, then its coverage table and
+/// alternate sets.
+fn write_alternate_subtable(alternates: &[(u16, Vec<u16>)]) -> Vec<u8> {
+    let mut alternates = alternates.to_vec();
+    alternates.sort();
+    let count = alternates.len() as u16;
+    let coverage: Vec<u16> = alternates.iter().map(|(glyph, _)| *glyph).collect();
+    let coverage = write_coverage(&coverage);
+
+    let mut data = Vec::new();
+    data.extend(1u16.to_be_bytes()); // substFormat
+    let coverage_offset = 6 + 2 * count;
+    data.extend(coverage_offset.to_be_bytes());
+    data.extend(count.to_be_bytes()); // alternateSetCount
+    let mut offset = coverage_offset + coverage.len() as u16;
+    for (_, set) in &alternates {
+        data.extend(offset.to_be_bytes());
+        offset += 2 + 2 * set.len() as u16;
+    }
+    data.extend(coverage);
+    for (_, set) in &alternates {
+        data.extend((set.len() as u16).to_be_bytes());
+        for glyph in set {
+            data.extend(glyph.to_be_bytes());
+        }
+    }
+
+    data
+}
+
+/// A ligature substitution subtable, then its coverage table of first
+/// glyphs and a ligature set for each of them.
+fn write_ligature_subtable(ligatures: &[(Vec<u16>, u16)]) -> Vec<u8> {
+    // Ligatures are grouped by their first glyph, and longer ones are tried
+    // first, so that `ffi` wins over `ff`.
+    let mut sets: BTreeMap<u16, Vec<(&[u16], u16)>> = BTreeMap::new();
+    for (components, ligature) in ligatures {
+        if let Some((first, rest)) = components.split_first() {
+            sets.entry(*first).or_default().push((rest, *ligature));
+        }
+    }
+    for set in sets.values_mut() {
+        set.sort_by_key(|(rest, _)| std::cmp::Reverse(rest.len()));
+    }
+    let count = sets.len() as u16;
+    let coverage: Vec<u16> = sets.keys().copied().collect();
+    let coverage = write_coverage(&coverage);
+
+    let set_tables: Vec<Vec<u8>> = sets
+        .values()
+        .map(|set| {
+            let mut table = Vec::new();
+            table.extend((set.len() as u16).to_be_bytes()); // ligatureCount
+            let mut offset = 2 + 2 * set.len() as u16;
+            for (rest, _) in set {
+                table.extend(offset.to_be_bytes());
+                offset += 4 + 2 * rest.len() as u16;
+            }
+            for (rest, ligature) in set {
+                table.extend(ligature.to_be_bytes());
+                table.extend((rest.len() as u16 + 1).to_be_bytes()); // componentCount
+                for glyph in *rest {
+                    table.extend(glyph.to_be_bytes());
+                }
+            }
+            table
+        })
+        .collect();
+
+    let mut data = Vec::new();
+    data.extend(1u16.to_be_bytes()); // substFormat
+    let coverage_offset = 6 + 2 * count;
+    data.extend(coverage_offset.to_be_bytes());
+    data.extend(count.to_be_bytes()); // ligatureSetCount
+    let mut offset = coverage_offset + coverage.len() as u16;
+    for table in &set_tables {
+        data.extend(offset.to_be_bytes());
+        offset += table.len() as u16;
+    }
+    data.extend(coverage);
+    for table in set_tables {
+        data.extend(table);
+    }
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ab_glyph::{Font, FontRef, GlyphId};
+
+    /// A font with an empty glyph for each of `characters`, in order, after
+    /// the `.notdef` glyph.
+    fn font_of(characters: &[char]) -> Vec<u8> {
+        let info = FontInfo {
+            family_name: "Test".into(),
+            units_per_em: 1000,
+            ascender: 800,
+            descender: -200,
+            x_height: 500,
+            cap_height: 700,
+        };
+        let glyph = Glyph {
+            contours: Vec::new(),
+            advance_width: 500,
+        };
+        let glyphs = vec![glyph; characters.len() + 1];
+        let cmap: Vec<(char, u16)> = characters
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (*c, i as u16 + 1))
+            .collect();
+        write_font(&info, &glyphs, &cmap, &Substitutions::default(), &[])
+    }
+
+    #[test]
+    fn maps_runs_of_characters_and_characters_outside_the_bmp() {
+        let characters: Vec<char> = ('A'..='Z').chain(['a', 'é', '😀']).collect();
+        let font = font_of(&characters);
+        let font = FontRef::try_from_slice(&font).unwrap();
+        for (i, c) in characters.iter().enumerate() {
+            assert_eq!(font.glyph_id(*c), GlyphId(i as u16 + 1), "{c}");
+        }
+        assert_eq!(font.glyph_id('b'), GlyphId(0));
+
+        let cmap: Vec<(char, u16)> = ('A'..='Z').zip(1..).chain([('a', 40)]).collect();
+        assert_eq!(
+            runs(cmap.iter().map(|(c, glyph)| (*c as u32, *glyph))),
+            vec![(0x41, 0x5A, 1), (0x61, 0x61, 40)]
+        );
+    }
+
+    #[test]
+    fn maps_more_characters_than_format_4_can_hold() {
+        // Every other CJK ideograph, so that no two are in a run.
+        let characters: Vec<char> = (0..9000)
+            .map(|i| char::from_u32(0x4E00 + i * 2).unwrap())
+            .collect();
+        assert!(write_format4(&characters.iter().map(|c| (*c, 1)).collect::<Vec<_>>()).is_none());
+        let font = font_of(&characters);
+        let font = FontRef::try_from_slice(&font).unwrap();
+        for i in [0, 4500, 8999] {
+            assert_eq!(font.glyph_id(characters[i]), GlyphId(i as u16 + 1));
+        }
+        assert_eq!(font.glyph_id('\u{4E01}'), GlyphId(0));
+    }
+}
@@ -0,0 +1,96 @@
+use image::{imageops::FilterType, GrayImage, Luma};
+
+use crate::glyph::PAPER;
+
+/// Make a letter image `factor` times bigger, for tracing at a high
+/// resolution. Letter images in shades of gray (`keep_gray`) are resampled
+/// with a Lanczos filter. Black-and-white ones are doubled with [`scale2x`],
+/// which rounds off the steps along diagonal strokes instead of making them
+/// bigger, as many times as fits, then resampled the rest of the way and
+/// thresholded again.
+pub fn upscale(cell: &GrayImage, factor: f32, keep_gray: bool) -> GrayImage {
+    let (width, height) = cell.dimensions();
+    let target = (
+        ((width as f32 * factor).round() as u32).max(1),
+        ((height as f32 * factor).round() as u32).max(1),
+    );
+    if keep_gray {
+        return image::imageops::resize(cell, target.0, target.1, FilterType::Lanczos3);
+    }
+
+    let mut scaled = cell.clone();
+    while scaled.width() * 2 <= target.0 && scaled.height() * 2 <= target.1 {
+        scaled = scale2x(&scaled);
+    }
+    if scaled.dimensions() != target {
+        scaled = image::imageops::resize(&scaled, target.0, target.1, FilterType::Lanczos3);
+        for pixel in scaled.pixels_mut() {
+            *pixel = if pixel.0[0] < 128 { Luma([0]) } else { PAPER };
+        }
+    }
+    scaled
+}
+
+/// Double the size of an image with the Scale2x algorithm (also known as
+/// EPX). Each pixel becomes four, and a corner takes the color of the two
+/// pixels beside it when they match each other but not the other two, so
+/// edges stay sharp and diagonal ones come out smooth.
+pub fn scale2x(image: &GrayImage) -> GrayImage {
+    let (width, height) = image.dimensions();
+    let at = |x: i64, y: i64| {
+        *image.get_pixel(
+            x.clamp(0, width as i64 - 1) as u32,
+            y.clamp(0, height as i64 - 1) as u32,
+        )
+    };
+    let mut scaled = GrayImage::new(width * 2, height * 2);
+    for (x, y, &pixel) in image.enumerate_pixels() {
+        let (sx, sy) = (x as i64, y as i64);
+        let above = at(sx, sy - 1);
+        let right = at(sx + 1, sy);
+        let left = at(sx - 1, sy);
+        let below = at(sx, sy + 1);
+        let corners = [
+            (left == above && left != below && above != right, above),
+            (above == right && above != left && right != below, right),
+            (below == left && below != right && left != above, left),
+            (right == below && right != above && below != left, below),
+        ];
+        for (i, (matches, color)) in corners.into_iter().enumerate() {
+            let (dx, dy) = (i as u32 % 2, i as u32 / 2);
+            scaled.put_pixel(x * 2 + dx, y * 2 + dy, if matches { color } else { pixel });
+        }
+    }
+    scaled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smooths_diagonal_strokes() {
+        // A diagonal line, one pixel wide.
+        let mut cell = GrayImage::from_pixel(3, 3, PAPER);
+        for i in 0..3 {
+            cell.put_pixel(i, i, Luma([0]));
+        }
+        let scaled = scale2x(&cell);
+        assert_eq!(scaled.dimensions(), (6, 6));
+        // Each ink pixel stays ink...
+        assert_eq!(scaled.get_pixel(2, 2).0[0], 0);
+        assert_eq!(scaled.get_pixel(3, 3).0[0], 0);
+        // ...and the steps between them are filled in.
+        assert_eq!(scaled.get_pixel(2, 1).0[0], 0);
+        assert_eq!(scaled.get_pixel(1, 2).0[0], 0);
+        // Corners away from the line stay paper.
+        assert_eq!(scaled.get_pixel(5, 0), &PAPER);
+
+        let upscaled = upscale(&cell, 2.5, false);
+        assert_eq!(upscaled.dimensions(), (8, 8));
+        assert!(upscaled
+            .pixels()
+            .all(|pixel| [0, 255].contains(&pixel.0[0])));
+        assert_eq!(upscale(&cell, 3.0, true).dimensions(), (9, 9));
+    }
+}
@@ -0,0 +1,169 @@
+use image::{GrayImage, Luma};
+use imageproc::contours::{find_contours, BorderType};
+
+/// A point, as `(x, y)`.
+pub type Point = (f32, f32);
+
+/// A closed outline traced around a shape of ink, in pixel coordinates with
+/// the origin at the top left of the image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outline {
+    pub points: Vec<Point>,
+    /// Whether this outline is the edge of a hole in a shape, like the inside
+    /// of an `o`, rather than the outside of a shape.
+    pub is_hole: bool,
+}
+
+/// Trace the outlines of every shape of ink in a glyph image.
+///
+/// Outlines are traced through the centers of each shape's edge pixels, then
+/// simplified: points closer than `tolerance` pixels to the line between
+/// their neighbors are dropped. Outlines that simplify to fewer than three
+/// points, such as specks of dust, are dropped entirely.
+pub fn trace(image: &GrayImage, tolerance: f32) -> Vec<Outline> {
+    let ink = GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        Luma([if image.get_pixel(x, y).0[0] < 128 {
+            255
+        } else {
+            0
+        }])
+    });
+
+    find_contours::<i32>(&ink)
+        .into_iter()
+        .filter_map(|contour| {
+            let points: Vec<Point> = contour
+                .points
+                .iter()
+                .map(|point| (point.x as f32 + 0.5, point.y as f32 + 0.5))
+                .collect();
+            let points = simplify_closed(&points, tolerance);
+            (points.len() >= 3).then(|| Outline {
+                points,
+                is_hole: contour.border_type == BorderType::Hole,
+            })
+        })
+        .collect()
+}
+
+/// The signed area of a polygon. It's positive when the points run clockwise
+/// on screen, where y points down.
+pub fn signed_area(points: &[Point]) -> f32 {
+    let mut area = 0.0;
+    for (i, a) in points.iter().enumerate() {
+        let b = points[(i + 1) % points.len()];
+        area += a.0 * b.1 - b.0 * a.1;
+    }
+
+    area / 2.0
+}
+
+/// Turn a polygon into a smooth closed curve made of quadratic Bézier
+/// segments. Each point of the polygon becomes a control point, and the
+/// curve passes through the midpoints between them. Returns the segments as
+/// `(control, end)` pairs, starting from the midpoint returned first.
+pub fn smooth_closed(points: &[Point]) -> (Point, Vec<(Point, Point)>) {
+    let midpoint = |a: Point, b: Point| ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+    let count = points.len();
+    let start = midpoint(points[count - 1], points[0]);
+    let segments = (0..count)
+        .map(|i| (points[i], midpoint(points[i], points[(i + 1) % count])))
+        .collect();
+
+    (start, segments)
+}
+
+/// Draw outlines as an SVG image of the given size, with each outline
+/// smoothed into curves by [`smooth_closed`].
+pub fn to_svg(outlines: &[Outline], width: u32, height: u32) -> String {
+    let mut path = String::new();
+    for outline in outlines {
+        let (start, segments) = smooth_closed(&outline.points);
+        path.push_str(&format!("M{:.1} {:.1}", start.0, start.1));
+        for (control, end) in segments {
+            path.push_str(&format!(
+                "Q{:.1} {:.1} {:.1} {:.1}",
+                control.0, control.1, end.0, end.1
+            ));
+        }
+        path.push('Z');
+    }
+
+    // Holes are cut out by the even-odd rule, whichever way their outlines
+    // run.
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\">\n\
+         <path fill=\"black\" fill-rule=\"evenodd\" d=\"{path}\"/>\n\
+         </svg>\n"
+    )
+}
+
+/// Simplify a closed polygon with the Ramer–Douglas–Peucker algorithm.
+fn simplify_closed(points: &[Point], tolerance: f32) -> Vec<Point> {
+    if points.len() < 4 {
+        return points.to_vec();
+    }
+
+    // Split the loop at the point farthest from the first one, and simplify
+    // each half as an open path.
+    let first = points[0];
+    let (split, _) = points
+        .iter()
+        .enumerate()
+        .map(|(i, point)| (i, (point.0 - first.0).hypot(point.1 - first.1)))
+        .fold(
+            (0, 0.0),
+            |best, next| if next.1 > best.1 { next } else { best },
+        );
+    if split == 0 {
+        return vec![first];
+    }
+
+    let mut simplified = simplify_open(&points[..=split], tolerance);
+    let mut second_half = points[split..].to_vec();
+    second_half.push(first);
+    let second_half = simplify_open(&second_half, tolerance);
+    // Both halves include the split point, and the second half ends where the
+    // first half starts.
+    simplified.pop();
+    simplified.extend_from_slice(&second_half[..second_half.len() - 1]);
+
+    simplified
+}
+
+/// Simplify an open polyline with the Ramer–Douglas–Peucker algorithm,
+/// keeping its ends.
+pub fn simplify_open(points: &[Point], tolerance: f32) -> Vec<Point> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    let (start, end) = (points[0], points[points.len() - 1]);
+    let (index, distance) = points[1..points.len() - 1]
+        .iter()
+        .enumerate()
+        .map(|(i, point)| (i + 1, distance_to_line(*point, start, end)))
+        .fold(
+            (0, 0.0),
+            |best, next| if next.1 > best.1 { next } else { best },
+        );
+    if distance <= tolerance {
+        return vec![start, end];
+    }
+
+    let mut simplified = simplify_open(&points[..=index], tolerance);
+    simplified.pop();
+    simplified.extend(simplify_open(&points[index..], tolerance));
+
+    simplified
+}
+
+fn distance_to_line(point: Point, start: Point, end: Point) -> f32 {
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let length = dx.hypot(dy);
+    if length == 0.0 {
+        return (point.0 - start.0).hypot(point.1 - start.1);
+    }
+
+    ((point.0 - start.0) * dy - (point.1 - start.1) * dx).abs() / length
+}
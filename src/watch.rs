@@ -0,0 +1,91 @@
+use anyhow::{bail, Context};
+use notify::{Config, EventKind, PollWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+
+use crate::inputs;
+
+/// How long a new file has to go unchanged before it's scanned. Scanners
+/// often write a page over several seconds, and scanning half a file would
+/// fail.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// Watch `dir` for new images and PDFs, and call `on_file` with each one once
+/// it has finished being written. Files that are already in `dir` are left
+/// alone. This runs until it's interrupted; if `on_file` fails, the error is
+/// logged and watching carries on.
+///
+/// Changes are reported by the system unless `poll_interval` is given, in
+/// which case `dir` is checked for changes that often instead. Polling is
+/// needed for network shares, where the system doesn't hear about files
+/// written by other computers.
+pub fn watch(
+    dir: &Path,
+    poll_interval: Option<Duration>,
+    mut on_file: impl FnMut(&Path) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher: Box<dyn Watcher> = match poll_interval {
+        Some(interval) => Box::new(PollWatcher::new(
+            sender,
+            Config::default().with_poll_interval(interval),
+        )?),
+        None => Box::new(notify::recommended_watcher(sender)?),
+    };
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("watching {}", dir.display()))?;
+    // A poll only notices a change once it's over, so wait for at least two
+    // polls to pass without one.
+    let settle_time = poll_interval.map_or(SETTLE_TIME, |interval| SETTLE_TIME.max(interval * 2));
+    info!(
+        "Watching {} for new scans. Press Ctrl+C to stop.",
+        dir.display()
+    );
+
+    // The files that have changed, and when they last did.
+    let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+    loop {
+        match receiver.recv_timeout(settle_time) {
+            Ok(Ok(event)) => {
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    for path in event.paths {
+                        let hidden = path
+                            .file_name()
+                            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+                        if inputs::is_input(&path) && !hidden {
+                            pending.insert(path, Instant::now());
+                        }
+                    }
+                }
+            }
+            Ok(Err(err)) => warn!("watching {}: {err}", dir.display()),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                bail!("stopped watching {}.", dir.display())
+            }
+        }
+
+        let mut settled: Vec<PathBuf> = pending
+            .iter()
+            .filter(|(_, changed)| changed.elapsed() >= settle_time)
+            .map(|(path, _)| path.clone())
+            .collect();
+        settled.sort();
+        for path in settled {
+            pending.remove(&path);
+            if !path.is_file() {
+                // It was moved or deleted before it settled.
+                continue;
+            }
+            if let Err(err) = on_file(&path) {
+                error!("{err:#}");
+            }
+        }
+    }
+}